@@ -20,7 +20,7 @@ use ublox_short_range::asynch::runner::Runner;
 use ublox_short_range::asynch::ublox_stack::dns::DnsSocket;
 use ublox_short_range::asynch::ublox_stack::tcp::TcpSocket;
 use ublox_short_range::asynch::ublox_stack::{StackResources, UbloxStack};
-use ublox_short_range::asynch::{new, Resources, State};
+use ublox_short_range::asynch::{min_urc_capacity, new, Resources, State};
 use ublox_short_range::atat::{self, AtatIngress};
 use ublox_short_range::command::custom_digest::EdmDigester;
 use ublox_short_range::command::edm::urc::EdmEvent;
@@ -29,7 +29,8 @@ use {defmt_rtt as _, panic_probe as _};
 
 const CMD_BUF_SIZE: usize = 128;
 const INGRESS_BUF_SIZE: usize = 1024;
-const URC_CAPACITY: usize = 2;
+const SOCKETS: usize = 2;
+const URC_CAPACITY: usize = min_urc_capacity(SOCKETS);
 
 type AtClient = ublox_short_range::atat::asynch::Client<
     'static,
@@ -168,7 +169,7 @@ async fn main(spawner: Spawner) {
 
     // Init network stack
     static STACK: StaticCell<Stack<embassy_net_ppp::Device<'static>>> = StaticCell::new();
-    static STACK_RESOURCES: StaticCell<StackResources<2>> = StaticCell::new();
+    static STACK_RESOURCES: StaticCell<StackResources<SOCKETS>> = StaticCell::new();
 
     let stack = &*STACK.init(UbloxStack::new(
         net_device,
@@ -16,14 +16,15 @@ use static_cell::make_static;
 use ublox_short_range::asynch::runner::Runner;
 use ublox_short_range::asynch::ublox_stack::tcp::TcpSocket;
 use ublox_short_range::asynch::ublox_stack::{StackResources, UbloxStack};
-use ublox_short_range::asynch::{new, State};
+use ublox_short_range::asynch::{min_urc_capacity, new, State};
 use ublox_short_range::atat::{self, AtatIngress};
 use ublox_short_range::command::custom_digest::EdmDigester;
 use ublox_short_range::command::edm::urc::EdmEvent;
 use {defmt_rtt as _, panic_probe as _};
 
 const RX_BUF_LEN: usize = 1024;
-const URC_CAPACITY: usize = 3;
+const SOCKETS: usize = 6;
+const URC_CAPACITY: usize = min_urc_capacity(SOCKETS);
 
 type AtClient = ublox_short_range::atat::asynch::Client<
     'static,
@@ -94,7 +95,7 @@ async fn main(spawner: Spawner) {
 
     // Init network stack
     static STACK: StaticCell<Stack<embassy_net_ppp::Device<'static>>> = StaticCell::new();
-    static STACK_RESOURCES: StaticCell<StackResources<6>> = StaticCell::new();
+    static STACK_RESOURCES: StaticCell<StackResources<SOCKETS>> = StaticCell::new();
 
     let stack = &*STACK.init(Stack::new(
         net_device,
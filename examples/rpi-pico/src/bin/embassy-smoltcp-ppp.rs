@@ -28,12 +28,13 @@ use reqwless::request::RequestBuilder as _;
 use reqwless::response::Response;
 use static_cell::StaticCell;
 use ublox_short_range::asynch::control::ControlResources;
-use ublox_short_range::asynch::{Resources, Runner};
+use ublox_short_range::asynch::{min_urc_capacity, Resources, Runner};
 use {defmt_rtt as _, panic_probe as _};
 
 const CMD_BUF_SIZE: usize = 128;
 const INGRESS_BUF_SIZE: usize = 512;
-const URC_CAPACITY: usize = 2;
+// The sockets are those of embassy-net, not of the module
+const URC_CAPACITY: usize = min_urc_capacity(0);
 
 pub struct WifiConfig {
     pub rst_pin: OutputOpenDrain<'static>,
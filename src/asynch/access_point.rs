@@ -0,0 +1,553 @@
+//! Running the module as a Wi-Fi access point.
+//!
+//! The access point is started with
+//! [`Control::start_ap`](super::control::Control::start_ap) and stopped with
+//! [`Control::stop_ap`](super::control::Control::stop_ap), or by name and
+//! password with
+//! [`Control::create_hotspot`](super::control::Control::create_hotspot) and
+//! [`Control::stop_hotspot`](super::control::Control::stop_hotspot).
+//!
+//! Whether it is up, and how many stations are attached to it, is tracked
+//! from the URCs of the module, and available from
+//! [`Control::ap_state`](super::control::Control::ap_state). The stations
+//! themselves are listed by
+//! [`Control::ap_stations`](super::control::Control::ap_stations), without
+//! querying the module, and by
+//! [`Control::connected_stations`](super::control::Control::connected_stations),
+//! which queries the module and corrects the tracked stations accordingly.
+use atat::asynch::AtatClient;
+use heapless::{FnvIndexMap, Vec};
+use no_std_net::Ipv4Addr;
+
+use crate::command::wifi::{
+    responses::WiFiAPStationListResponse,
+    types::{
+        AccessPointAction, AccessPointConfig, AccessPointId, Bssid, IPv4Mode, PasskeyR,
+        SecurityMode, SecurityModePSK,
+    },
+    urc::{WifiAPStationConnected, WifiAPStationDisconnected},
+    SetWifiAPConfig, WiFiAPStationList, WifiAPAction,
+};
+use crate::command::Urc;
+use crate::connection::{WiFiState, WifiConnection};
+use crate::error::Error;
+use crate::network::{WifiBand, WifiNetwork};
+use crate::options::{Band, ConnectionOptions, HotspotOptions, WifiAuthentication};
+
+/// State of the access point, as reported by the module.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ApState {
+    /// The access point is up, and accepts stations.
+    pub up: bool,
+    /// Number of stations attached to the access point.
+    pub stations: u8,
+}
+
+/// Maximum number of stations tracked, as attached to the access point.
+pub const MAX_AP_STATIONS: usize = 8;
+
+/// Station attached to the access point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConnectedStation {
+    /// Id assigned by the module, anew on every connection of the station.
+    pub station_id: u32,
+    pub mac_addr: Bssid,
+}
+
+/// Access point state kept by the runner, from the URCs of the module.
+pub(crate) struct AccessPoint {
+    up: bool,
+    /// MAC address of the attached stations, by station id.
+    stations: FnvIndexMap<u32, Bssid, MAX_AP_STATIONS>,
+}
+
+impl AccessPoint {
+    pub(crate) const fn new() -> Self {
+        Self {
+            up: false,
+            stations: FnvIndexMap::new(),
+        }
+    }
+
+    pub(crate) fn state(&self) -> ApState {
+        ApState {
+            up: self.up,
+            stations: self.stations.len() as u8,
+        }
+    }
+
+    pub(crate) fn stations(&self) -> Vec<ConnectedStation, MAX_AP_STATIONS> {
+        self.stations
+            .iter()
+            .map(|(&station_id, &mac_addr)| ConnectedStation {
+                station_id,
+                mac_addr,
+            })
+            .collect()
+    }
+
+    fn station_connected(&mut self, station_id: u32, mac_addr: Bssid) {
+        // A station reconnecting gets a new id, and its previous connection
+        // may not be reported down.
+        if let Some(previous) = self
+            .stations
+            .iter()
+            .find(|(_, mac)| **mac == mac_addr)
+            .map(|(id, _)| *id)
+        {
+            self.stations.remove(&previous);
+        }
+
+        if self.stations.insert(station_id, mac_addr).is_err() {
+            warn!(
+                "More than {} stations attached, not tracking {:?}",
+                MAX_AP_STATIONS, mac_addr
+            );
+        }
+    }
+
+    /// Replace the tracked stations with `stations`, as listed by the module,
+    /// to recover from missed URCs.
+    pub(crate) fn reconcile(&mut self, stations: &[ConnectedStation]) {
+        if !self.up {
+            return;
+        }
+
+        self.stations.clear();
+        for station in stations {
+            self.station_connected(station.station_id, station.mac_addr);
+        }
+    }
+}
+
+/// Channel to program for `configuration`, as the module derives the band of
+/// the access point from its channel.
+fn channel(configuration: &HotspotOptions) -> Result<Option<u8>, Error> {
+    let channel = configuration.channel.map(|channel| channel as u8);
+
+    match (&configuration.band, channel) {
+        // Lowest 5 GHz channel, available in all regulatory domains
+        (Some(Band::A), None) => Ok(Some(36)),
+        (Some(Band::A), Some(channel)) if WifiBand::from_channel(channel) != WifiBand::Band5GHz => {
+            Err(Error::InvalidChannel(channel))
+        }
+        (Some(Band::Bg), Some(channel))
+            if WifiBand::from_channel(channel) != WifiBand::Band2_4GHz =>
+        {
+            Err(Error::InvalidChannel(channel))
+        }
+        _ => Ok(channel),
+    }
+}
+
+/// Program the access point configuration and activate it.
+pub(crate) async fn start<A: AtatClient>(
+    at_client: &mut A,
+    options: ConnectionOptions<'_>,
+    configuration: HotspotOptions,
+) -> Result<(), Error> {
+    let channel = channel(&configuration)?;
+
+    // The module serves open and WPA2 personal access points only
+    if matches!(
+        options.auth,
+        WifiAuthentication::Wpa3Passphrase(_)
+            | WifiAuthentication::Wpa2Wpa3Passphrase(_)
+            | WifiAuthentication::Enterprise(_)
+    ) {
+        return Err(Error::Unimplemented);
+    }
+
+    // Deactivate network id 0
+    at_client
+        .send_retry(&WifiAPAction {
+            ap_config_id: AccessPointId::Id0,
+            ap_action: AccessPointAction::Deactivate,
+        })
+        .await?;
+
+    at_client
+        .send_retry(&WifiAPAction {
+            ap_config_id: AccessPointId::Id0,
+            ap_action: AccessPointAction::Reset,
+        })
+        .await?;
+
+    // Disable DHCP Server (static IP address will be used)
+    if options.ip.is_some() || options.subnet.is_some() || options.gateway.is_some() {
+        at_client
+            .send_retry(&SetWifiAPConfig {
+                ap_config_id: AccessPointId::Id0,
+                ap_config_param: AccessPointConfig::IPv4Mode(IPv4Mode::Static),
+            })
+            .await?;
+
+        at_client
+            .send_retry(&SetWifiAPConfig {
+                ap_config_id: AccessPointId::Id0,
+                ap_config_param: AccessPointConfig::IPv4Address(
+                    options.ip.unwrap_or(Ipv4Addr::new(192, 168, 2, 1)),
+                ),
+            })
+            .await?;
+
+        at_client
+            .send_retry(&SetWifiAPConfig {
+                ap_config_id: AccessPointId::Id0,
+                ap_config_param: AccessPointConfig::SubnetMask(
+                    options.subnet.unwrap_or(Ipv4Addr::new(255, 255, 255, 0)),
+                ),
+            })
+            .await?;
+
+        at_client
+            .send_retry(&SetWifiAPConfig {
+                ap_config_id: AccessPointId::Id0,
+                ap_config_param: AccessPointConfig::DefaultGateway(
+                    options.gateway.unwrap_or(Ipv4Addr::new(192, 168, 2, 1)),
+                ),
+            })
+            .await?;
+    }
+
+    // Network Primary + Secondary DNS
+    if let Some(primary) = options.dns.primary {
+        if let Some(secondary) = options.dns.secondary {
+            at_client
+                .send_retry(&SetWifiAPConfig {
+                    ap_config_id: AccessPointId::Id0,
+                    ap_config_param: AccessPointConfig::SecondaryDNS(secondary),
+                })
+                .await?;
+        }
+
+        at_client
+            .send_retry(&SetWifiAPConfig {
+                ap_config_id: AccessPointId::Id0,
+                ap_config_param: AccessPointConfig::PrimaryDNS(primary),
+            })
+            .await?;
+    }
+
+    at_client
+        .send_retry(&SetWifiAPConfig {
+            ap_config_id: AccessPointId::Id0,
+            ap_config_param: AccessPointConfig::DHCPServer(configuration.dhcp_server.into()),
+        })
+        .await?;
+
+    // Set the Network SSID to connect to
+    at_client
+        .send_retry(&SetWifiAPConfig {
+            ap_config_id: AccessPointId::Id0,
+            ap_config_param: AccessPointConfig::SSID(options.ssid),
+        })
+        .await?;
+
+    match options.auth {
+        WifiAuthentication::None => {
+            at_client
+                .send_retry(&SetWifiAPConfig {
+                    ap_config_id: AccessPointId::Id0,
+                    ap_config_param: AccessPointConfig::SecurityMode(
+                        SecurityMode::Open,
+                        SecurityModePSK::Open,
+                    ),
+                })
+                .await?;
+        }
+        WifiAuthentication::Wpa2Passphrase(passphrase) => {
+            at_client
+                .send_retry(&SetWifiAPConfig {
+                    ap_config_id: AccessPointId::Id0,
+                    ap_config_param: AccessPointConfig::SecurityMode(
+                        SecurityMode::Wpa2AesCcmp,
+                        SecurityModePSK::PSK,
+                    ),
+                })
+                .await?;
+
+            // Input passphrase
+            at_client
+                .send_retry(&SetWifiAPConfig {
+                    ap_config_id: AccessPointId::Id0,
+                    ap_config_param: AccessPointConfig::PSKPassphrase(PasskeyR::Passphrase(
+                        heapless::String::try_from(passphrase).map_err(|_| Error::Overflow)?,
+                    )),
+                })
+                .await?;
+        }
+        // Rejected above
+        WifiAuthentication::Wpa3Passphrase(_)
+        | WifiAuthentication::Wpa2Wpa3Passphrase(_)
+        | WifiAuthentication::Enterprise(_) => {}
+    }
+
+    if let Some(channel) = channel {
+        at_client
+            .send_retry(&SetWifiAPConfig {
+                ap_config_id: AccessPointId::Id0,
+                ap_config_param: AccessPointConfig::Channel(channel),
+            })
+            .await?;
+    }
+
+    at_client
+        .send_retry(&WifiAPAction {
+            ap_config_id: AccessPointId::Id0,
+            ap_action: AccessPointAction::Activate,
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Deactivate the access point.
+pub(crate) async fn stop<A: AtatClient>(at_client: &mut A) -> Result<(), Error> {
+    at_client
+        .send_retry(&WifiAPAction {
+            ap_config_id: AccessPointId::Id0,
+            ap_action: AccessPointAction::Deactivate,
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// List the stations attached to the access point, as known to the module.
+pub(crate) async fn connected_stations<A: AtatClient>(
+    at_client: &mut A,
+) -> Result<Vec<ConnectedStation, MAX_AP_STATIONS>, Error> {
+    let WiFiAPStationListResponse { stations } = at_client.send_retry(&WiFiAPStationList).await?;
+
+    Ok(stations
+        .into_iter()
+        .map(|station| ConnectedStation {
+            station_id: station.station_id,
+            mac_addr: station.mac_addr,
+        })
+        .collect())
+}
+
+/// Account for a URC of the access point in the connection state.
+pub(crate) fn on_urc(con: &mut WifiConnection, event: &Urc) {
+    match event {
+        Urc::WifiAPUp(_) => {
+            con.wifi_state = WiFiState::Connected;
+            con.network.replace(WifiNetwork::new_ap());
+            // The access point serves its own, static, IP configuration
+            con.ipv4_up = true;
+            con.ipv6_link_local_up = true;
+            con.ap = AccessPoint {
+                up: true,
+                stations: FnvIndexMap::new(),
+            };
+        }
+        Urc::WifiAPDown(_) => {
+            con.network.take();
+            con.wifi_state = WiFiState::Inactive;
+            con.ipv4_up = false;
+            con.ipv6_link_local_up = false;
+            con.ap = AccessPoint::new();
+        }
+        Urc::WifiAPStationConnected(WifiAPStationConnected {
+            station_id,
+            mac_addr,
+        }) => con.ap.station_connected(*station_id, *mac_addr),
+        Urc::WifiAPStationDisconnected(WifiAPStationDisconnected { station_id }) => {
+            con.ap.stations.remove(station_id);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::asynch::state::{self, LinkState};
+    use crate::options::Channel;
+    use crate::test_util::MockUbloxModule;
+    use atat::AtatUrc;
+
+    fn sent(client: &MockUbloxModule) -> std::vec::Vec<&[u8]> {
+        client.sent_commands().collect()
+    }
+
+    #[test]
+    fn command_sequence() {
+        let mut client = MockUbloxModule::new();
+
+        embassy_futures::block_on(start(
+            &mut client,
+            ConnectionOptions::new("ublox-ap"),
+            HotspotOptions::new().channel(Channel::Six),
+        ))
+        .unwrap();
+        embassy_futures::block_on(stop(&mut client)).unwrap();
+
+        assert_eq!(
+            sent(&client),
+            [
+                b"AT+UWAPCA=0,4\r\n".to_vec(),
+                b"AT+UWAPCA=0,0\r\n".to_vec(),
+                b"AT+UWAPC=0,106,1\r\n".to_vec(),
+                b"AT+UWAPC=0,2,\"ublox-ap\"\r\n".to_vec(),
+                b"AT+UWAPC=0,5,1,1\r\n".to_vec(),
+                b"AT+UWAPC=0,4,6\r\n".to_vec(),
+                b"AT+UWAPCA=0,3\r\n".to_vec(),
+                b"AT+UWAPCA=0,4\r\n".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn band() {
+        let mut client = MockUbloxModule::new();
+
+        embassy_futures::block_on(start(
+            &mut client,
+            ConnectionOptions::new("ublox-ap"),
+            HotspotOptions {
+                channel: None,
+                band: Some(Band::A),
+                dhcp_server: true,
+            },
+        ))
+        .unwrap();
+        assert!(sent(&client).contains(&b"AT+UWAPC=0,4,36\r\n".as_slice()));
+
+        // Nothing is sent for a channel outside of the band
+        let mut client = MockUbloxModule::new();
+        assert!(matches!(
+            embassy_futures::block_on(start(
+                &mut client,
+                ConnectionOptions::new("ublox-ap"),
+                HotspotOptions::new().band(Band::A).channel(Channel::Six),
+            )),
+            Err(Error::InvalidChannel(6))
+        ));
+        assert!(sent(&client).is_empty());
+    }
+
+    /// Feed URCs, as digested from the module, to the connection state.
+    fn urcs(ch: &state::Runner<'_>, lines: &[&[u8]]) {
+        for line in lines {
+            let event = Urc::parse(line).unwrap();
+            ch.update_connection_with(|con| on_urc(con, &event));
+        }
+    }
+
+    fn station(station_id: u32, mac_addr: &[u8]) -> ConnectedStation {
+        ConnectedStation {
+            station_id,
+            mac_addr: Bssid::parse(mac_addr).unwrap(),
+        }
+    }
+
+    #[test]
+    fn ap_up_flips_link_state() {
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        ch.mark_initialized();
+
+        urcs(&ch, &[b"+UUWAPU:0"]);
+        assert_eq!(ch.link_state(None), LinkState::Up);
+        assert_eq!(
+            ch.ap_state(),
+            ApState {
+                up: true,
+                stations: 0
+            }
+        );
+
+        urcs(
+            &ch,
+            &[
+                b"+UUWAPSTAC:1,D4CA6DF5F2F0",
+                b"+UUWAPSTAC:2,D4CA6DF5F2F1",
+                b"+UUWAPSTAD:1",
+            ],
+        );
+        assert_eq!(ch.ap_state().stations, 1);
+
+        urcs(&ch, &[b"+UUWAPD:0"]);
+        assert_eq!(ch.link_state(None), LinkState::Down);
+        assert_eq!(ch.ap_state(), ApState::default());
+        assert!(ch.ap_stations().is_empty());
+    }
+
+    #[test]
+    fn station_reconnect() {
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+
+        urcs(
+            &ch,
+            &[
+                b"+UUWAPU:0",
+                b"+UUWAPSTAC:1,D4CA6DF5F2F0",
+                b"+UUWAPSTAC:2,D4CA6DF5F2F1",
+            ],
+        );
+        assert_eq!(
+            ch.ap_stations(),
+            [station(1, b"D4CA6DF5F2F0"), station(2, b"D4CA6DF5F2F1")]
+        );
+
+        // Reconnecting without the previous connection being reported down
+        urcs(&ch, &[b"+UUWAPSTAC:3,D4CA6DF5F2F0"]);
+        assert_eq!(
+            ch.ap_stations(),
+            [station(2, b"D4CA6DF5F2F1"), station(3, b"D4CA6DF5F2F0")]
+        );
+
+        // The late disconnect of the previous connection is ignored
+        urcs(&ch, &[b"+UUWAPSTAD:1", b"+UUWAPSTAD:2"]);
+        assert_eq!(ch.ap_stations(), [station(3, b"D4CA6DF5F2F0")]);
+    }
+
+    #[test]
+    fn station_list() {
+        let response = WiFiAPStationList
+            .parse(Ok(
+                &b"+UWAPSTALIST:1,D4CA6DF5F2F0,-42\r\n+UWAPSTALIST:3,D4CA6DF5F2F1,-60"[..],
+            ))
+            .unwrap();
+
+        assert_eq!(response.stations.len(), 2);
+        assert_eq!(response.stations[1].station_id, 3);
+        assert_eq!(
+            response.stations[1].mac_addr,
+            Bssid::parse(b"D4CA6DF5F2F1").unwrap()
+        );
+        assert_eq!(response.stations[1].rssi, -60);
+    }
+
+    #[test]
+    fn station_list_reconciles() {
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+
+        // The connect of station 4, and the disconnect of station 1, were
+        // missed
+        urcs(
+            &ch,
+            &[
+                b"+UUWAPU:0",
+                b"+UUWAPSTAC:1,D4CA6DF5F2F0",
+                b"+UUWAPSTAC:2,D4CA6DF5F2F1",
+            ],
+        );
+        let listed = [station(2, b"D4CA6DF5F2F1"), station(4, b"D4CA6DF5F2F2")];
+        ch.update_connection_with(|con| con.ap.reconcile(&listed));
+        assert_eq!(ch.ap_stations(), listed);
+        assert_eq!(ch.ap_state().stations, 2);
+
+        // A list racing the access point going down is ignored
+        urcs(&ch, &[b"+UUWAPD:0"]);
+        ch.update_connection_with(|con| con.ap.reconcile(&listed));
+        assert!(ch.ap_stations().is_empty());
+    }
+}
@@ -1,79 +1,132 @@
-use core::cell::Cell;
-use core::str::FromStr as _;
+use core::cell::{Cell, RefCell};
 
 use atat::AtatCmd;
-use atat::{asynch::AtatClient, response_slot::ResponseSlotGuard, UrcChannel};
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Sender};
-use embassy_time::{with_timeout, Duration, Timer};
+use atat::{asynch::AtatClient, response_slot::ResponseSlotGuard, UrcChannel, UrcSubscription};
+use embassy_sync::{
+    blocking_mutex::raw::NoopRawMutex,
+    channel::Sender,
+    mutex::{Mutex, MutexGuard},
+    pipe::Pipe,
+    pubsub::WaitResult,
+    signal::Signal,
+};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use heapless::Vec;
 use no_std_net::Ipv4Addr;
 
+#[cfg(feature = "edm")]
+use crate::command::edm::types::EdmCapabilities;
 use crate::command::general::responses::SoftwareVersionResponse;
 use crate::command::general::types::FirmwareVersion;
 use crate::command::general::SoftwareVersion;
 use crate::command::gpio::responses::ReadGPIOResponse;
 use crate::command::gpio::types::GPIOMode;
 use crate::command::gpio::ConfigureGPIO;
+use crate::command::gpio::{
+    types::{GPIOId, GPIOValue},
+    WriteGPIO,
+};
 use crate::command::network::responses::NetworkStatusResponse;
 use crate::command::network::types::{NetworkStatus, NetworkStatusParameter};
+use crate::command::network::urc::NetworkError;
 use crate::command::network::GetNetworkStatus;
+use crate::command::network::SetNetworkHostName;
 use crate::command::ping::Ping;
+use crate::command::security::responses::{ListSecurityDataResponse, SecurityDataMD5};
+use crate::command::security::types::SecurityDataType;
+use crate::command::security::{
+    GetSecurityDataMD5, ListSecurityData, PrepareSecurityDataImport, RemoveSecurityData,
+    SendSecurityDataImport, MAX_SECURITY_DATA_SIZE,
+};
 use crate::command::system::responses::LocalAddressResponse;
 use crate::command::system::types::InterfaceID;
 use crate::command::system::GetLocalAddress;
-use crate::command::wifi::types::{IPv4Mode, PasskeyR};
-use crate::command::wifi::{ExecWifiStationAction, GetWifiStatus, SetWifiStationConfig};
-use crate::command::OnOff;
-use crate::command::{
-    gpio::ReadGPIO,
-    wifi::{
-        types::{
-            AccessPointAction, Authentication, SecurityMode, SecurityModePSK, StatusId,
-            WifiStationAction, WifiStationConfig, WifiStatus, WifiStatusVal,
-        },
-        WifiAPAction,
-    },
+use crate::command::system::{RebootDCE, ResetToFactoryDefaults};
+use crate::command::wifi::responses::{
+    ChannelListResponse, GetWifiStationConfigResponse, WifiScanResponse,
 };
-use crate::command::{
-    gpio::{
-        types::{GPIOId, GPIOValue},
-        WriteGPIO,
-    },
-    wifi::SetWifiAPConfig,
+use crate::command::wifi::types::{IPv4Mode, WifiStationConfigParameter, WifiStationConfigR};
+use crate::command::wifi::{
+    ExecWifiStationAction, GetChannelList, GetWifiStationConfig, GetWifiStatus, SetChannelList,
+    SetWifiStationConfig, WifiScan,
 };
-use crate::command::{network::SetNetworkHostName, wifi::types::AccessPointConfig};
 use crate::command::{
-    system::{RebootDCE, ResetToFactoryDefaults},
-    wifi::types::AccessPointId,
+    gpio::ReadGPIO,
+    wifi::types::{
+        Authentication, StatusId, WifiStationAction, WifiStationConfig, WifiStatus, WifiStatusVal,
+    },
 };
-use crate::connection::{DnsServers, StaticConfigV4, WiFiState};
+use crate::command::{OnOff, Urc};
+use crate::connection::{parse_ipv4, DnsServers, NetworkStatusSummary, StaticConfigV4, WiFiState};
 use crate::error::Error;
-use crate::options::{ConnectionOptions, HotspotOptions, WifiAuthentication};
+use crate::init_script::InitReport;
+use crate::network::{strongest_networks, WifiBand, WifiNetwork};
+#[cfg(feature = "ap")]
+use crate::options::HotspotOptions;
+use crate::options::{
+    ConnectionOptions, CredentialNamespace, EapMethod, EnterpriseCredentials, WifiAuthentication,
+    MAX_EAP_CREDENTIAL_LEN, MAX_EAP_DOMAIN_LEN, MAX_WPA3_PASSPHRASE_LEN,
+};
+use crate::restart_capture::RestartCapture;
+use crate::zeroize::zeroize;
 
+#[cfg(feature = "ap")]
+use super::access_point::{self, ApState, ConnectedStation, MAX_AP_STATIONS};
+use super::ping::{PingGuard, PingSession, PingSummary};
 use super::runner::{MAX_CMD_LEN, URC_SUBSCRIBERS};
-use super::state::LinkState;
+use super::state::{LinkEvent, LinkState, UrcStats, LINK_HISTORY_LEN};
 use super::{state, UbloxUrc};
 
-const CONFIG_ID: u8 = 0;
-
+pub(crate) const CONFIG_ID: u8 = 0;
+
+/// Number of station configurations supported by the module (ids 0-9).
+const MAX_STATION_CONFIGS: u8 = 10;
+
+/// Lock serializing access to the module egress.
+///
+/// Holds the deadline for the response to an abandoned command, if any, which
+/// has to be discarded before the next command can be sent.
+pub(crate) type CommandLock = Mutex<NoopRawMutex, Option<Instant>>;
+
+/// AT client forwarding commands to the runner.
+///
+/// Responses and URCs digested from a single UART read are handed off in the
+/// order they were received, so a response is always signalled before any URC
+/// following it. As the command lock is held until the waiting command has
+/// consumed its response, URC consumers issuing commands of their own (e.g.
+/// [`NetDevice`](super::network::NetDevice)) are queued behind it, and can
+/// never pick up a response meant for another command.
 pub(crate) struct ProxyClient<'a, const INGRESS_BUF_SIZE: usize> {
     pub(crate) req_sender: Sender<'a, NoopRawMutex, Vec<u8, MAX_CMD_LEN>, 1>,
     pub(crate) res_slot: &'a atat::ResponseSlot<INGRESS_BUF_SIZE>,
+    cmd_lock: &'a CommandLock,
     cooldown_timer: Cell<Option<Timer>>,
+    /// Lower bound of the response timeout, see
+    /// [`Timeouts::command_default`](crate::timeouts::Timeouts::command_default).
+    command_floor: Duration,
 }
 
 impl<'a, const INGRESS_BUF_SIZE: usize> ProxyClient<'a, INGRESS_BUF_SIZE> {
     pub fn new(
         req_sender: Sender<'a, NoopRawMutex, Vec<u8, MAX_CMD_LEN>, 1>,
         res_slot: &'a atat::ResponseSlot<INGRESS_BUF_SIZE>,
+        cmd_lock: &'a CommandLock,
+        command_floor: Duration,
     ) -> Self {
         Self {
             req_sender,
             res_slot,
+            cmd_lock,
             cooldown_timer: Cell::new(None),
+            command_floor,
         }
     }
 
+    /// Response timeout of a command declaring a timeout of `timeout_ms`.
+    fn response_timeout(&self, timeout_ms: u32) -> Duration {
+        core::cmp::max(Duration::from_millis(timeout_ms.into()), self.command_floor)
+    }
+
     async fn wait_response(
         &self,
         timeout: Duration,
@@ -82,14 +135,115 @@ impl<'a, const INGRESS_BUF_SIZE: usize> ProxyClient<'a, INGRESS_BUF_SIZE> {
             .await
             .map_err(|_| atat::Error::Timeout)
     }
-}
 
-impl<'a, const INGRESS_BUF_SIZE: usize> atat::asynch::AtatClient
-    for &ProxyClient<'a, INGRESS_BUF_SIZE>
-{
-    async fn send<Cmd: atat::AtatCmd>(&mut self, cmd: &Cmd) -> Result<Cmd::Response, atat::Error> {
+    /// Wait for the response to a previously abandoned command, if any, and
+    /// discard it, so it is not mistaken for the response to the next command.
+    async fn discard_abandoned(&self, abandoned: &mut Option<Instant>) {
+        if let Some(deadline) = abandoned.take() {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if with_timeout(timeout, self.res_slot.get()).await.is_ok() {
+                debug!("Discarded response to abandoned command");
+            }
+        }
+    }
+
+    /// Take exclusive access to the module egress, until the returned client
+    /// is dropped.
+    ///
+    /// Used for command sequences that must not be interleaved with commands
+    /// from other contexts, such as multi-part credential imports.
+    pub(crate) async fn exclusive(&self) -> ExclusiveClient<'_, 'a, INGRESS_BUF_SIZE> {
+        let mut guard = self.cmd_lock.lock().await;
+        self.discard_abandoned(&mut guard).await;
+
+        ExclusiveClient {
+            guard,
+            client: self,
+        }
+    }
+
+    /// Send a command, giving up on it if `abort` completes before the
+    /// response is received.
+    ///
+    /// Returns `Ok(None)` if the command was aborted. The module will still
+    /// respond to an aborted command, so the response is discarded before the
+    /// next command is sent.
+    pub(crate) async fn send_abortable<Cmd: AtatCmd>(
+        &self,
+        cmd: &Cmd,
+        timeout: Duration,
+        abort: impl core::future::Future,
+    ) -> Result<Option<Cmd::Response>, atat::Error> {
+        let mut abandoned = self.cmd_lock.lock().await;
+        self.discard_abandoned(&mut abandoned).await;
+
+        match embassy_futures::select::select(self.send_unlocked_with(cmd, timeout), abort).await {
+            embassy_futures::select::Either::First(res) => res.map(Some),
+            embassy_futures::select::Either::Second(_) => {
+                abandoned.replace(Instant::now() + timeout);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn send_raw(&self, data: &[u8]) -> Result<(), atat::Error> {
+        self.send_raw_counted(data, &mut 0).await
+    }
+
+    /// As [`send_raw`](Self::send_raw), adding the number of bytes handed to
+    /// the runner to `written` as they are.
+    async fn send_raw_counted(&self, data: &[u8], written: &mut usize) -> Result<(), atat::Error> {
+        if let Some(cooldown) = self.cooldown_timer.take() {
+            cooldown.await
+        }
+
+        for chunk in data.chunks(MAX_CMD_LEN) {
+            with_timeout(
+                self.command_floor,
+                self.req_sender.send(Vec::from_slice(chunk).unwrap()),
+            )
+            .await
+            .map_err(|_| atat::Error::Timeout)?;
+            *written += chunk.len();
+        }
+
+        self.cooldown_timer.set(Some(Timer::after_millis(20)));
+
+        Ok(())
+    }
+
+    async fn send_unlocked<Cmd: atat::AtatCmd>(
+        &self,
+        cmd: &Cmd,
+    ) -> Result<Cmd::Response, atat::Error> {
+        self.send_unlocked_with(cmd, self.response_timeout(Cmd::MAX_TIMEOUT_MS))
+            .await
+    }
+
+    async fn send_unlocked_with<Cmd: atat::AtatCmd>(
+        &self,
+        cmd: &Cmd,
+        timeout: Duration,
+    ) -> Result<Cmd::Response, atat::Error> {
+        self.write_unlocked(cmd).await?;
+
+        if !Cmd::EXPECTS_RESPONSE_CODE {
+            cmd.parse(Ok(&[]))
+        } else {
+            let response = self.wait_response(timeout).await?;
+            let response: &atat::Response<INGRESS_BUF_SIZE> = &response.borrow();
+            cmd.parse(response.into())
+        }
+    }
+
+    /// Write `cmd` to the module, without waiting for its response.
+    async fn write_unlocked<Cmd: atat::AtatCmd>(&self, cmd: &Cmd) -> Result<(), atat::Error> {
         let mut buf = [0u8; MAX_CMD_LEN];
         let len = cmd.write(&mut buf);
+        if len == 0 {
+            // The command refused to write a truncated frame
+            return Err(atat::Error::Write);
+        }
 
         if len < 50 {
             trace!(
@@ -100,36 +254,274 @@ impl<'a, const INGRESS_BUF_SIZE: usize> atat::asynch::AtatClient
             trace!("Sending command with long payload ({} bytes)", len);
         }
 
-        if let Some(cooldown) = self.cooldown_timer.take() {
-            cooldown.await
+        // Discard any late response to a previous command that timed out, to
+        // avoid handing it to this command.
+        self.res_slot.reset();
+
+        let res = self.send_raw(&buf[..len]).await;
+        // The command may carry credentials, such as a Wi-Fi passphrase
+        zeroize(&mut buf[..len]);
+        res
+    }
+}
+
+impl<'a, const INGRESS_BUF_SIZE: usize> atat::asynch::AtatClient
+    for &ProxyClient<'a, INGRESS_BUF_SIZE>
+{
+    async fn send<Cmd: atat::AtatCmd>(&mut self, cmd: &Cmd) -> Result<Cmd::Response, atat::Error> {
+        let mut abandoned = self.cmd_lock.lock().await;
+        self.discard_abandoned(&mut abandoned).await;
+        self.send_unlocked(cmd).await
+    }
+}
+
+/// AT client holding exclusive access to the module egress.
+///
+/// Commands issued from other contexts are queued until this is dropped.
+pub(crate) struct ExclusiveClient<'c, 'a, const INGRESS_BUF_SIZE: usize> {
+    guard: MutexGuard<'c, NoopRawMutex, Option<Instant>>,
+    client: &'c ProxyClient<'a, INGRESS_BUF_SIZE>,
+}
+
+impl<'c, 'a, const INGRESS_BUF_SIZE: usize> ExclusiveClient<'c, 'a, INGRESS_BUF_SIZE> {
+    /// Write raw bytes to the module, bypassing the command serialization.
+    pub(crate) async fn send_raw(&self, data: &[u8]) -> Result<(), atat::Error> {
+        self.client.send_raw(data).await
+    }
+
+    /// As [`send_raw`](Self::send_raw), adding the number of bytes written
+    /// to `written` as they are, so an interrupted write can be completed.
+    pub(crate) async fn send_raw_counted(
+        &self,
+        data: &[u8],
+        written: &mut usize,
+    ) -> Result<(), atat::Error> {
+        self.client.send_raw_counted(data, written).await
+    }
+
+    /// Write a command to the module, leaving its response to
+    /// [`receive`](Self::receive).
+    pub(crate) async fn write<Cmd: atat::AtatCmd>(&self, cmd: &Cmd) -> Result<(), atat::Error> {
+        self.client.write_unlocked(cmd).await
+    }
+
+    /// Give up on the response to `Cmd`, which is discarded before the next
+    /// command is sent, as for an aborted
+    /// [`send_abortable`](ProxyClient::send_abortable).
+    pub(crate) fn abandon<Cmd: atat::AtatCmd>(&mut self) {
+        self.guard
+            .replace(Instant::now() + self.client.response_timeout(Cmd::MAX_TIMEOUT_MS));
+    }
+
+    /// Wait for the response to a command, where the request part has been
+    /// written using [`ExclusiveClient::write`] or
+    /// [`ExclusiveClient::send_raw`].
+    pub(crate) async fn receive<Cmd: atat::AtatCmd>(
+        &self,
+        cmd: &Cmd,
+    ) -> Result<Cmd::Response, atat::Error> {
+        let response = self
+            .client
+            .wait_response(self.client.response_timeout(Cmd::MAX_TIMEOUT_MS))
+            .await?;
+        let response: &atat::Response<INGRESS_BUF_SIZE> = &response.borrow();
+        cmd.parse(response.into())
+    }
+
+    /// Wait for the response to `Cmd` and discard it, skipping the late
+    /// prompt of a command given up on ahead of it.
+    pub(crate) async fn discard_response<Cmd: atat::AtatCmd>(&self) {
+        let timeout = self.client.response_timeout(Cmd::MAX_TIMEOUT_MS);
+        loop {
+            match self.client.wait_response(timeout).await {
+                Ok(response) if matches!(&*response.borrow(), atat::Response::Prompt(_)) => {}
+                Ok(_) => return,
+                Err(e) => {
+                    warn!("No response to discard: {:?}", e);
+                    return;
+                }
+            }
         }
+    }
+}
 
-        // TODO: Guard against race condition!
-        with_timeout(
-            Duration::from_secs(1),
-            self.req_sender.send(Vec::try_from(&buf[..len]).unwrap()),
-        )
-        .await
-        .map_err(|_| atat::Error::Timeout)?;
+impl<'c, 'a, const INGRESS_BUF_SIZE: usize> atat::asynch::AtatClient
+    for ExclusiveClient<'c, 'a, INGRESS_BUF_SIZE>
+{
+    async fn send<Cmd: atat::AtatCmd>(&mut self, cmd: &Cmd) -> Result<Cmd::Response, atat::Error> {
+        self.client.send_unlocked(cmd).await
+    }
+}
 
-        self.cooldown_timer.set(Some(Timer::after_millis(20)));
+/// Exclusive access to the module, while its normal operation is suspended.
+///
+/// Created by [`Control::suspend`]. Reads and writes go straight to the UART,
+/// without any AT/URC processing by the runner. Normal operation is resumed
+/// when this guard is dropped, or through [`SuspendGuard::resume`].
+pub struct SuspendGuard<'a, 'b, const INGRESS_BUF_SIZE: usize> {
+    state_ch: &'b state::Runner<'a>,
+    raw_rx: &'a Pipe<NoopRawMutex, INGRESS_BUF_SIZE>,
+    raw_tx: &'a Pipe<NoopRawMutex, MAX_CMD_LEN>,
+    // Released after the suspension is ended in `drop`, so the runner takes
+    // back the UART before held back commands are sent.
+    _exclusive: ExclusiveClient<'b, 'a, INGRESS_BUF_SIZE>,
+    link_state: LinkState,
+    since: Instant,
+}
 
-        if !Cmd::EXPECTS_RESPONSE_CODE {
-            cmd.parse(Ok(&[]))
-        } else {
-            let response = self
-                .wait_response(Duration::from_millis(Cmd::MAX_TIMEOUT_MS.into()))
-                .await?;
-            let response: &atat::Response<INGRESS_BUF_SIZE> = &response.borrow();
-            cmd.parse(response.into())
+impl<'a, 'b, const INGRESS_BUF_SIZE: usize> SuspendGuard<'a, 'b, INGRESS_BUF_SIZE> {
+    /// Resume normal operation, waiting for the connection state to be
+    /// re-synchronized with the module.
+    pub async fn resume(self) -> SuspendReport {
+        let state_ch = self.state_ch;
+        let mut report = SuspendReport {
+            duration: self.since.elapsed(),
+            discarded: self.raw_rx.len(),
+            link_before: self.link_state,
+            link_after: self.link_state,
+        };
+
+        drop(self);
+        state_ch.wait_resumed().await;
+
+        report.link_after = state_ch.link_state(None);
+        report
+    }
+}
+
+impl<'a, 'b, const INGRESS_BUF_SIZE: usize> Drop for SuspendGuard<'a, 'b, INGRESS_BUF_SIZE> {
+    fn drop(&mut self) {
+        let discarded = self.raw_rx.len();
+        if discarded > 0 {
+            warn!("Discarding {} bytes received while suspended", discarded);
+        }
+
+        debug!("Resuming from suspend");
+        self.state_ch.end_suspend();
+    }
+}
+
+impl<'a, 'b, const INGRESS_BUF_SIZE: usize> embedded_io_async::ErrorType
+    for SuspendGuard<'a, 'b, INGRESS_BUF_SIZE>
+{
+    type Error = core::convert::Infallible;
+}
+
+impl<'a, 'b, const INGRESS_BUF_SIZE: usize> embedded_io_async::Read
+    for SuspendGuard<'a, 'b, INGRESS_BUF_SIZE>
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.raw_rx.read(buf).await)
+    }
+}
+
+impl<'a, 'b, const INGRESS_BUF_SIZE: usize> embedded_io_async::Write
+    for SuspendGuard<'a, 'b, INGRESS_BUF_SIZE>
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.raw_tx.write(buf).await)
+    }
+}
+
+/// What happened while the normal operation of the module was suspended, see
+/// [`SuspendGuard::resume`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SuspendReport {
+    /// Time spent suspended.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub duration: Duration,
+    /// Bytes received from the module but not read through the guard. These
+    /// were discarded, along with any URCs among them.
+    pub discarded: usize,
+    /// Link state when the suspension started.
+    pub link_before: LinkState,
+    /// Link state after re-synchronizing with the module.
+    pub link_after: LinkState,
+}
+
+/// How [`Control::update_credentials`] applied the new credentials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CredentialUpdate {
+    /// The active configuration was updated, keeping the link up.
+    InPlace,
+    /// The configuration was deactivated and activated again, dropping the
+    /// link.
+    Reactivated {
+        /// The link was up before the configuration was deactivated, so all
+        /// sockets open over it were reset and have to be opened again.
+        sockets_reset: bool,
+    },
+}
+
+/// An imported certificate or private key, as listed by
+/// [`Control::list_credentials`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredCredentials<const N: usize> {
+    /// Name, with the namespace prefix stripped.
+    pub name: heapless::String<N>,
+    /// MD5 the module keeps of the data, as a hex string.
+    pub md5: heapless::String<32>,
+}
+
+/// Observer of the URCs received from the module, alongside the runner. See
+/// [`Control::observe_urcs`].
+pub struct UrcObserver<'a, const URC_CAPACITY: usize> {
+    subscription: UrcSubscription<'a, UbloxUrc, URC_CAPACITY, { URC_SUBSCRIBERS }>,
+    lost: u64,
+}
+
+impl<'a, const URC_CAPACITY: usize> UrcObserver<'a, URC_CAPACITY> {
+    fn new(subscription: UrcSubscription<'a, UbloxUrc, URC_CAPACITY, { URC_SUBSCRIBERS }>) -> Self {
+        Self {
+            subscription,
+            lost: 0,
+        }
+    }
+
+    /// Wait for the next URC.
+    ///
+    /// The observer must be polled promptly. Like any subscriber of the URC
+    /// channel, an observer holding `URC_CAPACITY` pending URCs stalls the
+    /// ingress until it catches up.
+    pub async fn next(&mut self) -> Urc {
+        loop {
+            match self.subscription.next_message().await {
+                WaitResult::Lagged(n) => {
+                    warn!("URC observer lagging, lost {} URCs", n);
+                    self.lost += n;
+                }
+                #[cfg(feature = "edm")]
+                WaitResult::Message(event) => {
+                    if let Some(urc) = event.extract_urc() {
+                        return urc;
+                    }
+                }
+                #[cfg(not(feature = "edm"))]
+                WaitResult::Message(urc) => return urc,
+            }
         }
     }
+
+    /// Number of URCs this observer missed.
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
 }
 
 pub struct Control<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize> {
     state_ch: state::Runner<'a>,
     at_client: ProxyClient<'a, INGRESS_BUF_SIZE>,
     urc_channel: &'a UrcChannel<UbloxUrc, URC_CAPACITY, { URC_SUBSCRIBERS }>,
+    raw_rx: &'a Pipe<NoopRawMutex, INGRESS_BUF_SIZE>,
+    raw_tx: &'a Pipe<NoopRawMutex, MAX_CMD_LEN>,
+    scan_abort: Signal<NoopRawMutex, ()>,
+    credential_namespace: Cell<CredentialNamespace>,
+    /// Channel list to restore after [`Control::scan_channels`], the default
+    /// list if empty. Kept until restored, so a scan dropped before it could
+    /// restore the list is followed up by the next scan.
+    restore_channels: RefCell<Option<Vec<u8, 10>>>,
 }
 
 impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
@@ -140,17 +532,96 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
         urc_channel: &'a UrcChannel<UbloxUrc, URC_CAPACITY, { URC_SUBSCRIBERS }>,
         req_sender: Sender<'a, NoopRawMutex, Vec<u8, MAX_CMD_LEN>, 1>,
         res_slot: &'a atat::ResponseSlot<INGRESS_BUF_SIZE>,
+        cmd_lock: &'a CommandLock,
+        raw_rx: &'a Pipe<NoopRawMutex, INGRESS_BUF_SIZE>,
+        raw_tx: &'a Pipe<NoopRawMutex, MAX_CMD_LEN>,
     ) -> Self {
+        let command_floor = state_ch.timeouts().command_default;
         Self {
             state_ch,
-            at_client: ProxyClient::new(req_sender, res_slot),
+            at_client: ProxyClient::new(req_sender, res_slot, cmd_lock, command_floor),
             urc_channel,
+            raw_rx,
+            raw_tx,
+            scan_abort: Signal::new(),
+            credential_namespace: Cell::new(CredentialNamespace::default()),
+            restore_channels: RefCell::new(None),
+        }
+    }
+
+    /// Set the namespace applied to the names of the certificates and
+    /// private keys imported and listed by this client, see
+    /// [`CredentialNamespace`].
+    pub fn set_credential_namespace(&self, namespace: CredentialNamespace) {
+        self.credential_namespace.set(namespace);
+    }
+
+    pub fn credential_namespace(&self) -> CredentialNamespace {
+        self.credential_namespace.get()
+    }
+
+    /// Suspend the normal operation of the module, handing exclusive access
+    /// to it over to the returned guard, e.g. for a firmware update or a raw
+    /// data mode session.
+    ///
+    /// In-flight commands are completed first. While suspended, the runner
+    /// does not process URCs, operations of `Control` fail with
+    /// [`Error::Suspended`], and commands of the network stack, such as
+    /// socket writes, are held back until the suspension ends. Held back
+    /// commands wait for the command lock rather than in the request queue of
+    /// the runner, so none is sent on resume after its sender gave up on it.
+    /// Bytes received from the module can be read from the guard, and are
+    /// discarded on resume otherwise.
+    ///
+    /// On resume, the Wi-Fi and network status are queried from the module,
+    /// as any URCs reporting changes were missed.
+    pub async fn suspend(&self) -> Result<SuspendGuard<'a, '_, INGRESS_BUF_SIZE>, Error> {
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+
+        let exclusive = self.at_client.exclusive().await;
+        if !self.state_ch.try_suspend() {
+            return Err(Error::Suspended);
+        }
+        debug!("Suspending");
+
+        let guard = SuspendGuard {
+            state_ch: &self.state_ch,
+            raw_rx: self.raw_rx,
+            raw_tx: self.raw_tx,
+            _exclusive: exclusive,
+            link_state: self.state_ch.link_state(None),
+            since: Instant::now(),
+        };
+        self.state_ch.wait_paused(true).await;
+
+        Ok(guard)
+    }
+
+    /// Fail with [`Error::Suspended`] while normal operation is suspended, see
+    /// [`Control::suspend`].
+    fn ensure_resumed(&self) -> Result<(), Error> {
+        if self.state_ch.is_suspended() {
+            return Err(Error::Suspended);
         }
+        Ok(())
+    }
+
+    /// Number of simultaneous peer connections supported by the module, as
+    /// identified at initialization, or `None` if the module model is not
+    /// known.
+    ///
+    /// Every connected socket occupies a peer connection, so this bounds the
+    /// useful size of the socket set of the network stack.
+    pub async fn module_max_sockets(&self) -> Option<usize> {
+        self.state_ch.wait_for_initialized().await;
+        self.state_ch.max_peers()
     }
 
     /// Set the hostname of the device
     pub async fn set_hostname(&self, hostname: &str) -> Result<(), Error> {
         self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
 
         (&self.at_client)
             .send_retry(&SetNetworkHostName {
@@ -163,6 +634,7 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
     /// Gets the firmware version of the device
     pub async fn get_version(&self) -> Result<FirmwareVersion, Error> {
         self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
 
         let SoftwareVersionResponse { version } =
             (&self.at_client).send_retry(&SoftwareVersion).await?;
@@ -172,6 +644,7 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
     /// Gets the MAC address of the device
     pub async fn hardware_address(&mut self) -> Result<[u8; 6], Error> {
         self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
 
         let LocalAddressResponse { mac } = (&self.at_client)
             .send_retry(&GetLocalAddress {
@@ -183,6 +656,8 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
     }
 
     pub async fn get_wifi_status(&self) -> Result<WifiStatusVal, Error> {
+        self.ensure_resumed()?;
+
         match (&self.at_client)
             .send_retry(&GetWifiStatus {
                 status_id: StatusId::Status,
@@ -195,11 +670,84 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
         }
     }
 
+    pub fn link_state(&self) -> LinkState {
+        self.state_ch.link_state(None)
+    }
+
     pub async fn wait_for_link_state(&self, link_state: LinkState) {
         self.state_ch.wait_for_link_state(link_state).await
     }
 
+    /// The most recent link state transitions, oldest first.
+    ///
+    /// The history is kept across module resets and reconnects, and is only
+    /// cleared when the runner is started, and by
+    /// [`Control::clear_link_history`].
+    pub fn link_history(&self) -> Vec<LinkEvent, LINK_HISTORY_LEN> {
+        self.state_ch.link_history()
+    }
+
+    /// Clear the recorded link state transitions.
+    pub fn clear_link_history(&self) {
+        self.state_ch.clear_link_history()
+    }
+
+    /// Outcome of the [`InitScript`](crate::init_script::InitScript) on the
+    /// last module boot.
+    pub fn init_report(&self) -> InitReport {
+        self.state_ch.init_report()
+    }
+
+    /// Capabilities advertised by the module on entering EDM, or `None`
+    /// before the module is initialized.
+    ///
+    /// Writes are sent in chunks of at most the advertised maximum payload.
+    #[cfg(feature = "edm")]
+    pub fn edm_capabilities(&self) -> Option<EdmCapabilities> {
+        self.state_ch.edm_capabilities()
+    }
+
+    /// Diagnostics captured after the last unexpected restart of the module,
+    /// if any. See [`restart_capture`](crate::restart_capture).
+    pub fn last_restart(&self) -> Option<RestartCapture> {
+        self.state_ch.last_restart()
+    }
+
+    /// The last network error reported by the module, if any, such as an IP
+    /// address conflict. An error on the station interface takes the link
+    /// down, until the module reports the network up again.
+    pub fn last_network_error(&self) -> Option<NetworkError> {
+        self.state_ch.last_network_error()
+    }
+
+    /// Observe the URCs received from the module, including those the runner
+    /// does not handle, such as URCs of firmware features not modeled by this
+    /// crate.
+    ///
+    /// The runner keeps handling every URC as usual. A single observer is
+    /// supported at a time, [`Error::Overflow`] is returned while another one
+    /// exists.
+    pub fn observe_urcs(&self) -> Result<UrcObserver<'a, URC_CAPACITY>, Error> {
+        let subscription = self.urc_channel.subscribe().map_err(|_| Error::Overflow)?;
+        Ok(UrcObserver::new(subscription))
+    }
+
+    /// Occupancy statistics of the URC channel.
+    ///
+    /// A `high_water` mark at `URC_CAPACITY`, or any lost URCs, indicate that
+    /// the URC channel is undersized for the load.
+    pub fn urc_stats(&self) -> UrcStats {
+        self.state_ch.urc_stats()
+    }
+
+    /// Reset the URC channel statistics.
+    pub fn reset_urc_stats(&self) {
+        self.state_ch.reset_urc_stats()
+    }
+
     pub async fn config_v4(&self) -> Result<Option<StaticConfigV4>, Error> {
+        self.ensure_resumed()?;
+
         let NetworkStatusResponse {
             status: NetworkStatus::IPv4Address(ipv4),
             ..
@@ -213,10 +761,7 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
             return Err(Error::Network);
         };
 
-        let ipv4_addr = core::str::from_utf8(ipv4.as_slice())
-            .ok()
-            .and_then(|s| Ipv4Addr::from_str(s).ok())
-            .and_then(|ip| (!ip.is_unspecified()).then_some(ip));
+        let ipv4_addr = parse_ipv4(&ipv4);
 
         let NetworkStatusResponse {
             status: NetworkStatus::Gateway(gateway),
@@ -231,10 +776,20 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
             return Err(Error::Network);
         };
 
-        let gateway_addr = core::str::from_utf8(gateway.as_slice())
-            .ok()
-            .and_then(|s| Ipv4Addr::from_str(s).ok())
-            .and_then(|ip| (!ip.is_unspecified()).then_some(ip));
+        let gateway_addr = parse_ipv4(&gateway);
+
+        let dns_servers = self.get_dns_config().await?;
+
+        Ok(ipv4_addr.map(|address| StaticConfigV4 {
+            address,
+            gateway: gateway_addr,
+            dns_servers,
+        }))
+    }
+
+    /// DNS servers in use by the station, queried from the module.
+    pub async fn get_dns_config(&self) -> Result<DnsServers, Error> {
+        self.ensure_resumed()?;
 
         let NetworkStatusResponse {
             status: NetworkStatus::PrimaryDNS(primary),
@@ -249,10 +804,7 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
             return Err(Error::Network);
         };
 
-        let primary = core::str::from_utf8(primary.as_slice())
-            .ok()
-            .and_then(|s| Ipv4Addr::from_str(s).ok())
-            .and_then(|ip| (!ip.is_unspecified()).then_some(ip));
+        let primary = parse_ipv4(&primary);
 
         let NetworkStatusResponse {
             status: NetworkStatus::SecondaryDNS(secondary),
@@ -267,19 +819,22 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
             return Err(Error::Network);
         };
 
-        let secondary = core::str::from_utf8(secondary.as_slice())
-            .ok()
-            .and_then(|s| Ipv4Addr::from_str(s).ok())
-            .and_then(|ip| (!ip.is_unspecified()).then_some(ip));
+        let secondary = parse_ipv4(&secondary);
 
-        Ok(ipv4_addr.map(|address| StaticConfigV4 {
-            address,
-            gateway: gateway_addr,
-            dns_servers: DnsServers { primary, secondary },
-        }))
+        Ok(DnsServers { primary, secondary })
+    }
+
+    /// IP configuration of the interface `interface_id`, queried from the
+    /// module.
+    pub async fn network_status(&self, interface_id: u8) -> Result<NetworkStatusSummary, Error> {
+        self.ensure_resumed()?;
+
+        super::network::network_status(&mut &self.at_client, interface_id).await
     }
 
     pub async fn get_connected_ssid(&self) -> Result<heapless::String<64>, Error> {
+        self.ensure_resumed()?;
+
         match (&self.at_client)
             .send_retry(&GetWifiStatus {
                 status_id: StatusId::SSID,
@@ -292,8 +847,30 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
         }
     }
 
+    /// Signal strength of the current station connection, in dBm, queried
+    /// from the module.
+    ///
+    /// Fails with [`Error::Network`] if the station is not connected.
+    pub async fn get_rssi(&self) -> Result<i32, Error> {
+        self.ensure_resumed()?;
+
+        match (&self.at_client)
+            .send_retry(&GetWifiStatus {
+                status_id: StatusId::Rssi,
+            })
+            .await?
+            .status_id
+        {
+            // Reported by the module when not connected
+            WifiStatus::Rssi(-32768) => Err(Error::Network),
+            WifiStatus::Rssi(rssi) => Ok(rssi),
+            _ => Err(Error::AT(atat::Error::InvalidResponse)),
+        }
+    }
+
     pub async fn factory_reset(&self) -> Result<(), Error> {
         self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
 
         (&self.at_client)
             .send_retry(&ResetToFactoryDefaults)
@@ -303,192 +880,94 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
         Ok(())
     }
 
+    /// Start an access point, see [`access_point`](super::access_point).
+    #[cfg(feature = "ap")]
     pub async fn start_ap(
         &self,
         options: ConnectionOptions<'_>,
         configuration: HotspotOptions,
     ) -> Result<(), Error> {
         self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
 
-        // Deactivate network id 0
-        (&self.at_client)
-            .send_retry(&WifiAPAction {
-                ap_config_id: AccessPointId::Id0,
-                ap_action: AccessPointAction::Deactivate,
-            })
-            .await?;
-
-        (&self.at_client)
-            .send_retry(&WifiAPAction {
-                ap_config_id: AccessPointId::Id0,
-                ap_action: AccessPointAction::Reset,
-            })
-            .await?;
-
-        // Disable DHCP Server (static IP address will be used)
-        if options.ip.is_some() || options.subnet.is_some() || options.gateway.is_some() {
-            (&self.at_client)
-                .send_retry(&SetWifiAPConfig {
-                    ap_config_id: AccessPointId::Id0,
-                    ap_config_param: AccessPointConfig::IPv4Mode(IPv4Mode::Static),
-                })
-                .await?;
+        access_point::start(&mut &self.at_client, options, configuration).await?;
 
-            (&self.at_client)
-                .send_retry(&SetWifiAPConfig {
-                    ap_config_id: AccessPointId::Id0,
-                    ap_config_param: AccessPointConfig::IPv4Address(
-                        options.ip.unwrap_or(Ipv4Addr::new(192, 168, 2, 1)),
-                    ),
-                })
-                .await?;
+        self.state_ch.set_should_connect(true);
 
-            (&self.at_client)
-                .send_retry(&SetWifiAPConfig {
-                    ap_config_id: AccessPointId::Id0,
-                    ap_config_param: AccessPointConfig::SubnetMask(
-                        options.subnet.unwrap_or(Ipv4Addr::new(255, 255, 255, 0)),
-                    ),
-                })
-                .await?;
+        Ok(())
+    }
 
-            (&self.at_client)
-                .send_retry(&SetWifiAPConfig {
-                    ap_config_id: AccessPointId::Id0,
-                    ap_config_param: AccessPointConfig::DefaultGateway(
-                        options.gateway.unwrap_or(Ipv4Addr::new(192, 168, 2, 1)),
-                    ),
-                })
-                .await?;
-        }
+    /// Stop the access point.
+    #[cfg(feature = "ap")]
+    pub async fn stop_ap(&self) -> Result<(), Error> {
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+        self.state_ch.set_should_connect(false);
 
-        // Network Primary + Secondary DNS
-        let primary = match options.dns.as_slice() {
-            &[primary] => Some(primary),
-            &[primary, secondary] => {
-                (&self.at_client)
-                    .send_retry(&SetWifiAPConfig {
-                        ap_config_id: AccessPointId::Id0,
-                        ap_config_param: AccessPointConfig::SecondaryDNS(secondary),
-                    })
-                    .await?;
+        access_point::stop(&mut &self.at_client).await
+    }
 
-                Some(primary)
+    /// Start an access point named `ssid`, secured with the WPA2 personal
+    /// `password`, or open without one, as [`start_ap`](Control::start_ap).
+    #[cfg(feature = "ap")]
+    pub async fn create_hotspot(
+        &self,
+        ssid: &str,
+        configuration: HotspotOptions,
+        password: Option<&str>,
+    ) -> Result<(), Error> {
+        let options = match password {
+            Some(password) if !password.is_empty() => {
+                ConnectionOptions::new(ssid).wpa2_passphrase(password)
             }
-            _ => None,
+            _ => ConnectionOptions::new(ssid).no_auth(),
         };
 
-        if let Some(primary) = primary {
-            (&self.at_client)
-                .send_retry(&SetWifiAPConfig {
-                    ap_config_id: AccessPointId::Id0,
-                    ap_config_param: AccessPointConfig::PrimaryDNS(primary),
-                })
-                .await?;
-        }
+        self.start_ap(options, configuration).await
+    }
 
-        (&self.at_client)
-            .send_retry(&SetWifiAPConfig {
-                ap_config_id: AccessPointId::Id0,
-                ap_config_param: AccessPointConfig::DHCPServer(configuration.dhcp_server.into()),
-            })
-            .await?;
+    /// Stop the access point started by
+    /// [`create_hotspot`](Control::create_hotspot).
+    #[cfg(feature = "ap")]
+    pub async fn stop_hotspot(&self) -> Result<(), Error> {
+        self.stop_ap().await
+    }
 
-        // Set the Network SSID to connect to
-        (&self.at_client)
-            .send_retry(&SetWifiAPConfig {
-                ap_config_id: AccessPointId::Id0,
-                ap_config_param: AccessPointConfig::SSID(options.ssid),
-            })
-            .await?;
-
-        match options.auth {
-            WifiAuthentication::None => {
-                (&self.at_client)
-                    .send_retry(&SetWifiAPConfig {
-                        ap_config_id: AccessPointId::Id0,
-                        ap_config_param: AccessPointConfig::SecurityMode(
-                            SecurityMode::Open,
-                            SecurityModePSK::Open,
-                        ),
-                    })
-                    .await?;
-            }
-            WifiAuthentication::Wpa2Passphrase(passphrase) => {
-                (&self.at_client)
-                    .send_retry(&SetWifiAPConfig {
-                        ap_config_id: AccessPointId::Id0,
-                        ap_config_param: AccessPointConfig::SecurityMode(
-                            SecurityMode::Wpa2AesCcmp,
-                            SecurityModePSK::PSK,
-                        ),
-                    })
-                    .await?;
-
-                // Input passphrase
-                (&self.at_client)
-                    .send_retry(&SetWifiAPConfig {
-                        ap_config_id: AccessPointId::Id0,
-                        ap_config_param: AccessPointConfig::PSKPassphrase(PasskeyR::Passphrase(
-                            // FIXME:
-                            heapless::String::try_from(passphrase).unwrap(),
-                        )),
-                    })
-                    .await?;
-            } // WifiAuthentication::Wpa2Psk(_psk) => {
-              //     unimplemented!()
-              //     // (&self.at_client)
-              //     //     .send_retry(&SetWifiStationConfig {
-              //     //         config_id: CONFIG_ID,
-              //     //         config_param: WifiStationConfig::Authentication(Authentication::WpaWpa2Psk),
-              //     //     })
-              //     //     .await?;
-
-              //     // (&self.at_client)
-              //     //     .send_retry(&SetWifiStationConfig {
-              //     //         config_id: CONFIG_ID,
-              //     //         config_param: WifiStationConfig::WpaPskOrPassphrase(todo!("hex values?!")),
-              //     //     })
-              //     //     .await?;
-              // }
-        }
-
-        if let Some(channel) = configuration.channel {
-            (&self.at_client)
-                .send_retry(&SetWifiAPConfig {
-                    ap_config_id: AccessPointId::Id0,
-                    ap_config_param: AccessPointConfig::Channel(channel as u8),
-                })
-                .await?;
-        }
-
-        (&self.at_client)
-            .send_retry(&WifiAPAction {
-                ap_config_id: AccessPointId::Id0,
-                ap_action: AccessPointAction::Activate,
-            })
-            .await?;
-
-        self.state_ch.set_should_connect(true);
+    /// Whether the access point is up, and how many stations are attached to
+    /// it.
+    #[cfg(feature = "ap")]
+    pub fn ap_state(&self) -> ApState {
+        self.state_ch.ap_state()
+    }
 
-        Ok(())
+    /// Stations attached to the access point, as tracked from the URCs of
+    /// the module.
+    #[cfg(feature = "ap")]
+    pub fn ap_stations(&self) -> Vec<ConnectedStation, MAX_AP_STATIONS> {
+        self.state_ch.ap_stations()
     }
 
-    /// Closes access point.
-    pub async fn close_ap(&self) -> Result<(), Error> {
-        self.state_ch.wait_for_initialized().await;
-        self.state_ch.set_should_connect(false);
+    /// List the stations attached to the access point, querying the module.
+    ///
+    /// The stations tracked from the URCs of the module, as returned by
+    /// [`ap_stations`](Control::ap_stations), are replaced with the list, to
+    /// recover from missed URCs.
+    #[cfg(feature = "ap")]
+    pub async fn connected_stations(
+        &self,
+    ) -> Result<Vec<ConnectedStation, MAX_AP_STATIONS>, Error> {
+        self.ensure_resumed()?;
 
-        (&self.at_client)
-            .send_retry(&WifiAPAction {
-                ap_config_id: AccessPointId::Id0,
-                ap_action: AccessPointAction::Deactivate,
-            })
-            .await?;
-        Ok(())
+        let stations = access_point::connected_stations(&mut &self.at_client).await?;
+        self.state_ch
+            .update_connection_with(|con| con.ap.reconcile(&stations));
+
+        Ok(stations)
     }
 
     pub async fn peek_join_sta(&self, options: ConnectionOptions<'_>) -> Result<(), Error> {
+        self.ensure_resumed()?;
+
         (&self.at_client)
             .send_retry(&ExecWifiStationAction {
                 config_id: CONFIG_ID,
@@ -510,99 +989,193 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
             })
             .await?;
 
-        match options.auth {
-            WifiAuthentication::None => {
-                (&self.at_client)
-                    .send_retry(&SetWifiStationConfig {
-                        config_id: CONFIG_ID,
-                        config_param: WifiStationConfig::Authentication(Authentication::Open),
-                    })
-                    .await?;
-            }
-            WifiAuthentication::Wpa2Passphrase(passphrase) => {
-                (&self.at_client)
-                    .send_retry(&SetWifiStationConfig {
-                        config_id: CONFIG_ID,
-                        config_param: WifiStationConfig::Authentication(Authentication::WpaWpa2Psk),
-                    })
-                    .await?;
+        set_station_auth(
+            &mut &self.at_client,
+            CONFIG_ID,
+            self.credential_namespace.get(),
+            options.auth,
+        )
+        .await?;
 
-                (&self.at_client)
-                    .send_retry(&SetWifiStationConfig {
-                        config_id: CONFIG_ID,
-                        config_param: WifiStationConfig::WpaPskOrPassphrase(passphrase),
-                    })
-                    .await?;
-            } // WifiAuthentication::Wpa2Psk(_psk) => {
-              //     unimplemented!()
-              //     // (&self.at_client)
-              //     //     .send_retry(&SetWifiStationConfig {
-              //     //         config_id: CONFIG_ID,
-              //     //         config_param: WifiStationConfig::Authentication(Authentication::WpaWpa2Psk),
-              //     //     })
-              //     //     .await?;
-
-              //     // (&self.at_client)
-              //     //     .send_retry(&SetWifiStationConfig {
-              //     //         config_id: CONFIG_ID,
-              //     //         config_param: WifiStationConfig::WpaPskOrPassphrase(todo!("hex values?!")),
-              //     //     })
-              //     //     .await?;
-              // }
-        }
-
-        if options.ip.is_some() || options.subnet.is_some() || options.gateway.is_some() {
-            (&self.at_client)
-                .send_retry(&SetWifiStationConfig {
-                    config_id: CONFIG_ID,
-                    config_param: WifiStationConfig::IPv4Mode(IPv4Mode::Static),
-                })
-                .await?;
-        }
+        set_station_ipv4(&mut &self.at_client, CONFIG_ID, &options).await?;
 
-        // Network IP address
-        if let Some(ip) = options.ip {
-            (&self.at_client)
-                .send_retry(&SetWifiStationConfig {
-                    config_id: CONFIG_ID,
-                    config_param: WifiStationConfig::IPv4Address(ip),
-                })
-                .await?;
+        (&self.at_client)
+            .send_retry(&ExecWifiStationAction {
+                config_id: CONFIG_ID,
+                action: WifiStationAction::Activate,
+            })
+            .await?;
+
+        self.wait_for_join(
+            options.ssid,
+            options
+                .join_timeout
+                .unwrap_or(self.state_ch.timeouts().connect),
+        )
+        .await?;
+
+        if options.persist {
+            self.persist_station_config(CONFIG_ID).await?;
         }
-        // Network Subnet mask
-        if let Some(subnet) = options.subnet {
-            (&self.at_client)
-                .send_retry(&SetWifiStationConfig {
-                    config_id: CONFIG_ID,
-                    config_param: WifiStationConfig::SubnetMask(subnet),
-                })
-                .await?;
+
+        Ok(())
+    }
+
+    /// Replace the credentials of the station configuration `config_id`,
+    /// without changing the rest of the configuration.
+    ///
+    /// The credentials are first written to the active configuration, keeping
+    /// the current association and all sockets intact. The new credentials
+    /// are then used the next time the module associates with the network.
+    ///
+    /// If the firmware refuses to modify the active configuration, the
+    /// configuration is deactivated, updated and activated again. This drops
+    /// the link, and thereby all open sockets. The update completes once the
+    /// link of the reactivated configuration is up, within
+    /// [`Timeouts::connect`](crate::timeouts::Timeouts::connect).
+    ///
+    /// Returns which of the two was done.
+    pub async fn update_credentials(
+        &self,
+        config_id: u8,
+        auth: WifiAuthentication<'_>,
+    ) -> Result<CredentialUpdate, Error> {
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+
+        match set_station_auth(
+            &mut &self.at_client,
+            config_id,
+            self.credential_namespace.get(),
+            auth,
+        )
+        .await
+        {
+            Ok(()) => return Ok(CredentialUpdate::InPlace),
+            Err(Error::AT(atat::Error::Error | atat::Error::CmeError(_))) => {
+                info!("Active station config is read-only, reactivating to update credentials");
+            }
+            Err(e) => return Err(e),
         }
-        // Network Default gateway
-        if let Some(gateway) = options.gateway {
-            (&self.at_client)
-                .send_retry(&SetWifiStationConfig {
-                    config_id: CONFIG_ID,
-                    config_param: WifiStationConfig::DefaultGateway(gateway),
+
+        let sockets_reset = self.state_ch.link_state(None) == LinkState::Up;
+
+        (&self.at_client)
+            .send_retry(&ExecWifiStationAction {
+                config_id,
+                action: WifiStationAction::Deactivate,
+            })
+            .await?;
+
+        // The module reports the link down in a while. Take it down now, so
+        // only the link of the reactivated configuration is waited for.
+        self.state_ch
+            .update_connection_with(|con| con.wifi_state = WiFiState::NotConnected);
+
+        set_station_auth(
+            &mut &self.at_client,
+            config_id,
+            self.credential_namespace.get(),
+            auth,
+        )
+        .await?;
+
+        (&self.at_client)
+            .send_retry(&ExecWifiStationAction {
+                config_id,
+                action: WifiStationAction::Activate,
+            })
+            .await?;
+
+        with_timeout(
+            self.state_ch.timeouts().connect,
+            self.state_ch.wait_for_link_state(LinkState::Up),
+        )
+        .await?;
+
+        Ok(CredentialUpdate::Reactivated { sockets_reset })
+    }
+
+    /// Find a station configuration id that is not in use, i.e. one with an
+    /// empty SSID.
+    ///
+    /// The configuration ids address a fixed table of slots, so writing a
+    /// configuration never fails for a full table, it overwrites the slot.
+    /// Use this to pick the id of an additional configuration without
+    /// overwriting one in use. Fails with [`Error::ConfigTableFull`] if all
+    /// station configurations are in use.
+    pub async fn free_config_slot(&self) -> Result<u8, Error> {
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+
+        for config_id in 0..MAX_STATION_CONFIGS {
+            let GetWifiStationConfigResponse { parameter, .. } = (&self.at_client)
+                .send_retry(&GetWifiStationConfig {
+                    config_id,
+                    parameter: Some(WifiStationConfigParameter::SSID),
                 })
                 .await?;
+
+            if matches!(parameter, WifiStationConfigR::SSID(ssid) if ssid.is_empty()) {
+                return Ok(config_id);
+            }
         }
 
+        Err(Error::ConfigTableFull)
+    }
+
+    /// Store the station configuration `config_id` to persistent memory.
+    ///
+    /// Station configurations (`+UWSC`) only survive a reboot of the module,
+    /// when explicitly stored using the store action (`+UWSCA=<id>,1`).
+    /// Storing the general settings using `&W`
+    /// ([`StoreCurrentConfig`](crate::command::system::StoreCurrentConfig))
+    /// does not persist station configurations.
+    ///
+    /// Note that the configurations made by this driver are not active on
+    /// startup, so a persisted configuration has to be activated again after
+    /// a reboot.
+    pub async fn persist_station_config(&self, config_id: u8) -> Result<(), Error> {
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+
         (&self.at_client)
             .send_retry(&ExecWifiStationAction {
-                config_id: CONFIG_ID,
-                action: WifiStationAction::Activate,
+                config_id,
+                action: WifiStationAction::Store,
             })
             .await?;
 
-        self.wait_for_join(options.ssid, Duration::from_secs(20))
+        Ok(())
+    }
+
+    /// Reset the station configuration `config_id` to factory defaults.
+    ///
+    /// This only affects the active configuration. Call
+    /// [`Control::persist_station_config`] afterwards, to also clear the
+    /// configuration from persistent memory.
+    pub async fn reset_station_config(&self, config_id: u8) -> Result<(), Error> {
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+
+        (&self.at_client)
+            .send_retry(&ExecWifiStationAction {
+                config_id,
+                action: WifiStationAction::Reset,
+            })
             .await?;
 
         Ok(())
     }
 
+    /// Join the network described by `options`.
+    ///
+    /// Returns once the module is associated with the network and the link is
+    /// up, or with [`Error::Timeout`] if that does not happen within
+    /// [`ConnectionOptions::join_timeout`].
     pub async fn join_sta(&self, options: ConnectionOptions<'_>) -> Result<(), Error> {
         self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
 
         if matches!(self.get_wifi_status().await?, WifiStatusVal::Connected) {
             // Wifi already connected. Check if the SSID is the same
@@ -625,6 +1198,7 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
     /// Leave the wifi, with which we are currently associated.
     pub async fn leave(&self) -> Result<(), Error> {
         self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
         self.state_ch.set_should_connect(false);
 
         match self.get_wifi_status().await? {
@@ -640,7 +1214,7 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
         }
 
         with_timeout(
-            Duration::from_secs(10),
+            self.state_ch.timeouts().disconnect,
             self.state_ch.wait_connection_down(),
         )
         .await
@@ -673,19 +1247,203 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
         }
     }
 
-    // /// Start a wifi scan
-    // ///
-    // /// Returns a `Stream` of networks found by the device
-    // ///
-    // /// # Note
-    // /// Device events are currently implemented using a bounded queue.
-    // /// To not miss any events, you should make sure to always await the stream.
-    // pub async fn scan(&mut self, scan_opts: ScanOptions) -> Scanner<'_> {
-    //     todo!()
-    // }
+    /// Wait until the station interface has acquired an IPv4 address, e.g.
+    /// through DHCP, and return it.
+    ///
+    /// The link coming up does not mean that an address has been assigned
+    /// yet, so use this after [`Control::join_sta`] before opening sockets.
+    /// The network status is polled every
+    /// [`Timeouts::ip_poll`](crate::timeouts::Timeouts::ip_poll) until an
+    /// address is reported, or [`Error::Timeout`] is returned after
+    /// `timeout`.
+    pub async fn wait_for_ip(&self, timeout: Duration) -> Result<Ipv4Addr, Error> {
+        let poll_interval = self.state_ch.timeouts().ip_poll;
+        let fut = async {
+            loop {
+                if let Some(config) = self.config_v4().await? {
+                    return Ok(config.address);
+                }
+                Timer::after(poll_interval).await;
+            }
+        };
+
+        with_timeout(timeout, fut)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+
+    /// Scan the surroundings for networks.
+    ///
+    /// The networks are returned strongest first, by RSSI, each access point
+    /// once. At most the `N` strongest networks are returned. Entries that
+    /// cannot be parsed are skipped.
+    ///
+    /// Note that the module response itself is bounded to
+    /// [`MAX_SCAN_RESULTS`](crate::command::wifi::responses::MAX_SCAN_RESULTS)
+    /// networks, and by the ingress buffer of the runner.
+    ///
+    /// Returns [`Error::Cancelled`] if the scan is aborted using
+    /// [`Control::abort_scan`].
+    pub async fn scan<const N: usize>(&self) -> Result<Vec<WifiNetwork, N>, Error> {
+        self.restore_scan_channels().await?;
+        self.scan_for(None).await
+    }
+
+    /// Scan for the networks named `ssid`, probing for it directly.
+    ///
+    /// A directed scan is faster than [`Control::scan`], and also finds
+    /// hidden networks, which are reported with `ssid` as their SSID.
+    /// Truncation and aborting work as for [`Control::scan`].
+    pub async fn scan_with_ssid<const N: usize>(
+        &self,
+        ssid: &str,
+    ) -> Result<Vec<WifiNetwork, N>, Error> {
+        let ssid = heapless::String::try_from(ssid).map_err(|_| Error::BadLength)?;
+        self.restore_scan_channels().await?;
+        self.scan_for(Some(&ssid)).await
+    }
+
+    /// Scan `channels` only, for the networks named `ssid` if given.
+    ///
+    /// The channel list is set for the scan as by
+    /// [`Control::set_scan_channels`], and the previous channel list is
+    /// restored once the scan is done. A previous list longer than
+    /// [`Control::set_scan_channels`] takes is the default list of the
+    /// module, and is restored as by [`Control::reset_scan_channels`].
+    ///
+    /// Results are returned as for [`Control::scan`], also if the channel
+    /// list could not be restored. The restore is then retried by the next
+    /// scan, as it is if the scan is dropped before it completes.
+    pub async fn scan_channels<const N: usize>(
+        &self,
+        channels: &[u8],
+        ssid: Option<&str>,
+    ) -> Result<Vec<WifiNetwork, N>, Error> {
+        let ssid = ssid
+            .map(heapless::String::try_from)
+            .transpose()
+            .map_err(|_| Error::BadLength)?;
+
+        self.restore_scan_channels().await?;
+        let ChannelListResponse { channels: previous } = self.send_at(&GetChannelList).await?;
+        let previous = Vec::from_slice(&previous).unwrap_or_default();
+
+        self.restore_channels.replace(Some(previous));
+        let res = match self.set_scan_channels_unrestored(channels).await {
+            Ok(()) => self.scan_for(ssid.as_ref()).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = self.restore_scan_channels().await {
+            warn!("Failed to restore the channel list after a scan: {:?}", e);
+        }
+
+        res
+    }
+
+    /// Restore the channel list left by [`Control::scan_channels`], if any.
+    async fn restore_scan_channels(&self) -> Result<(), Error> {
+        let Some(channels) = self.restore_channels.borrow().clone() else {
+            return Ok(());
+        };
+
+        self.send_at(&SetChannelList { channels }).await?;
+        self.restore_channels.replace(None);
+        Ok(())
+    }
+
+    async fn scan_for<const N: usize>(
+        &self,
+        ssid: Option<&heapless::String<64>>,
+    ) -> Result<Vec<WifiNetwork, N>, Error> {
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+
+        self.scan_abort.reset();
+
+        let Some(WifiScanResponse { network_list }) = self
+            .at_client
+            .send_abortable(
+                &WifiScan {
+                    ssid: ssid.map(|s| s.as_str()),
+                },
+                self.state_ch.timeouts().scan,
+                self.scan_abort.wait(),
+            )
+            .await?
+        else {
+            info!("Scan aborted");
+            return Err(Error::Cancelled);
+        };
+
+        if network_list.len() > N {
+            warn!(
+                "Scan found {} networks, keeping the strongest {}",
+                network_list.len(),
+                N
+            );
+        }
+
+        Ok(strongest_networks(network_list.into_iter().filter_map(
+            |network| match ssid {
+                Some(ssid) => WifiNetwork::from_directed_scan(network, ssid).ok(),
+                None => WifiNetwork::try_from(network).ok(),
+            },
+        )))
+    }
+
+    /// Abort an in-progress [`Control::scan`], making it return
+    /// [`Error::Cancelled`].
+    ///
+    /// The module reports all scan results in a single response, so no partial
+    /// results are available. As the module itself cannot interrupt a scan,
+    /// the next command is delayed until the module has completed it, and the
+    /// results are discarded.
+    ///
+    /// Has no effect if no scan is in progress.
+    pub fn abort_scan(&self) {
+        self.scan_abort.signal(());
+    }
+
+    /// Restrict scans, including those of joining a network, to `channels`,
+    /// at most 10 of them.
+    ///
+    /// The module may further limit the channels in use, to comply with the
+    /// regulatory region it determines it operates in.
+    pub async fn set_scan_channels(&self, channels: &[u8]) -> Result<(), Error> {
+        self.set_scan_channels_unrestored(channels).await?;
+        // Overrides the list to restore after a dropped scan
+        self.restore_channels.replace(None);
+        Ok(())
+    }
+
+    async fn set_scan_channels_unrestored(&self, channels: &[u8]) -> Result<(), Error> {
+        if let Some(&channel) = channels.iter().find(|&&c| !WifiBand::is_valid_channel(c)) {
+            return Err(Error::InvalidChannel(channel));
+        }
+        if channels.is_empty() {
+            return Err(Error::BadLength);
+        }
+        let channels = Vec::from_slice(channels).map_err(|_| Error::Overflow)?;
+
+        self.send_at(&SetChannelList { channels }).await?;
+        Ok(())
+    }
+
+    /// Restore the default channel list for scans, see
+    /// [`Control::set_scan_channels`].
+    pub async fn reset_scan_channels(&self) -> Result<(), Error> {
+        self.send_at(&SetChannelList {
+            channels: Vec::new(),
+        })
+        .await?;
+        self.restore_channels.replace(None);
+        Ok(())
+    }
 
     pub async fn send_at<Cmd: AtatCmd>(&self, cmd: &Cmd) -> Result<Cmd::Response, Error> {
         self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
         Ok((&self.at_client).send_retry(cmd).await?)
     }
 
@@ -710,65 +1468,1619 @@ impl<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
         Ok(value as u8 != 0)
     }
 
-    #[cfg(feature = "ppp")]
-    pub async fn ping(
-        &self,
-        hostname: &str,
-    ) -> Result<crate::command::ping::urc::PingResponse, Error> {
+    /// Ping `hostname`, an IP address or a domain name, with `count` echo
+    /// requests, and summarize the replies.
+    ///
+    /// Fails with [`Error::Dns`] if no reply is received, with
+    /// [`PingError::Timeout`](crate::command::ping::types::PingError::Timeout)
+    /// for an unreachable host, or with the error reported by the module. A
+    /// single ping is supported at a time, [`Error::Busy`] is returned while
+    /// another one is in progress. The `+UUPINGER` URC does not name the
+    /// host, so the ping and the resolves of the network stack, which ping
+    /// the host to resolve, wait for each other to be done.
+    pub async fn ping(&self, hostname: &str, count: u16) -> Result<PingSummary, Error> {
+        if count == 0 {
+            return Err(Error::BadLength);
+        }
+        if !self.state_ch.start_ping().await {
+            return Err(Error::Busy);
+        }
+        let _guard = PingGuard::new(&self.state_ch);
+
         let mut urc_sub = self.urc_channel.subscribe().map_err(|_| Error::Overflow)?;
 
         self.send_at(&Ping {
             hostname,
-            retry_num: 1,
+            retry_num: count.into(),
         })
         .await?;
 
-        let result_fut = async {
-            loop {
-                match urc_sub.next_message_pure().await {
-                    crate::command::Urc::PingResponse(r) => return Ok(r),
-                    crate::command::Urc::PingErrorResponse(e) => return Err(Error::Dns(e.error)),
-                    _ => {}
+        let mut session = PingSession::new(hostname, count.into());
+        while !session.is_done() {
+            let next = with_timeout(self.state_ch.timeouts().ping, urc_sub.next_message_pure());
+            // Gave up on the remaining replies
+            let Ok(event) = next.await else {
+                break;
+            };
+
+            #[cfg(feature = "edm")]
+            let Some(urc) = event.extract_urc() else {
+                continue;
+            };
+            #[cfg(not(feature = "edm"))]
+            let urc = event;
+
+            session.on_urc(&urc);
+        }
+
+        session.finish()
+    }
+
+    /// List the imported certificates or private keys of `data_type` in the
+    /// namespace of this client, with the namespace prefix stripped from
+    /// their names.
+    ///
+    /// The module lists names only, so the MD5 of each listed item is queried
+    /// separately, as by [`credentials_md5`](Control::credentials_md5). Names
+    /// that do not fit in `N` characters are skipped.
+    pub async fn list_credentials<const N: usize>(
+        &self,
+        data_type: SecurityDataType,
+    ) -> Result<Vec<StoredCredentials<N>, 16>, Error> {
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+
+        let namespace = self.credential_namespace.get();
+        let ListSecurityDataResponse { entries } = (&self.at_client)
+            .send_retry(&ListSecurityData { types: data_type })
+            .await?;
+
+        let mut credentials = Vec::new();
+        for entry in entries.iter() {
+            let Some(name) = namespace
+                .strip(&entry.internal_name)
+                .and_then(|name| heapless::String::try_from(name).ok())
+            else {
+                continue;
+            };
+
+            let md5 = stored_md5(
+                &mut &self.at_client,
+                data_type.clone(),
+                &entry.internal_name,
+            )
+            .await?;
+            // Never more entries than listed
+            credentials.push(StoredCredentials { name, md5 }).ok();
+        }
+
+        Ok(credentials)
+    }
+
+    /// Remove the imported certificate or private key `name` of `data_type`,
+    /// in the namespace of this client.
+    pub async fn remove_credentials(
+        &self,
+        data_type: SecurityDataType,
+        name: &str,
+    ) -> Result<(), Error> {
+        let name = self.credential_namespace.get().apply(name)?;
+
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+
+        (&self.at_client)
+            .send_retry(&RemoveSecurityData {
+                types: data_type,
+                name: &name,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    /// MD5 of the imported certificate or private key `name` of `data_type`,
+    /// in the namespace of this client, as a hex string.
+    ///
+    /// Comparing it to the MD5 of a certificate or private key tells whether
+    /// it needs to be imported, see
+    /// [`import_credentials`](Control::import_credentials).
+    pub async fn credentials_md5(
+        &self,
+        data_type: SecurityDataType,
+        name: &str,
+    ) -> Result<heapless::String<32>, Error> {
+        let name = self.credential_namespace.get().apply(name)?;
+
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+
+        stored_md5(&mut &self.at_client, data_type, &name).await
+    }
+
+    /// Import a certificate or private key into the module, as `name`.
+    ///
+    /// The namespace of this client is prefixed to `name`, see
+    /// [`CredentialNamespace`]. If a certificate or private key of the same
+    /// type and full name exists, the import fails with
+    /// [`Error::CredentialNameCollision`], unless `overwrite` is set.
+    ///
+    /// The module egress is held exclusively for the entire import, from the
+    /// check of the existing credentials on, so other commands are queued
+    /// until it has completed and cannot import or remove credentials in
+    /// between. If the import has not completed within `timeout`, it is
+    /// aborted with [`Error::Timeout`]. As the module takes no commands until
+    /// it has received all of `data`, data left unwritten is replaced by
+    /// zeros, so the credential may be left imported with invalid content.
+    ///
+    /// The module has no way of reporting the space left in its credential
+    /// store, so the import is rejected up front with [`Error::StorageFull`]
+    /// when the module refuses it for lack of memory, and with
+    /// [`Error::BadLength`] if `data` exceeds [`MAX_SECURITY_DATA_SIZE`],
+    /// before any data is transferred. Other refusals are reported as
+    /// [`Error::AT`].
+    ///
+    /// If `md5_sum` is given, it is checked against the MD5 the module
+    /// computes of the imported data, failing with
+    /// [`Error::CredentialsMismatch`] on a difference. If a certificate or
+    /// private key with that MD5 is already imported as `name`, the import
+    /// is skipped altogether.
+    pub async fn import_credentials(
+        &self,
+        data_type: SecurityDataType,
+        name: &str,
+        data: &[u8],
+        md5_sum: Option<&str>,
+        overwrite: bool,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        if data.len() > MAX_SECURITY_DATA_SIZE {
+            return Err(Error::BadLength);
+        }
+
+        let namespace = self.credential_namespace.get();
+        // Validate before querying the module
+        let full_name = namespace.apply(name)?;
+
+        self.state_ch.wait_for_initialized().await;
+        self.ensure_resumed()?;
+
+        let mut at_client = self.at_client.exclusive().await;
+
+        if let Some(md5_sum) = md5_sum {
+            let imported =
+                is_imported(&mut at_client, data_type.clone(), &full_name, md5_sum).await?;
+            if imported {
+                info!("{:?} is imported already", full_name.as_str());
+                return Ok(());
+            }
+        }
+
+        let ListSecurityDataResponse { entries } = at_client
+            .send_retry(&ListSecurityData {
+                types: data_type.clone(),
+            })
+            .await?;
+        let name = namespace.import_name(
+            name,
+            entries.iter().map(|e| e.internal_name.as_str()),
+            overwrite,
+        )?;
+        let name = name.as_str();
+
+        info!("Importing {:?} bytes as {:?}", data.len(), name);
+
+        // Bytes of the data written, while the module waits for the rest of
+        // the data or the response to it
+        let mut data_written = None;
+
+        let import_fut = async {
+            let prepare = PrepareSecurityDataImport {
+                data_type: data_type.clone(),
+                data_size: data.len(),
+                internal_name: name,
+                password: None,
+            };
+            at_client.write(&prepare).await?;
+
+            // The module waits for the data from here on, even if its prompt
+            // is late
+            data_written = Some(0);
+            if let Err(e) = at_client.receive(&prepare).await {
+                if !matches!(e, atat::Error::Timeout) {
+                    data_written = None;
                 }
+                return Err(import_error(e));
             }
+
+            let written = data_written.insert(0);
+            at_client.send_raw_counted(data, written).await?;
+
+            let response = at_client
+                .receive(&SendSecurityDataImport {
+                    data: atat::serde_bytes::Bytes::new(data),
+                })
+                .await;
+            if !matches!(response, Err(atat::Error::Timeout)) {
+                data_written = None;
+            }
+
+            Ok::<_, Error>(response?)
         };
 
-        with_timeout(Duration::from_secs(15), result_fut).await?
-    }
-
-    // FIXME: This could probably be improved
-    // #[cfg(feature = "internal-network-stack")]
-    // pub async fn import_credentials(
-    //     &mut self,
-    //     data_type: SecurityDataType,
-    //     name: &str,
-    //     data: &[u8],
-    //     md5_sum: Option<&str>,
-    // ) -> Result<(), atat::Error> {
-    //     assert!(name.len() < 16);
-
-    //     info!("Importing {:?} bytes as {:?}", data.len(), name);
-
-    //     (&self.at_client)
-    //         .send_retry(&PrepareSecurityDataImport {
-    //             data_type,
-    //             data_size: data.len(),
-    //             internal_name: name,
-    //             password: None,
-    //         })
-    //         .await?;
-
-    //     let import_data = self
-    //         .at_client
-    //         .send_retry(&SendSecurityDataImport {
-    //             data: atat::serde_bytes::Bytes::new(data),
-    //         })
-    //         .await?;
-
-    //     if let Some(hash) = md5_sum {
-    //         assert_eq!(import_data.md5_string.as_str(), hash);
-    //     }
-
-    //     Ok(())
-    // }
+        let result = with_timeout(timeout, import_fut).await;
+
+        if let Some(written) = data_written {
+            warn!("Import interrupted after {:?} bytes", written);
+            abort_import(&mut at_client, data_type, name, data.len() - written).await;
+        }
+
+        let import_data = result.map_err(|_| Error::Timeout)??;
+
+        if let Some(hash) = md5_sum {
+            if import_data.md5_string.as_str() != hash {
+                return Err(Error::CredentialsMismatch);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// MD5 of the imported certificate or private key `full_name` of `data_type`.
+async fn stored_md5<A: AtatClient>(
+    at_client: &mut A,
+    data_type: SecurityDataType,
+    full_name: &str,
+) -> Result<heapless::String<32>, Error> {
+    let SecurityDataMD5 { md5_string, .. } = at_client
+        .send_retry(&GetSecurityDataMD5 {
+            types: data_type,
+            name: heapless::String::try_from(full_name).map_err(|_| Error::Overflow)?,
+        })
+        .await?;
+
+    heapless::String::try_from(md5_string.as_str())
+        .map_err(|_| Error::AT(atat::Error::InvalidResponse))
+}
+
+/// Complete the data of an interrupted import of `name` with `remaining`
+/// zeros, as the module takes no commands until it has received all of it,
+/// and remove the credential it was completed into.
+async fn abort_import<const INGRESS_BUF_SIZE: usize>(
+    at_client: &mut ExclusiveClient<'_, '_, INGRESS_BUF_SIZE>,
+    data_type: SecurityDataType,
+    name: &str,
+    remaining: usize,
+) {
+    let padding = [0u8; MAX_CMD_LEN];
+    let mut remaining = remaining;
+    while remaining > 0 {
+        let len = remaining.min(padding.len());
+        if let Err(e) = at_client.send_raw(&padding[..len]).await {
+            warn!("Failed to complete interrupted import: {:?}", e);
+            at_client.abandon::<SendSecurityDataImport>();
+            return;
+        }
+        remaining -= len;
+    }
+
+    at_client.discard_response::<SendSecurityDataImport>().await;
+
+    if let Err(e) = at_client
+        .send(&RemoveSecurityData {
+            types: data_type,
+            name,
+        })
+        .await
+    {
+        warn!("Failed to remove interrupted import: {:?}", e);
+    }
+}
+
+/// Error of a refused credential import.
+///
+/// Only the memory full error means the credential store is full. A plain
+/// `ERROR` does not tell why the import was refused.
+fn import_error(e: atat::Error) -> Error {
+    match e {
+        atat::Error::CmeError(atat::CmeError::MemoryFull) => Error::StorageFull,
+        e => Error::AT(e),
+    }
+}
+
+/// Whether a certificate or private key of `data_type` with MD5 `md5_sum` is
+/// imported as `full_name`.
+async fn is_imported<A: AtatClient>(
+    at_client: &mut A,
+    data_type: SecurityDataType,
+    full_name: &str,
+    md5_sum: &str,
+) -> Result<bool, Error> {
+    let ListSecurityDataResponse { entries } = at_client
+        .send_retry(&ListSecurityData {
+            types: data_type.clone(),
+        })
+        .await?;
+    if !entries.iter().any(|e| e.internal_name == full_name) {
+        return Ok(false);
+    }
+
+    let stored = stored_md5(at_client, data_type, full_name).await?;
+    Ok(stored.eq_ignore_ascii_case(md5_sum))
+}
+
+/// Write the authentication `auth` to the station configuration `config_id`.
+///
+/// WPA2/WPA3 mixed mode falls back to WPA2 on firmware without WPA3 support.
+/// WPA3 only is not, as such a network would not accept WPA2 anyway.
+///
+/// The certificates and private keys of enterprise credentials are looked up
+/// in `namespace`.
+async fn set_station_auth<A: AtatClient>(
+    at_client: &mut A,
+    config_id: u8,
+    namespace: CredentialNamespace,
+    auth: WifiAuthentication<'_>,
+) -> Result<(), Error> {
+    let (authentication, passphrase) = match auth {
+        WifiAuthentication::None => (Authentication::Open, None),
+        WifiAuthentication::Wpa2Passphrase(passphrase) => {
+            (Authentication::WpaWpa2Psk, Some(passphrase))
+        }
+        WifiAuthentication::Wpa3Passphrase(passphrase) => {
+            (Authentication::Wpa3Psk, Some(passphrase))
+        }
+        WifiAuthentication::Wpa2Wpa3Passphrase(passphrase) => {
+            (Authentication::Wpa2Wpa3Psk, Some(passphrase))
+        }
+        WifiAuthentication::Enterprise(credentials) => {
+            return set_station_enterprise(at_client, config_id, namespace, credentials).await;
+        }
+    };
+
+    let wpa3 = matches!(
+        authentication,
+        Authentication::Wpa3Psk | Authentication::Wpa2Wpa3Psk
+    );
+    if wpa3 && passphrase.is_some_and(|p| p.len() > MAX_WPA3_PASSPHRASE_LEN) {
+        return Err(Error::BadLength);
+    }
+
+    let res = at_client
+        .send_retry(&SetWifiStationConfig {
+            config_id,
+            config_param: WifiStationConfig::Authentication(authentication.clone()),
+        })
+        .await;
+    match res {
+        Err(atat::Error::Error | atat::Error::CmeError(_))
+            if authentication == Authentication::Wpa2Wpa3Psk =>
+        {
+            warn!("WPA3 not supported by the firmware, falling back to WPA2");
+            at_client
+                .send_retry(&SetWifiStationConfig {
+                    config_id,
+                    config_param: WifiStationConfig::Authentication(Authentication::WpaWpa2Psk),
+                })
+                .await?;
+        }
+        res => {
+            res?;
+        }
+    }
+
+    if let Some(passphrase) = passphrase {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::WpaPskOrPassphrase(passphrase),
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Write the enterprise `credentials` to the station configuration
+/// `config_id`.
+///
+/// The CA certificate is checked to be imported before anything is written,
+/// as the module would only fail the activation of the configuration
+/// otherwise.
+async fn set_station_enterprise<A: AtatClient>(
+    at_client: &mut A,
+    config_id: u8,
+    namespace: CredentialNamespace,
+    credentials: EnterpriseCredentials<'_>,
+) -> Result<(), Error> {
+    let too_long = |s: Option<&str>, max| s.is_some_and(|s| s.len() > max);
+    if too_long(credentials.username, MAX_EAP_CREDENTIAL_LEN)
+        || too_long(credentials.password, MAX_EAP_CREDENTIAL_LEN)
+        || too_long(credentials.domain, MAX_EAP_DOMAIN_LEN)
+    {
+        return Err(Error::BadLength);
+    }
+
+    let ca_certificate = namespace.apply(credentials.ca_certificate)?;
+    let client_certificate = credentials
+        .client_certificate
+        .map(|name| namespace.apply(name))
+        .transpose()?;
+    let client_private_key = credentials
+        .client_private_key
+        .map(|name| namespace.apply(name))
+        .transpose()?;
+
+    let ListSecurityDataResponse { entries } = at_client
+        .send_retry(&ListSecurityData {
+            types: SecurityDataType::TrustedRootCA,
+        })
+        .await?;
+    if !entries
+        .iter()
+        .any(|e| e.internal_name == ca_certificate.as_str())
+    {
+        return Err(Error::MissingCaCertificate);
+    }
+
+    let authentication = match credentials.method {
+        EapMethod::Peap => Authentication::PEAP,
+        EapMethod::Tls => Authentication::EAPTLS,
+    };
+    at_client
+        .send_retry(&SetWifiStationConfig {
+            config_id,
+            config_param: WifiStationConfig::Authentication(authentication),
+        })
+        .await?;
+
+    if let Some(username) = credentials.username {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::UserName(username),
+            })
+            .await?;
+    }
+
+    if let Some(password) = credentials.password {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::EAPPassword(password),
+            })
+            .await?;
+    }
+
+    if let Some(domain) = credentials.domain {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::DomainName(domain),
+            })
+            .await?;
+    }
+
+    at_client
+        .send_retry(&SetWifiStationConfig {
+            config_id,
+            config_param: WifiStationConfig::CACertificateName(&ca_certificate),
+        })
+        .await?;
+
+    if let Some(name) = &client_certificate {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::ClientCertificateName(name),
+            })
+            .await?;
+    }
+
+    if let Some(name) = &client_private_key {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::ClientPrivateKey(name),
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Write the IPv4 configuration of `options` to the station configuration
+/// `config_id`. Without any of them, the station keeps using DHCP.
+async fn set_station_ipv4<A: AtatClient>(
+    at_client: &mut A,
+    config_id: u8,
+    options: &ConnectionOptions<'_>,
+) -> Result<(), Error> {
+    if options.ip.is_some() || options.subnet.is_some() || options.gateway.is_some() {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::IPv4Mode(IPv4Mode::Static),
+            })
+            .await?;
+    }
+
+    // Network IP address
+    if let Some(ip) = options.ip {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::IPv4Address(ip),
+            })
+            .await?;
+    }
+    // Network Subnet mask
+    if let Some(subnet) = options.subnet {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::SubnetMask(subnet),
+            })
+            .await?;
+    }
+    // Network Default gateway
+    if let Some(gateway) = options.gateway {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::DefaultGateway(gateway),
+            })
+            .await?;
+    }
+    // Network Primary + Secondary DNS
+    if let Some(primary) = options.dns.primary {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::DNSServer1(primary),
+            })
+            .await?;
+    }
+    if let Some(secondary) = options.dns.secondary {
+        at_client
+            .send_retry(&SetWifiStationConfig {
+                config_id,
+                config_param: WifiStationConfig::DNSServer2(secondary),
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, not(feature = "edm")))]
+mod test {
+    use super::*;
+    use crate::asynch::state::{LinkEvent, LINK_HISTORY_LEN};
+    use crate::command::{Urc, AT};
+    use crate::test_util::{harness, Harness, ManualClock, MockUbloxModule};
+    use crate::timeouts::Timeouts;
+    use atat::{AtDigester, AtatIngress as _, Ingress, ResponseSlot};
+    use core::cell::RefCell;
+    use embassy_futures::{
+        block_on,
+        join::{join, join3},
+        select::{select, Either},
+    };
+    use embassy_sync::channel::Channel;
+    use embedded_io_async::{Read as _, Write as _};
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        CommandDone,
+        UrcReceived,
+        DeferredSent,
+        DeferredDone,
+    }
+
+    #[test]
+    fn response_and_urc_in_single_read() {
+        let res_slot = ResponseSlot::<256>::new();
+        let urc_channel = UrcChannel::<UbloxUrc, 2, { URC_SUBSCRIBERS }>::new();
+        let req_slot = Channel::<NoopRawMutex, Vec<u8, MAX_CMD_LEN>, 1>::new();
+        let cmd_lock = Mutex::new(None);
+
+        let mut ingress_buf = [0u8; 256];
+        let mut ingress = Ingress::new(
+            AtDigester::<UbloxUrc>::new(),
+            &mut ingress_buf,
+            &res_slot,
+            &urc_channel,
+        );
+
+        // Equivalent to the clients of `Control` and `NetDevice`
+        let control_client = ProxyClient::new(
+            req_slot.sender(),
+            &res_slot,
+            &cmd_lock,
+            Timeouts::DEFAULT.command_default,
+        );
+        let device_client = ProxyClient::new(
+            req_slot.sender(),
+            &res_slot,
+            &cmd_lock,
+            Timeouts::DEFAULT.command_default,
+        );
+        let mut urc_subscription = urc_channel.subscribe().unwrap();
+
+        let events = RefCell::new(heapless::Vec::<Event, 8>::new());
+
+        let command = async {
+            assert!((&control_client).send(&AT).await.is_ok());
+            events.borrow_mut().push(Event::CommandDone).unwrap();
+        };
+
+        // A URC consumer issuing a command as a reaction to the URC
+        let consumer = async {
+            let urc = urc_subscription.next_message_pure().await;
+            assert!(matches!(urc, Urc::NetworkUp(_)));
+            events.borrow_mut().push(Event::UrcReceived).unwrap();
+
+            assert!((&device_client).send(&AT).await.is_ok());
+            events.borrow_mut().push(Event::DeferredDone).unwrap();
+        };
+
+        let module = async {
+            req_slot.receive().await;
+            // Response and URC arriving in a single read
+            ingress.write(b"\r\nOK\r\n+UUNU:0\r\n").await;
+
+            req_slot.receive().await;
+            events.borrow_mut().push(Event::DeferredSent).unwrap();
+            ingress.write(b"\r\nOK\r\n").await;
+        };
+
+        block_on(join3(command, consumer, module));
+
+        let events = events.into_inner();
+        let position = |e: Event| events.iter().position(|x| *x == e).unwrap();
+
+        assert_eq!(events.len(), 4);
+        assert!(position(Event::CommandDone) < position(Event::DeferredSent));
+        assert!(position(Event::UrcReceived) < position(Event::DeferredSent));
+        assert!(position(Event::DeferredSent) < position(Event::DeferredDone));
+    }
+
+    #[test]
+    fn observer_sees_urcs_alongside_runner() {
+        let res_slot = ResponseSlot::<256>::new();
+        let urc_channel = UrcChannel::<UbloxUrc, 2, { URC_SUBSCRIBERS }>::new();
+
+        let mut ingress_buf = [0u8; 256];
+        let mut ingress = Ingress::new(
+            AtDigester::<UbloxUrc>::new(),
+            &mut ingress_buf,
+            &res_slot,
+            &urc_channel,
+        );
+
+        let mut urc_subscription = urc_channel.subscribe().unwrap();
+        let mut observer = UrcObserver::new(urc_channel.subscribe().unwrap());
+
+        block_on(async {
+            ingress.write(b"+UUNU:0\r\n").await;
+            ingress.write(b"+UUND:0\r\n").await;
+
+            // Consuming URCs in the runner does not take them from the observer
+            assert!(matches!(
+                urc_subscription.next_message_pure().await,
+                Urc::NetworkUp(_)
+            ));
+            assert!(matches!(
+                urc_subscription.next_message_pure().await,
+                Urc::NetworkDown(_)
+            ));
+            assert!(matches!(observer.next().await, Urc::NetworkUp(_)));
+            assert!(matches!(observer.next().await, Urc::NetworkDown(_)));
+        });
+
+        assert_eq!(observer.lost(), 0);
+    }
+
+    #[test]
+    fn suspend_and_resume() {
+        let mut state = state::State::new();
+        let state_ch = state::Runner::new(&mut state);
+        let res_slot = ResponseSlot::<256>::new();
+        let urc_channel = UrcChannel::<UbloxUrc, 2, { URC_SUBSCRIBERS }>::new();
+        let req_slot = Channel::<NoopRawMutex, Vec<u8, MAX_CMD_LEN>, 1>::new();
+        let cmd_lock = Mutex::new(None);
+        let raw_rx = Pipe::<NoopRawMutex, 256>::new();
+        let raw_tx = Pipe::<NoopRawMutex, MAX_CMD_LEN>::new();
+
+        let control = Control::new(
+            state_ch.clone(),
+            &urc_channel,
+            req_slot.sender(),
+            &res_slot,
+            &cmd_lock,
+            &raw_rx,
+            &raw_tx,
+        );
+        state_ch.mark_initialized();
+
+        // Equivalent to the bridge and the network device of the runner
+        let runner = async {
+            state_ch.wait_suspended(true).await;
+            state_ch.set_paused(true);
+
+            state_ch.wait_suspended(false).await;
+            state_ch.set_paused(false);
+
+            state_ch.wait_resync_pending().await;
+            state_ch.update_connection_with(|con| {
+                con.wifi_state = WiFiState::Connected;
+                con.ipv4_up = true;
+                con.ipv6_link_local_up = true;
+            });
+            state_ch.resync_done();
+        };
+
+        let updater = async {
+            let mut guard = control.suspend().await.unwrap();
+
+            // Normal operations fail while suspended
+            assert!(matches!(control.send_at(&AT).await, Err(Error::Suspended)));
+            assert!(matches!(control.suspend().await, Err(Error::Suspended)));
+
+            // Raw traffic window
+            guard.write_all(b"AT+UFWUPD=0\r").await.unwrap();
+            let mut buf = [0u8; 12];
+            assert_eq!(raw_tx.read(&mut buf).await, 12);
+            assert_eq!(&buf, b"AT+UFWUPD=0\r");
+
+            raw_rx.write_all(b"CCC").await;
+            let mut buf = [0u8; 1];
+            guard.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"C");
+
+            let report = guard.resume().await;
+            assert_eq!(report.discarded, 2);
+            assert_eq!(report.link_before, LinkState::Down);
+            assert_eq!(report.link_after, LinkState::Up);
+
+            assert!(control.ensure_resumed().is_ok());
+        };
+
+        block_on(join(runner, updater));
+    }
+
+    #[test]
+    fn command_timeout_floor() {
+        let res_slot = ResponseSlot::<256>::new();
+        let req_slot = Channel::<NoopRawMutex, Vec<u8, MAX_CMD_LEN>, 1>::new();
+        let cmd_lock = Mutex::new(None);
+
+        let client = ProxyClient::new(
+            req_slot.sender(),
+            &res_slot,
+            &cmd_lock,
+            Duration::from_secs(3),
+        );
+
+        // Raised to the floor
+        assert_eq!(client.response_timeout(1000), Duration::from_secs(3));
+        // Longer timeouts are kept
+        assert_eq!(client.response_timeout(5000), Duration::from_secs(5));
+
+        // A command declaring a shorter timeout waits for the floor
+        let clock = ManualClock::new();
+        let mut at_client = &client;
+        let mut send = core::pin::pin!(at_client.send(&AT));
+        assert!(embassy_futures::poll_once(send.as_mut()).is_pending());
+        clock.advance(2000);
+        assert!(embassy_futures::poll_once(send.as_mut()).is_pending());
+        clock.advance(1000);
+        assert!(matches!(
+            embassy_futures::poll_once(send.as_mut()),
+            core::task::Poll::Ready(Err(atat::Error::Timeout))
+        ));
+    }
+
+    fn sent(client: &MockUbloxModule) -> std::vec::Vec<&[u8]> {
+        client.sent_commands().collect()
+    }
+
+    fn station_ipv4_commands(options: ConnectionOptions) -> std::vec::Vec<std::vec::Vec<u8>> {
+        let mut client = MockUbloxModule::new();
+        block_on(set_station_ipv4(&mut client, CONFIG_ID, &options)).unwrap();
+        client.sent_commands().map(<[u8]>::to_vec).collect()
+    }
+
+    #[test]
+    fn station_static_dns() {
+        let options = ConnectionOptions::new("ublox")
+            .ip_address(Ipv4Addr::new(192, 168, 1, 10))
+            .dns1(Ipv4Addr::new(8, 8, 8, 8))
+            .dns2(Ipv4Addr::new(1, 1, 1, 1));
+
+        assert_eq!(
+            station_ipv4_commands(options),
+            [
+                &b"AT+UWSC=0,100,1\r\n"[..],
+                b"AT+UWSC=0,101,\"192.168.1.10\"\r\n",
+                b"AT+UWSC=0,104,\"8.8.8.8\"\r\n",
+                b"AT+UWSC=0,105,\"1.1.1.1\"\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn station_dhcp() {
+        assert!(station_ipv4_commands(ConnectionOptions::new("ublox")).is_empty());
+    }
+
+    #[test]
+    fn credentials_imported() {
+        let mut client = MockUbloxModule::new();
+        client.respond("AT+USECMNG=3", "+USECMNG:0,\"app_ca\"");
+        client.respond(
+            "AT+USECMNG=4",
+            "+USECMNG:4,0,\"app_ca\",\"0E4D4B7D7B2AB0B5A3B6C4E1C86F0E73\"",
+        );
+
+        let imported = |client: &mut MockUbloxModule, name, md5_sum| {
+            block_on(is_imported(
+                client,
+                SecurityDataType::TrustedRootCA,
+                name,
+                md5_sum,
+            ))
+            .unwrap()
+        };
+
+        assert!(imported(
+            &mut client,
+            "app_ca",
+            "0e4d4b7d7b2ab0b5a3b6c4e1c86f0e73"
+        ));
+        assert_eq!(
+            sent(&client),
+            [&b"AT+USECMNG=3,0\r\n"[..], b"AT+USECMNG=4,0,\"app_ca\"\r\n"]
+        );
+
+        // Changed data is imported again
+        assert!(!imported(
+            &mut client,
+            "app_ca",
+            "d41d8cd98f00b204e9800998ecf8427e"
+        ));
+
+        // Data not imported yet, without asking the module for its MD5
+        client.clear_sent();
+        assert!(!imported(
+            &mut client,
+            "app_cert",
+            "0e4d4b7d7b2ab0b5a3b6c4e1c86f0e73"
+        ));
+        assert_eq!(sent(&client), [b"AT+USECMNG=3,0\r\n"]);
+    }
+
+    #[cfg(feature = "ap")]
+    #[test]
+    fn hotspot() {
+        use crate::options::{Band, Channel};
+
+        let mut module = MockUbloxModule::new();
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        harness
+            .serve(
+                &mut module,
+                control.create_hotspot(
+                    "ublox-ap",
+                    HotspotOptions::new().channel(Channel::Six),
+                    Some("secret"),
+                ),
+            )
+            .unwrap();
+        let commands = sent(&module);
+        assert!(commands.contains(&&b"AT+UWAPC=0,2,\"ublox-ap\"\r\n"[..]));
+        assert!(commands.contains(&&b"AT+UWAPC=0,5,2,2\r\n"[..]));
+        assert!(commands.contains(&&b"AT+UWAPC=0,4,6\r\n"[..]));
+        assert_eq!(commands.last(), Some(&&b"AT+UWAPCA=0,3\r\n"[..]));
+
+        harness.serve(&mut module, control.stop_hotspot()).unwrap();
+        assert_eq!(sent(&module).last(), Some(&&b"AT+UWAPCA=0,4\r\n"[..]));
+
+        // Open without a password
+        for password in [None, Some("")] {
+            module.clear_sent();
+            harness
+                .serve(
+                    &mut module,
+                    control.create_hotspot("ublox-ap", HotspotOptions::new(), password),
+                )
+                .unwrap();
+            assert!(sent(&module).contains(&&b"AT+UWAPC=0,5,1,1\r\n"[..]));
+        }
+
+        // The channel is checked against the band
+        module.clear_sent();
+        let created = harness.serve(
+            &mut module,
+            control.create_hotspot(
+                "ublox-ap",
+                HotspotOptions::new().band(Band::A).channel(Channel::Six),
+                None,
+            ),
+        );
+        assert!(matches!(created, Err(Error::InvalidChannel(6))));
+        assert!(sent(&module).is_empty());
+    }
+
+    #[test]
+    fn credentials_listed() {
+        let mut module = MockUbloxModule::new();
+        module.respond("AT+USECMNG=3", "+USECMNG:0,\"app_ca\"");
+        module.respond(
+            "AT+USECMNG=4",
+            "+USECMNG:4,0,\"app_ca\",\"0E4D4B7D7B2AB0B5A3B6C4E1C86F0E73\"",
+        );
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        let listed = harness
+            .serve(
+                &mut module,
+                control.list_credentials::<32>(SecurityDataType::TrustedRootCA),
+            )
+            .unwrap();
+        assert_eq!(
+            listed,
+            [StoredCredentials {
+                name: heapless::String::try_from("app_ca").unwrap(),
+                md5: heapless::String::try_from("0E4D4B7D7B2AB0B5A3B6C4E1C86F0E73").unwrap(),
+            }]
+        );
+        assert_eq!(
+            sent(&module),
+            [&b"AT+USECMNG=3,0\r\n"[..], b"AT+USECMNG=4,0,\"app_ca\"\r\n"]
+        );
+    }
+
+    #[test]
+    fn import_holds_egress_from_check() {
+        let mut module = MockUbloxModule::new();
+        module.respond("AT+USECMNG=3", "+USECMNG:0,\"other_ca\"");
+
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        // A removal of the credential issued while it is being checked
+        let (imported, removed) = harness.serve(
+            &mut module,
+            join(
+                control.import_credentials(
+                    SecurityDataType::TrustedRootCA,
+                    "ca",
+                    b"caca",
+                    None,
+                    false,
+                    Duration::from_secs(5),
+                ),
+                control.remove_credentials(SecurityDataType::TrustedRootCA, "ca"),
+            ),
+        );
+        imported.unwrap();
+        removed.unwrap();
+
+        assert_eq!(module.imported("ca"), Some(b"caca".as_slice()));
+        assert_eq!(
+            sent(&module),
+            [
+                &b"AT+USECMNG=3,0\r\n"[..],
+                b"AT+USECMNG=0,0,\"ca\",4\r\n",
+                b"AT+USECMNG=2,0,\"ca\"\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn import_with_urcs() {
+        let mut module = MockUbloxModule::new();
+        module.import_md5("0e4d4b7d7b2ab0b5a3b6c4e1c86f0e73");
+        module.inject_urc_after("AT+USECMNG=3", "+UUWLD:0,0");
+        // Between the prompt and the data
+        module.inject_urc_after("AT+USECMNG=0", "+UUNU:0");
+
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        harness
+            .serve(
+                &mut module,
+                control.import_credentials(
+                    SecurityDataType::TrustedRootCA,
+                    "ca",
+                    b"caca",
+                    Some("0e4d4b7d7b2ab0b5a3b6c4e1c86f0e73"),
+                    false,
+                    Duration::from_secs(5),
+                ),
+            )
+            .unwrap();
+
+        assert_eq!(module.imported("ca"), Some(b"caca".as_slice()));
+    }
+
+    #[test]
+    fn import_interrupted() {
+        let mut module = MockUbloxModule::new();
+
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        // Eight writes of data, the mock module taking 20 ms for each
+        let data = [b'a'; 2048];
+        let imported = harness.serve(
+            &mut module,
+            control.import_credentials(
+                SecurityDataType::TrustedRootCA,
+                "ca",
+                &data,
+                None,
+                false,
+                Duration::from_millis(50),
+            ),
+        );
+        assert!(matches!(imported, Err(Error::Timeout)));
+
+        // The credential completed with zeros is removed
+        assert_eq!(
+            sent(&module).last(),
+            Some(&&b"AT+USECMNG=2,0,\"ca\"\r\n"[..])
+        );
+
+        // The module takes commands again, with the response to the import
+        // discarded
+        module.clear_sent();
+        harness
+            .serve(
+                &mut module,
+                control.remove_credentials(SecurityDataType::TrustedRootCA, "ca"),
+            )
+            .unwrap();
+        assert_eq!(sent(&module), [b"AT+USECMNG=2,0,\"ca\"\r\n"]);
+
+        // Completed with zeros
+        let imported = module.imported("ca").unwrap();
+        assert_eq!(imported.len(), data.len());
+        assert_eq!(imported.first(), Some(&b'a'));
+        assert_eq!(imported.last(), Some(&0));
+    }
+
+    #[test]
+    fn import_prompt_timeout() {
+        // The module takes the data, but its prompt is lost
+        let mut module = MockUbloxModule::new();
+        module.stall("AT+USECMNG=0");
+
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        let imported = harness.serve(
+            &mut module,
+            control.import_credentials(
+                SecurityDataType::TrustedRootCA,
+                "ca",
+                b"caca",
+                None,
+                false,
+                Duration::from_millis(0),
+            ),
+        );
+        assert!(matches!(imported, Err(Error::Timeout)));
+
+        // Completed with zeros, and removed
+        assert_eq!(module.imported("ca"), Some([0u8; 4].as_slice()));
+        assert_eq!(
+            sent(&module),
+            [
+                &b"AT+USECMNG=3,0\r\n"[..],
+                b"AT+USECMNG=0,0,\"ca\",4\r\n",
+                b"AT+USECMNG=2,0,\"ca\"\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn import_response_timeout() {
+        // The module takes longer to store the data than the import may take
+        let mut module = MockUbloxModule::new();
+        module.import_time(Duration::from_secs(2));
+
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        let imported = harness.serve(
+            &mut module,
+            control.import_credentials(
+                SecurityDataType::TrustedRootCA,
+                "ca",
+                b"caca",
+                None,
+                false,
+                Duration::from_secs(1),
+            ),
+        );
+        assert!(matches!(imported, Err(Error::Timeout)));
+
+        // Removed once stored, with the late response discarded
+        assert_eq!(module.imported("ca"), Some(b"caca".as_slice()));
+        assert_eq!(
+            sent(&module),
+            [
+                &b"AT+USECMNG=3,0\r\n"[..],
+                b"AT+USECMNG=0,0,\"ca\",4\r\n",
+                b"AT+USECMNG=2,0,\"ca\"\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn import_refused() {
+        assert!(matches!(
+            import_error(atat::Error::CmeError(atat::CmeError::MemoryFull)),
+            Error::StorageFull
+        ));
+        assert!(matches!(
+            import_error(atat::Error::Error),
+            Error::AT(atat::Error::Error)
+        ));
+        assert!(matches!(
+            import_error(atat::Error::CmeError(atat::CmeError::OperationNotAllowed)),
+            Error::AT(atat::Error::CmeError(atat::CmeError::OperationNotAllowed))
+        ));
+    }
+
+    fn station_auth_commands(
+        auth: WifiAuthentication,
+        rejected: &[&'static str],
+    ) -> (Result<(), Error>, std::vec::Vec<std::vec::Vec<u8>>) {
+        let mut client = MockUbloxModule::new();
+        for prefix in rejected {
+            client.fail(prefix, atat::Error::Error);
+        }
+        let res = block_on(set_station_auth(
+            &mut client,
+            CONFIG_ID,
+            CredentialNamespace::default(),
+            auth,
+        ));
+        (res, client.sent_commands().map(<[u8]>::to_vec).collect())
+    }
+
+    #[test]
+    fn station_wpa3() {
+        let (res, sent) =
+            station_auth_commands(WifiAuthentication::Wpa3Passphrase("passphrase"), &[]);
+        assert!(res.is_ok());
+        assert_eq!(
+            sent,
+            [&b"AT+UWSC=0,5,7\r\n"[..], b"AT+UWSC=0,8,\"passphrase\"\r\n"]
+        );
+
+        let (res, sent) =
+            station_auth_commands(WifiAuthentication::Wpa2Wpa3Passphrase("passphrase"), &[]);
+        assert!(res.is_ok());
+        assert_eq!(
+            sent,
+            [&b"AT+UWSC=0,5,6\r\n"[..], b"AT+UWSC=0,8,\"passphrase\"\r\n"]
+        );
+
+        // Nothing is sent for a passphrase WPA3 does not accept
+        let psk = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+        let (res, sent) = station_auth_commands(WifiAuthentication::Wpa3Passphrase(psk), &[]);
+        assert!(matches!(res, Err(Error::BadLength)));
+        assert!(sent.is_empty());
+    }
+
+    #[test]
+    fn station_wpa3_fallback() {
+        // Firmware without WPA3 support
+        let rejected = ["AT+UWSC=0,5,6", "AT+UWSC=0,5,7"];
+
+        let (res, sent) = station_auth_commands(
+            WifiAuthentication::Wpa2Wpa3Passphrase("passphrase"),
+            &rejected,
+        );
+        assert!(res.is_ok());
+        assert_eq!(
+            sent,
+            [
+                &b"AT+UWSC=0,5,6\r\n"[..],
+                b"AT+UWSC=0,5,2\r\n",
+                b"AT+UWSC=0,8,\"passphrase\"\r\n",
+            ]
+        );
+
+        let (res, sent) =
+            station_auth_commands(WifiAuthentication::Wpa3Passphrase("passphrase"), &rejected);
+        assert!(matches!(res, Err(Error::AT(atat::Error::Error))));
+        assert_eq!(sent, [b"AT+UWSC=0,5,7\r\n"]);
+    }
+
+    #[test]
+    fn station_enterprise() {
+        let namespace = CredentialNamespace::new("app_");
+        let peap = EnterpriseCredentials::peap("alice", "secret", "ca").domain("example.com");
+        let tls = EnterpriseCredentials::tls("ca", "cert", "key").username("device-1");
+
+        let mut client = MockUbloxModule::new();
+        client.respond("AT+USECMNG=3,0", "+USECMNG:0,\"app_ca\"");
+
+        let res = block_on(set_station_auth(
+            &mut client,
+            CONFIG_ID,
+            namespace,
+            WifiAuthentication::Enterprise(peap),
+        ));
+        assert!(res.is_ok());
+        assert_eq!(
+            sent(&client),
+            [
+                &b"AT+USECMNG=3,0\r\n"[..],
+                b"AT+UWSC=0,5,4\r\n",
+                b"AT+UWSC=0,10,\"alice\"\r\n",
+                b"AT+UWSC=0,9,\"secret\"\r\n",
+                b"AT+UWSC=0,11,\"example.com\"\r\n",
+                b"AT+UWSC=0,14,\"app_ca\"\r\n",
+            ]
+        );
+
+        client.clear_sent();
+        let res = block_on(set_station_auth(
+            &mut client,
+            CONFIG_ID,
+            namespace,
+            WifiAuthentication::Enterprise(tls),
+        ));
+        assert!(res.is_ok());
+        assert_eq!(
+            sent(&client),
+            [
+                &b"AT+USECMNG=3,0\r\n"[..],
+                b"AT+UWSC=0,5,5\r\n",
+                b"AT+UWSC=0,10,\"device-1\"\r\n",
+                b"AT+UWSC=0,14,\"app_ca\"\r\n",
+                b"AT+UWSC=0,12,\"app_cert\"\r\n",
+                b"AT+UWSC=0,13,\"app_key\"\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn station_enterprise_missing_ca() {
+        let peap = EnterpriseCredentials::peap("alice", "secret", "ca");
+
+        // Nothing is written without the CA certificate in the module
+        let (res, sent) = station_auth_commands(WifiAuthentication::Enterprise(peap), &[]);
+        assert!(matches!(res, Err(Error::MissingCaCertificate)));
+        assert_eq!(sent, [b"AT+USECMNG=3,0\r\n"]);
+
+        // Nor for a password the module does not accept
+        let password = "x".repeat(32);
+        let peap = EnterpriseCredentials::peap("alice", &password, "ca");
+        let (res, sent) = station_auth_commands(WifiAuthentication::Enterprise(peap), &[]);
+        assert!(matches!(res, Err(Error::BadLength)));
+        assert!(sent.is_empty());
+    }
+
+    /// Update the credentials of a connected station to `rotated`, with
+    /// `module` serving the commands, returning the outcome and the link
+    /// history.
+    fn update_credentials_with(
+        module: &mut MockUbloxModule,
+    ) -> (
+        Result<CredentialUpdate, Error>,
+        heapless::Vec<LinkEvent, LINK_HISTORY_LEN>,
+    ) {
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+        ch.update_connection_with(|con| harness::connect(con, Default::default(), 6));
+
+        let res = harness.serve(module, async {
+            match select(
+                control
+                    .update_credentials(CONFIG_ID, WifiAuthentication::Wpa2Passphrase("rotated")),
+                harness.device(&ch),
+            )
+            .await
+            {
+                Either::First(res) => res,
+                Either::Second(never) => never,
+            }
+        });
+        (res, ch.link_history())
+    }
+
+    fn link_states(link_history: &[LinkEvent]) -> std::vec::Vec<LinkState> {
+        link_history.iter().map(|event| event.link_state).collect()
+    }
+
+    #[test]
+    fn update_credentials_in_place() {
+        let mut module = MockUbloxModule::new();
+
+        let (res, link_history) = update_credentials_with(&mut module);
+        assert_eq!(res.unwrap(), CredentialUpdate::InPlace);
+        assert_eq!(
+            sent(&module),
+            [&b"AT+UWSC=0,5,2\r\n"[..], b"AT+UWSC=0,8,\"rotated\"\r\n"]
+        );
+        assert_eq!(link_states(&link_history), [LinkState::Up]);
+    }
+
+    #[test]
+    fn update_credentials_reactivated() {
+        let mut module = MockUbloxModule::new();
+        // Firmware refusing to modify the active configuration
+        module.fail_once("AT+UWSC=0,5,2", atat::Error::Error);
+
+        let (res, link_history) = update_credentials_with(&mut module);
+        assert_eq!(
+            res.unwrap(),
+            CredentialUpdate::Reactivated {
+                sockets_reset: true
+            }
+        );
+        assert_eq!(
+            sent(&module),
+            [
+                &b"AT+UWSC=0,5,2\r\n"[..],
+                b"AT+UWSCA=0,4\r\n",
+                b"AT+UWSC=0,5,2\r\n",
+                b"AT+UWSC=0,8,\"rotated\"\r\n",
+                b"AT+UWSCA=0,3\r\n",
+            ]
+        );
+        // Done once the link of the reactivated configuration is up
+        assert_eq!(
+            link_states(&link_history),
+            [LinkState::Up, LinkState::Down, LinkState::Up]
+        );
+    }
+
+    #[test]
+    fn update_credentials_failed() {
+        let mut module = MockUbloxModule::new();
+        module.fail_once("AT+UWSC=0,5,2", atat::Error::Error);
+        module.fail("AT+UWSCA=0,3", atat::Error::Error);
+
+        let (res, link_history) = update_credentials_with(&mut module);
+        assert!(matches!(res, Err(Error::AT(atat::Error::Error))));
+        assert_eq!(link_states(&link_history), [LinkState::Up, LinkState::Down]);
+    }
+
+    /// Join the network "office" with `options`, returning the commands sent.
+    fn join_commands(options: ConnectionOptions) -> std::vec::Vec<std::vec::Vec<u8>> {
+        let mut module = MockUbloxModule::new();
+        module.respond("AT+UWSSTAT=0", "+UWSSTAT:0,\"office\"");
+
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        harness
+            .serve(&mut module, async {
+                match select(control.peek_join_sta(options), harness.device(&ch)).await {
+                    Either::First(res) => res,
+                    Either::Second(never) => never,
+                }
+            })
+            .unwrap();
+        module.sent_commands().map(<[u8]>::to_vec).collect()
+    }
+
+    #[test]
+    fn join_persisted() {
+        let options = ConnectionOptions::new("office").wpa2_passphrase("secret");
+        let join = [
+            &b"AT+UWSCA=0,0\r\n"[..],
+            b"AT+UWSC=0,0,0\r\n",
+            b"AT+UWSC=0,2,\"office\"\r\n",
+            b"AT+UWSC=0,5,2\r\n",
+            b"AT+UWSC=0,8,\"secret\"\r\n",
+            b"AT+UWSCA=0,3\r\n",
+            b"AT+UWSSTAT=0\r\n",
+        ];
+
+        assert_eq!(join_commands(options.clone()), join);
+
+        // Stored only once the network has been joined
+        let mut persisted = join.to_vec();
+        persisted.push(&b"AT+UWSCA=0,1\r\n"[..]);
+        assert_eq!(join_commands(options.persist(true)), persisted);
+    }
+
+    #[test]
+    fn free_config_slot() {
+        let mut module = MockUbloxModule::new();
+        for config_id in 0..MAX_STATION_CONFIGS {
+            module.respond(
+                &format!("AT+UWSC={},2", config_id),
+                &format!("+UWSC:{},2,\"office\"", config_id),
+            );
+        }
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        let res = harness.serve(&mut module, control.free_config_slot());
+        assert!(matches!(res, Err(Error::ConfigTableFull)));
+        assert_eq!(sent(&module).len(), usize::from(MAX_STATION_CONFIGS));
+
+        module.respond_once("AT+UWSC=1,2", "+UWSC:1,2,\"\"");
+        module.clear_sent();
+        let res = harness.serve(&mut module, control.free_config_slot());
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(sent(&module), [&b"AT+UWSC=0,2\r\n"[..], b"AT+UWSC=1,2\r\n"]);
+    }
+
+    #[test]
+    fn station_config_reset() {
+        let mut module = MockUbloxModule::new();
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        harness
+            .serve(&mut module, async {
+                control.reset_station_config(1).await?;
+                control.persist_station_config(1).await
+            })
+            .unwrap();
+        assert_eq!(
+            sent(&module),
+            [&b"AT+UWSCA=1,0\r\n"[..], b"AT+UWSCA=1,1\r\n"]
+        );
+    }
+
+    #[test]
+    fn ping_held_back_by_resolve() {
+        let mut module = MockUbloxModule::new();
+        module.inject_urc_after(
+            "AT+UPING",
+            "+UUPING:1,32,\"example.com\",93.184.216.34,52,20",
+        );
+
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        // The module is pinging a host to resolve its name
+        ch.set_resolve_ping(true);
+        let summary = harness.serve(&mut module, async {
+            let mut ping = core::pin::pin!(control.ping("example.com", 1));
+            assert!(embassy_futures::poll_once(ping.as_mut()).is_pending());
+            assert!(harness.requests.is_empty());
+
+            ch.set_resolve_ping(false);
+            ping.await
+        });
+        assert_eq!(summary.unwrap().received, 1);
+        assert_eq!(sent(&module), [&b"AT+UPING=\"example.com\",1\r\n"[..]]);
+    }
+
+    /// A module reporting `channels` as its channel list, and a network on
+    /// channel 36.
+    fn channel_list_module(channels: &str) -> MockUbloxModule {
+        let mut module = MockUbloxModule::new();
+        module.respond("AT+UWCL?", &format!("+UWCL:{}", channels));
+        module.add_scan_result("+UWSCAN:D4CA6DF5F2F1,1,\"office\",36,-52,18,8,8");
+        module
+    }
+
+    #[test]
+    fn scan_channels_restores_previous_list() {
+        let mut module = channel_list_module("1,6,11");
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        let res = harness.serve(&mut module, control.scan_channels::<4>(&[36], None));
+        assert_eq!(res.unwrap().len(), 1);
+        assert_eq!(
+            sent(&module),
+            [
+                &b"AT+UWCL?\r\n"[..],
+                b"AT+UWCL=36\r\n",
+                b"AT+UWSCAN\r\n",
+                b"AT+UWCL=1,6,11\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_channels_restore_failed() {
+        let mut module = channel_list_module("1,6,11");
+        module.fail_once("AT+UWCL=1,6,11", atat::Error::Error);
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        harness.serve(&mut module, async {
+            // The scan result is returned regardless
+            let res = control.scan_channels::<4>(&[36], None).await;
+            assert_eq!(res.unwrap().len(), 1);
+
+            // The next scan restores the list first
+            assert!(control.scan::<4>().await.is_ok());
+        });
+        assert_eq!(
+            sent(&module),
+            [
+                &b"AT+UWCL?\r\n"[..],
+                b"AT+UWCL=36\r\n",
+                b"AT+UWSCAN\r\n",
+                b"AT+UWCL=1,6,11\r\n",
+                b"AT+UWCL=1,6,11\r\n",
+                b"AT+UWSCAN\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_channels_dropped() {
+        // The default list of the module, too long to be written back
+        let mut module = channel_list_module("1,2,3,4,5,6,7,8,9,10,11,36,40,44,48");
+        // Never answered
+        module.fail_once("AT+UWSCAN", atat::Error::Timeout);
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+
+        harness.serve(&mut module, async {
+            let scan = control.scan_channels::<4>(&[36], None);
+            let dropped = async {
+                for _ in 0..50 {
+                    embassy_futures::yield_now().await;
+                }
+            };
+            assert!(matches!(select(scan, dropped).await, Either::Second(())));
+
+            assert!(control.scan::<4>().await.is_ok());
+        });
+        assert_eq!(
+            sent(&module),
+            [
+                &b"AT+UWCL?\r\n"[..],
+                b"AT+UWCL=36\r\n",
+                b"AT+UWSCAN\r\n",
+                b"AT+UWCL\r\n",
+                b"AT+UWSCAN\r\n",
+            ]
+        );
+    }
 }
@@ -0,0 +1,314 @@
+//! Handing a running module over across a reboot of the host.
+//!
+//! Before rebooting, e.g. to apply a firmware update of the host,
+//! [`UbloxStack::detach`](super::ublox_stack::UbloxStack::detach) persists a
+//! [`DetachedState`] into a buffer of the application, while the module keeps
+//! its link and peers. After the reboot,
+//! [`Runner::attach`](super::Runner::attach) adopts the module as it is,
+//! without resetting it. The peers that survived are reported in an
+//! [`AttachReport`], and can be adopted by new sockets.
+use embedded_nal_async::SocketAddr;
+use heapless::Vec;
+use no_std_net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use ublox_sockets::{ChannelId, PeerHandle};
+
+use crate::command::data_mode::types::IPProtocol;
+use crate::command::edm::types::EdmCapabilities;
+use crate::error::Error;
+
+/// Maximum number of peers kept in a [`DetachedState`].
+pub const MAX_DETACHED_PEERS: usize = 8;
+
+/// Version of the encoding of [`DetachedState`], bumped on every change.
+const FORMAT_VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 6;
+const MAX_PEER_LEN: usize = 22;
+const NONE: u8 = 0xFF;
+
+/// A peer of the module, as known to the driver when detaching.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DetachedPeer {
+    pub peer_handle: PeerHandle,
+    /// EDM channel of the peer, if it was connected.
+    pub channel_id: Option<ChannelId>,
+    pub protocol: IPProtocol,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub remote: SocketAddr,
+}
+
+/// Driver state persisted across a reboot of the host, see the
+/// [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DetachedState {
+    /// Station configuration the module was asked to connect with, if any.
+    pub config_id: Option<u8>,
+    /// Capabilities the module advertised on entering EDM, which it does
+    /// not advertise again until it is reset.
+    pub edm_capabilities: EdmCapabilities,
+    pub peers: Vec<DetachedPeer, MAX_DETACHED_PEERS>,
+}
+
+impl DetachedState {
+    /// Maximum encoded length of a state, see [`DetachedState::to_bytes`].
+    pub const MAX_LEN: usize = HEADER_LEN + MAX_DETACHED_PEERS * MAX_PEER_LEN;
+
+    /// Encode the state into `buf`, returning the number of bytes written.
+    ///
+    /// Fails with [`Error::Overflow`] if `buf` is too small, which never
+    /// happens for buffers of at least [`DetachedState::MAX_LEN`] bytes.
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut w = Writer { buf, pos: 0 };
+
+        w.put(&[FORMAT_VERSION, self.config_id.unwrap_or(NONE)])?;
+        w.put(&[self.edm_capabilities.version])?;
+        w.put(&self.edm_capabilities.max_payload.to_le_bytes())?;
+        w.put(&[self.peers.len() as u8])?;
+
+        for peer in &self.peers {
+            let protocol = match peer.protocol {
+                IPProtocol::TCP => 0,
+                IPProtocol::UDP => 1,
+            };
+            w.put(&[
+                peer.peer_handle.0,
+                peer.channel_id.map_or(NONE, |c| c.0),
+                protocol,
+            ])?;
+            match peer.remote.ip() {
+                IpAddr::V4(ip) => {
+                    w.put(&[4])?;
+                    w.put(&ip.octets())?;
+                }
+                IpAddr::V6(ip) => {
+                    w.put(&[6])?;
+                    w.put(&ip.octets())?;
+                }
+            }
+            w.put(&peer.remote.port().to_le_bytes())?;
+        }
+
+        Ok(w.pos)
+    }
+
+    /// Decode a state encoded by [`DetachedState::to_bytes`].
+    ///
+    /// Fails with [`Error::InvalidDetachedState`] on truncated or corrupted
+    /// input, or input encoded by an incompatible version of the driver.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader { buf };
+
+        let [version, config_id, edm_version, lo, hi, peer_count] = r.take()?;
+        if version != FORMAT_VERSION {
+            return Err(Error::InvalidDetachedState);
+        }
+
+        let mut peers = Vec::new();
+        for _ in 0..peer_count {
+            let [peer_handle, channel_id, protocol, ip_version] = r.take()?;
+            let protocol = match protocol {
+                0 => IPProtocol::TCP,
+                1 => IPProtocol::UDP,
+                _ => return Err(Error::InvalidDetachedState),
+            };
+            let ip = match ip_version {
+                4 => IpAddr::V4(Ipv4Addr::from(r.take::<4>()?)),
+                6 => IpAddr::V6(Ipv6Addr::from(r.take::<16>()?)),
+                _ => return Err(Error::InvalidDetachedState),
+            };
+            let port = u16::from_le_bytes(r.take()?);
+
+            peers
+                .push(DetachedPeer {
+                    peer_handle: PeerHandle(peer_handle),
+                    channel_id: (channel_id != NONE).then_some(ChannelId(channel_id)),
+                    protocol,
+                    remote: SocketAddr::new(ip, port),
+                })
+                .map_err(|_| Error::InvalidDetachedState)?;
+        }
+
+        if !r.buf.is_empty() {
+            return Err(Error::InvalidDetachedState);
+        }
+
+        Ok(Self {
+            config_id: (config_id != NONE).then_some(config_id),
+            edm_capabilities: EdmCapabilities {
+                version: edm_version,
+                max_payload: u16::from_le_bytes([lo, hi]),
+            },
+            peers,
+        })
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl Writer<'_> {
+    fn put(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.pos + data.len();
+        self.buf
+            .get_mut(self.pos..end)
+            .ok_or(Error::Overflow)?
+            .copy_from_slice(data);
+        self.pos = end;
+        Ok(())
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        if self.buf.len() < N {
+            return Err(Error::InvalidDetachedState);
+        }
+        let (head, tail) = self.buf.split_at(N);
+        self.buf = tail;
+        Ok(head.try_into().unwrap())
+    }
+}
+
+/// Reason a peer of the [`DetachedState`] is reported closed on attach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AttachCloseReason {
+    /// The peer closed while the host was away, and is no longer listed by
+    /// the module.
+    ClosedWhileDetached,
+    /// The module could not be adopted, and was reset along with all of its
+    /// peers.
+    ModuleReset,
+    /// The peers of the module could not be listed, so the peer was closed
+    /// rather than adopted.
+    PeerListFailed,
+}
+
+/// Status of a peer of the [`DetachedState`] after attaching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PeerStatus {
+    /// The peer is still connected, and can be adopted by a socket.
+    Alive,
+    Closed(AttachCloseReason),
+}
+
+/// Outcome of attaching to a module, for every peer of the
+/// [`DetachedState`].
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AttachReport {
+    pub peers: Vec<(DetachedPeer, PeerStatus), MAX_DETACHED_PEERS>,
+}
+
+impl AttachReport {
+    /// Status of the peer with the given handle, if it was a peer of the
+    /// [`DetachedState`].
+    pub fn status(&self, peer_handle: PeerHandle) -> Option<PeerStatus> {
+        self.peers
+            .iter()
+            .find(|(peer, _)| peer.peer_handle == peer_handle)
+            .map(|(_, status)| *status)
+    }
+}
+
+/// An attach handed from the runner to the stack, to reconcile the peers of
+/// the previous state with those of the module.
+#[derive(Debug, Clone)]
+pub(crate) struct PendingAttach {
+    pub previous: DetachedState,
+    /// The module was reset, as it could not be adopted.
+    pub module_reset: bool,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn state() -> DetachedState {
+        let mut peers = Vec::new();
+        peers
+            .push(DetachedPeer {
+                peer_handle: PeerHandle(1),
+                channel_id: Some(ChannelId(2)),
+                protocol: IPProtocol::TCP,
+                remote: "192.168.0.2:5000".parse().unwrap(),
+            })
+            .unwrap();
+        peers
+            .push(DetachedPeer {
+                peer_handle: PeerHandle(3),
+                channel_id: None,
+                protocol: IPProtocol::UDP,
+                remote: "[fe80::1]:123".parse().unwrap(),
+            })
+            .unwrap();
+
+        DetachedState {
+            config_id: Some(0),
+            edm_capabilities: EdmCapabilities {
+                version: 1,
+                max_payload: 1024,
+            },
+            peers,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let state = state();
+        let mut buf = [0u8; DetachedState::MAX_LEN];
+        let len = state.to_bytes(&mut buf).unwrap();
+        assert_eq!(DetachedState::from_bytes(&buf[..len]).unwrap(), state);
+
+        let empty = DetachedState {
+            config_id: None,
+            edm_capabilities: EdmCapabilities::DEFAULT,
+            peers: Vec::new(),
+        };
+        let len = empty.to_bytes(&mut buf).unwrap();
+        assert_eq!(len, HEADER_LEN);
+        assert_eq!(DetachedState::from_bytes(&buf[..len]).unwrap(), empty);
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let mut buf = [0u8; 16];
+        assert!(matches!(state().to_bytes(&mut buf), Err(Error::Overflow)));
+    }
+
+    #[test]
+    fn invalid() {
+        let mut buf = [0u8; DetachedState::MAX_LEN];
+        let len = state().to_bytes(&mut buf).unwrap();
+
+        // Truncated
+        assert!(matches!(
+            DetachedState::from_bytes(&buf[..len - 1]),
+            Err(Error::InvalidDetachedState)
+        ));
+        // Trailing garbage
+        assert!(matches!(
+            DetachedState::from_bytes(&buf[..len + 1]),
+            Err(Error::InvalidDetachedState)
+        ));
+        // Other version
+        buf[0] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            DetachedState::from_bytes(&buf[..len]),
+            Err(Error::InvalidDetachedState)
+        ));
+        assert!(matches!(
+            DetachedState::from_bytes(&[]),
+            Err(Error::InvalidDetachedState)
+        ));
+    }
+}
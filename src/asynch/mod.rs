@@ -1,8 +1,14 @@
+#[cfg(feature = "ap")]
+pub mod access_point;
 #[cfg(feature = "ppp")]
 mod at_udp_socket;
 pub mod control;
+#[cfg(feature = "internal-network-stack")]
+pub mod detach;
 pub mod network;
+pub mod ping;
 mod resources;
+mod roaming;
 pub mod runner;
 #[cfg(feature = "internal-network-stack")]
 pub mod ublox_stack;
@@ -10,8 +16,8 @@ pub mod ublox_stack;
 pub(crate) mod state;
 
 pub use resources::Resources;
-pub use runner::Runner;
-pub use state::LinkState;
+pub use runner::{min_urc_capacity, Runner};
+pub use state::{LinkEvent, LinkState, UrcStats, LINK_HISTORY_LEN};
 
 #[cfg(feature = "edm")]
 pub type UbloxUrc = crate::command::edm::urc::EdmEvent;
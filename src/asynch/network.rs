@@ -1,31 +1,47 @@
 use atat::{asynch::AtatClient, UrcChannel, UrcSubscription};
-use core::str::FromStr as _;
 use embassy_time::{with_timeout, Duration, Timer};
 use embedded_hal::digital::OutputPin as _;
-use no_std_net::{Ipv4Addr, Ipv6Addr};
 
 use crate::{
     command::{
         network::{
-            responses::{APStatusResponse, NetworkStatusResponse},
-            types::{APStatusParameter, InterfaceType, NetworkStatus, NetworkStatusParameter},
+            responses::NetworkStatusResponse,
+            types::InterfaceType,
             urc::{NetworkDown, NetworkUp},
-            GetAPStatus, GetNetworkStatus,
+            GetNetworkStatus,
         },
         system::{RebootDCE, StoreCurrentConfig},
         wifi::{
-            types::{AccessPointStatus, DisconnectReason},
+            types::{DisconnectReason, StatusId, WifiStatus, WifiStatusVal},
             urc::{WifiLinkConnected, WifiLinkDisconnected},
+            GetWifiStatus,
         },
         Urc,
     },
-    connection::WiFiState,
+    connection::{NetworkStatusSummary, WiFiState},
     error::Error,
+    init_script::{self, InitReport, InitStage},
     network::WifiNetwork,
-    WifiConfig,
+    restart_capture, WifiConfig,
 };
 
-use super::{runner::URC_SUBSCRIBERS, state, UbloxUrc};
+#[cfg(feature = "ap")]
+use crate::command::{
+    network::{responses::APStatusResponse, types::APStatusParameter, GetAPStatus},
+    wifi::types::AccessPointStatus,
+};
+#[cfg(feature = "ipv6")]
+use crate::{
+    command::network::types::{NetworkStatus, NetworkStatusParameter},
+    connection::parse_ipv6,
+};
+
+#[cfg(feature = "ap")]
+use super::access_point;
+use super::{
+    runner::{next_urc, URC_SUBSCRIBERS},
+    state, UbloxUrc,
+};
 
 pub(crate) struct NetDevice<'a, 'b, C, A, const URC_CAPACITY: usize> {
     ch: &'b state::Runner<'a>,
@@ -55,13 +71,14 @@ where
 
     pub async fn run(&mut self) -> Result<(), Error> {
         loop {
-            match embassy_futures::select::select(
-                self.urc_subscription.next_message_pure(),
+            match embassy_futures::select::select3(
+                next_urc(self.ch, &mut self.urc_subscription),
                 self.ch.wait_for_wifi_state_change(),
+                self.ch.wait_resync_pending(),
             )
             .await
             {
-                embassy_futures::select::Either::First(event) => {
+                embassy_futures::select::Either3::First(event) => {
                     #[cfg(feature = "edm")]
                     let Some(event) = event.extract_urc() else {
                         continue;
@@ -69,6 +86,12 @@ where
 
                     self.handle_urc(event).await?;
                 }
+                embassy_futures::select::Either3::Third(_) => {
+                    if let Err(e) = self.resync().await {
+                        warn!("Failed to resync connection state: {:?}", e);
+                    }
+                    self.ch.resync_done();
+                }
                 _ => {}
             }
 
@@ -82,6 +105,16 @@ where
         match event {
             Urc::StartUp => {
                 error!("AT startup event?! Device restarted unintentionally!");
+
+                if let Some(budget) = C::RESTART_CAPTURE_BUDGET {
+                    let capture = restart_capture::capture(&mut self.at_client, budget).await;
+                    self.ch.set_last_restart(capture);
+                }
+
+                // The module lost its configuration, and is back in AT mode.
+                // Returning hands it back to the runner for initialization.
+                self.ch.mark_uninitialized();
+                return Err(Error::Uninitialized);
             }
             Urc::WifiLinkConnected(WifiLinkConnected {
                 connection_id: _,
@@ -97,8 +130,14 @@ where
             }
             Urc::WifiLinkDisconnected(WifiLinkDisconnected { reason, .. }) => {
                 info!("Wifi link disconnected");
+                self.ch.set_disconnect_reason(reason);
+                let reassociating = self.ch.is_roaming();
                 self.ch.update_connection_with(|con| {
                     con.wifi_state = match reason {
+                        // Deactivated for re-association, to be activated again
+                        DisconnectReason::NetworkDisabled if reassociating => {
+                            WiFiState::NotConnected
+                        }
                         DisconnectReason::NetworkDisabled => {
                             con.network.take();
                             warn!("Wifi network disabled!");
@@ -112,22 +151,18 @@ where
                     }
                 })
             }
-            Urc::WifiAPUp(_) => self.ch.update_connection_with(|con| {
-                con.wifi_state = WiFiState::Connected;
-                con.network.replace(WifiNetwork::new_ap());
-            }),
-            Urc::WifiAPDown(_) => self.ch.update_connection_with(|con| {
-                con.network.take();
-                con.wifi_state = WiFiState::Inactive;
-            }),
-            Urc::WifiAPStationConnected(_) => warn!("Not yet implemented [WifiAPStationConnected]"),
-            Urc::WifiAPStationDisconnected(_) => {
-                warn!("Not yet implemented [WifiAPStationDisconnected]")
-            }
+            #[cfg(feature = "ap")]
+            event @ (Urc::WifiAPUp(_)
+            | Urc::WifiAPDown(_)
+            | Urc::WifiAPStationConnected(_)
+            | Urc::WifiAPStationDisconnected(_)) => self
+                .ch
+                .update_connection_with(|con| access_point::on_urc(con, &event)),
             Urc::EthernetLinkUp(_) => warn!("Not yet implemented [EthernetLinkUp]"),
             Urc::EthernetLinkDown(_) => warn!("Not yet implemented [EthernetLinkDown]"),
             Urc::NetworkUp(NetworkUp { interface_id }) => {
                 if interface_id > 10 {
+                    #[cfg(feature = "ap")]
                     self.ap_status_callback().await?;
                 } else {
                     self.network_status_callback(interface_id).await?;
@@ -135,18 +170,81 @@ where
             }
             Urc::NetworkDown(NetworkDown { interface_id }) => {
                 if interface_id > 10 {
+                    #[cfg(feature = "ap")]
                     self.ap_status_callback().await?;
                 } else {
                     self.network_status_callback(interface_id).await?;
                 }
             }
-            Urc::NetworkError(_) => warn!("Not yet implemented [NetworkError]"),
+            Urc::NetworkError(error) => {
+                error!(
+                    "Network error on interface {}: {:?}",
+                    error.interface_id, error.error
+                );
+                let station = error.interface_id <= 10;
+                self.ch.set_network_error(error);
+
+                if station {
+                    // The IP configuration of the station is no longer usable
+                    self.ch.update_connection_with(|con| {
+                        con.ipv4_up = false;
+
+                        #[cfg(feature = "ipv6")]
+                        {
+                            con.ipv6_up = false;
+                        }
+                    });
+                }
+            }
             _ => {}
         }
 
         Ok(())
     }
 
+    /// Re-synchronize the station connection state with the module, after a
+    /// suspension during which URCs were not processed, or after attaching
+    /// to a module that kept running on its own.
+    async fn resync(&mut self) -> Result<(), Error> {
+        let status = self
+            .at_client
+            .send_retry(&GetWifiStatus {
+                status_id: StatusId::Status,
+            })
+            .await?;
+        let connected = matches!(
+            status.status_id,
+            WifiStatus::Status(WifiStatusVal::Connected)
+        );
+        debug!("Resync, Wi-Fi connected: {:?}", connected);
+
+        let mut station = true;
+        self.ch.update_connection_with(|con| {
+            if con.network.is_some() && con.is_access_point() {
+                station = false;
+            } else if connected {
+                con.wifi_state = WiFiState::Connected;
+            } else {
+                if con.wifi_state == WiFiState::Connected {
+                    con.wifi_state = WiFiState::NotConnected;
+                }
+                con.ipv6_link_local_up = false;
+                con.ipv4_up = false;
+
+                #[cfg(feature = "ipv6")]
+                {
+                    con.ipv6_up = false;
+                }
+            }
+        });
+
+        if station && connected {
+            self.network_status_callback(0).await?;
+        }
+
+        Ok(())
+    }
+
     async fn network_status_callback(&mut self, interface_id: u8) -> Result<(), Error> {
         // Normally a check for this interface type being
         // `InterfaceType::WifiStation`` should be made but there is a bug in
@@ -155,44 +253,15 @@ where
         // the wifi station has been started. So we assume that this type is
         // also ok.
         info!("Entered network_status_callback");
-        let NetworkStatusResponse {
-            status:
-                NetworkStatus::InterfaceType(InterfaceType::WifiStation | InterfaceType::Unknown),
-            ..
-        } = self
-            .at_client
-            .send_retry(&GetNetworkStatus {
-                interface_id,
-                status: NetworkStatusParameter::InterfaceType,
-            })
-            .await?
-        else {
-            return Err(Error::Network);
-        };
-
-        let NetworkStatusResponse {
-            status: NetworkStatus::IPv4Address(ipv4),
-            ..
-        } = self
-            .at_client
-            .send_retry(&GetNetworkStatus {
-                interface_id,
-                status: NetworkStatusParameter::IPv4Address,
-            })
-            .await?
-        else {
+        let status = network_status(&mut self.at_client, interface_id).await?;
+        if !matches!(
+            status.interface_type,
+            InterfaceType::WifiStation | InterfaceType::Unknown
+        ) {
             return Err(Error::Network);
-        };
-        info!(
-            "Network status callback ipv4: {:?}",
-            core::str::from_utf8(&ipv4).ok()
-        );
+        }
 
-        let ipv4_up = core::str::from_utf8(ipv4.as_slice())
-            .ok()
-            .and_then(|s| Ipv4Addr::from_str(s).ok())
-            .map(|ip| !ip.is_unspecified())
-            .unwrap_or_default();
+        let ipv4_up = status.ipv4.is_some();
         info!("Network status callback ipv4: {:?}", ipv4_up);
 
         #[cfg(feature = "ipv6")]
@@ -211,37 +280,10 @@ where
                 return Err(Error::Network);
             };
 
-            core::str::from_utf8(ipv6.as_slice())
-                .ok()
-                .and_then(|s| Ipv6Addr::from_str(s).ok())
-                .map(|ip| !ip.is_unspecified())
-                .unwrap_or_default()
-        };
-
-        let NetworkStatusResponse {
-            status: NetworkStatus::IPv6LinkLocalAddress(ipv6_link_local),
-            ..
-        } = self
-            .at_client
-            .send_retry(&GetNetworkStatus {
-                interface_id,
-                status: NetworkStatusParameter::IPv6LinkLocalAddress,
-            })
-            .await?
-        else {
-            return Err(Error::Network);
+            parse_ipv6(&ipv6).is_some()
         };
-        info!(
-            "Network status callback ipv6: {:?}",
-            core::str::from_utf8(&ipv6_link_local).ok()
-        );
-
-        let ipv6_link_local_up = core::str::from_utf8(ipv6_link_local.as_slice())
-            .ok()
-            .and_then(|s| Ipv6Addr::from_str(s).ok())
-            .map(|ip| !ip.is_unspecified())
-            .unwrap_or_default();
 
+        let ipv6_link_local_up = status.ipv6_link_local.is_some();
         info!("Network status callback ipv6: {:?}", ipv6_link_local_up);
 
         // Use `ipv4_addr` & `ipv6_addr` to determine link state
@@ -258,6 +300,7 @@ where
         Ok(())
     }
 
+    #[cfg(feature = "ap")]
     async fn ap_status_callback(&mut self) -> Result<(), Error> {
         let APStatusResponse {
             status_val: AccessPointStatus::Status(ap_status),
@@ -291,7 +334,7 @@ where
     async fn wait_startup(&mut self, timeout: Duration) -> Result<(), Error> {
         let fut = async {
             loop {
-                let event = self.urc_subscription.next_message_pure().await;
+                let event = next_urc(self.ch, &mut self.urc_subscription).await;
 
                 #[cfg(feature = "edm")]
                 let Some(event) = event.extract_urc() else {
@@ -308,24 +351,21 @@ where
     }
 
     pub async fn reset(&mut self) -> Result<(), Error> {
-        if let Some(reset_pin) = self.config.reset_pin() {
+        let startup_timeout = if let Some(reset_pin) = self.config.reset_pin() {
             warn!("Reset pin found! Hard resetting Ublox Short Range");
             reset_pin.set_low().ok();
             Timer::after(Duration::from_millis(100)).await;
             reset_pin.set_high().ok();
+            C::TIMEOUTS.startup
         } else {
             warn!("No reset pin found! Soft resetting Ublox Short Range");
             self.at_client.send_retry(&RebootDCE).await?;
-        }
+            C::TIMEOUTS.reboot
+        };
 
         self.ch.mark_uninitialized();
 
-        self.wait_startup(Duration::from_secs(5)).await?;
-
-        #[cfg(feature = "edm")]
-        self.enter_edm(Duration::from_secs(4)).await?;
-
-        Ok(())
+        self.boot(startup_timeout).await
     }
 
     #[allow(dead_code)]
@@ -339,13 +379,34 @@ where
 
         self.ch.mark_uninitialized();
 
-        self.wait_startup(Duration::from_secs(5)).await?;
+        self.boot(C::TIMEOUTS.reboot).await
+    }
+
+    /// Bring up a module that was just reset, running the init script of the
+    /// configuration around the switch to EDM.
+    async fn boot(&mut self, startup_timeout: Duration) -> Result<(), Error> {
+        self.wait_startup(startup_timeout).await?;
+        info!("Module started");
+
+        self.ch
+            .set_init_report(InitReport::start(self.config.init_script()));
+        self.run_init_script(InitStage::BeforeEdm).await?;
 
-        info!("Module started again");
         #[cfg(feature = "edm")]
-        self.enter_edm(Duration::from_secs(4)).await?;
+        self.enter_edm(C::TIMEOUTS.edm_entry).await?;
 
-        Ok(())
+        self.run_init_script(InitStage::AfterEdm).await
+    }
+
+    async fn run_init_script(&mut self, stage: InitStage) -> Result<(), Error> {
+        let ch = self.ch;
+        init_script::run(
+            &mut self.at_client,
+            self.config.init_script(),
+            stage,
+            |i, result| ch.set_init_result(i, result),
+        )
+        .await
     }
 
     #[cfg(feature = "edm")]
@@ -356,18 +417,42 @@ where
         let fut = async {
             loop {
                 // Ignore AT results until we are successful in EDM mode
-                if let Ok(_) = self
+                let capabilities = if let Ok(capabilities) = self
                     .at_client
                     .send_retry(&crate::command::edm::SwitchToEdmCommand)
                     .await
                 {
-                    // After executing the data mode command or the extended data
-                    // mode command, a delay of 50 ms is required before start of
-                    // data transmission.
-                    Timer::after(Duration::from_millis(50)).await;
-                    break;
-                }
-                Timer::after(Duration::from_millis(10)).await;
+                    debug!(
+                        "EDM version {}, max payload {}",
+                        capabilities.version, capabilities.max_payload
+                    );
+                    capabilities
+                } else if self
+                    .at_client
+                    .send(&crate::command::edm::EdmAtCmdWrapper(crate::command::AT))
+                    .await
+                    .is_ok()
+                {
+                    // The start event may have been lost, while the module did
+                    // switch. In that case resending the switch command only
+                    // times out, so check whether the module already talks EDM.
+                    // The capabilities were lost along with the start event.
+                    // Those of an earlier switch still hold for the firmware.
+                    debug!("Module already in EDM mode");
+                    self.ch
+                        .edm_capabilities()
+                        .unwrap_or(crate::command::edm::types::EdmCapabilities::DEFAULT)
+                } else {
+                    Timer::after(Duration::from_millis(10)).await;
+                    continue;
+                };
+
+                self.ch.set_edm_capabilities(capabilities);
+                // After executing the data mode command or the extended data
+                // mode command, a delay of 50 ms is required before start of
+                // data transmission.
+                Timer::after(C::TIMEOUTS.edm_settle).await;
+                break;
             }
         };
 
@@ -378,3 +463,94 @@ where
         Ok(())
     }
 }
+
+/// Query the IP configuration of the interface `interface_id`.
+pub(crate) async fn network_status<A: AtatClient>(
+    at_client: &mut A,
+    interface_id: u8,
+) -> Result<NetworkStatusSummary, Error> {
+    let mut summary = NetworkStatusSummary::new();
+    for status in NetworkStatusSummary::QUERIES {
+        let NetworkStatusResponse { status, .. } = at_client
+            .send_retry(&GetNetworkStatus {
+                interface_id,
+                status,
+            })
+            .await?;
+        summary.update(status)?;
+    }
+    Ok(summary)
+}
+
+#[cfg(all(test, not(feature = "edm")))]
+mod test {
+    use super::*;
+    use crate::test_util::{Harness, MockUbloxModule};
+    use state::LinkState;
+
+    struct NoPin;
+
+    impl embedded_hal::digital::ErrorType for NoPin {
+        type Error = core::convert::Infallible;
+    }
+
+    impl embedded_hal::digital::OutputPin for NoPin {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    struct Config;
+
+    impl<'a> WifiConfig<'a> for Config {
+        type ResetPin = NoPin;
+
+        const RESTART_CAPTURE_BUDGET: Option<Duration> = Some(Duration::from_secs(1));
+
+        #[cfg(feature = "ppp")]
+        const PPP_CONFIG: embassy_net_ppp::Config<'a> = embassy_net_ppp::Config {
+            username: b"",
+            password: b"",
+        };
+    }
+
+    #[test]
+    fn restart_reinitializes() {
+        let harness = Harness::new();
+        let mut module = MockUbloxModule::new();
+        module.respond("AT+CGMR", "8.0.0-014");
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+        let client = harness.client();
+        let mut config = Config;
+
+        let mut device = NetDevice::new(&ch, &mut config, &client, &harness.urc_channel);
+        module.inject_urc("+STARTUP");
+        let result = harness.serve(&mut module, device.run());
+
+        // Handed back to the runner, to initialize the module again
+        assert!(matches!(result, Err(Error::Uninitialized)));
+        assert!(ch.link_state(None) == LinkState::Uninitialized);
+
+        let capture = ch.last_restart().unwrap();
+        assert!(!capture.truncated);
+        assert!(capture.data.ends_with(b"AT+CGMR\r\n8.0.0-014\r\n"));
+
+        // Clients wait for the initialization
+        module.clear_sent();
+        let mut hostname = core::pin::pin!(control.set_hostname("ublox"));
+        assert!(embassy_futures::poll_once(hostname.as_mut()).is_pending());
+
+        ch.mark_initialized();
+        harness.serve(&mut module, hostname).unwrap();
+        assert_eq!(
+            module.sent_commands().collect::<std::vec::Vec<_>>(),
+            [&b"AT+UNHN=\"ublox\"\r\n"[..]]
+        );
+    }
+}
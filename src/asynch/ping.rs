@@ -0,0 +1,213 @@
+//! Pinging hosts from the module, see
+//! [`Control::ping`](super::control::Control::ping).
+//!
+//! The module reports the outcome of every echo request of a `+UPING` with a
+//! `+UUPING` URC, or fails the whole command with a `+UUPINGER` URC. A
+//! [`PingSession`] collects these URCs into a [`PingSummary`].
+use embassy_time::Duration;
+
+use crate::command::ping::types::PingError;
+use crate::command::ping::urc::{PingErrorResponse, PingResponse};
+use crate::command::Urc;
+use crate::error::Error;
+
+use super::state;
+
+/// Statistics of a completed ping, with at least one reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PingSummary {
+    /// Number of echo requests sent.
+    pub sent: u32,
+    /// Number of echo replies received.
+    pub received: u32,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub min_rtt: Duration,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub avg_rtt: Duration,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub max_rtt: Duration,
+    /// TTL of the last reply.
+    pub ttl: u8,
+    /// Payload size of the echo packets, in bytes.
+    pub size: u16,
+}
+
+/// A ping in progress, fed with the URCs of the module.
+///
+/// `+UUPINGER` carries no hostname, so any ping error is attributed to the
+/// session, with the pings resolving names for the network stack held back
+/// meanwhile. Replies for other hosts are ignored.
+pub(crate) struct PingSession<'a> {
+    hostname: &'a str,
+    count: u32,
+    sent: u32,
+    received: u32,
+    /// Round trip times of the replies, in milliseconds.
+    min_rtt: u32,
+    max_rtt: u32,
+    total_rtt: u64,
+    ttl: u8,
+    size: u16,
+    error: Option<PingError>,
+}
+
+impl<'a> PingSession<'a> {
+    pub(crate) fn new(hostname: &'a str, count: u32) -> Self {
+        Self {
+            hostname,
+            count,
+            sent: 0,
+            received: 0,
+            min_rtt: u32::MAX,
+            max_rtt: 0,
+            total_rtt: 0,
+            ttl: 0,
+            size: 0,
+            error: None,
+        }
+    }
+
+    /// Account for `urc`, returning `true` once the ping is done.
+    pub(crate) fn on_urc(&mut self, urc: &Urc) -> bool {
+        match urc {
+            Urc::PingResponse(reply) if reply.hostname.eq_ignore_ascii_case(self.hostname) => {
+                self.on_reply(reply)
+            }
+            Urc::PingErrorResponse(PingErrorResponse { error }) => {
+                self.error = Some(*error);
+            }
+            _ => {}
+        }
+
+        self.is_done()
+    }
+
+    fn on_reply(&mut self, reply: &PingResponse) {
+        self.sent += 1;
+        self.size = reply.ping_size;
+
+        // According to AT manual, rtt = -1 means the echo request timed out
+        if let Ok(rtt) = u32::try_from(reply.rtt) {
+            self.received += 1;
+            self.min_rtt = self.min_rtt.min(rtt);
+            self.max_rtt = self.max_rtt.max(rtt);
+            self.total_rtt += u64::from(rtt);
+            self.ttl = reply.ttl;
+        }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.error.is_some() || self.sent >= self.count
+    }
+
+    /// Summary of the replies received, or the reason none were received.
+    pub(crate) fn finish(self) -> Result<PingSummary, Error> {
+        if self.received == 0 {
+            return Err(Error::Dns(self.error.unwrap_or(PingError::Timeout)));
+        }
+
+        Ok(PingSummary {
+            sent: self.sent,
+            received: self.received,
+            min_rtt: Duration::from_millis(self.min_rtt.into()),
+            avg_rtt: Duration::from_millis(self.total_rtt / u64::from(self.received)),
+            max_rtt: Duration::from_millis(self.max_rtt.into()),
+            ttl: self.ttl,
+            size: self.size,
+        })
+    }
+}
+
+/// Marks a ping in progress, until dropped.
+pub(crate) struct PingGuard<'a, 'b> {
+    state_ch: &'b state::Runner<'a>,
+}
+
+impl<'a, 'b> PingGuard<'a, 'b> {
+    pub(crate) fn new(state_ch: &'b state::Runner<'a>) -> Self {
+        Self { state_ch }
+    }
+}
+
+impl<'a, 'b> Drop for PingGuard<'a, 'b> {
+    fn drop(&mut self) {
+        self.state_ch.end_ping();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use atat::AtatUrc;
+
+    fn feed(session: &mut PingSession, urc: &[u8]) -> bool {
+        session.on_urc(&Urc::parse(urc).unwrap())
+    }
+
+    #[test]
+    fn summary() {
+        let mut session = PingSession::new("example.com", 3);
+
+        assert!(!feed(
+            &mut session,
+            b"+UUPING:1,32,\"example.com\",93.184.216.34,52,20"
+        ));
+        // A reply for a concurrent resolve of another host
+        assert!(!feed(
+            &mut session,
+            b"+UUPING:1,32,\"other.com\",10.0.0.1,64,1"
+        ));
+        assert!(!feed(
+            &mut session,
+            b"+UUPING:2,32,\"example.com\",93.184.216.34,52,-1"
+        ));
+        assert!(feed(
+            &mut session,
+            b"+UUPING:3,32,\"example.com\",93.184.216.34,50,41"
+        ));
+
+        assert_eq!(
+            session.finish().unwrap(),
+            PingSummary {
+                sent: 3,
+                received: 2,
+                min_rtt: Duration::from_millis(20),
+                avg_rtt: Duration::from_millis(30),
+                max_rtt: Duration::from_millis(41),
+                ttl: 50,
+                size: 32,
+            }
+        );
+    }
+
+    #[test]
+    fn unreachable() {
+        let mut session = PingSession::new("10.0.0.2", 2);
+
+        assert!(!feed(
+            &mut session,
+            b"+UUPING:1,32,\"10.0.0.2\",10.0.0.2,0,-1"
+        ));
+        assert!(feed(
+            &mut session,
+            b"+UUPING:2,32,\"10.0.0.2\",10.0.0.2,0,-1"
+        ));
+
+        assert!(matches!(
+            session.finish(),
+            Err(Error::Dns(PingError::Timeout))
+        ));
+    }
+
+    #[test]
+    fn error() {
+        let mut session = PingSession::new("nonexistent.example.com", 4);
+
+        assert!(feed(&mut session, b"+UUPINGER:8"));
+        assert!(matches!(
+            session.finish(),
+            Err(Error::Dns(PingError::CannotResolveHost))
+        ));
+    }
+}
@@ -1,7 +1,8 @@
 use atat::{ResponseSlot, UrcChannel};
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, mutex::Mutex, pipe::Pipe};
 
 use super::{
+    control::CommandLock,
     runner::{MAX_CMD_LEN, URC_SUBSCRIBERS},
     state, UbloxUrc,
 };
@@ -11,8 +12,12 @@ pub struct Resources<const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize> {
 
     pub(crate) res_slot: ResponseSlot<INGRESS_BUF_SIZE>,
     pub(crate) req_slot: Channel<NoopRawMutex, heapless::Vec<u8, MAX_CMD_LEN>, 1>,
+    pub(crate) cmd_lock: CommandLock,
     pub(crate) urc_channel: UrcChannel<UbloxUrc, URC_CAPACITY, { URC_SUBSCRIBERS }>,
     pub(crate) ingress_buf: [u8; INGRESS_BUF_SIZE],
+
+    pub(crate) raw_rx: Pipe<NoopRawMutex, INGRESS_BUF_SIZE>,
+    pub(crate) raw_tx: Pipe<NoopRawMutex, MAX_CMD_LEN>,
 }
 
 impl<const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize> Default
@@ -32,8 +37,12 @@ impl<const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
 
             res_slot: ResponseSlot::new(),
             req_slot: Channel::new(),
+            cmd_lock: Mutex::new(None),
             urc_channel: UrcChannel::new(),
             ingress_buf: [0; INGRESS_BUF_SIZE],
+
+            raw_rx: Pipe::new(),
+            raw_tx: Pipe::new(),
         }
     }
 }
@@ -0,0 +1,411 @@
+//! Roaming between the access points of a network, see
+//! [`RoamingConfig`](crate::options::RoamingConfig).
+use atat::asynch::AtatClient;
+use embassy_time::{with_timeout, Timer};
+
+use crate::command::wifi::{
+    responses::WifiScanResponse,
+    types::{Bssid, StatusId, WifiStationAction, WifiStatus},
+    ExecWifiStationAction, GetWifiStatus, WifiScan,
+};
+use crate::connection::WiFiState;
+use crate::error::Error;
+use crate::options::RoamingConfig;
+
+use super::control::{ProxyClient, CONFIG_ID};
+use super::state;
+
+/// Signal strength samples of the station connection, deciding when to
+/// roam.
+struct RoamingMonitor {
+    config: RoamingConfig,
+    /// Consecutive samples below the threshold.
+    weak_samples: u8,
+}
+
+impl RoamingMonitor {
+    fn new(config: RoamingConfig) -> Self {
+        Self {
+            config,
+            weak_samples: 0,
+        }
+    }
+
+    /// Forget the weak samples so far.
+    fn reset(&mut self) {
+        self.weak_samples = 0;
+    }
+
+    /// Account for a sample of `rssi`, returning `true` if it completes the
+    /// weak samples to roam on.
+    fn on_sample(&mut self, rssi: i32) -> bool {
+        if rssi >= self.config.rssi_threshold {
+            self.weak_samples = 0;
+            return false;
+        }
+
+        self.weak_samples = self.weak_samples.saturating_add(1);
+        if self.weak_samples < self.config.hysteresis.max(1) {
+            return false;
+        }
+
+        self.weak_samples = 0;
+        true
+    }
+}
+
+/// Roam between the access points of the network the station is joined to,
+/// as configured by `config`. Never returns.
+///
+/// Runs next to the network device, so URCs are still processed while
+/// scanning. Commands are queued behind those of the network device and
+/// [`Control`](super::control::Control).
+pub(crate) async fn run<const INGRESS_BUF_SIZE: usize>(
+    ch: &state::Runner<'_>,
+    config: Option<RoamingConfig>,
+    at_client: &ProxyClient<'_, INGRESS_BUF_SIZE>,
+) -> ! {
+    let Some(config) = config else {
+        loop {
+            core::future::pending::<()>().await;
+        }
+    };
+
+    let mut monitor = RoamingMonitor::new(config);
+    // Access point the module re-associated with, instead of the stronger
+    // one roamed to. Re-associating again would only pick it again.
+    let mut stuck_on = None;
+
+    loop {
+        Timer::after(config.interval).await;
+
+        let Some(bssid) = ch.station_bssid().filter(|_| !ch.is_paused(None)) else {
+            stuck_on = None;
+            monitor.reset();
+            continue;
+        };
+
+        if stuck_on == Some(bssid) {
+            continue;
+        }
+        stuck_on = None;
+
+        let target = match sample(ch, at_client, &mut monitor, bssid).await {
+            Ok(Some(target)) => target,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("Failed to sample signal strength for roaming: {:?}", e);
+                continue;
+            }
+        };
+
+        match roam(ch, at_client, bssid, target).await {
+            Ok(associated) if associated == bssid => stuck_on = Some(bssid),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to roam: {:?}", e),
+        }
+    }
+}
+
+/// Sample the signal strength of the connection to `bssid`, returning the
+/// access point to roam to if the samples call for it and a stronger one is
+/// in range.
+///
+/// The scan for the access points of the network is abandoned if the station
+/// connection changes meanwhile.
+async fn sample<const INGRESS_BUF_SIZE: usize>(
+    ch: &state::Runner<'_>,
+    mut at_client: &ProxyClient<'_, INGRESS_BUF_SIZE>,
+    monitor: &mut RoamingMonitor,
+    bssid: Bssid,
+) -> Result<Option<Bssid>, Error> {
+    let rssi = match at_client
+        .send_retry(&GetWifiStatus {
+            status_id: StatusId::Rssi,
+        })
+        .await?
+        .status_id
+    {
+        // Reported by the module when not connected
+        WifiStatus::Rssi(-32768) => return Err(Error::Network),
+        WifiStatus::Rssi(rssi) => rssi,
+        _ => return Err(Error::AT(atat::Error::InvalidResponse)),
+    };
+
+    if !monitor.on_sample(rssi) {
+        return Ok(None);
+    }
+
+    let WifiStatus::SSID(ssid) = at_client
+        .send_retry(&GetWifiStatus {
+            status_id: StatusId::SSID,
+        })
+        .await?
+        .status_id
+    else {
+        return Err(Error::AT(atat::Error::InvalidResponse));
+    };
+
+    let Some(WifiScanResponse { network_list }) = at_client
+        .send_abortable(
+            &WifiScan { ssid: Some(&ssid) },
+            ch.timeouts().scan,
+            ch.wait_for_wifi_state_change(),
+        )
+        .await?
+    else {
+        debug!("Connection changed while scanning for roaming");
+        return Ok(None);
+    };
+
+    let Some(best) = network_list
+        .iter()
+        .filter(|network| network.bssid != bssid)
+        .max_by_key(|network| network.rssi)
+    else {
+        debug!("No other access point to roam to");
+        return Ok(None);
+    };
+
+    if best.rssi <= rssi {
+        debug!(
+            "No stronger access point to roam to, best {} at {} dBm",
+            best.bssid, best.rssi
+        );
+        return Ok(None);
+    }
+
+    info!(
+        "Roaming from {} at {} dBm, {} received at {} dBm",
+        bssid, rssi, best.bssid, best.rssi
+    );
+    Ok(Some(best.bssid))
+}
+
+/// Re-associate with the network to roam from `from` to `to`, returning the
+/// access point the station associated with.
+///
+/// The station configuration of the module has no parameter to pin the
+/// access point to, so the module picks one itself, normally the strongest.
+/// The access point it picked is checked against `to` once associated.
+///
+/// The link is held up until the station is connected again, within
+/// [`Timeouts::connect`](crate::timeouts::Timeouts::connect).
+async fn roam<const INGRESS_BUF_SIZE: usize>(
+    ch: &state::Runner<'_>,
+    mut at_client: &ProxyClient<'_, INGRESS_BUF_SIZE>,
+    from: Bssid,
+    to: Bssid,
+) -> Result<Bssid, Error> {
+    ch.set_roaming(true);
+    ch.update_connection_with(|con| con.wifi_state = WiFiState::NotConnected);
+
+    let res = async {
+        at_client
+            .send_retry(&ExecWifiStationAction {
+                config_id: CONFIG_ID,
+                action: WifiStationAction::Deactivate,
+            })
+            .await?;
+        at_client
+            .send_retry(&ExecWifiStationAction {
+                config_id: CONFIG_ID,
+                action: WifiStationAction::Activate,
+            })
+            .await?;
+
+        with_timeout(ch.timeouts().connect, async {
+            loop {
+                if let Some(bssid) = ch.station_bssid() {
+                    return bssid;
+                }
+                ch.wait_for_wifi_state_change().await;
+            }
+        })
+        .await
+        .map_err(|_| Error::Timeout)
+    }
+    .await;
+
+    // Takes the link down, unless the station connected again
+    ch.set_roaming(false);
+
+    let associated = match res {
+        Ok(associated) => associated,
+        Err(e) => {
+            // The connection state may no longer match the module
+            ch.request_resync();
+            return Err(e);
+        }
+    };
+
+    if associated == to {
+        info!("Roamed to {}", to);
+    } else if associated == from {
+        warn!("Re-associated with {}, instead of roaming to {}", from, to);
+    } else {
+        info!("Roamed to {}, instead of {}", associated, to);
+    }
+
+    Ok(associated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hysteresis() {
+        let mut monitor = RoamingMonitor::new(RoamingConfig::new(-70).hysteresis(3));
+
+        // A good sample starts over
+        assert!(!monitor.on_sample(-75));
+        assert!(!monitor.on_sample(-75));
+        assert!(!monitor.on_sample(-70));
+        assert!(!monitor.on_sample(-72));
+        assert!(!monitor.on_sample(-80));
+        assert!(monitor.on_sample(-78));
+
+        // Counting starts over after roaming
+        assert!(!monitor.on_sample(-75));
+
+        let mut monitor = RoamingMonitor::new(RoamingConfig::new(-70).hysteresis(0));
+        assert!(monitor.on_sample(-75));
+    }
+
+    #[cfg(not(feature = "edm"))]
+    mod module {
+        use super::*;
+        use crate::asynch::state::{LinkEvent, LinkState, LINK_HISTORY_LEN};
+        use crate::test_util::{harness, Harness, MockUbloxModule};
+        use embassy_futures::select::{select, Either};
+
+        const BSSID: Bssid = Bssid([0xD4, 0xCA, 0x6D, 0xF5, 0xF2, 0xF0]);
+        const STRONGER: Bssid = Bssid([0xD4, 0xCA, 0x6D, 0xF5, 0xF2, 0xF1]);
+
+        const RSSI: &str = "AT+UWSSTAT=6\r\n";
+
+        fn connect(ch: &state::Runner<'_>) {
+            ch.update_connection_with(|con| harness::connect(con, BSSID, 6));
+        }
+
+        fn office(module: &mut MockUbloxModule, rssi: &str) {
+            module.respond(RSSI, rssi);
+            module.respond("AT+UWSSTAT=0", "+UWSSTAT:0,\"office\"");
+        }
+
+        fn sent(module: &MockUbloxModule) -> std::vec::Vec<&[u8]> {
+            module.sent_commands().collect()
+        }
+
+        fn sample_once(module: &mut MockUbloxModule) -> Result<Option<Bssid>, Error> {
+            let harness = Harness::new();
+            let client = harness.client();
+            let mut state = state::State::new();
+            let ch = state::Runner::new(&mut state);
+            connect(&ch);
+
+            let mut monitor = RoamingMonitor::new(RoamingConfig::new(-70).hysteresis(1));
+            harness.serve(module, sample(&ch, &client, &mut monitor, BSSID))
+        }
+
+        fn roam_once(
+            module: &mut MockUbloxModule,
+        ) -> (
+            Result<Bssid, Error>,
+            heapless::Vec<LinkEvent, LINK_HISTORY_LEN>,
+        ) {
+            let harness = Harness::new();
+            let client = harness.client();
+            let mut state = state::State::new();
+            let ch = state::Runner::new(&mut state);
+            connect(&ch);
+
+            let res = harness.serve(module, async {
+                match select(roam(&ch, &client, BSSID, STRONGER), harness.device(&ch)).await {
+                    Either::First(res) => res,
+                    Either::Second(never) => never,
+                }
+            });
+            (res, ch.link_history())
+        }
+
+        #[test]
+        fn picks_strongest_access_point() {
+            let mut module = MockUbloxModule::new();
+            office(&mut module, "+UWSSTAT:6,-78");
+            module.add_scan_result("+UWSCAN:D4CA6DF5F2F0,1,\"office\",6,-77,18,8,8");
+            module.add_scan_result("+UWSCAN:D4CA6DF5F2F1,1,\"office\",36,-52,18,8,8");
+            module.add_scan_result("+UWSCAN:D4CA6DF5F2F2,1,\"office\",11,-64,18,8,8");
+
+            assert_eq!(sample_once(&mut module).unwrap(), Some(STRONGER));
+            assert_eq!(
+                sent(&module),
+                [
+                    RSSI.as_bytes(),
+                    b"AT+UWSSTAT=0\r\n",
+                    b"AT+UWSCAN=\"office\"\r\n",
+                ]
+            );
+        }
+
+        #[test]
+        fn stays_without_stronger_access_point() {
+            let mut module = MockUbloxModule::new();
+            office(&mut module, "+UWSSTAT:6,-75");
+            module.add_scan_result("+UWSCAN:D4CA6DF5F2F0,1,\"office\",6,-74,18,8,8");
+            module.add_scan_result("+UWSCAN:D4CA6DF5F2F1,1,\"office\",36,-81,18,8,8");
+
+            assert_eq!(sample_once(&mut module).unwrap(), None);
+        }
+
+        #[test]
+        fn not_connected() {
+            let mut module = MockUbloxModule::new();
+            module.respond(RSSI, "+UWSSTAT:6,-32768");
+
+            assert!(matches!(sample_once(&mut module), Err(Error::Network)));
+            assert_eq!(sent(&module), [RSSI.as_bytes()]);
+        }
+
+        #[test]
+        fn roams() {
+            let mut module = MockUbloxModule::new();
+            module.station_bssid("D4CA6DF5F2F1");
+
+            let (res, link_history) = roam_once(&mut module);
+            assert_eq!(res.unwrap(), STRONGER);
+            assert_eq!(
+                sent(&module),
+                [&b"AT+UWSCA=0,4\r\n"[..], b"AT+UWSCA=0,3\r\n"]
+            );
+
+            // The link is held up while re-associating
+            assert_eq!(link_history.len(), 1);
+            assert_eq!(link_history[0].link_state, LinkState::Up);
+        }
+
+        #[test]
+        fn reassociates_with_same_access_point() {
+            let mut module = MockUbloxModule::new();
+            module.station_bssid("D4CA6DF5F2F0");
+
+            let (res, link_history) = roam_once(&mut module);
+            assert_eq!(res.unwrap(), BSSID);
+            assert_eq!(link_history.len(), 1);
+        }
+
+        #[test]
+        fn failed_reassociation() {
+            let mut module = MockUbloxModule::new();
+            module.fail("AT+UWSCA=0,3", atat::Error::Error);
+
+            let (res, link_history) = roam_once(&mut module);
+            assert!(matches!(res, Err(Error::AT(_))));
+            assert_eq!(
+                link_history.last().map(|event| event.link_state),
+                Some(LinkState::Down)
+            );
+        }
+    }
+}
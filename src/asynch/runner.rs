@@ -1,9 +1,9 @@
-use super::{control::Control, network::NetDevice, state, Resources, UbloxUrc};
+use super::{control::Control, network::NetDevice, roaming, state, Resources, UbloxUrc};
 use crate::{
-    asynch::control::ProxyClient,
+    asynch::control::{CommandLock, ProxyClient},
     command::{
         data_mode::{self, ChangeMode},
-        general::SoftwareVersion,
+        general::{responses::ModelIdentificationResponse, ModelIdentification, SoftwareVersion},
         system::{
             types::{BaudRate, ChangeAfterConfirm, EchoOn, FlowControl, Parity, StopBits},
             SetEcho, SetRS232Settings,
@@ -16,6 +16,7 @@ use crate::{
     },
     config::Transport,
     error::Error,
+    zeroize::zeroize,
     WifiConfig, DEFAULT_BAUD_RATE,
 };
 use atat::{
@@ -23,22 +24,103 @@ use atat::{
     AtatIngress as _, UrcChannel,
 };
 use embassy_futures::select::Either;
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_sync::{
+    blocking_mutex::raw::{NoopRawMutex, RawMutex},
+    channel::Channel,
+    pipe::Pipe,
+    pubsub::{Subscriber, WaitResult},
+};
 use embassy_time::{Duration, Timer};
 use embedded_io_async::{BufRead, Write};
 
+#[cfg(feature = "internal-network-stack")]
+use super::detach::{DetachedState, PendingAttach};
+#[cfg(feature = "internal-network-stack")]
+use crate::command::edm::urc::EdmEvent;
+
+// Includes a subscriber for a `UrcObserver`, and one for a ping.
 #[cfg(feature = "ppp")]
-pub(crate) const URC_SUBSCRIBERS: usize = 2;
+pub(crate) const URC_SUBSCRIBERS: usize = 4;
 #[cfg(feature = "ppp")]
 type Digester = atat::AtDigester<UbloxUrc>;
 
+// Includes a subscriber for a `UrcObserver`, one for a ping, and the two
+// lanes of the network stack.
 #[cfg(feature = "internal-network-stack")]
-pub(crate) const URC_SUBSCRIBERS: usize = 3;
+pub(crate) const URC_SUBSCRIBERS: usize = 6;
 #[cfg(feature = "internal-network-stack")]
 type Digester = crate::command::custom_digest::EdmDigester;
 
 pub(crate) const MAX_CMD_LEN: usize = 256;
 
+/// Minimum `URC_CAPACITY` required for a stack with `sockets` sockets.
+///
+/// Each socket may have a connect and a disconnect event pending, on top of
+/// up to four pending link and network events. Undersized URC channels stall
+/// the ingress under bursts of URCs, delaying command responses and socket
+/// events.
+pub const fn min_urc_capacity(sockets: usize) -> usize {
+    2 * sockets + 4
+}
+
+/// Simultaneous peer connections supported by a module, by the model
+/// reported by the module, or `None` if the model is not known.
+pub(crate) fn module_max_peers(model: &str) -> Option<usize> {
+    ["ODIN-W2", "NINA-W1"]
+        .iter()
+        .any(|prefix| model.starts_with(prefix))
+        .then_some(7)
+}
+
+/// Simultaneous peer connections supported by a module, by its response to
+/// [`ModelIdentification`]. Used to check the socket set size against, so a
+/// failed identification is not fatal.
+fn identified_max_peers(
+    response: Result<ModelIdentificationResponse, atat::Error>,
+) -> Option<usize> {
+    match response {
+        Ok(ModelIdentificationResponse { model }) => module_max_peers(&model),
+        Err(e) => {
+            warn!("Failed to identify the module model: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Compile time check of the `URC_CAPACITY` against [`min_urc_capacity`].
+pub(crate) struct UrcCapacityCheck<const SOCK: usize, const URC_CAPACITY: usize>;
+
+impl<const SOCK: usize, const URC_CAPACITY: usize> UrcCapacityCheck<SOCK, URC_CAPACITY> {
+    pub(crate) const OK: () = assert!(
+        URC_CAPACITY >= min_urc_capacity(SOCK),
+        "URC_CAPACITY is too small, see `min_urc_capacity`"
+    );
+}
+
+/// Wait for the next URC on `subscription`, recording the channel occupancy
+/// and any lost URCs in the statistics of `ch`.
+pub(crate) async fn next_urc<M, T, const CAP: usize, const SUBS: usize, const PUBS: usize>(
+    ch: &state::Runner<'_>,
+    subscription: &mut Subscriber<'_, M, T, CAP, SUBS, PUBS>,
+) -> T
+where
+    M: RawMutex,
+    T: Clone,
+{
+    loop {
+        match subscription.next_message().await {
+            WaitResult::Lagged(n) => {
+                warn!("URC channel full, lost {} URCs", n);
+                ch.record_urc_lost(n);
+            }
+            WaitResult::Message(urc) => {
+                ch.record_urc_backlog(subscription.available() as usize + 1);
+                return urc;
+            }
+        }
+    }
+}
+
 async fn at_bridge<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>(
     transport: &mut impl Transport,
     req_slot: &Channel<NoopRawMutex, heapless::Vec<u8, MAX_CMD_LEN>, 1>,
@@ -57,8 +139,9 @@ async fn at_bridge<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
 
     let tx_fut = async {
         loop {
-            let msg = req_slot.receive().await;
+            let mut msg = req_slot.receive().await;
             let _ = tx.write_all(&msg).await;
+            zeroize(&mut msg);
         }
     };
 
@@ -67,6 +150,87 @@ async fn at_bridge<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
     unreachable!()
 }
 
+/// Time to wait before reading the transport again, after it returned no
+/// data, or an error.
+const RAW_READ_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Shuffle raw bytes between the transport and the pipes handed out by
+/// [`Control::suspend`], without any AT/EDM processing.
+async fn raw_bridge<const INGRESS_BUF_SIZE: usize>(
+    transport: &mut impl Transport,
+    raw_tx: &Pipe<NoopRawMutex, MAX_CMD_LEN>,
+    raw_rx: &Pipe<NoopRawMutex, INGRESS_BUF_SIZE>,
+) -> ! {
+    let (mut tx, mut rx) = transport.split_ref();
+
+    let tx_fut = async {
+        let mut buf = [0u8; 64];
+        loop {
+            let n = raw_tx.read(&mut buf).await;
+            let _ = tx.write_all(&buf[..n]).await;
+        }
+    };
+
+    let rx_fut = async {
+        let mut buf = [0u8; 64];
+        loop {
+            match rx.read(&mut buf).await {
+                Ok(n) if n > 0 => raw_rx.write_all(&buf[..n]).await,
+                // Nothing to read, or a failed read: wait for the transport to
+                // recover, rather than spin on it
+                _ => Timer::after(RAW_READ_BACKOFF).await,
+            }
+        }
+    };
+
+    embassy_futures::join::join(tx_fut, rx_fut).await;
+
+    unreachable!()
+}
+
+/// Run the AT bridge, handing the transport over to [`raw_bridge`] while
+/// normal operation is suspended through [`Control::suspend`].
+///
+/// Commands are held back by the command lock while suspended, so nothing is
+/// left in `req_slot` to be sent once the AT bridge takes over again.
+async fn bridge<'a, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>(
+    ch: &state::Runner<'_>,
+    transport: &mut impl Transport,
+    req_slot: &Channel<NoopRawMutex, heapless::Vec<u8, MAX_CMD_LEN>, 1>,
+    ingress: &mut atat::Ingress<
+        'a,
+        Digester,
+        UbloxUrc,
+        INGRESS_BUF_SIZE,
+        URC_CAPACITY,
+        { URC_SUBSCRIBERS },
+    >,
+    raw_tx: &Pipe<NoopRawMutex, MAX_CMD_LEN>,
+    raw_rx: &Pipe<NoopRawMutex, INGRESS_BUF_SIZE>,
+) -> ! {
+    loop {
+        embassy_futures::select::select(
+            at_bridge(transport, req_slot, ingress),
+            ch.wait_suspended(true),
+        )
+        .await;
+
+        debug!("Pausing URC processing");
+        ch.set_paused(true);
+
+        embassy_futures::select::select(
+            raw_bridge(transport, raw_tx, raw_rx),
+            ch.wait_suspended(false),
+        )
+        .await;
+
+        raw_tx.clear();
+        raw_rx.clear();
+        ch.set_paused(false);
+        debug!("Resuming URC processing");
+    }
+}
+
 /// Background runner for the Ublox Module.
 ///
 /// You must call `.run()` in a background task for the Ublox Module to operate.
@@ -82,9 +246,18 @@ pub struct Runner<'a, T: Transport, C, const INGRESS_BUF_SIZE: usize, const URC_
         atat::Ingress<'a, Digester, UbloxUrc, INGRESS_BUF_SIZE, URC_CAPACITY, { URC_SUBSCRIBERS }>,
     pub res_slot: &'a atat::ResponseSlot<INGRESS_BUF_SIZE>,
     pub req_slot: &'a Channel<NoopRawMutex, heapless::Vec<u8, MAX_CMD_LEN>, 1>,
+    cmd_lock: &'a CommandLock,
+
+    raw_rx: &'a Pipe<NoopRawMutex, INGRESS_BUF_SIZE>,
+    raw_tx: &'a Pipe<NoopRawMutex, MAX_CMD_LEN>,
 
     #[cfg(feature = "ppp")]
     ppp_runner: Option<embassy_net_ppp::Runner<'a>>,
+
+    /// State of a previous host boot to adopt the module with, see
+    /// [`Runner::attach`].
+    #[cfg(feature = "internal-network-stack")]
+    attach: Option<DetachedState>,
 }
 
 impl<'a, T, C, const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
@@ -98,7 +271,11 @@ where
         resources: &'a mut Resources<INGRESS_BUF_SIZE, URC_CAPACITY>,
         config: C,
     ) -> (Self, Control<'a, INGRESS_BUF_SIZE, URC_CAPACITY>) {
+        #[allow(clippy::let_unit_value)]
+        let () = UrcCapacityCheck::<0, URC_CAPACITY>::OK;
+
         let ch_runner = state::Runner::new(&mut resources.ch);
+        ch_runner.set_timeouts(C::TIMEOUTS);
 
         let ingress = atat::Ingress::new(
             Digester::new(),
@@ -112,6 +289,9 @@ where
             &resources.urc_channel,
             resources.req_slot.sender(),
             &resources.res_slot,
+            &resources.cmd_lock,
+            &resources.raw_rx,
+            &resources.raw_tx,
         );
 
         (
@@ -125,14 +305,44 @@ where
                 ingress,
                 res_slot: &resources.res_slot,
                 req_slot: &resources.req_slot,
+                cmd_lock: &resources.cmd_lock,
+
+                raw_rx: &resources.raw_rx,
+                raw_tx: &resources.raw_tx,
 
                 #[cfg(feature = "ppp")]
                 ppp_runner: None,
+
+                #[cfg(feature = "internal-network-stack")]
+                attach: None,
             },
             control,
         )
     }
 
+    /// Create a runner adopting a module left running by a previous boot of
+    /// the host, as persisted by
+    /// [`UbloxStack::detach`](super::ublox_stack::UbloxStack::detach).
+    ///
+    /// Instead of resetting the module, the runner checks that it is alive
+    /// and still in EDM, and re-synchronizes the connection state with it.
+    /// The network stack then reconciles the peers of `previous` with those
+    /// of the module, see
+    /// [`UbloxStack::attach_report`](super::ublox_stack::UbloxStack::attach_report).
+    /// If the module cannot be adopted, it is initialized from scratch, and
+    /// all peers are reported closed.
+    #[cfg(feature = "internal-network-stack")]
+    pub fn attach(
+        transport: T,
+        resources: &'a mut Resources<INGRESS_BUF_SIZE, URC_CAPACITY>,
+        config: C,
+        previous: DetachedState,
+    ) -> (Self, Control<'a, INGRESS_BUF_SIZE, URC_CAPACITY>) {
+        let (mut runner, control) = Self::new(transport, resources, config);
+        runner.attach = Some(previous);
+        (runner, control)
+    }
+
     #[cfg(feature = "ppp")]
     pub fn ppp_stack<'d: 'a, const N_RX: usize, const N_TX: usize>(
         &mut self,
@@ -152,6 +362,8 @@ where
             at_client: core::cell::RefCell::new(ProxyClient::new(
                 self.req_slot.sender(),
                 &self.res_slot,
+                self.cmd_lock,
+                C::TIMEOUTS.command_default,
             )),
             urc_channel: &self.urc_channel,
         }
@@ -165,7 +377,12 @@ where
         self.transport.set_baudrate(baudrate as u32);
 
         let baud_fut = async {
-            let at_client = ProxyClient::new(self.req_slot.sender(), self.res_slot);
+            let at_client = ProxyClient::new(
+                self.req_slot.sender(),
+                self.res_slot,
+                self.cmd_lock,
+                C::TIMEOUTS.command_default,
+            );
 
             // Hard reset module
             NetDevice::new(&self.ch, &mut self.config, &at_client, self.urc_channel)
@@ -254,7 +471,12 @@ where
                         NetDevice::new(
                             &self.ch,
                             &mut self.config,
-                            &ProxyClient::new(self.req_slot.sender(), self.res_slot),
+                            &ProxyClient::new(
+                                self.req_slot.sender(),
+                                self.res_slot,
+                                self.cmd_lock,
+                                C::TIMEOUTS.command_default,
+                            ),
                             self.urc_channel,
                         )
                         .restart(true),
@@ -271,26 +493,57 @@ where
             return Err(Error::BaudDetection);
         }
 
-        let at_client = ProxyClient::new(self.req_slot.sender(), self.res_slot);
+        let at_client = ProxyClient::new(
+            self.req_slot.sender(),
+            self.res_slot,
+            self.cmd_lock,
+            C::TIMEOUTS.command_default,
+        );
 
+        let ch = &self.ch;
+        // The setup commands are sent one at a time, each awaiting its
+        // response. The module processes a single command at a time, and
+        // responses are handed over through a single response slot, so
+        // pipelining commands would drop responses rather than save time.
         let setup_fut = async {
+            // The digesters cannot handle echo, so it is disabled before
+            // anything else. If that fails, the echo setting of the module is
+            // unknown, and initialization is aborted. The next attempt starts
+            // over from a module reset, which restores a known state.
+            if let Err(e) = (&at_client).send_retry(&SetEcho { on: EchoOn::Off }).await {
+                error!("Failed to disable echo: {:?}", e);
+                return Err(e.into());
+            }
+
             (&at_client).send_retry(&SoftwareVersion).await?;
 
-            (&at_client)
-                .send_retry(&SetEcho { on: EchoOn::Off })
-                .await?;
+            let model = (&at_client).send_retry(&ModelIdentification).await;
+            ch.set_max_peers(identified_max_peers(model));
+
             (&at_client)
                 .send_retry(&SetWifiConfig {
                     config_param: WifiConfigParam::DropNetworkOnLinkLoss(OnOff::On),
                 })
                 .await?;
 
-            // Disable all power savings for now
-            (&at_client)
-                .send_retry(&SetWifiConfig {
-                    config_param: WifiConfigParam::PowerSaveMode(PowerSaveMode::ActiveMode),
-                })
-                .await?;
+            if let Some(wake_config) = C::WAKE_CONFIG {
+                wake_config
+                    .validate(C::FLOW_CONTROL)
+                    .map_err(Error::WakeConfig)?;
+
+                let (dtr_reset, dtr, dsr, power_save) = wake_config.commands();
+                (&at_client).send_retry(&dtr_reset).await?;
+                (&at_client).send_retry(&dtr).await?;
+                (&at_client).send_retry(&dsr).await?;
+                (&at_client).send_retry(&power_save).await?;
+            } else {
+                // Disable all power savings for now
+                (&at_client)
+                    .send_retry(&SetWifiConfig {
+                        config_param: WifiConfigParam::PowerSaveMode(PowerSaveMode::ActiveMode),
+                    })
+                    .await?;
+            }
 
             #[cfg(feature = "internal-network-stack")]
             if let Some(size) = C::TLS_IN_BUFFER_SIZE {
@@ -333,22 +586,121 @@ where
         Ok(())
     }
 
+    /// Adopt a running module, checking that it is alive and in EDM, without
+    /// resetting it.
+    ///
+    /// The module announces a restart with a start event, which also means it
+    /// dropped its peers, so seeing one while probing fails the attach.
+    #[cfg(feature = "internal-network-stack")]
+    async fn reattach(&mut self, previous: &DetachedState) -> Result<(), Error> {
+        info!("Attaching to running module");
+        self.transport.set_baudrate(C::BAUD_RATE as u32);
+
+        let at_client = ProxyClient::new(
+            self.req_slot.sender(),
+            self.res_slot,
+            self.cmd_lock,
+            C::TIMEOUTS.command_default,
+        );
+        let mut urc_subscription = self.urc_channel.subscribe().unwrap();
+
+        let ch = &self.ch;
+        let probe_fut = async {
+            (&at_client)
+                .send_retry(&crate::command::edm::EdmAtCmdWrapper(AT))
+                .await?;
+
+            // Only identified on initialization, which the attach skips
+            let model = (&at_client)
+                .send_retry(&crate::command::edm::EdmAtCmdWrapper(ModelIdentification))
+                .await;
+            ch.set_max_peers(identified_max_peers(model));
+
+            while let Some(event) = urc_subscription.try_next_message_pure() {
+                if matches!(
+                    event,
+                    EdmEvent::StartUp | EdmEvent::ATEvent(crate::command::Urc::StartUp)
+                ) {
+                    warn!("Module restarted while detached");
+                    return Err(Error::Uninitialized);
+                }
+            }
+
+            Ok::<(), Error>(())
+        };
+
+        match embassy_futures::select::select(
+            probe_fut,
+            at_bridge(&mut self.transport, self.req_slot, &mut self.ingress),
+        )
+        .await
+        {
+            Either::First(r) => r?,
+            Either::Second(_) => unreachable!(),
+        }
+
+        self.ch.set_edm_capabilities(previous.edm_capabilities);
+        self.ch.set_should_connect(previous.config_id.is_some());
+        self.ch.mark_initialized();
+        self.ch.request_resync();
+
+        Ok(())
+    }
+
     #[cfg(feature = "internal-network-stack")]
     pub async fn run(&mut self) -> ! {
+        // Kept across the re-initializations after module restarts below
+        self.ch.clear_link_history();
         loop {
-            if self.init().await.is_err() {
+            if let Some(previous) = self.attach.take() {
+                let result = self.reattach(&previous).await;
+                if let Err(e) = &result {
+                    warn!("Failed to attach to module: {:?}", e);
+                }
+
+                self.ch.set_pending_attach(PendingAttach {
+                    previous,
+                    module_reset: result.is_err(),
+                });
+
+                if result.is_err() && self.init().await.is_err() {
+                    continue;
+                }
+            } else if self.init().await.is_err() {
                 continue;
             }
 
-            embassy_futures::select::select(
+            embassy_futures::select::select3(
                 NetDevice::new(
                     &self.ch,
                     &mut self.config,
-                    &ProxyClient::new(self.req_slot.sender(), &self.res_slot),
+                    &ProxyClient::new(
+                        self.req_slot.sender(),
+                        &self.res_slot,
+                        self.cmd_lock,
+                        C::TIMEOUTS.command_default,
+                    ),
                     self.urc_channel,
                 )
                 .run(),
-                at_bridge(&mut self.transport, &self.req_slot, &mut self.ingress),
+                roaming::run(
+                    &self.ch,
+                    C::ROAMING,
+                    &ProxyClient::new(
+                        self.req_slot.sender(),
+                        &self.res_slot,
+                        self.cmd_lock,
+                        C::TIMEOUTS.command_default,
+                    ),
+                ),
+                bridge(
+                    &self.ch,
+                    &mut self.transport,
+                    &self.req_slot,
+                    &mut self.ingress,
+                    self.raw_tx,
+                    self.raw_rx,
+                ),
             )
             .await;
         }
@@ -356,6 +708,8 @@ where
 
     #[cfg(feature = "ppp")]
     pub async fn run(&mut self, stack: embassy_net::Stack<'_>) -> ! {
+        // Kept across the re-initializations after module restarts below
+        self.ch.clear_link_history();
         loop {
             if self.init().await.is_err() {
                 continue;
@@ -367,7 +721,14 @@ where
                 // Allow control to send/receive AT commands directly on the
                 // UART, until we are ready to establish connection using PPP
                 let _ = embassy_futures::select::select(
-                    at_bridge(&mut self.transport, self.req_slot, &mut self.ingress),
+                    bridge(
+                        &self.ch,
+                        &mut self.transport,
+                        self.req_slot,
+                        &mut self.ingress,
+                        self.raw_tx,
+                        self.raw_rx,
+                    ),
                     self.ch.wait_connected(),
                 )
                 .await;
@@ -465,13 +826,30 @@ where
             };
 
             let device_fut = async {
-                let _ = NetDevice::new(
-                    &self.ch,
-                    &mut self.config,
-                    &ProxyClient::new(self.req_slot.sender(), self.res_slot),
-                    self.urc_channel,
+                let _ = embassy_futures::select::select(
+                    NetDevice::new(
+                        &self.ch,
+                        &mut self.config,
+                        &ProxyClient::new(
+                            self.req_slot.sender(),
+                            self.res_slot,
+                            self.cmd_lock,
+                            C::TIMEOUTS.command_default,
+                        ),
+                        self.urc_channel,
+                    )
+                    .run(),
+                    roaming::run(
+                        &self.ch,
+                        C::ROAMING,
+                        &ProxyClient::new(
+                            self.req_slot.sender(),
+                            self.res_slot,
+                            self.cmd_lock,
+                            C::TIMEOUTS.command_default,
+                        ),
+                    ),
                 )
-                .run()
                 .await;
 
                 warn!("Breaking to reboot device");
@@ -481,3 +859,120 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::ManualClock;
+    use embassy_futures::block_on;
+    use embassy_sync::pubsub::PubSubChannel;
+
+    #[test]
+    fn urc_capacity() {
+        assert_eq!(min_urc_capacity(0), 4);
+        assert_eq!(min_urc_capacity(4), 12);
+    }
+
+    #[test]
+    fn max_peers() {
+        assert_eq!(module_max_peers("ODIN-W262"), Some(7));
+        assert_eq!(module_max_peers("NINA-W132"), Some(7));
+        assert_eq!(module_max_peers("NINA-B112"), None);
+
+        let model = ModelIdentificationResponse {
+            model: heapless::String::try_from("NINA-W132").unwrap(),
+        };
+        assert_eq!(identified_max_peers(Ok(model)), Some(7));
+        assert_eq!(identified_max_peers(Err(atat::Error::Timeout)), None);
+    }
+
+    /// A transport with nothing to read, ever, counting the reads.
+    #[derive(Clone, Default)]
+    struct Drained {
+        reads: std::rc::Rc<core::cell::Cell<usize>>,
+    }
+
+    impl embedded_io_async::ErrorType for Drained {
+        type Error = core::convert::Infallible;
+    }
+
+    impl embedded_io_async::Read for Drained {
+        async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.reads.set(self.reads.get() + 1);
+            Ok(0)
+        }
+    }
+
+    impl Write for Drained {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            Ok(buf.len())
+        }
+    }
+
+    impl Transport for Drained {
+        fn set_baudrate(&mut self, _baudrate: u32) {}
+
+        fn split_ref(&mut self) -> (impl Write, impl embedded_io_async::Read) {
+            (self.clone(), self.clone())
+        }
+    }
+
+    #[test]
+    fn raw_bridge_backs_off() {
+        let clock = ManualClock::new();
+        let raw_tx = Pipe::<NoopRawMutex, MAX_CMD_LEN>::new();
+        let raw_rx = Pipe::<NoopRawMutex, 64>::new();
+        let mut transport = Drained::default();
+        let reads = transport.reads.clone();
+
+        // Yields, rather than spinning on the empty reads
+        let mut bridge = core::pin::pin!(raw_bridge(&mut transport, &raw_tx, &raw_rx));
+        assert!(embassy_futures::poll_once(bridge.as_mut()).is_pending());
+        assert!(embassy_futures::poll_once(bridge.as_mut()).is_pending());
+
+        // And reads again once the backoff expired
+        let before = reads.get();
+        clock.advance_by(RAW_READ_BACKOFF);
+        assert!(embassy_futures::poll_once(bridge.as_mut()).is_pending());
+        assert!(reads.get() > before);
+    }
+
+    #[test]
+    fn urc_high_water() {
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+
+        let channel = PubSubChannel::<NoopRawMutex, u8, 4, 1, 1>::new();
+        let publisher = channel.publisher().unwrap();
+        let mut subscription = channel.subscriber().unwrap();
+
+        // Burst of three URCs
+        for urc in 0..3 {
+            publisher.publish_immediate(urc);
+        }
+
+        assert_eq!(block_on(next_urc(&ch, &mut subscription)), 0);
+        assert_eq!(ch.urc_stats().high_water, 3);
+        assert_eq!(block_on(next_urc(&ch, &mut subscription)), 1);
+        assert_eq!(block_on(next_urc(&ch, &mut subscription)), 2);
+        assert_eq!(ch.urc_stats().high_water, 3);
+        assert_eq!(ch.urc_stats().lost, 0);
+
+        // Burst overflowing the channel
+        for urc in 3..9 {
+            publisher.publish_immediate(urc);
+        }
+
+        assert_eq!(block_on(next_urc(&ch, &mut subscription)), 5);
+        assert_eq!(
+            ch.urc_stats(),
+            state::UrcStats {
+                high_water: 4,
+                lost: 2,
+            }
+        );
+
+        ch.reset_urc_stats();
+        assert_eq!(ch.urc_stats(), state::UrcStats::default());
+    }
+}
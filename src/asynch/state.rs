@@ -7,8 +7,29 @@ use core::task::{Context, Poll};
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
 
+#[cfg(feature = "ap")]
+use super::access_point::{ApState, ConnectedStation, MAX_AP_STATIONS};
+#[cfg(feature = "internal-network-stack")]
+use super::detach::PendingAttach;
+#[cfg(feature = "edm")]
+use crate::command::edm::types::EdmCapabilities;
+use crate::command::network::urc::NetworkError;
+use crate::command::wifi::types::{Bssid, DisconnectReason};
 use crate::connection::{WiFiState, WifiConnection};
+use crate::init_script::{InitCommandResult, InitReport};
+use crate::restart_capture::RestartCapture;
+use crate::timeouts::Timeouts;
+
+/// Number of link state transitions kept in the link history.
+pub const LINK_HISTORY_LEN: usize = 16;
+
+/// Window within which a Wi-Fi disconnect and a link down transition are
+/// taken to be the same underlying event. The module reports the loss of the
+/// IP layer (`+UUND`) and of the Wi-Fi link (`+UUWLD`) in either order.
+const LINK_EVENT_WINDOW: Duration = Duration::from_secs(2);
 
 /// The link state of a network device.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -22,6 +43,31 @@ pub enum LinkState {
     Up,
 }
 
+/// A recorded link state transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinkEvent {
+    /// Time of the transition.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub timestamp: Instant,
+    /// The link state transitioned to.
+    pub link_state: LinkState,
+    /// Reason reported by the module, if the link went down due to a Wi-Fi
+    /// disconnect.
+    pub reason: Option<DisconnectReason>,
+}
+
+/// Occupancy statistics of the URC channel, as observed by its subscribers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UrcStats {
+    /// Highest number of URCs seen pending for a single subscriber. Reaching
+    /// `URC_CAPACITY` means URCs were lost, or the ingress was stalled.
+    pub high_water: usize,
+    /// Number of URCs lost because the channel was full.
+    pub lost: u64,
+}
+
 pub(crate) struct State {
     shared: Mutex<NoopRawMutex, RefCell<Shared>>,
 }
@@ -32,9 +78,35 @@ impl State {
             shared: Mutex::new(RefCell::new(Shared {
                 should_connect: false,
                 link_state: LinkState::Uninitialized,
+                link_ups: 0,
                 wifi_connection: WifiConnection::new(),
+                link_history: Deque::new(),
+                disconnect_reason: None,
+                paused: false,
+                suspended: false,
+                resync_pending: false,
+                ping_active: false,
+                resolve_ping: false,
+                roaming: false,
+                urc_stats: UrcStats {
+                    high_water: 0,
+                    lost: 0,
+                },
+                max_peers: None,
+                #[cfg(feature = "edm")]
+                edm_capabilities: None,
+                #[cfg(feature = "internal-network-stack")]
+                pending_attach: None,
+                init_report: InitReport::new(),
+                last_restart: None,
+                last_network_error: None,
+                timeouts: Timeouts::DEFAULT,
                 state_waker: WakerRegistration::new(),
                 connection_waker: WakerRegistration::new(),
+                pause_waker: WakerRegistration::new(),
+                resync_waker: WakerRegistration::new(),
+                resume_waker: WakerRegistration::new(),
+                ping_waker: WakerRegistration::new(),
             })),
         }
     }
@@ -43,10 +115,123 @@ impl State {
 /// State of the LinkState
 pub(crate) struct Shared {
     link_state: LinkState,
+    /// Number of times the link came up.
+    link_ups: u32,
     should_connect: bool,
     wifi_connection: WifiConnection,
+    link_history: Deque<LinkEvent, LINK_HISTORY_LEN>,
+    /// Reason of a Wi-Fi disconnect not yet attributed to a link down
+    /// transition, and when it was reported.
+    disconnect_reason: Option<(DisconnectReason, Instant)>,
+    /// The runner handed the UART over to a `SuspendGuard`.
+    paused: bool,
+    /// Normal operation is suspended by a `SuspendGuard`, and the runner is
+    /// to hand the UART over to it.
+    suspended: bool,
+    /// The connection state is to be re-synchronized with the module, after
+    /// a suspension during which URCs were not processed.
+    resync_pending: bool,
+    /// A ping of the application is in progress, so the ping URCs are not
+    /// those of a resolve of the network stack.
+    ping_active: bool,
+    /// A ping resolving a name for the network stack is outstanding, so the
+    /// ping URCs are not those of the application.
+    resolve_ping: bool,
+    /// The station is re-associating to roam to another access point, so the
+    /// link is held up until it is done.
+    roaming: bool,
+    urc_stats: UrcStats,
+    /// Simultaneous peer connections supported by the module, if known.
+    max_peers: Option<usize>,
+    /// Capabilities advertised by the module on entering EDM, if entered.
+    #[cfg(feature = "edm")]
+    edm_capabilities: Option<EdmCapabilities>,
+    /// Attach to be reconciled by the network stack, once the connection
+    /// state is re-synchronized.
+    #[cfg(feature = "internal-network-stack")]
+    pending_attach: Option<PendingAttach>,
+    init_report: InitReport,
+    /// Diagnostics captured after the last unexpected restart of the module.
+    last_restart: Option<RestartCapture>,
+    /// Last network error reported by the module.
+    last_network_error: Option<NetworkError>,
+    /// Timeouts of the configuration, for the clients without access to it.
+    timeouts: Timeouts,
     state_waker: WakerRegistration,
     connection_waker: WakerRegistration,
+    pause_waker: WakerRegistration,
+    resync_waker: WakerRegistration,
+    resume_waker: WakerRegistration,
+    ping_waker: WakerRegistration,
+}
+
+impl Shared {
+    fn set_link_state(&mut self, link_state: LinkState, now: Instant) {
+        if self.link_state == link_state {
+            return;
+        }
+
+        let reason = match link_state {
+            LinkState::Up => {
+                self.link_ups = self.link_ups.wrapping_add(1);
+                self.disconnect_reason = None;
+                None
+            }
+            // A reason reported long before belongs to an earlier event
+            _ => self
+                .disconnect_reason
+                .take()
+                .filter(|(_, at)| now.saturating_duration_since(*at) <= LINK_EVENT_WINDOW)
+                .map(|(reason, _)| reason),
+        };
+
+        if self.link_history.is_full() {
+            self.link_history.pop_front();
+        }
+        self.link_history
+            .push_back(LinkEvent {
+                timestamp: now,
+                link_state,
+                reason,
+            })
+            .ok();
+
+        self.link_state = link_state;
+    }
+
+    /// Update the link state to follow the connection.
+    fn update_link_state(&mut self, now: Instant) {
+        let link_state = if self.wifi_connection.is_connected()
+            || (self.roaming && self.link_state == LinkState::Up)
+        {
+            LinkState::Up
+        } else {
+            LinkState::Down
+        };
+        self.set_link_state(link_state, now);
+    }
+
+    /// Attribute a Wi-Fi disconnect to the link down transition it caused.
+    ///
+    /// If the link went down shortly before, from being up, the reason is
+    /// added to that transition, so the disconnect is recorded as a single
+    /// event. Otherwise it is kept for the next link down transition.
+    fn set_disconnect_reason(&mut self, reason: DisconnectReason, now: Instant) {
+        let mut events = self.link_history.iter_mut().rev();
+        if let (Some(last), Some(previous)) = (events.next(), events.next()) {
+            if self.link_state == LinkState::Down
+                && last.link_state == LinkState::Down
+                && last.reason.is_none()
+                && previous.link_state == LinkState::Up
+                && now.saturating_duration_since(last.timestamp) <= LINK_EVENT_WINDOW
+            {
+                last.reason = Some(reason);
+                return;
+            }
+        }
+
+        self.disconnect_reason = Some((reason, now));
+    }
 }
 
 #[derive(Clone)]
@@ -64,7 +249,7 @@ impl<'d> Runner<'d> {
     pub(crate) fn mark_initialized(&self) {
         self.shared.lock(|s| {
             let s = &mut *s.borrow_mut();
-            s.link_state = LinkState::Down;
+            s.set_link_state(LinkState::Down, Instant::now());
             s.state_waker.wake();
         })
     }
@@ -72,7 +257,8 @@ impl<'d> Runner<'d> {
     pub(crate) fn mark_uninitialized(&self) {
         self.shared.lock(|s| {
             let s = &mut *s.borrow_mut();
-            s.link_state = LinkState::Uninitialized;
+            s.roaming = false;
+            s.set_link_state(LinkState::Uninitialized, Instant::now());
             s.state_waker.wake();
         })
     }
@@ -85,6 +271,10 @@ impl<'d> Runner<'d> {
         })
     }
 
+    pub(crate) fn should_connect(&self) -> bool {
+        self.shared.lock(|s| s.borrow().should_connect)
+    }
+
     pub(crate) async fn wait_for_initialized(&self) {
         if self.link_state(None) != LinkState::Uninitialized {
             return;
@@ -109,6 +299,16 @@ impl<'d> Runner<'d> {
         })
     }
 
+    /// Number of times the link came up, while it is up. A change tells
+    /// that the link went down in between, even if it was never seen down.
+    #[cfg(any(test, feature = "internal-network-stack"))]
+    pub(crate) fn link_ups(&self) -> Option<u32> {
+        self.shared.lock(|s| {
+            let s = s.borrow();
+            (s.link_state == LinkState::Up).then_some(s.link_ups)
+        })
+    }
+
     pub(crate) async fn wait_for_link_state(&self, ls: LinkState) {
         if self.link_state(None) == ls {
             return;
@@ -132,17 +332,227 @@ impl<'d> Runner<'d> {
                 s.wifi_connection.is_connected()
             );
 
-            s.link_state = if s.wifi_connection.is_connected() {
-                LinkState::Up
-            } else {
-                LinkState::Down
-            };
+            s.update_link_state(Instant::now());
 
             s.state_waker.wake();
             s.connection_waker.wake();
         })
     }
 
+    /// Hold the link up while re-associating for roaming, or release it,
+    /// taking the link down if the station did not re-associate.
+    pub(crate) fn set_roaming(&self, roaming: bool) {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            s.roaming = roaming;
+            if !roaming {
+                s.update_link_state(Instant::now());
+                s.state_waker.wake();
+            }
+        })
+    }
+
+    pub(crate) fn is_roaming(&self) -> bool {
+        self.shared.lock(|s| s.borrow().roaming)
+    }
+
+    /// BSSID of the access point the station is connected to, if any.
+    pub(crate) fn station_bssid(&self) -> Option<Bssid> {
+        self.shared.lock(|s| {
+            let s = s.borrow();
+            let con = &s.wifi_connection;
+            con.network
+                .as_ref()
+                .filter(|_| con.is_station() && con.is_connected())
+                .map(|network| network.bssid)
+        })
+    }
+
+    #[cfg(feature = "ap")]
+    pub(crate) fn ap_state(&self) -> ApState {
+        self.shared.lock(|s| s.borrow().wifi_connection.ap.state())
+    }
+
+    #[cfg(feature = "ap")]
+    pub(crate) fn ap_stations(&self) -> heapless::Vec<ConnectedStation, MAX_AP_STATIONS> {
+        self.shared
+            .lock(|s| s.borrow().wifi_connection.ap.stations())
+    }
+
+    /// Record the reason of a Wi-Fi disconnect with the link down transition
+    /// it caused, which may come before or after it.
+    pub(crate) fn set_disconnect_reason(&self, reason: DisconnectReason) {
+        self.shared
+            .lock(|s| s.borrow_mut().set_disconnect_reason(reason, Instant::now()))
+    }
+
+    /// Recorded link state transitions, oldest first.
+    pub(crate) fn link_history(&self) -> heapless::Vec<LinkEvent, LINK_HISTORY_LEN> {
+        self.shared
+            .lock(|s| s.borrow().link_history.iter().copied().collect())
+    }
+
+    pub(crate) fn clear_link_history(&self) {
+        self.shared.lock(|s| {
+            s.borrow_mut().link_history.clear();
+        })
+    }
+
+    pub(crate) fn record_urc_backlog(&self, pending: usize) {
+        self.shared.lock(|s| {
+            let stats = &mut s.borrow_mut().urc_stats;
+            stats.high_water = stats.high_water.max(pending);
+        })
+    }
+
+    pub(crate) fn record_urc_lost(&self, lost: u64) {
+        self.shared.lock(|s| {
+            s.borrow_mut().urc_stats.lost += lost;
+        })
+    }
+
+    pub(crate) fn urc_stats(&self) -> UrcStats {
+        self.shared.lock(|s| s.borrow().urc_stats)
+    }
+
+    pub(crate) fn reset_urc_stats(&self) {
+        self.shared.lock(|s| {
+            s.borrow_mut().urc_stats = UrcStats::default();
+        })
+    }
+
+    pub(crate) fn set_max_peers(&self, max_peers: Option<usize>) {
+        self.shared.lock(|s| {
+            s.borrow_mut().max_peers = max_peers;
+        })
+    }
+
+    pub(crate) fn max_peers(&self) -> Option<usize> {
+        self.shared.lock(|s| s.borrow().max_peers)
+    }
+
+    /// Mark a ping in progress, once the module is done with the ping of a
+    /// resolve of the network stack. Returns `false` if a ping is already in
+    /// progress.
+    pub(crate) async fn start_ping(&self) -> bool {
+        poll_fn(|cx| {
+            self.shared.lock(|s| {
+                let s = &mut *s.borrow_mut();
+                if s.ping_active {
+                    return Poll::Ready(false);
+                }
+                if s.resolve_ping {
+                    s.ping_waker.register(cx.waker());
+                    return Poll::Pending;
+                }
+                s.ping_active = true;
+                Poll::Ready(true)
+            })
+        })
+        .await
+    }
+
+    pub(crate) fn end_ping(&self) {
+        self.shared.lock(|s| s.borrow_mut().ping_active = false)
+    }
+
+    #[cfg(feature = "internal-network-stack")]
+    pub(crate) fn ping_active(&self) -> bool {
+        self.shared.lock(|s| s.borrow().ping_active)
+    }
+
+    /// Mark whether a ping resolving a name for the network stack is
+    /// outstanding, holding back the pings of the application meanwhile.
+    #[cfg(any(test, feature = "internal-network-stack"))]
+    pub(crate) fn set_resolve_ping(&self, outstanding: bool) {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            if core::mem::replace(&mut s.resolve_ping, outstanding) && !outstanding {
+                s.ping_waker.wake();
+            }
+        })
+    }
+
+    #[cfg(feature = "edm")]
+    pub(crate) fn set_edm_capabilities(&self, capabilities: EdmCapabilities) {
+        self.shared.lock(|s| {
+            s.borrow_mut().edm_capabilities = Some(capabilities);
+        })
+    }
+
+    #[cfg(feature = "edm")]
+    pub(crate) fn edm_capabilities(&self) -> Option<EdmCapabilities> {
+        self.shared.lock(|s| s.borrow().edm_capabilities)
+    }
+
+    #[cfg(feature = "internal-network-stack")]
+    pub(crate) fn set_pending_attach(&self, attach: PendingAttach) {
+        self.shared.lock(|s| {
+            s.borrow_mut().pending_attach = Some(attach);
+        })
+    }
+
+    /// Take the pending attach, once the connection state it depends on has
+    /// been re-synchronized.
+    #[cfg(feature = "internal-network-stack")]
+    pub(crate) fn take_pending_attach(&self) -> Option<PendingAttach> {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            if s.resync_pending {
+                return None;
+            }
+            s.pending_attach.take()
+        })
+    }
+
+    pub(crate) fn set_init_report(&self, report: InitReport) {
+        self.shared.lock(|s| {
+            s.borrow_mut().init_report = report;
+        })
+    }
+
+    pub(crate) fn set_init_result(&self, index: usize, result: InitCommandResult) {
+        self.shared.lock(|s| {
+            if let Some(entry) = s.borrow_mut().init_report.script.get_mut(index) {
+                *entry = result;
+            }
+        })
+    }
+
+    pub(crate) fn init_report(&self) -> InitReport {
+        self.shared.lock(|s| s.borrow().init_report.clone())
+    }
+
+    pub(crate) fn set_last_restart(&self, capture: RestartCapture) {
+        self.shared.lock(|s| {
+            s.borrow_mut().last_restart = Some(capture);
+        })
+    }
+
+    pub(crate) fn last_restart(&self) -> Option<RestartCapture> {
+        self.shared.lock(|s| s.borrow().last_restart.clone())
+    }
+
+    pub(crate) fn set_network_error(&self, error: NetworkError) {
+        self.shared.lock(|s| {
+            s.borrow_mut().last_network_error = Some(error);
+        })
+    }
+
+    pub(crate) fn last_network_error(&self) -> Option<NetworkError> {
+        self.shared.lock(|s| s.borrow().last_network_error.clone())
+    }
+
+    pub(crate) fn set_timeouts(&self, timeouts: Timeouts) {
+        self.shared.lock(|s| {
+            s.borrow_mut().timeouts = timeouts;
+        })
+    }
+
+    pub(crate) fn timeouts(&self) -> Timeouts {
+        self.shared.lock(|s| s.borrow().timeouts)
+    }
+
     pub(crate) fn connection_down(&self, cx: Option<&mut Context>) -> bool {
         self.shared.lock(|s| {
             let s = &mut *s.borrow_mut();
@@ -213,4 +623,243 @@ impl<'d> Runner<'d> {
         })
         .await
     }
+
+    /// Mark normal operation suspended, returning `false` if it already is.
+    pub(crate) fn try_suspend(&self) -> bool {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            s.pause_waker.wake();
+            !core::mem::replace(&mut s.suspended, true)
+        })
+    }
+
+    pub(crate) fn is_suspended(&self) -> bool {
+        self.shared.lock(|s| s.borrow().suspended)
+    }
+
+    /// Wait for normal operation to be suspended, or for the suspension to
+    /// end.
+    pub(crate) async fn wait_suspended(&self, suspended: bool) {
+        poll_fn(|cx| {
+            self.shared.lock(|s| {
+                let s = &mut *s.borrow_mut();
+                if s.suspended == suspended {
+                    return Poll::Ready(());
+                }
+                s.pause_waker.register(cx.waker());
+                Poll::Pending
+            })
+        })
+        .await
+    }
+
+    /// End a suspension, requesting the connection state to be
+    /// re-synchronized with the module.
+    pub(crate) fn end_suspend(&self) {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            s.suspended = false;
+            s.pause_waker.wake();
+            s.resync_pending = true;
+            s.resync_waker.wake();
+        })
+    }
+
+    /// Request the connection state to be re-synchronized with the module,
+    /// e.g. after adopting a module that kept running on its own.
+    pub(crate) fn request_resync(&self) {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            s.resync_pending = true;
+            s.resync_waker.wake();
+        })
+    }
+
+    pub(crate) async fn wait_resync_pending(&self) {
+        poll_fn(|cx| {
+            self.shared.lock(|s| {
+                let s = &mut *s.borrow_mut();
+                if s.resync_pending {
+                    return Poll::Ready(());
+                }
+                s.resync_waker.register(cx.waker());
+                Poll::Pending
+            })
+        })
+        .await
+    }
+
+    pub(crate) fn resync_done(&self) {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            s.resync_pending = false;
+            s.resume_waker.wake();
+        })
+    }
+
+    /// Wait for the end of a suspension, including the re-synchronization of
+    /// the connection state.
+    pub(crate) async fn wait_resumed(&self) {
+        poll_fn(|cx| {
+            self.shared.lock(|s| {
+                let s = &mut *s.borrow_mut();
+                if !s.suspended && !s.resync_pending {
+                    return Poll::Ready(());
+                }
+                s.resume_waker.register(cx.waker());
+                Poll::Pending
+            })
+        })
+        .await
+    }
+
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            s.paused = paused;
+            s.state_waker.wake();
+        })
+    }
+
+    pub(crate) fn is_paused(&self, cx: Option<&mut Context>) -> bool {
+        self.shared.lock(|s| {
+            let s = &mut *s.borrow_mut();
+            if let Some(cx) = cx {
+                s.state_waker.register(cx.waker());
+            }
+            s.paused
+        })
+    }
+
+    pub(crate) async fn wait_paused(&self, paused: bool) {
+        if self.is_paused(None) == paused {
+            return;
+        }
+
+        poll_fn(|cx| {
+            if self.is_paused(Some(cx)) == paused {
+                return Poll::Ready(());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(millis: u64) -> Instant {
+        Instant::from_millis(millis)
+    }
+
+    /// Link down transitions in the history, with their reasons.
+    fn downs(state: &State) -> heapless::Vec<Option<DisconnectReason>, LINK_HISTORY_LEN> {
+        state.shared.lock(|s| {
+            s.borrow()
+                .link_history
+                .iter()
+                .filter(|e| e.link_state == LinkState::Down)
+                .map(|e| e.reason)
+                .collect()
+        })
+    }
+
+    fn with_shared(state: &State, f: impl FnOnce(&mut Shared)) {
+        state.shared.lock(|s| f(&mut s.borrow_mut()))
+    }
+
+    fn link_up(state: &State) {
+        with_shared(state, |s| {
+            s.set_link_state(LinkState::Down, at(0));
+            s.set_link_state(LinkState::Up, at(0));
+        });
+    }
+
+    #[test]
+    fn disconnect_before_network_down() {
+        let state = State::new();
+        link_up(&state);
+
+        with_shared(&state, |s| {
+            s.set_disconnect_reason(DisconnectReason::SecurityProblems, at(1000));
+            s.set_link_state(LinkState::Down, at(1000));
+            s.set_link_state(LinkState::Down, at(1200));
+        });
+
+        assert_eq!(
+            downs(&state).as_slice(),
+            &[None, Some(DisconnectReason::SecurityProblems)]
+        );
+    }
+
+    #[test]
+    fn network_down_before_disconnect() {
+        let state = State::new();
+        link_up(&state);
+
+        with_shared(&state, |s| {
+            s.set_link_state(LinkState::Down, at(1000));
+            s.set_disconnect_reason(DisconnectReason::OutOfRange, at(1500));
+        });
+
+        assert_eq!(
+            downs(&state).as_slice(),
+            &[None, Some(DisconnectReason::OutOfRange)]
+        );
+
+        // The reason is not carried over to the next disconnect
+        with_shared(&state, |s| {
+            s.set_link_state(LinkState::Up, at(5000));
+            s.set_link_state(LinkState::Down, at(6000));
+        });
+        assert_eq!(
+            downs(&state).as_slice(),
+            &[None, Some(DisconnectReason::OutOfRange), None]
+        );
+    }
+
+    #[test]
+    fn single_urc() {
+        let state = State::new();
+        link_up(&state);
+
+        // Only the network down is reported
+        with_shared(&state, |s| s.set_link_state(LinkState::Down, at(1000)));
+        assert_eq!(downs(&state).as_slice(), &[None, None]);
+
+        // A disconnect long after is not attributed to it, nor to the next
+        // link down transition
+        with_shared(&state, |s| {
+            s.set_disconnect_reason(DisconnectReason::OutOfRange, at(10_000));
+            s.set_link_state(LinkState::Up, at(20_000));
+            s.set_link_state(LinkState::Down, at(30_000));
+        });
+        assert_eq!(downs(&state).as_slice(), &[None, None, None]);
+    }
+
+    #[test]
+    fn roaming_holds_link_up() {
+        let state = State::new();
+        link_up(&state);
+
+        // The station disconnects to re-associate
+        with_shared(&state, |s| {
+            s.roaming = true;
+            s.set_disconnect_reason(DisconnectReason::Roaming, at(1000));
+            s.update_link_state(at(1000));
+        });
+        assert_eq!(downs(&state).as_slice(), &[None]);
+
+        // The re-association failed
+        with_shared(&state, |s| {
+            s.roaming = false;
+            s.update_link_state(at(2000));
+        });
+        assert_eq!(
+            downs(&state).as_slice(),
+            &[None, Some(DisconnectReason::Roaming)]
+        );
+    }
 }
@@ -1,6 +1,7 @@
 use core::{cell::RefCell, future::poll_fn, task::Poll};
 
 use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{Duration, Instant};
 use embedded_nal_async::AddrType;
 use no_std_net::IpAddr;
 
@@ -18,6 +19,13 @@ pub enum Error {
     NameTooLong,
     /// Name lookup failed
     Failed,
+    /// Name lookup did not complete within
+    /// [`Timeouts::dns`](crate::timeouts::Timeouts::dns)
+    Timeout,
+    /// No name is known for the address
+    NotFound,
+    /// Too many other queries are in flight
+    TooManyQueries,
 }
 
 /// From u-connectXpress AT commands manual:
@@ -31,10 +39,34 @@ pub const MAX_DOMAIN_NAME_LENGTH: usize = 64;
 #[cfg(feature = "nina-w1xx")]
 pub const MAX_DOMAIN_NAME_LENGTH: usize = 128;
 
+/// Default time after which a pending resolve is given up on, see
+/// [`Timeouts::dns`](crate::timeouts::Timeouts::dns). The module gives up on
+/// an unresponsive DNS server after 8 seconds.
+pub const DNS_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub struct DnsTableEntry {
     pub domain_name: heapless::String<MAX_DOMAIN_NAME_LENGTH>,
     pub state: DnsState,
     pub waker: WakerRegistration,
+    /// Deadline of the resolve, while pending.
+    deadline: Option<Instant>,
+    /// The caller gave up on the query, so the entry is freed once the
+    /// module is done with it.
+    cancelled: bool,
+    /// How the module is asked to resolve the name.
+    method: ResolveMethod,
+}
+
+/// How the module is asked to resolve a name, see
+/// [`UbloxStack::set_resolve_command`].
+#[derive(PartialEq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResolveMethod {
+    /// A single ping of the host, reporting the address with the `+UUPING`
+    /// URC of the echo reply.
+    Ping,
+    /// The `+UDNSRN` resolution command.
+    Command,
 }
 
 #[derive(PartialEq, Clone)]
@@ -51,36 +83,228 @@ impl DnsTableEntry {
             domain_name,
             state: DnsState::New,
             waker: WakerRegistration::new(),
+            deadline: None,
+            cancelled: false,
+            method: ResolveMethod::Ping,
         }
     }
+
+    fn in_flight(&self) -> bool {
+        matches!(self.state, DnsState::New | DnsState::Pending)
+    }
 }
 
 pub struct DnsTable {
     pub table: heapless::Deque<DnsTableEntry, 4>,
+    /// Time after which a pending resolve is given up on.
+    timeout: Duration,
+    /// Resolve names with the `+UDNSRN` command first.
+    resolve_command: bool,
+    /// Name of the last resolving ping issued, until the module reports its
+    /// outcome, along with the time after which the module is done with it
+    /// regardless. See [`DnsTable::fail_pending`].
+    ping: Option<(heapless::String<MAX_DOMAIN_NAME_LENGTH>, Instant)>,
 }
 
 impl DnsTable {
     pub const fn new() -> Self {
         Self {
             table: heapless::Deque::new(),
+            timeout: DNS_TIMEOUT,
+            resolve_command: false,
+            ping: None,
         }
     }
-    pub fn upsert(&mut self, new_entry: DnsTableEntry) {
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Resolve names submitted from now on with the `+UDNSRN` command first,
+    /// falling back to a ping if the command fails.
+    pub fn set_resolve_command(&mut self, enabled: bool) {
+        self.resolve_command = enabled;
+    }
+
+    fn method(&self) -> ResolveMethod {
+        match self.resolve_command {
+            true => ResolveMethod::Command,
+            false => ResolveMethod::Ping,
+        }
+    }
+
+    /// Submit a query, joining a query for the same name that is already in
+    /// flight.
+    ///
+    /// A full table makes room by evicting the oldest completed query. Queries
+    /// in flight are never evicted, so fails with [`Error::TooManyQueries`]
+    /// if all of them are.
+    pub fn upsert(&mut self, mut new_entry: DnsTableEntry) -> Result<(), Error> {
+        let method = self.method();
         if let Some(entry) = self
             .table
             .iter_mut()
             .find(|e| e.domain_name == new_entry.domain_name)
         {
-            entry.state = new_entry.state;
-            return;
+            if !entry.in_flight() {
+                entry.state = new_entry.state;
+                entry.deadline = None;
+                entry.method = method;
+            }
+            entry.cancelled = false;
+            return Ok(());
         }
 
         if self.table.is_full() {
-            self.table.pop_front();
+            let mut evicted = false;
+            for _ in 0..self.table.len() {
+                let Some(entry) = self.table.pop_front() else {
+                    break;
+                };
+                if evicted || entry.in_flight() {
+                    unsafe {
+                        self.table.push_back_unchecked(entry);
+                    }
+                } else {
+                    evicted = true;
+                }
+            }
+            if !evicted {
+                return Err(Error::TooManyQueries);
+            }
         }
+        new_entry.method = method;
         unsafe {
             self.table.push_back_unchecked(new_entry);
         }
+        Ok(())
+    }
+
+    /// Start the next resolve through `method`, if no other resolve is
+    /// pending.
+    ///
+    /// Resolves are issued one at a time, so a resolve stuck on an
+    /// unresponsive DNS server holds up other resolves only, while other
+    /// commands are interleaved. The deadline of a resolve covers its
+    /// fallback, see [`DnsTable::fall_back`].
+    ///
+    /// A ping is only issued once the module is done with the previous one,
+    /// even if its resolve timed out already, so a late error of the previous
+    /// ping is not taken for that of the next.
+    pub fn next_query(&mut self, now: Instant, method: ResolveMethod) -> Option<&DnsTableEntry> {
+        self.expire(now);
+
+        if self.table.iter().any(|e| e.state == DnsState::Pending) {
+            return None;
+        }
+        if method == ResolveMethod::Ping && self.ping_outstanding(now) {
+            return None;
+        }
+
+        let timeout = self.timeout;
+        let entry = self
+            .table
+            .iter_mut()
+            .find(|e| e.state == DnsState::New && e.method == method)?;
+        entry.state = DnsState::Pending;
+        entry.deadline.get_or_insert(now + timeout);
+        if method == ResolveMethod::Ping {
+            // The module gives up on the ping within `DNS_TIMEOUT`
+            self.ping = Some((entry.domain_name.clone(), now + timeout.max(DNS_TIMEOUT)));
+        }
+        Some(entry)
+    }
+
+    /// Resolve `domain_name` through a ping, after the resolution command
+    /// failed for it.
+    pub fn fall_back(&mut self, domain_name: &str) {
+        if let Some(entry) = self.get_mut(domain_name) {
+            if entry.state == DnsState::Pending && entry.method == ResolveMethod::Command {
+                entry.state = DnsState::New;
+                entry.method = ResolveMethod::Ping;
+            }
+        }
+        self.free_cancelled();
+    }
+
+    /// Fail pending resolves past their deadline.
+    pub fn expire(&mut self, now: Instant) {
+        for entry in self.table.iter_mut() {
+            if entry.state == DnsState::Pending && entry.deadline.is_some_and(|d| now >= d) {
+                warn!("DNS query for {} timed out", entry.domain_name.as_str());
+                entry.state = DnsState::Error(PingError::Timeout);
+                entry.waker.wake();
+            }
+        }
+        self.free_cancelled();
+    }
+
+    /// Whether the module may still report the outcome of the last resolving
+    /// ping issued.
+    pub fn ping_outstanding(&self, now: Instant) -> bool {
+        self.ping.as_ref().is_some_and(|(_, end)| now < *end)
+    }
+
+    /// Complete the pending resolve of `domain_name`.
+    pub fn complete(&mut self, domain_name: &str, state: DnsState) {
+        if self
+            .ping
+            .as_ref()
+            .is_some_and(|(name, _)| name.as_str() == domain_name)
+        {
+            self.ping = None;
+        }
+        if let Some(entry) = self.get_mut(domain_name) {
+            if entry.state == DnsState::Pending {
+                entry.state = state;
+                entry.waker.wake();
+            }
+        }
+        self.free_cancelled();
+    }
+
+    /// Complete the resolve of the last ping issued, on an error of a ping
+    /// not attributed to a name. The error of a ping that timed out already
+    /// only concludes the ping.
+    pub fn fail_pending(&mut self, error: PingError) {
+        let Some((domain_name, _)) = self.ping.take() else {
+            return;
+        };
+        if let Some(entry) = self.get_mut(&domain_name) {
+            if entry.state == DnsState::Pending && entry.method == ResolveMethod::Ping {
+                entry.state = DnsState::Error(error);
+                entry.waker.wake();
+            }
+        }
+        self.free_cancelled();
+    }
+
+    /// Give up on the query for `domain_name`. Queries not yet issued are
+    /// freed right away, pending ones once the module is done with them.
+    pub fn cancel(&mut self, domain_name: &str) {
+        if let Some(entry) = self.get_mut(domain_name) {
+            if entry.in_flight() {
+                entry.cancelled = true;
+            }
+        }
+        self.free_cancelled();
+    }
+
+    fn free_cancelled(&mut self) {
+        for _ in 0..self.table.len() {
+            let Some(entry) = self.table.pop_front() else {
+                break;
+            };
+            if !(entry.cancelled && entry.state != DnsState::Pending) {
+                unsafe {
+                    self.table.push_back_unchecked(entry);
+                }
+            }
+        }
     }
 
     pub fn get(&self, domain_name: &str) -> Option<&DnsTableEntry> {
@@ -123,6 +347,10 @@ impl<'a> DnsSocket<'a> {
     }
 
     /// Make a query for a given name and return the corresponding IP addresses.
+    ///
+    /// Fails with [`Error::Timeout`] if the module has not resolved the name
+    /// within [`Timeouts::dns`](crate::timeouts::Timeouts::dns). The next
+    /// query for the name is then issued to the module anew.
     pub async fn query(&self, name: &str, addr_type: AddrType) -> Result<IpAddr, Error> {
         match addr_type {
             AddrType::IPv4 => {
@@ -142,15 +370,26 @@ impl<'a> DnsSocket<'a> {
 
         {
             let mut s = self.stack.borrow_mut();
-            s.dns_table.upsert(DnsTableEntry::new(name_string.clone()));
+            s.dns_table
+                .upsert(DnsTableEntry::new(name_string.clone()))?;
             s.waker.wake();
         }
 
+        // Free the entry if the caller gives up on the query
+        let _guard = CancelOnDrop {
+            stack: self.stack,
+            domain_name: &name_string,
+        };
+
         poll_fn(|cx| {
             let mut s = self.stack.borrow_mut();
-            let query = s.dns_table.get_mut(&name_string).unwrap();
+            let Some(query) = s.dns_table.get_mut(&name_string) else {
+                // Evicted by other queries
+                return Poll::Ready(Err(Error::Failed));
+            };
             match query.state {
                 DnsState::Resolved(ip) => Poll::Ready(Ok(ip)),
+                DnsState::Error(PingError::Timeout) => Poll::Ready(Err(Error::Timeout)),
                 DnsState::Error(_e) => Poll::Ready(Err(Error::Failed)),
                 _ => {
                     query.waker.register(cx.waker());
@@ -160,6 +399,34 @@ impl<'a> DnsSocket<'a> {
         })
         .await
     }
+
+    /// Look up the name that resolved to `addr`, writing it to `result` and
+    /// returning its length.
+    ///
+    /// The module does not do reverse DNS, so only the names of addresses
+    /// previously resolved through the stack, and still in the DNS table, are
+    /// known.
+    pub fn reverse_query(&self, addr: IpAddr, result: &mut [u8]) -> Result<usize, Error> {
+        let s = self.stack.borrow();
+        let name = s.dns_table.reverse_lookup(addr).ok_or(Error::NotFound)?;
+        result
+            .get_mut(..name.len())
+            .ok_or(Error::NameTooLong)?
+            .copy_from_slice(name.as_bytes());
+        Ok(name.len())
+    }
+}
+
+struct CancelOnDrop<'a> {
+    stack: &'a RefCell<SocketStack>,
+    domain_name: &'a str,
+}
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        // Completed queries are kept, for reverse lookups
+        self.stack.borrow_mut().dns_table.cancel(self.domain_name);
+    }
 }
 
 impl<'a> embedded_nal_async::Dns for DnsSocket<'a> {
@@ -175,9 +442,288 @@ impl<'a> embedded_nal_async::Dns for DnsSocket<'a> {
 
     async fn get_host_by_address(
         &self,
-        _addr: IpAddr,
-        _result: &mut [u8],
+        addr: IpAddr,
+        result: &mut [u8],
     ) -> Result<usize, Self::Error> {
-        unimplemented!()
+        self.reverse_query(addr, result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::pin::pin;
+
+    use super::super::fixture::socket_stack;
+
+    fn query(domain_name: &str) -> DnsTableEntry {
+        DnsTableEntry::new(heapless::String::try_from(domain_name).unwrap())
+    }
+
+    #[test]
+    fn one_resolve_at_a_time() {
+        let mut table = DnsTable::new();
+        table.upsert(query("a.example.com")).unwrap();
+        table.upsert(query("b.example.com")).unwrap();
+
+        let now = Instant::from_secs(0);
+        assert_eq!(
+            table
+                .next_query(now, ResolveMethod::Ping)
+                .unwrap()
+                .domain_name
+                .as_str(),
+            "a.example.com"
+        );
+        assert!(table.next_query(now, ResolveMethod::Ping).is_none());
+
+        // Joining the pending query does not issue it again
+        table.upsert(query("a.example.com")).unwrap();
+        assert!(table.next_query(now, ResolveMethod::Ping).is_none());
+
+        let ip = IpAddr::V4(no_std_net::Ipv4Addr::new(10, 0, 0, 1));
+        table.complete("a.example.com", DnsState::Resolved(ip));
+        assert_eq!(
+            table
+                .next_query(now, ResolveMethod::Ping)
+                .unwrap()
+                .domain_name
+                .as_str(),
+            "b.example.com"
+        );
+        assert_eq!(table.reverse_lookup(ip), Some("a.example.com"));
+    }
+
+    #[test]
+    fn resolve_times_out() {
+        let mut table = DnsTable::new();
+        table.upsert(query("a.example.com")).unwrap();
+        table.upsert(query("b.example.com")).unwrap();
+
+        let start = Instant::from_secs(0);
+        assert!(table.next_query(start, ResolveMethod::Ping).is_some());
+        assert!(table
+            .next_query(start + Duration::from_secs(9), ResolveMethod::Ping)
+            .is_none());
+
+        let query = table
+            .next_query(start + DNS_TIMEOUT, ResolveMethod::Ping)
+            .unwrap();
+        assert_eq!(query.domain_name.as_str(), "b.example.com");
+        assert!(table.get("a.example.com").unwrap().state == DnsState::Error(PingError::Timeout));
+
+        // A late response is ignored
+        let ip = IpAddr::V4(no_std_net::Ipv4Addr::new(10, 0, 0, 1));
+        table.complete("a.example.com", DnsState::Resolved(ip));
+        assert!(table.get("a.example.com").unwrap().state == DnsState::Error(PingError::Timeout));
+    }
+
+    #[test]
+    fn late_ping_error_not_taken_for_next_query() {
+        let mut table = DnsTable::new();
+        table.set_timeout(Duration::from_secs(2));
+        table.upsert(query("a.example.com")).unwrap();
+        table.upsert(query("b.example.com")).unwrap();
+
+        let start = Instant::from_secs(0);
+        assert!(table.next_query(start, ResolveMethod::Ping).is_some());
+
+        // The resolve timed out, but the module is still pinging
+        let later = start + Duration::from_secs(2);
+        assert!(table.next_query(later, ResolveMethod::Ping).is_none());
+        assert!(table.get("a.example.com").unwrap().state == DnsState::Error(PingError::Timeout));
+
+        // The late error only concludes the ping of the first name
+        table.fail_pending(PingError::CannotResolveHost);
+        let next = table.next_query(later, ResolveMethod::Ping).unwrap();
+        assert_eq!(next.domain_name.as_str(), "b.example.com");
+        assert!(table.get("a.example.com").unwrap().state == DnsState::Error(PingError::Timeout));
+
+        // An error without a ping outstanding is ignored
+        table.complete("b.example.com", DnsState::Error(PingError::Other));
+        table.upsert(query("c.example.com")).unwrap();
+        table.fail_pending(PingError::CannotResolveHost);
+        assert!(table.get("c.example.com").unwrap().state == DnsState::New);
+    }
+
+    #[test]
+    fn pending_query_not_evicted() {
+        let mut table = DnsTable::new();
+        let now = Instant::from_secs(0);
+        table.upsert(query("a.example.com")).unwrap();
+        assert!(table.next_query(now, ResolveMethod::Ping).is_some());
+        for name in ["b.example.com", "c.example.com", "d.example.com"] {
+            table.upsert(query(name)).unwrap();
+        }
+        assert_eq!(
+            table.upsert(query("e.example.com")),
+            Err(Error::TooManyQueries)
+        );
+
+        // A completed query makes room, while the pending one is kept
+        let ip = IpAddr::V4(no_std_net::Ipv4Addr::new(10, 0, 0, 1));
+        table.complete("a.example.com", DnsState::Resolved(ip));
+        let next = table.next_query(now, ResolveMethod::Ping).unwrap();
+        assert_eq!(next.domain_name.as_str(), "b.example.com");
+        table.upsert(query("e.example.com")).unwrap();
+        assert!(table.get("a.example.com").is_none());
+        assert!(table.get("b.example.com").unwrap().state == DnsState::Pending);
+        assert!(table.get("e.example.com").unwrap().state == DnsState::New);
+    }
+
+    #[test]
+    fn resolve_times_out_after_configured_timeout() {
+        let mut table = DnsTable::new();
+        table.set_timeout(Duration::from_secs(2));
+        table.upsert(query("a.example.com")).unwrap();
+
+        let start = Instant::from_secs(0);
+        assert!(table.next_query(start, ResolveMethod::Ping).is_some());
+        table.expire(start + Duration::from_secs(1));
+        assert!(table.get("a.example.com").unwrap().state == DnsState::Pending);
+
+        table.expire(start + Duration::from_secs(2));
+        assert!(table.get("a.example.com").unwrap().state == DnsState::Error(PingError::Timeout));
+    }
+
+    #[test]
+    fn resolve_command_falls_back_to_ping() {
+        let mut table = DnsTable::new();
+        table.set_resolve_command(true);
+        table.upsert(query("a.example.com")).unwrap();
+
+        let start = Instant::from_secs(0);
+        assert!(table.next_query(start, ResolveMethod::Ping).is_none());
+        assert!(table.next_query(start, ResolveMethod::Command).is_some());
+
+        // A ping error is not taken for that of the resolution command
+        table.fail_pending(PingError::Other);
+        assert!(table.get("a.example.com").unwrap().state == DnsState::Pending);
+
+        table.fall_back("a.example.com");
+        assert!(table.next_query(start, ResolveMethod::Command).is_none());
+        let later = start + Duration::from_secs(5);
+        assert!(table.next_query(later, ResolveMethod::Ping).is_some());
+
+        // The fallback is part of the same resolve, and of its deadline
+        table.expire(start + DNS_TIMEOUT);
+        assert!(table.get("a.example.com").unwrap().state == DnsState::Error(PingError::Timeout));
+    }
+
+    #[test]
+    fn query_times_out() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let socket = DnsSocket { stack: &stack };
+
+        let start = Instant::from_secs(0);
+        {
+            let mut query = pin!(socket.query("a.example.com", AddrType::IPv4));
+            assert!(embassy_futures::poll_once(query.as_mut()).is_pending());
+
+            assert!(stack
+                .borrow_mut()
+                .dns_table
+                .next_query(start, ResolveMethod::Ping)
+                .is_some());
+            assert!(embassy_futures::poll_once(query.as_mut()).is_pending());
+
+            stack.borrow_mut().dns_table.expire(start + DNS_TIMEOUT);
+            assert_eq!(
+                embassy_futures::poll_once(query.as_mut()),
+                Poll::Ready(Err(Error::Timeout))
+            );
+        }
+
+        // The next query starts over
+        let mut query = pin!(socket.query("a.example.com", AddrType::IPv4));
+        assert!(embassy_futures::poll_once(query.as_mut()).is_pending());
+        assert!(stack.borrow().dns_table.get("a.example.com").unwrap().state == DnsState::New);
+    }
+
+    #[test]
+    fn cancel() {
+        let mut table = DnsTable::new();
+        table.upsert(query("a.example.com")).unwrap();
+        table.upsert(query("b.example.com")).unwrap();
+
+        // Not yet issued, so freed right away
+        table.cancel("b.example.com");
+        assert!(table.get("b.example.com").is_none());
+
+        // Pending, so kept until the module responds
+        let now = Instant::from_secs(0);
+        assert!(table.next_query(now, ResolveMethod::Ping).is_some());
+        table.cancel("a.example.com");
+        assert!(table.get("a.example.com").is_some());
+        assert!(table.next_query(now, ResolveMethod::Ping).is_none());
+
+        table.fail_pending(PingError::Other);
+        assert!(table.get("a.example.com").is_none());
+    }
+
+    #[test]
+    fn reverse_query() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let socket = DnsSocket { stack: &stack };
+
+        let ip = IpAddr::V4(no_std_net::Ipv4Addr::new(10, 0, 0, 1));
+        {
+            let table = &mut stack.borrow_mut().dns_table;
+            table.upsert(query("a.example.com")).unwrap();
+            table.next_query(Instant::from_secs(0), ResolveMethod::Ping);
+            table.complete("a.example.com", DnsState::Resolved(ip));
+        }
+
+        let mut buf = [0u8; 32];
+        let len = socket.reverse_query(ip, &mut buf).unwrap();
+        assert_eq!(&buf[..len], b"a.example.com");
+
+        // The name does not fit
+        assert_eq!(
+            socket.reverse_query(ip, &mut buf[..4]),
+            Err(Error::NameTooLong)
+        );
+
+        // Never resolved
+        let unknown = IpAddr::V4(no_std_net::Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(
+            socket.reverse_query(unknown, &mut buf),
+            Err(Error::NotFound)
+        );
+    }
+
+    #[test]
+    fn get_host_by_address() {
+        use embedded_nal_async::Dns;
+
+        let stack = RefCell::new(socket_stack::<1>());
+        let socket = DnsSocket { stack: &stack };
+
+        let ip = IpAddr::V4(no_std_net::Ipv4Addr::new(10, 0, 0, 1));
+        let other = IpAddr::V4(no_std_net::Ipv4Addr::new(10, 0, 0, 2));
+        {
+            let table = &mut stack.borrow_mut().dns_table;
+            table.upsert(query("a.example.com")).unwrap();
+            table.upsert(query("b.example.com")).unwrap();
+            let now = Instant::from_secs(0);
+            table.next_query(now, ResolveMethod::Ping);
+            table.complete("a.example.com", DnsState::Resolved(ip));
+            table.next_query(now, ResolveMethod::Ping);
+            table.complete("b.example.com", DnsState::Resolved(other));
+        }
+
+        // Each address maps back to the name that resolved to it
+        let mut buf = [0u8; 32];
+        let len = embassy_futures::block_on(socket.get_host_by_address(ip, &mut buf)).unwrap();
+        assert_eq!(&buf[..len], b"a.example.com");
+        let len = embassy_futures::block_on(socket.get_host_by_address(other, &mut buf)).unwrap();
+        assert_eq!(&buf[..len], b"b.example.com");
+
+        // An address no name resolved to
+        let unknown = IpAddr::V4(no_std_net::Ipv4Addr::new(10, 0, 0, 3));
+        assert_eq!(
+            embassy_futures::block_on(socket.get_host_by_address(unknown, &mut buf)),
+            Err(Error::NotFound)
+        );
     }
 }
@@ -0,0 +1,45 @@
+//! Socket stacks and sockets for the tests of the network stack.
+#[cfg(feature = "socket-tcp")]
+use ublox_sockets::tcp;
+#[cfg(feature = "socket-udp")]
+use ublox_sockets::udp;
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+use ublox_sockets::SocketHandle;
+use ublox_sockets::{SocketSet, SocketStorage};
+
+use super::SocketStack;
+
+/// An empty socket stack, with room for `N` sockets.
+pub(super) fn socket_stack<const N: usize>() -> SocketStack {
+    let storage = Box::leak(Box::new([SocketStorage::EMPTY; N]));
+    SocketStack::new(SocketSet::new(&mut storage[..]))
+}
+
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+fn buffer(len: usize) -> &'static mut [u8] {
+    Box::leak(vec![0u8; len].into_boxed_slice())
+}
+
+/// Add a TCP socket with 16 byte buffers to `s`.
+#[cfg(feature = "socket-tcp")]
+pub(super) fn tcp_socket(s: &mut SocketStack) -> SocketHandle {
+    tcp_socket_with(s, 16, 16)
+}
+
+/// Add a TCP socket with buffers of `rx_len` and `tx_len` bytes to `s`.
+#[cfg(feature = "socket-tcp")]
+pub(super) fn tcp_socket_with(s: &mut SocketStack, rx_len: usize, tx_len: usize) -> SocketHandle {
+    s.sockets.add(tcp::Socket::new(
+        tcp::SocketBuffer::new(buffer(rx_len)),
+        tcp::SocketBuffer::new(buffer(tx_len)),
+    ))
+}
+
+/// Add a UDP socket with 16 byte buffers to `s`.
+#[cfg(feature = "socket-udp")]
+pub(super) fn udp_socket(s: &mut SocketStack) -> SocketHandle {
+    s.sockets.add(udp::Socket::new(
+        udp::SocketBuffer::new(buffer(16)),
+        udp::SocketBuffer::new(buffer(16)),
+    ))
+}
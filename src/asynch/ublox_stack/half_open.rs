@@ -0,0 +1,137 @@
+//! Detection of half-open TCP connections.
+//!
+//! A remote host vanishing without closing the connection leaves the module
+//! reporting the peer as connected until its own timeout expires, while sent
+//! data disappears. Sockets with
+//! [`SocketOptions::half_open_check`](super::SocketOptions::half_open_check)
+//! set are monitored for sends without any data received in return, in which
+//! case the module is queried for its list of peers.
+use embassy_time::{Duration, Instant};
+
+/// Number of consecutive peer status checks without progress, after which
+/// the connection is considered half-open.
+const STALE_CHECKS: u8 = 2;
+
+/// Half-open detection state of a single socket.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HalfOpenMonitor {
+    /// Time of the last received data, or of the start of monitoring.
+    idle_since: Instant,
+    last_check: Option<Instant>,
+    /// Data has been sent since the last received data.
+    sent: bool,
+    stale_checks: u8,
+}
+
+impl HalfOpenMonitor {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self {
+            idle_since: now,
+            last_check: None,
+            sent: false,
+            stale_checks: 0,
+        }
+    }
+
+    pub(crate) fn on_rx(&mut self, now: Instant) {
+        *self = Self::new(now);
+    }
+
+    pub(crate) fn on_tx(&mut self) {
+        self.sent = true;
+    }
+
+    /// Whether a peer status check is due, after `idle` without received
+    /// data despite sends. Records the check as started.
+    pub(crate) fn poll_check(&mut self, now: Instant, idle: Duration) -> bool {
+        let due = self.sent
+            && now.saturating_duration_since(self.idle_since) >= idle
+            && self
+                .last_check
+                .map_or(true, |t| now.saturating_duration_since(t) >= idle);
+
+        if due {
+            self.last_check = Some(now);
+        }
+        due
+    }
+
+    /// Account for the result of a peer status check, returning `true` if the
+    /// connection is half-open.
+    ///
+    /// A connection is half-open if the module no longer lists the peer, or
+    /// if no data was received over [`STALE_CHECKS`] consecutive checks.
+    pub(crate) fn on_status(&mut self, peer_listed: bool) -> bool {
+        if !peer_listed {
+            return true;
+        }
+
+        self.stale_checks += 1;
+        self.stale_checks >= STALE_CHECKS
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const IDLE: Duration = Duration::from_secs(10);
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_secs(secs)
+    }
+
+    #[test]
+    fn peer_gone() {
+        let mut monitor = HalfOpenMonitor::new(at(0));
+        monitor.on_tx();
+
+        assert!(!monitor.poll_check(at(5), IDLE));
+        assert!(monitor.poll_check(at(10), IDLE));
+        // The check is in progress
+        assert!(!monitor.poll_check(at(11), IDLE));
+
+        assert!(monitor.on_status(false));
+    }
+
+    #[test]
+    fn no_progress() {
+        let mut monitor = HalfOpenMonitor::new(at(0));
+        monitor.on_tx();
+
+        // Scripted status responses, all listing the peer
+        assert!(monitor.poll_check(at(10), IDLE));
+        assert!(!monitor.on_status(true));
+
+        assert!(!monitor.poll_check(at(15), IDLE));
+        assert!(monitor.poll_check(at(20), IDLE));
+        assert!(monitor.on_status(true));
+    }
+
+    #[test]
+    fn healthy_traffic() {
+        let mut monitor = HalfOpenMonitor::new(at(0));
+
+        for secs in (0..100).step_by(5) {
+            monitor.on_tx();
+            monitor.on_rx(at(secs + 1));
+            assert!(!monitor.poll_check(at(secs + 4), IDLE));
+        }
+
+        // A check that found the peer listed is forgotten on received data
+        monitor.on_tx();
+        assert!(monitor.poll_check(at(120), IDLE));
+        assert!(!monitor.on_status(true));
+        monitor.on_rx(at(121));
+        monitor.on_tx();
+        assert!(monitor.poll_check(at(131), IDLE));
+        assert!(!monitor.on_status(true));
+    }
+
+    #[test]
+    fn idle_without_sends() {
+        let mut monitor = HalfOpenMonitor::new(at(0));
+
+        assert!(!monitor.poll_check(at(1000), IDLE));
+    }
+}
@@ -1,3 +1,5 @@
+#[cfg(feature = "socket-udp")]
+pub mod sntp;
 #[cfg(feature = "socket-tcp")]
 pub mod tcp;
 #[cfg(feature = "socket-tcp")]
@@ -7,49 +9,109 @@ pub mod udp;
 
 mod device;
 pub mod dns;
+#[cfg(test)]
+mod fixture;
+#[cfg(feature = "socket-tcp")]
+mod half_open;
+#[cfg(feature = "socket-tcp")]
+mod paused_rx;
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
 mod peer_builder;
+mod peer_set;
+mod urc_lanes;
 
 pub use device::Device;
+#[cfg(feature = "socket-tcp")]
+pub use paused_rx::MAX_STAGED_RX;
+#[cfg(feature = "socket-tcp")]
+pub use peer_builder::{SocketOptions, TCP_MSS_RANGE};
 
 use core::cell::RefCell;
 use core::future::poll_fn;
-use core::ops::{DerefMut, Rem};
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+use core::ops::DerefMut;
 use core::task::Poll;
 
-use crate::command::data_mode::responses::ConnectPeerResponse;
-use crate::command::data_mode::urc::PeerDisconnected;
-use crate::command::data_mode::{ClosePeerConnection, ConnectPeer};
-use crate::command::edm::types::{DataEvent, Protocol};
+use crate::command::data_mode::{responses::PeerListResponse, ClosePeerConnection, PeerList};
+use crate::command::dns::responses::ResolveNameResponse;
+use crate::command::dns::types::ResolutionType;
+use crate::command::dns::ResolveName;
+use crate::command::edm::types::{DataEvent, EdmCapabilities, PAYLOAD_OVERHEAD};
 use crate::command::edm::urc::EdmEvent;
-use crate::command::edm::{EdmAtCmdWrapper, EdmDataCommand};
+use crate::command::edm::EdmAtCmdWrapper;
+use crate::command::network::urc::NetworkDown;
 use crate::command::ping::types::PingError;
 use crate::command::ping::urc::{PingErrorResponse, PingResponse};
 use crate::command::ping::Ping;
 use crate::command::Urc;
-use peer_builder::{PeerUrlBuilder, SecurityCredentials};
 
-use self::dns::{DnsSocket, DnsState, DnsTable};
+use self::dns::{DnsSocket, DnsState, DnsTable, ResolveMethod};
+use self::peer_set::PeerSet;
+use self::urc_lanes::UrcLanes;
 
-use super::control::ProxyClient;
+use super::control::{ProxyClient, CONFIG_ID};
+use super::detach::{
+    AttachCloseReason, AttachReport, DetachedPeer, DetachedState, PeerStatus, PendingAttach,
+    MAX_DETACHED_PEERS,
+};
+use super::runner::UrcCapacityCheck;
+use super::state;
 
 use embassy_futures::select;
 use embassy_sync::waitqueue::WakerRegistration;
-use embassy_time::{Duration, Ticker};
+use embassy_time::{Duration, Instant, Ticker};
 use embedded_nal_async::SocketAddr;
 use no_std_net::IpAddr;
 use portable_atomic::{AtomicBool, AtomicU8, Ordering};
-use ublox_sockets::{
-    AnySocket, ChannelId, PeerHandle, Socket, SocketHandle, SocketSet, SocketStorage,
+use ublox_sockets::{ChannelId, PeerHandle, Socket, SocketHandle, SocketSet, SocketStorage};
+
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+use self::peer_builder::PeerUrlBuilder;
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+use crate::command::data_mode::{
+    responses::ConnectPeerResponse, types::IPProtocol, urc::PeerDisconnected, ConnectPeer,
 };
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+use crate::command::edm::{types::Protocol, EdmDataCommand};
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+use ublox_sockets::AnySocket;
 
+#[cfg(feature = "socket-tcp")]
+use self::half_open::HalfOpenMonitor;
+#[cfg(feature = "socket-tcp")]
+use self::paused_rx::PausedRx;
+#[cfg(feature = "socket-tcp")]
+use self::peer_builder::SecurityCredentials;
+#[cfg(feature = "socket-tcp")]
+use self::tcp::CloseReason;
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+use crate::command::data_mode::urc::PeerConnected;
+#[cfg(feature = "socket-tcp")]
+use crate::timeouts::Timeouts;
 #[cfg(feature = "socket-tcp")]
 use ublox_sockets::TcpState;
 
+#[cfg(feature = "socket-udp")]
+use crate::command::data_mode::{
+    types::{IPVersion, ServerType, UDPBehaviour},
+    ServerConfiguration,
+};
 #[cfg(feature = "socket-udp")]
 use ublox_sockets::UdpState;
 
 const MAX_EGRESS_SIZE: usize = 2048;
 
+/// Number of bytes written per EDM data packet, given the capabilities
+/// advertised by the module. The channel id takes one byte of the payload.
+///
+/// At least one byte is written per packet, even if the module advertises no
+/// room for data, so sending always makes progress.
+fn egress_chunk(capabilities: EdmCapabilities) -> usize {
+    (capabilities.max_payload as usize)
+        .saturating_sub(1)
+        .clamp(1, MAX_EGRESS_SIZE)
+}
+
 pub struct StackResources<const SOCK: usize> {
     sockets: [SocketStorage<'static>; SOCK],
 }
@@ -73,457 +135,3671 @@ pub struct UbloxStack<const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
     device: Device<'static, INGRESS_BUF_SIZE, URC_CAPACITY>,
     last_tx_socket: AtomicU8,
     should_tx: AtomicBool,
+    socket_capacity: usize,
+}
+
+/// Socket lifecycle transitions, logged with the `socket-trace` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(
+    not(any(feature = "socket-tcp", feature = "socket-udp")),
+    allow(dead_code)
+)]
+pub(crate) enum SocketTransition {
+    /// A socket was added to the socket set.
+    Create,
+    /// The module accepted a connect request, assigning a peer handle.
+    Connect,
+    /// The module reported the connection, assigning an EDM channel.
+    Connected,
+    /// Data of the given length was received on the channel.
+    Data(usize),
+    /// The socket requested the peer to be closed.
+    Close,
+    /// The module reported the EDM channel as closed.
+    ChannelClosed,
+    /// The module reported the peer as disconnected.
+    PeerClosed,
+    /// The socket was removed from the socket set.
+    Drop,
+}
+
+#[cfg(feature = "socket-trace")]
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct SocketTrace {
+    transition: SocketTransition,
+    handle: Option<SocketHandle>,
+    peer_handle: Option<PeerHandle>,
+    channel_id: Option<ChannelId>,
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    remote: Option<SocketAddr>,
+}
+
+/// Log a socket lifecycle transition, if the `socket-trace` feature is
+/// enabled. Compiles to nothing otherwise.
+#[inline(always)]
+#[allow(unused_variables)]
+pub(crate) fn trace_transition(
+    transition: SocketTransition,
+    handle: Option<SocketHandle>,
+    peer_handle: Option<PeerHandle>,
+    channel_id: Option<ChannelId>,
+    remote: Option<SocketAddr>,
+) {
+    #[cfg(feature = "socket-trace")]
+    info!(
+        "[socket-trace] {:?}",
+        SocketTrace {
+            transition,
+            handle,
+            peer_handle,
+            channel_id,
+            remote,
+        }
+    );
+}
+
+/// Accounting of the data received on an EDM channel.
+///
+/// Every received byte is either delivered to a socket, staged for a paused
+/// socket, or attributed to one of the known loss categories. Data events lost
+/// before reaching the stack, due to an undersized URC channel, are counted in
+/// [`UrcStats::lost`](crate::asynch::UrcStats::lost) instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelRxStats {
+    /// Number of data events received on the channel.
+    pub events: u32,
+    /// Bytes received on the channel.
+    pub received: u64,
+    /// Bytes enqueued in the receive buffer of the socket.
+    pub delivered: u64,
+    /// Bytes dropped because the receive buffer of the socket was full.
+    pub overflow: u64,
+    /// Bytes dropped because no socket accepting data was bound to the
+    /// channel.
+    pub unknown_channel: u64,
+    /// Bytes currently staged for a paused socket, see
+    /// [`TcpSocket::pause_rx`](tcp::TcpSocket::pause_rx).
+    pub staged: u64,
+    /// Bytes dropped because the socket was paused, with its staging limit
+    /// reached.
+    pub paused_overflow: u64,
+}
+
+/// What became of the data of a data event.
+#[derive(Debug, Clone, Copy)]
+enum RxOutcome {
+    /// Bytes enqueued in the receive buffer of a socket.
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    Delivered(usize),
+    /// Bytes staged for a paused socket.
+    #[cfg(feature = "socket-tcp")]
+    Staged(usize),
+    /// No socket took the data.
+    Unknown,
+}
+
+/// Peer URL of a TCP connection to `remote`.
+///
+/// The URL is canonical: equivalent connections have equal URLs, which
+/// [`TcpClient::connect_dedup`](tcp::client::TcpClient::connect_dedup) relies
+/// on to share connections.
+#[cfg(feature = "socket-tcp")]
+pub(crate) fn tcp_peer_url(
+    dns_table: &DnsTable,
+    remote: SocketAddr,
+    local_port: Option<u16>,
+    creds: Option<&SecurityCredentials>,
+    options: Option<&SocketOptions>,
+) -> Result<heapless::String<128>, crate::error::Error> {
+    let mut builder = PeerUrlBuilder::new();
+
+    if let Some(hostname) = dns_table.reverse_lookup(remote.ip()) {
+        builder.hostname(hostname).port(remote.port())
+    } else {
+        builder.address(&remote)
+    };
+
+    if let Some(creds) = creds {
+        builder.creds(creds);
+    }
+
+    if let Some(options) = options {
+        builder.options(options);
+    }
+
+    builder.set_local_port(local_port).tcp()
+}
+
+/// Establish the connection of a connecting TCP socket, once both the EDM
+/// connect event and the peer connected URC of its peer arrived, in any
+/// order. Only then is it safe to send, so this is what completes
+/// [`TcpSocket::connect`](tcp::TcpSocket::connect).
+#[cfg(feature = "socket-tcp")]
+fn establish(
+    handle: SocketHandle,
+    tcp: &mut ublox_sockets::tcp::Socket,
+    connected_peers: &mut ConnectedPeers,
+) {
+    let (Some(peer_handle), Some(channel_id)) = (tcp.peer_handle, tcp.edm_channel) else {
+        return;
+    };
+    let Some(i) = connected_peers.iter().position(|p| *p == peer_handle) else {
+        return;
+    };
+    if tcp.state() != TcpState::SynSent {
+        return;
+    }
+
+    connected_peers.swap_remove(i);
+    // Wakes the connecting task
+    tcp.set_state(TcpState::Established);
+    trace_transition(
+        SocketTransition::Connected,
+        Some(handle),
+        Some(peer_handle),
+        Some(channel_id),
+        tcp.remote_endpoint,
+    );
+}
+
+/// Identity of a socket for its entire life.
+///
+/// Unlike its [`SocketHandle`], which is reused by the next socket added to
+/// the set, and its [`PeerHandle`], which is reused by the module, the id of
+/// a socket is never shared with a socket alive at the same time. Responses
+/// to commands issued for a socket carry its id, so they cannot be mistaken
+/// for responses to a socket that took over its handle in the meantime.
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub(crate) struct SocketId(u16);
+
+/// Number of sockets that can be given a [`SocketId`].
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+const MAX_SOCKET_IDS: usize = 16;
+
+/// Checks that the socket set of a stack fits the per-socket state of
+/// [`SocketStack`], which holds up to [`MAX_SOCKET_IDS`] entries.
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+struct SocketSetCheck<const SOCK: usize>;
+
+#[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+impl<const SOCK: usize> SocketSetCheck<SOCK> {
+    const OK: () = assert!(
+        SOCK <= MAX_SOCKET_IDS,
+        "The socket set holds more sockets than the stack supports"
+    );
+}
+
+/// Why the connections of the TCP sockets were closed, see
+/// [`TcpSocket::close_reason`](tcp::TcpSocket::close_reason).
+#[cfg(feature = "socket-tcp")]
+type CloseReasons = heapless::FnvIndexMap<SocketHandle, CloseReason, MAX_SOCKET_IDS>;
+
+/// TCP peers reported connected by the module, see [`establish`].
+#[cfg(feature = "socket-tcp")]
+type ConnectedPeers = heapless::Vec<PeerHandle, MAX_SOCKET_IDS>;
+
+/// Record why the connection of `handle` was closed.
+#[cfg(feature = "socket-tcp")]
+fn record_close_reason(
+    close_reasons: &mut CloseReasons,
+    handle: SocketHandle,
+    reason: CloseReason,
+) {
+    // Room for every socket of the set, see `SocketSetCheck`
+    if close_reasons.insert(handle, reason).is_err() {
+        error!("No room for the close reason of socket {}", handle);
+    }
+}
+
+/// Datagrams whose boundaries are kept per UDP socket. Further datagrams are
+/// merged into the last one until the socket catches up.
+#[cfg(feature = "socket-udp")]
+const MAX_UDP_DATAGRAMS: usize = 8;
+
+/// Lengths of the datagrams in the receive buffers of the UDP sockets, which
+/// do not keep the boundaries themselves.
+#[cfg(feature = "socket-udp")]
+type UdpDatagrams =
+    heapless::FnvIndexMap<SocketHandle, heapless::Deque<usize, MAX_UDP_DATAGRAMS>, MAX_SOCKET_IDS>;
+
+/// Record a datagram of `len` bytes received by `handle`.
+#[cfg(feature = "socket-udp")]
+fn record_datagram(udp_datagrams: &mut UdpDatagrams, handle: SocketHandle, len: usize) {
+    // Room for every socket of the set, see `SocketSetCheck`
+    if !udp_datagrams.contains_key(&handle)
+        && udp_datagrams
+            .insert(handle, heapless::Deque::new())
+            .is_err()
+    {
+        error!("No room for the datagrams of socket {}", handle);
+        return;
+    }
+    if let Some(lengths) = udp_datagrams.get_mut(&handle) {
+        if let Err(len) = lengths.push_back(len) {
+            if let Some(last) = lengths.back_mut() {
+                *last += len;
+            }
+        }
+    }
+}
+
+/// Module server ids used for UDP sockets bound to a local port. The lower
+/// ids are left for the application.
+#[cfg(feature = "socket-udp")]
+pub(crate) const UDP_SERVER_IDS: [u8; 2] = [5, 6];
+
+/// Dynamic ports, allocated to UDP sockets bound to port 0.
+#[cfg(feature = "socket-udp")]
+const DYNAMIC_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// Time after which the peer the module opened for the most recent sender
+/// to a bound UDP socket is closed, if nothing was received from it.
+#[cfg(feature = "socket-udp")]
+const UDP_SENDER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A UDP socket bound to a local port, with the module server listening on
+/// it.
+#[cfg(feature = "socket-udp")]
+pub(crate) struct UdpListener {
+    pub(crate) port: u16,
+    pub(crate) server_id: u8,
+    /// Whether the server has been configured in the module.
+    pub(crate) active: bool,
+    /// When the socket last received from its current sender, see
+    /// [`UDP_SENDER_IDLE_TIMEOUT`].
+    pub(crate) last_rx: Instant,
 }
 
+/// Number of EDM channels for which receive statistics are kept.
+const RX_STATS_CHANNELS: usize = 16;
+
+/// Attempts to list the peers of the module on attach, before the peers of
+/// the previous host boot are given up on.
+const MAX_ATTACH_ATTEMPTS: u8 = 3;
+
 pub(crate) struct SocketStack {
     sockets: SocketSet<'static>,
     waker: WakerRegistration,
     dns_table: DnsTable,
-    dropped_sockets: heapless::Vec<PeerHandle, 3>,
+    /// A ping of the application is in progress, see
+    /// [`Control::ping`](crate::asynch::control::Control::ping). Resolves
+    /// share its URCs, so they are held back until it is done.
+    ping_active: bool,
+    dropped_sockets: PeerSet,
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    socket_ids: heapless::FnvIndexMap<SocketHandle, SocketId, MAX_SOCKET_IDS>,
+    /// Next id to try for a new socket, see [`SocketStack::add_socket_id`].
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    next_socket_id: u16,
+    #[cfg(feature = "socket-tcp")]
     credential_map: heapless::FnvIndexMap<SocketHandle, SecurityCredentials, 2>,
+    #[cfg(feature = "socket-tcp")]
+    socket_options: heapless::FnvIndexMap<SocketHandle, SocketOptions, MAX_SOCKET_IDS>,
+    #[cfg(feature = "socket-udp")]
+    udp_listeners: heapless::FnvIndexMap<SocketHandle, UdpListener, MAX_SOCKET_IDS>,
+    /// Server ids of dropped UDP listeners, to be disabled in the module.
+    #[cfg(feature = "socket-udp")]
+    stopped_servers: heapless::Vec<u8, 2>,
+    /// UDP sockets whose peer the module rejected, see
+    /// [`SocketStack::connect_failed`].
+    #[cfg(feature = "socket-udp")]
+    udp_connect_failed: heapless::FnvIndexSet<SocketHandle, MAX_SOCKET_IDS>,
+    #[cfg(feature = "socket-udp")]
+    udp_datagrams: UdpDatagrams,
+    /// Next dynamic port to try for a UDP socket bound to port 0.
+    #[cfg(feature = "socket-udp")]
+    next_local_port: u16,
+    rx_stats: heapless::FnvIndexMap<u8, ChannelRxStats, RX_STATS_CHANNELS>,
+    #[cfg(feature = "socket-tcp")]
+    half_open: heapless::FnvIndexMap<SocketHandle, HalfOpenMonitor, MAX_SOCKET_IDS>,
+    #[cfg(feature = "socket-tcp")]
+    close_reasons: CloseReasons,
+    #[cfg(feature = "socket-tcp")]
+    paused_rx: heapless::FnvIndexMap<SocketHandle, PausedRx, MAX_SOCKET_IDS>,
+    /// TCP peers reported connected by the module, whose socket did not get
+    /// its EDM channel yet, see [`establish`].
+    #[cfg(feature = "socket-tcp")]
+    connected_peers: ConnectedPeers,
+    #[cfg(feature = "socket-tcp")]
+    connect_timeout: Duration,
+    /// Maximum number of bytes written per EDM data packet.
+    egress_chunk: usize,
+    link_up: bool,
+    /// Incremented every time the link comes up.
+    link_epoch: u32,
+    /// The state of the driver was persisted, see [`UbloxStack::detach`].
+    detached: bool,
+    /// Attach waiting for its peers to be reconciled with the module.
+    pending_attach: Option<PendingAttach>,
+    /// Failed attempts to list the peers for the pending attach.
+    attach_attempts: u8,
+    attach_report: Option<AttachReport>,
+    /// Peers of the previous host boot, still alive and not yet adopted by
+    /// a socket.
+    attached_peers: heapless::Vec<DetachedPeer, MAX_DETACHED_PEERS>,
 }
 
-impl<const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
-    UbloxStack<INGRESS_BUF_SIZE, URC_CAPACITY>
-{
-    pub fn new<const SOCK: usize>(
-        device: Device<'static, INGRESS_BUF_SIZE, URC_CAPACITY>,
-        resources: &'static mut StackResources<SOCK>,
-    ) -> Self {
-        let sockets = SocketSet::new(&mut resources.sockets[..]);
-
-        let socket = SocketStack {
+impl SocketStack {
+    fn new(sockets: SocketSet<'static>) -> Self {
+        Self {
             sockets,
             dns_table: DnsTable::new(),
+            ping_active: false,
             waker: WakerRegistration::new(),
-            dropped_sockets: heapless::Vec::new(),
+            dropped_sockets: PeerSet::new(),
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            socket_ids: heapless::IndexMap::new(),
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            next_socket_id: 0,
+            #[cfg(feature = "socket-tcp")]
             credential_map: heapless::IndexMap::new(),
-        };
-
-        Self {
-            socket: RefCell::new(socket),
-            device,
-            last_tx_socket: AtomicU8::new(0),
-            should_tx: AtomicBool::new(false),
+            #[cfg(feature = "socket-tcp")]
+            socket_options: heapless::IndexMap::new(),
+            #[cfg(feature = "socket-udp")]
+            udp_listeners: heapless::IndexMap::new(),
+            #[cfg(feature = "socket-udp")]
+            stopped_servers: heapless::Vec::new(),
+            #[cfg(feature = "socket-udp")]
+            udp_connect_failed: heapless::IndexSet::new(),
+            #[cfg(feature = "socket-udp")]
+            udp_datagrams: heapless::IndexMap::new(),
+            #[cfg(feature = "socket-udp")]
+            next_local_port: *DYNAMIC_PORTS.start(),
+            rx_stats: heapless::IndexMap::new(),
+            #[cfg(feature = "socket-tcp")]
+            half_open: heapless::IndexMap::new(),
+            #[cfg(feature = "socket-tcp")]
+            close_reasons: heapless::IndexMap::new(),
+            #[cfg(feature = "socket-tcp")]
+            paused_rx: heapless::IndexMap::new(),
+            #[cfg(feature = "socket-tcp")]
+            connected_peers: heapless::Vec::new(),
+            #[cfg(feature = "socket-tcp")]
+            connect_timeout: Timeouts::DEFAULT.socket_connect,
+            egress_chunk: MAX_EGRESS_SIZE,
+            link_up: false,
+            link_epoch: 0,
+            detached: false,
+            pending_attach: None,
+            attach_attempts: 0,
+            attach_report: None,
+            attached_peers: heapless::Vec::new(),
         }
     }
 
-    pub async fn run(&self) -> ! {
-        let mut tx_buf = [0u8; MAX_EGRESS_SIZE];
+    /// Give the socket just added as `handle` a new id, skipping the ids of
+    /// the sockets still in the set. Ids wrap around, well after any socket
+    /// holding an id from the previous round would have been dropped.
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    pub(crate) fn add_socket_id(&mut self, handle: SocketHandle) {
+        let id = loop {
+            let id = SocketId(self.next_socket_id);
+            self.next_socket_id = self.next_socket_id.wrapping_add(1);
+            if !self.socket_ids.values().any(|i| *i == id) {
+                break id;
+            }
+        };
 
-        let Device {
-            urc_channel,
-            state_ch,
-            at_client,
-        } = &self.device;
+        // Unreachable for the sockets of a stack, see `SocketSetCheck`. A
+        // socket without an id fails to connect, see `has_socket_id`.
+        if self.socket_ids.insert(handle, id).is_err() {
+            error!(
+                "More than {} sockets, socket {} has no id",
+                MAX_SOCKET_IDS, handle
+            );
+        }
+    }
 
-        let mut urc_subscription = urc_channel.subscribe().unwrap();
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    pub(crate) fn remove_socket_id(&mut self, handle: SocketHandle) {
+        self.socket_ids.remove(&handle);
+    }
 
-        loop {
-            // FIXME: It feels like this can be written smarter/simpler?
-            let should_tx = poll_fn(|cx| match self.should_tx.load(Ordering::Relaxed) {
-                true => {
-                    self.should_tx.store(false, Ordering::Relaxed);
-                    Poll::Ready(())
-                }
-                false => {
-                    self.should_tx.store(true, Ordering::Relaxed);
-                    self.socket.borrow_mut().waker.register(cx.waker());
-                    Poll::<()>::Pending
-                }
-            });
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    fn socket_id(&self, handle: SocketHandle) -> Option<SocketId> {
+        self.socket_ids.get(&handle).copied()
+    }
 
-            let ticker = Ticker::every(Duration::from_millis(100));
-            futures_util::pin_mut!(ticker);
+    /// Whether the socket `handle` has an id, without which the responses
+    /// to its connect cannot be told apart from those of a previous socket
+    /// of the same handle.
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    pub(crate) fn has_socket_id(&self, handle: SocketHandle) -> bool {
+        self.socket_ids.contains_key(&handle)
+    }
 
-            match select::select3(
-                urc_subscription.next_message_pure(),
-                should_tx,
-                ticker.next(),
-            )
-            .await
-            {
-                select::Either3::First(event) => {
-                    Self::socket_rx(event, &self.socket);
-                }
-                select::Either3::Second(_) | select::Either3::Third(_) => {
-                    if let Some(ev) = self.tx_event(&mut tx_buf) {
-                        Self::socket_tx(ev, &self.socket, &at_client).await;
-                    }
-                }
+    /// Handle the response of the module to the connect of the socket
+    /// `handle` with id `socket_id`, with the assigned peer, or `None` if the
+    /// module rejected it.
+    ///
+    /// The socket may have been dropped while the module was connecting, and
+    /// its handle taken over by a new socket. The peer then belongs to no
+    /// socket, and is closed.
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    fn connect_response(
+        &mut self,
+        handle: SocketHandle,
+        socket_id: SocketId,
+        peer_handle: Option<PeerHandle>,
+    ) {
+        if self.socket_id(handle) != Some(socket_id) {
+            if let Some(peer_handle) = peer_handle {
+                warn!(
+                    "Socket {} dropped while connecting, releasing peer {}",
+                    handle, peer_handle
+                );
+                self.dropped_sockets.insert(peer_handle);
             }
+            return;
+        }
+
+        match peer_handle {
+            Some(peer_handle) => self.bind_peer(handle, peer_handle),
+            None => self.connect_failed(handle),
         }
     }
 
-    /// Make a query for a given name and return the corresponding IP addresses.
-    // #[cfg(feature = "dns")]
-    pub async fn dns_query(
-        &self,
-        name: &str,
-        addr_type: embedded_nal_async::AddrType,
-    ) -> Result<IpAddr, dns::Error> {
-        DnsSocket::new(self).query(name, addr_type).await
+    /// Update the link state. The module drops all of its peers along with
+    /// the link, so the sockets are reset as soon as the link goes down.
+    /// Every time the link comes up, a new link epoch starts, and sockets left
+    /// over from the previous epoch are reset.
+    fn set_link_up(&mut self, link_up: bool) {
+        if link_up && !self.link_up {
+            self.link_epoch = self.link_epoch.wrapping_add(1);
+            debug!("Link up, starting link epoch {}", self.link_epoch);
+            self.reset_stale_sockets();
+        } else if !link_up && self.link_up {
+            debug!("Link down, resetting sockets");
+            self.reset_stale_sockets();
+        }
+        self.link_up = link_up;
     }
 
-    fn socket_rx(event: EdmEvent, socket: &RefCell<SocketStack>) {
-        match event {
-            EdmEvent::IPv4ConnectEvent(ev) => {
-                let endpoint = SocketAddr::new(ev.remote_ip.into(), ev.remote_port);
-                Self::connect_event(ev.channel_id, ev.protocol, endpoint, socket);
-            }
-            EdmEvent::IPv6ConnectEvent(ev) => {
-                let endpoint = SocketAddr::new(ev.remote_ip.into(), ev.remote_port);
-                Self::connect_event(ev.channel_id, ev.protocol, endpoint, socket);
-            }
-            EdmEvent::DisconnectEvent(channel_id) => {
-                let mut s = socket.borrow_mut();
-                for (_handle, socket) in s.sockets.iter_mut() {
-                    match socket {
-                        #[cfg(feature = "socket-udp")]
-                        Socket::Udp(udp) if udp.edm_channel == Some(channel_id) => {
-                            udp.edm_channel = None;
-                            break;
-                        }
-                        #[cfg(feature = "socket-tcp")]
-                        Socket::Tcp(tcp) if tcp.edm_channel == Some(channel_id) => {
-                            tcp.edm_channel = None;
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
-            }
-            EdmEvent::DataEvent(DataEvent { channel_id, data }) => {
-                let mut s = socket.borrow_mut();
-                for (_handle, socket) in s.sockets.iter_mut() {
-                    match socket {
-                        #[cfg(feature = "socket-udp")]
-                        Socket::Udp(udp)
-                            if udp.edm_channel == Some(channel_id) =>
-                            // FIXME:
-                            // if udp.edm_channel == Some(channel_id) && udp.may_recv() =>
-                        {
-                            let n = udp.rx_enqueue_slice(&data);
-                            if n < data.len() {
-                                error!(
-                                    "[{}] UDP RX data overflow! Discarding {} bytes",
-                                    udp.peer_handle,
-                                    data.len() - n
-                                );
-                            }
-                            break;
-                        }
-                        #[cfg(feature = "socket-tcp")]
-                        Socket::Tcp(tcp)
-                            if tcp.edm_channel == Some(channel_id) && tcp.may_recv() =>
-                        {
-                            let n = tcp.rx_enqueue_slice(&data);
-                            if n < data.len() {
-                                error!(
-                                    "[{}] TCP RX data overflow! Discarding {} bytes",
-                                    tcp.peer_handle,
-                                    data.len() - n
-                                );
-                            }
-                            break;
-                        }
-                        _ => {}
-                    }
-                }
-            }
-            EdmEvent::ATEvent(Urc::PeerDisconnected(PeerDisconnected { handle })) => {
-                let mut s = socket.borrow_mut();
-                for (_handle, socket) in s.sockets.iter_mut() {
-                    match socket {
-                        #[cfg(feature = "socket-udp")]
-                        Socket::Udp(udp) if udp.peer_handle == Some(handle) => {
-                            udp.peer_handle = None;
-                            // FIXME:
-                            // udp.set_state(UdpState::TimeWait);
-                            break;
-                        }
-                        #[cfg(feature = "socket-tcp")]
-                        Socket::Tcp(tcp) if tcp.peer_handle == Some(handle) => {
-                            tcp.peer_handle = None;
-                            tcp.set_state(TcpState::TimeWait);
-                            break;
-                        }
-                        _ => {}
-                    }
+    /// Reset the sockets referring to peers of a previous link epoch, which
+    /// the module has dropped along with the link.
+    ///
+    /// Pending TCP connects are aborted, leaving the socket closed and ready
+    /// to connect again. Established TCP connections are reset as if the peer
+    /// disconnected, with [`CloseReason::LinkLost`], so pending reads fail
+    /// rather than report the end of the stream. UDP sockets keep their
+    /// remote endpoint, and are reconnected by the stack.
+    fn reset_stale_sockets(&mut self) {
+        #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+        for (handle, socket) in self.sockets.iter_mut() {
+            match socket {
+                #[cfg(feature = "socket-udp")]
+                Socket::Udp(udp) if udp.peer_handle.is_some() || udp.edm_channel.is_some() => {
+                    warn!("Resetting stale UDP socket {}", handle);
+                    udp.peer_handle = None;
+                    udp.edm_channel = None;
+                    udp.set_state(UdpState::Closed);
                 }
-            }
-            EdmEvent::ATEvent(Urc::PingResponse(PingResponse {
-                ip, hostname, rtt, ..
-            })) => {
-                let mut s = socket.borrow_mut();
-                if let Some(query) = s.dns_table.get_mut(&hostname) {
-                    match query.state {
-                        DnsState::Pending if rtt == -1 => {
-                            // According to AT manual, rtt = -1 means the PING has timed out
-                            query.state = DnsState::Error(PingError::Timeout);
-                            query.waker.wake();
-                        }
-                        DnsState::Pending => {
-                            query.state = DnsState::Resolved(ip);
-                            query.waker.wake();
-                        }
-                        _ => {}
+                #[cfg(feature = "socket-tcp")]
+                Socket::Tcp(tcp) => match tcp.state() {
+                    TcpState::Closed if tcp.remote_endpoint.is_none() => {}
+                    TcpState::TimeWait => {}
+                    TcpState::Closed | TcpState::SynSent => {
+                        warn!("Aborting stale TCP connect of socket {}", handle);
+                        tcp.remote_endpoint = None;
+                        tcp.peer_handle = None;
+                        tcp.edm_channel = None;
+                        tcp.set_state(TcpState::Closed);
                     }
-                }
-            }
-            EdmEvent::ATEvent(Urc::PingErrorResponse(PingErrorResponse { error })) => {
-                let mut s = socket.borrow_mut();
-                for query in s.dns_table.table.iter_mut() {
-                    match query.state {
-                        DnsState::Pending => {
-                            query.state = DnsState::Error(error);
-                            query.waker.wake();
-                        }
-                        _ => {}
+                    _ => {
+                        warn!("Resetting stale TCP socket {}", handle);
+                        tcp.peer_handle = None;
+                        tcp.edm_channel = None;
+                        tcp.set_state(TcpState::TimeWait);
+                        record_close_reason(&mut self.close_reasons, handle, CloseReason::LinkLost);
                     }
-                }
+                },
+                _ => {}
             }
-            _ => {}
         }
+
+        // Peers of the previous epoch are gone already
+        self.dropped_sockets.clear();
+        #[cfg(feature = "socket-tcp")]
+        self.connected_peers.clear();
+        self.waker.wake();
     }
 
-    fn tx_event<'data>(&self, buf: &'data mut [u8]) -> Option<TxEvent<'data>> {
-        let mut s = self.socket.borrow_mut();
-        for query in s.dns_table.table.iter_mut() {
-            if let DnsState::New = query.state {
-                query.state = DnsState::Pending;
-                buf[..query.domain_name.len()].copy_from_slice(query.domain_name.as_bytes());
-                return Some(TxEvent::Dns {
-                    hostname: core::str::from_utf8(&buf[..query.domain_name.len()]).unwrap(),
-                });
-            }
+    /// Account for the result of a peer status check of a socket, see
+    /// [`HalfOpenMonitor`]. A connection detected as half-open is closed.
+    #[cfg(feature = "socket-tcp")]
+    fn peer_status(&mut self, handle: SocketHandle, peer_handle: PeerHandle, peer_listed: bool) {
+        let Some(monitor) = self.half_open.get_mut(&handle) else {
+            return;
+        };
+        if !monitor.on_status(peer_listed) {
+            return;
         }
+        self.half_open.remove(&handle);
 
-        // Handle delayed close-by-drop here
-        if let Some(dropped_peer_handle) = s.dropped_sockets.pop() {
-            warn!("Handling dropped socket {}", dropped_peer_handle);
-            return Some(TxEvent::Close {
-                peer_handle: dropped_peer_handle,
-            });
+        let tcp = self.sockets.get_mut::<ublox_sockets::tcp::Socket>(handle);
+        if tcp.peer_handle != Some(peer_handle) {
+            return;
         }
 
-        // Make sure to give all sockets an even opportunity to TX
-        // let skip = self
-        //     .last_tx_socket
-        //     .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
-        //         let next = v + 1;
-        //         Some(next.rem(s.sockets.sockets.len() as u8))
-        //     })
-        //     .unwrap();
-        let skip = 0;
+        warn!("Half-open connection detected on socket {}", handle);
+        trace_transition(
+            SocketTransition::PeerClosed,
+            Some(handle),
+            Some(peer_handle),
+            tcp.edm_channel,
+            None,
+        );
+        tcp.peer_handle = None;
+        tcp.edm_channel = None;
+        tcp.set_state(TcpState::TimeWait);
 
-        let SocketStack {
-            sockets,
-            dns_table,
-            credential_map,
-            ..
-        } = s.deref_mut();
+        // Release the peer in the module, if it still holds on to it
+        if peer_listed {
+            self.dropped_sockets.insert(peer_handle);
+        }
+        record_close_reason(
+            &mut self.close_reasons,
+            handle,
+            CloseReason::HalfOpenDetected,
+        );
+        self.waker.wake();
+    }
 
-        for (handle, socket) in sockets.iter_mut().skip(skip as usize) {
-            match socket {
-                #[cfg(feature = "socket-udp")]
-                Socket::Udp(_udp) => todo!(),
-                #[cfg(feature = "socket-tcp")]
-                Socket::Tcp(tcp) => {
-                    tcp.poll();
-
-                    match tcp.state() {
-                        TcpState::Closed => {
-                            if let Some(addr) = tcp.remote_endpoint() {
-                                let mut builder = PeerUrlBuilder::new();
+    /// Pause the receive path of a socket, staging up to `limit` bytes of
+    /// received data, see [`PausedRx`]. Pausing a paused socket keeps the
+    /// data staged so far.
+    #[cfg(feature = "socket-tcp")]
+    fn pause_rx(&mut self, handle: SocketHandle, limit: usize) -> Result<(), crate::error::Error> {
+        if self.paused_rx.contains_key(&handle) {
+            return Ok(());
+        }
+        self.paused_rx
+            .insert(handle, PausedRx::new(limit))
+            .map_err(|_| crate::error::Error::SocketMapMemory)?;
+        Ok(())
+    }
 
-                                if let Some(hostname) = dns_table.reverse_lookup(addr.ip()) {
-                                    builder.hostname(hostname).port(addr.port())
-                                } else {
-                                    builder.address(&addr)
-                                };
+    /// Resume the receive path of a socket, handing the staged data to the
+    /// socket in order.
+    ///
+    /// Fails with [`tcp::Error::RxBufferFull`], leaving the socket paused,
+    /// if the staged data does not fit the receive buffer yet. Fails with
+    /// [`tcp::Error::PausedOverflow`] if data was dropped while paused, or the
+    /// socket can no longer receive the staged data. The staged data is
+    /// handed to the socket regardless.
+    #[cfg(feature = "socket-tcp")]
+    fn resume_rx(&mut self, handle: SocketHandle) -> Result<(), tcp::Error> {
+        let Some(paused) = self.paused_rx.get(&handle) else {
+            return Ok(());
+        };
 
-                                if let Some(creds) = credential_map.get(&handle) {
-                                    info!("Found credentials {} for {}", creds, handle);
-                                    builder.creds(creds);
-                                }
+        let staged = paused.staged().len();
+        let tcp = self.sockets.get_mut::<ublox_sockets::tcp::Socket>(handle);
+        let may_recv = tcp.may_recv();
+        if may_recv && tcp.recv_capacity() - tcp.recv_queue() < staged {
+            return Err(tcp::Error::RxBufferFull);
+        }
 
-                                let url =
-                                    builder.set_local_port(tcp.local_port).tcp::<128>().unwrap();
+        let Some(paused) = self.paused_rx.remove(&handle) else {
+            return Ok(());
+        };
+        let n = if may_recv {
+            tcp.rx_enqueue_slice(paused.staged())
+        } else {
+            0
+        };
+        if n < staged {
+            error!(
+                "[{}] Discarding {} staged bytes on resume",
+                handle,
+                staged - n
+            );
+        }
 
-                                // FIXME: Write directly into `buf` instead
-                                buf[..url.len()].copy_from_slice(url.as_bytes());
+        if let Some(stats) = paused
+            .channel_id()
+            .and_then(|channel_id| self.rx_stats.get_mut(&channel_id.0))
+        {
+            stats.staged = stats.staged.saturating_sub(staged as u64);
+            stats.delivered += n as u64;
+            stats.overflow += (staged - n) as u64;
+        }
 
-                                return Some(TxEvent::Connect {
-                                    socket_handle: handle,
-                                    url: core::str::from_utf8(&buf[..url.len()]).unwrap(),
-                                });
-                            }
-                        }
-                        // We transmit data in all states where we may have data in the buffer,
-                        // or the transmit half of the connection is still open.
-                        TcpState::Established | TcpState::CloseWait | TcpState::LastAck => {
-                            if let Some(edm_channel) = tcp.edm_channel {
-                                return tcp.tx_dequeue(|payload| {
-                                    let len = core::cmp::min(payload.len(), MAX_EGRESS_SIZE);
-                                    let res = if len != 0 {
-                                        buf[..len].copy_from_slice(&payload[..len]);
-                                        Some(TxEvent::Send {
-                                            edm_channel,
-                                            data: &buf[..len],
-                                        })
-                                    } else {
-                                        None
-                                    };
+        if paused.dropped() > 0 || n < staged {
+            warn!(
+                "[{}] {} bytes dropped while paused",
+                handle,
+                paused.dropped() + staged - n
+            );
+            return Err(tcp::Error::PausedOverflow);
+        }
+        Ok(())
+    }
 
-                                    (len, res)
-                                });
-                            }
-                        }
-                        TcpState::FinWait1 => {
-                            return Some(TxEvent::Close {
-                                peer_handle: tcp.peer_handle.unwrap(),
-                            });
-                        }
-                        TcpState::Listen => todo!(),
-                        TcpState::SynReceived => todo!(),
-                        _ => {}
-                    };
+    /// Bind the peer handle the module assigned on connect to a socket.
+    ///
+    /// The module reuses peer handles right after closing a peer, possibly
+    /// before the close has reached a socket still bound to the handle. Such
+    /// a stale socket is evicted, as if its peer disconnected, so the new
+    /// connection cannot be mistaken for it.
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    fn bind_peer(&mut self, handle: SocketHandle, peer_handle: PeerHandle) {
+        for (stale, socket) in self.sockets.iter_mut() {
+            if stale == handle {
+                continue;
+            }
+            match socket {
+                #[cfg(feature = "socket-udp")]
+                Socket::Udp(udp) if udp.peer_handle == Some(peer_handle) => {
+                    warn!("Peer {} reused, evicting socket {}", peer_handle, stale);
+                    trace_transition(
+                        SocketTransition::PeerClosed,
+                        Some(stale),
+                        Some(peer_handle),
+                        udp.edm_channel,
+                        None,
+                    );
+                    udp.peer_handle = None;
+                    udp.edm_channel = None;
+                }
+                #[cfg(feature = "socket-tcp")]
+                Socket::Tcp(tcp) if tcp.peer_handle == Some(peer_handle) => {
+                    warn!("Peer {} reused, evicting socket {}", peer_handle, stale);
+                    trace_transition(
+                        SocketTransition::PeerClosed,
+                        Some(stale),
+                        Some(peer_handle),
+                        tcp.edm_channel,
+                        None,
+                    );
+                    tcp.peer_handle = None;
+                    tcp.edm_channel = None;
+                    tcp.set_state(TcpState::TimeWait);
                 }
                 _ => {}
+            }
+        }
+
+        let SocketStack {
+            sockets,
+            #[cfg(feature = "socket-tcp")]
+            connected_peers,
+            #[cfg(feature = "socket-tcp")]
+            dropped_sockets,
+            ..
+        } = self;
+        let Some((_, socket)) = sockets.iter_mut().find(|(h, _)| *h == handle) else {
+            return;
+        };
+        let remote = match socket {
+            #[cfg(feature = "socket-udp")]
+            Socket::Udp(udp) => {
+                udp.peer_handle = Some(peer_handle);
+                udp.endpoint
+            }
+            // The connect was aborted while the module was connecting, the
+            // peer is of no use anymore
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(tcp)
+                if tcp.state() != TcpState::Closed || tcp.remote_endpoint.is_none() =>
+            {
+                warn!("Releasing peer {} of aborted connect", peer_handle);
+                dropped_sockets.insert(peer_handle);
+                return;
+            }
+            #[cfg(feature = "socket-tcp")]
+            Socket::Tcp(tcp) => {
+                tcp.peer_handle = Some(peer_handle);
+                tcp.set_state(TcpState::SynSent);
+                // The connect events may have arrived already
+                establish(handle, tcp, connected_peers);
+                tcp.remote_endpoint
+            }
+            #[allow(unreachable_patterns)]
+            _ => None,
+        };
+        trace_transition(
+            SocketTransition::Connect,
+            Some(handle),
+            Some(peer_handle),
+            None,
+            remote,
+        );
+    }
+
+    /// Allocate a dynamic port for a UDP socket bound to port 0, skipping
+    /// the local ports of all other sockets.
+    #[cfg(feature = "socket-udp")]
+    fn get_local_port(&mut self) -> u16 {
+        loop {
+            let port = self.next_local_port;
+            self.next_local_port = if port == *DYNAMIC_PORTS.end() {
+                *DYNAMIC_PORTS.start()
+            } else {
+                port + 1
             };
+
+            if !self.local_port_in_use(port) {
+                return port;
+            }
         }
+    }
 
-        None
+    /// Record a TCP peer reported connected by the module, for its socket to
+    /// be established, see [`establish`]. When out of room, the oldest peer
+    /// no connecting socket waits for is forgotten.
+    #[cfg(feature = "socket-tcp")]
+    fn peer_connected(&mut self, peer_handle: PeerHandle) {
+        if self.connected_peers.is_full() {
+            let sockets = &self.sockets;
+            let awaited = |p: &PeerHandle| {
+                sockets.iter().any(|(_, socket)| {
+                    matches!(
+                        socket,
+                        Socket::Tcp(tcp)
+                            if tcp.state() == TcpState::SynSent && tcp.peer_handle == Some(*p)
+                    )
+                })
+            };
+            match self.connected_peers.iter().position(|p| !awaited(p)) {
+                Some(i) => {
+                    self.connected_peers.remove(i);
+                }
+                // Room for every socket of the set, see `SocketSetCheck`
+                None => {
+                    error!("No room for connected peer {}", peer_handle);
+                    return;
+                }
+            }
+        }
+        self.connected_peers.push(peer_handle).ok();
     }
 
-    async fn socket_tx<'data>(
-        ev: TxEvent<'data>,
-        socket: &RefCell<SocketStack>,
-        at_client: &RefCell<ProxyClient<'_, INGRESS_BUF_SIZE>>,
-    ) {
-        use atat::asynch::AtatClient;
+    /// Whether a UDP socket is bound to `port`, or a TCP socket connects
+    /// from it.
+    #[cfg(feature = "socket-udp")]
+    fn local_port_in_use(&self, port: u16) -> bool {
+        self.udp_listeners.values().any(|l| l.port == port)
+            || self.sockets.iter().any(|(_, socket)| match socket {
+                #[cfg(feature = "socket-tcp")]
+                Socket::Tcp(tcp) => tcp.local_port == Some(port),
+                #[allow(unreachable_patterns)]
+                _ => false,
+            })
+    }
 
-        let mut at = at_client.borrow_mut();
-        match ev {
-            TxEvent::Connect { socket_handle, url } => {
-                match at
-                    .send_retry(&EdmAtCmdWrapper(ConnectPeer { url: &url }))
-                    .await
+    /// Hand the peer the module opened for a sender to `local_port` to the
+    /// socket bound to the port. The socket only talks to the most recent
+    /// sender, so the peer of the previous sender is closed.
+    #[cfg(feature = "socket-udp")]
+    fn sender_peer(&mut self, local_port: u16, peer_handle: PeerHandle) {
+        // The peer of a connect of the socket itself
+        let owned = self.sockets.iter().any(|(_, socket)| {
+            matches!(socket, Socket::Udp(udp) if udp.peer_handle == Some(peer_handle))
+        });
+        if owned {
+            return;
+        }
+
+        let Some((&handle, listener)) = self
+            .udp_listeners
+            .iter_mut()
+            .find(|(_, l)| l.port == local_port)
+        else {
+            return;
+        };
+        listener.last_rx = Instant::now();
+
+        let udp = self.sockets.get_mut::<ublox_sockets::udp::Socket>(handle);
+        if let Some(previous) = udp.peer_handle.replace(peer_handle) {
+            debug!(
+                "Socket {} has a new sender, releasing peer {}",
+                handle, previous
+            );
+            self.dropped_sockets.insert(previous);
+        }
+    }
+
+    /// Detach the peer of the current sender from a bound socket that
+    /// received nothing from it within [`UDP_SENDER_IDLE_TIMEOUT`] of `now`,
+    /// returning the peer to close.
+    #[cfg(feature = "socket-udp")]
+    fn expire_sender_peer(&mut self, now: Instant) -> Option<PeerHandle> {
+        let SocketStack {
+            sockets,
+            udp_listeners,
+            ..
+        } = self;
+
+        udp_listeners
+            .iter()
+            .filter(|(_, l)| l.last_rx + UDP_SENDER_IDLE_TIMEOUT <= now)
+            .find_map(|(&handle, _)| {
+                let udp = sockets.get_mut::<ublox_sockets::udp::Socket>(handle);
+                let peer_handle = udp.peer_handle.take()?;
+                debug!(
+                    "Sender of socket {} idle, releasing peer {}",
+                    handle, peer_handle
+                );
+                udp.endpoint = None;
+                udp.edm_channel = None;
+                udp.set_state(UdpState::Closed);
+                Some(peer_handle)
+            })
+    }
+
+    /// Configure the module server `server_id` again, after the module
+    /// failed to start listening on it.
+    #[cfg(feature = "socket-udp")]
+    fn listen_failed(&mut self, server_id: u8) {
+        if let Some(listener) = self
+            .udp_listeners
+            .values_mut()
+            .find(|l| l.server_id == server_id)
+        {
+            listener.active = false;
+        }
+    }
+
+    /// Abort the connect of a socket rejected by the module, leaving the
+    /// socket closed and ready to connect again. Otherwise the connect would
+    /// be retried for as long as the socket lives.
+    ///
+    /// A UDP socket also drops the data queued for the rejected remote
+    /// endpoint, and is marked failed until it is connected again, see
+    /// [`SendError::ConnectFailed`](udp::SendError::ConnectFailed).
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    fn connect_failed(&mut self, handle: SocketHandle) {
+        match self.sockets.iter_mut().find(|(h, _)| *h == handle) {
+            #[cfg(feature = "socket-tcp")]
+            Some((_, Socket::Tcp(tcp))) => {
+                if tcp.state() != TcpState::Closed || tcp.peer_handle.is_some() {
+                    return;
+                }
+
+                tcp.remote_endpoint = None;
+                // Wakes the connecting task
+                tcp.set_state(TcpState::Closed);
+            }
+            #[cfg(feature = "socket-udp")]
+            Some((_, Socket::Udp(udp))) => {
+                if udp.state() != UdpState::Closed || udp.peer_handle.is_some() {
+                    return;
+                }
+
+                warn!("Peer of UDP socket {} rejected", handle);
+                udp.endpoint = None;
+                while udp.send_queue() > 0 {
+                    udp.tx_dequeue(|payload| (payload.len(), ()));
+                }
+                // Room for every socket of the set, see `SocketSetCheck`
+                self.udp_connect_failed.insert(handle).ok();
+                // Wakes the sending task
+                udp.set_state(UdpState::Closed);
+            }
+            _ => {}
+        }
+    }
+
+    /// Abort the connect of a TCP socket, e.g. on a timeout, leaving the
+    /// socket closed and ready to connect again. The peer is closed, if the
+    /// module assigned one already.
+    #[cfg(feature = "socket-tcp")]
+    fn abort_connect(&mut self, handle: SocketHandle) {
+        let tcp = self.sockets.get_mut::<ublox_sockets::tcp::Socket>(handle);
+        if !matches!(tcp.state(), TcpState::Closed | TcpState::SynSent) {
+            return;
+        }
+
+        if let Some(peer_handle) = tcp.peer_handle.take() {
+            self.connected_peers.retain(|p| *p != peer_handle);
+            self.dropped_sockets.insert(peer_handle);
+        }
+        tcp.remote_endpoint = None;
+        tcp.edm_channel = None;
+        tcp.set_state(TcpState::Closed);
+        self.waker.wake();
+    }
+
+    /// Hand the data of a data event on `channel_id` to the socket bound to
+    /// the channel, returning what became of it.
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    fn deliver(&mut self, channel_id: ChannelId, data: &[u8]) -> (RxOutcome, Option<SocketHandle>) {
+        let SocketStack {
+            sockets,
+            #[cfg(feature = "socket-tcp")]
+            paused_rx,
+            #[cfg(feature = "socket-udp")]
+            udp_listeners,
+            #[cfg(feature = "socket-udp")]
+            udp_datagrams,
+            ..
+        } = self;
+
+        for (handle, socket) in sockets.iter_mut() {
+            match socket {
+                #[cfg(feature = "socket-udp")]
+                Socket::Udp(udp)
+                    if udp.edm_channel == Some(channel_id) =>
+                    // FIXME:
+                    // if udp.edm_channel == Some(channel_id) && udp.may_recv() =>
                 {
-                    Ok(ConnectPeerResponse { peer_handle }) => {
-                        let mut s = socket.borrow_mut();
-                        let tcp = s
-                            .sockets
-                            .get_mut::<ublox_sockets::tcp::Socket>(socket_handle);
-                        tcp.peer_handle = Some(peer_handle);
-                        tcp.set_state(TcpState::SynSent);
+                    if let Some(listener) = udp_listeners.get_mut(&handle) {
+                        listener.last_rx = Instant::now();
                     }
-                    Err(e) => {
-                        error!("Failed to connect?! {}", e)
+                    let n = udp.rx_enqueue_slice(data);
+                    if n > 0 {
+                        record_datagram(udp_datagrams, handle, n);
                     }
+                    if n < data.len() {
+                        error!(
+                            "[{}] UDP RX data overflow! Discarding {} bytes",
+                            udp.peer_handle,
+                            data.len() - n
+                        );
+                    }
+                    return (RxOutcome::Delivered(n), Some(handle));
                 }
-            }
-            TxEvent::Send { edm_channel, data } => {
-                warn!("Sending {} bytes on {}", data.len(), edm_channel);
-                at.send_retry(&EdmDataCommand {
-                    channel: edm_channel,
-                    data,
-                })
-                .await
-                .ok();
-            }
-            TxEvent::Close { peer_handle } => {
-                at.send_retry(&EdmAtCmdWrapper(ClosePeerConnection { peer_handle }))
-                    .await
-                    .ok();
-            }
-            TxEvent::Dns { hostname } => {
-                match at
-                    .send_retry(&EdmAtCmdWrapper(Ping {
-                        hostname: &hostname,
-                        retry_num: 1,
-                    }))
-                    .await
+                #[cfg(feature = "socket-tcp")]
+                Socket::Tcp(tcp)
+                    if tcp.edm_channel == Some(channel_id) && tcp.may_recv() =>
                 {
-                    Ok(_) => {}
-                    Err(_) => {
-                        let mut s = socket.borrow_mut();
-                        if let Some(query) = s.dns_table.get_mut(&hostname) {
-                            match query.state {
-                                DnsState::Pending => {
-                                    query.state = DnsState::Error(PingError::Other);
-                                    query.waker.wake();
-                                }
-                                _ => {}
-                            }
+                    if let Some(paused) = paused_rx.get_mut(&handle) {
+                        let n = paused.stage(channel_id, data);
+                        if n < data.len() {
+                            warn!(
+                                "[{}] TCP RX paused, staging limit reached! Discarding {} bytes",
+                                tcp.peer_handle,
+                                data.len() - n
+                            );
                         }
+                        return (RxOutcome::Staged(n), Some(handle));
                     }
+
+                    let n = tcp.rx_enqueue_slice(data);
+                    if n < data.len() {
+                        error!(
+                            "[{}] TCP RX data overflow! Discarding {} bytes",
+                            tcp.peer_handle,
+                            data.len() - n
+                        );
+                    }
+                    return (RxOutcome::Delivered(n), Some(handle));
                 }
+                _ => {}
             }
         }
+
+        (RxOutcome::Unknown, None)
     }
 
-    fn connect_event(
-        channel_id: ChannelId,
-        protocol: Protocol,
-        endpoint: SocketAddr,
-        socket: &RefCell<SocketStack>,
-    ) {
-        let mut s = socket.borrow_mut();
-        for (_handle, socket) in s.sockets.iter_mut() {
-            match protocol {
-                #[cfg(feature = "socket-tcp")]
-                Protocol::TCP => match ublox_sockets::tcp::Socket::downcast_mut(socket) {
-                    Some(tcp) if tcp.remote_endpoint == Some(endpoint) => {
-                        tcp.edm_channel = Some(channel_id);
-                        tcp.set_state(TcpState::Established);
-                        break;
-                    }
-                    _ => {}
-                },
+    /// Peers of the sockets, and of the previous host boot not yet adopted,
+    /// to persist on detach.
+    fn detached_peers(&self) -> heapless::Vec<DetachedPeer, MAX_DETACHED_PEERS> {
+        #[cfg_attr(
+            not(any(feature = "socket-tcp", feature = "socket-udp")),
+            allow(unused_mut)
+        )]
+        let mut peers = self.attached_peers.clone();
+
+        #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+        for (handle, socket) in self.sockets.iter() {
+            let (peer_handle, channel_id, protocol, remote) = match socket {
                 #[cfg(feature = "socket-udp")]
-                Protocol::UDP => match ublox_sockets::udp::Socket::downcast_mut(socket) {
-                    Some(udp) if udp.endpoint == Some(endpoint) => {
-                        udp.edm_channel = Some(channel_id);
-                        udp.set_state(UdpState::Established);
-                        break;
-                    }
-                    _ => {}
-                },
-                _ => {}
+                Socket::Udp(udp) => (
+                    udp.peer_handle,
+                    udp.edm_channel,
+                    IPProtocol::UDP,
+                    udp.endpoint,
+                ),
+                #[cfg(feature = "socket-tcp")]
+                Socket::Tcp(tcp) => (
+                    tcp.peer_handle,
+                    tcp.edm_channel,
+                    IPProtocol::TCP,
+                    tcp.remote_endpoint,
+                ),
+                #[allow(unreachable_patterns)]
+                _ => continue,
+            };
+
+            if let (Some(peer_handle), Some(remote)) = (peer_handle, remote) {
+                let peer = DetachedPeer {
+                    peer_handle,
+                    channel_id,
+                    protocol,
+                    remote,
+                };
+                if peers.push(peer).is_err() {
+                    warn!("No room to detach peer of socket {}", handle);
+                }
             }
         }
+
+        peers
     }
-}
 
-// TODO: This extra data clone step can probably be avoided by adding a
-// waker/context based API to ATAT.
-enum TxEvent<'data> {
-    Connect {
-        socket_handle: SocketHandle,
-        url: &'data str,
-    },
-    Send {
-        edm_channel: ChannelId,
-        data: &'data [u8],
-    },
-    Close {
+    /// Reconcile the peers of the pending attach with the peers `listed` by
+    /// the module, recording the outcome in the attach report.
+    ///
+    /// Peers still listed are kept for sockets to adopt, the others closed
+    /// while the host was away. Peers listed by the module, but unknown to
+    /// the previous host boot, are closed.
+    fn resync_connections(&mut self, listed: &[PeerHandle]) {
+        let Some(attach) = self.pending_attach.take() else {
+            return;
+        };
+        self.attach_attempts = 0;
+
+        let mut report = AttachReport::default();
+        for peer in attach.previous.peers {
+            let status = if attach.module_reset {
+                PeerStatus::Closed(AttachCloseReason::ModuleReset)
+            } else if listed.contains(&peer.peer_handle) {
+                self.attached_peers.push(peer.clone()).ok();
+                PeerStatus::Alive
+            } else {
+                warn!("Peer {} closed while detached", peer.peer_handle);
+                PeerStatus::Closed(AttachCloseReason::ClosedWhileDetached)
+            };
+            report.peers.push((peer, status)).ok();
+        }
+
+        for &peer_handle in listed {
+            if report.status(peer_handle).is_none() {
+                warn!("Closing peer {} unknown to the previous boot", peer_handle);
+                self.dropped_sockets.insert(peer_handle);
+            }
+        }
+
+        self.attach_report = Some(report);
+        self.waker.wake();
+    }
+
+    /// Account for a failure to list the peers of the module for the pending
+    /// attach.
+    ///
+    /// Once out of attempts, the peers of the previous host boot are closed,
+    /// as they cannot be told apart from peers that closed while the host was
+    /// away.
+    fn resync_connections_failed(&mut self) {
+        self.attach_attempts += 1;
+        if self.attach_attempts < MAX_ATTACH_ATTEMPTS {
+            return;
+        }
+
+        let Some(attach) = self.pending_attach.take() else {
+            return;
+        };
+        self.attach_attempts = 0;
+
+        let mut report = AttachReport::default();
+        for peer in attach.previous.peers {
+            warn!(
+                "Closing peer {}, as the peers could not be listed",
+                peer.peer_handle
+            );
+            self.dropped_sockets.insert(peer.peer_handle);
+            report
+                .peers
+                .push((peer, PeerStatus::Closed(AttachCloseReason::PeerListFailed)))
+                .ok();
+        }
+
+        self.attach_report = Some(report);
+        self.waker.wake();
+    }
+
+    /// Hand an alive peer of the previous host boot over to a TCP socket.
+    #[cfg(feature = "socket-tcp")]
+    fn adopt(
+        &mut self,
+        handle: SocketHandle,
         peer_handle: PeerHandle,
-    },
-    Dns {
-        hostname: &'data str,
-    },
-}
+    ) -> Result<(), crate::error::Error> {
+        let i = self
+            .attached_peers
+            .iter()
+            .position(|peer| {
+                peer.peer_handle == peer_handle
+                    && peer.protocol == IPProtocol::TCP
+                    && peer.channel_id.is_some()
+            })
+            .ok_or(crate::error::Error::SocketNotFound)?;
 
-#[cfg(feature = "defmt")]
-impl defmt::Format for TxEvent<'_> {
-    fn format(&self, fmt: defmt::Formatter) {
-        match self {
-            TxEvent::Connect { .. } => defmt::write!(fmt, "TxEvent::Connect"),
-            TxEvent::Send { .. } => defmt::write!(fmt, "TxEvent::Send"),
-            TxEvent::Close { .. } => defmt::write!(fmt, "TxEvent::Close"),
-            TxEvent::Dns { .. } => defmt::write!(fmt, "TxEvent::Dns"),
+        let tcp = self.sockets.get_mut::<ublox_sockets::tcp::Socket>(handle);
+        if tcp.state() != TcpState::Closed {
+            return Err(crate::error::Error::AlreadyConnected);
+        }
+
+        let peer = self.attached_peers.swap_remove(i);
+        tcp.remote_endpoint = Some(peer.remote);
+        tcp.peer_handle = Some(peer.peer_handle);
+        tcp.edm_channel = peer.channel_id;
+        tcp.set_state(TcpState::Established);
+        trace_transition(
+            SocketTransition::Connected,
+            Some(handle),
+            tcp.peer_handle,
+            tcp.edm_channel,
+            tcp.remote_endpoint,
+        );
+        Ok(())
+    }
+
+    /// Close the peers of the previous host boot not adopted by a socket.
+    fn release_attached_peers(&mut self) {
+        while let Some(peer) = self.attached_peers.pop() {
+            self.dropped_sockets.insert(peer.peer_handle);
+        }
+        self.waker.wake();
+    }
+
+    /// Account for a data event of `len` bytes on `channel_id`.
+    fn record_rx(&mut self, channel_id: ChannelId, len: usize, outcome: RxOutcome) {
+        if !self.rx_stats.contains_key(&channel_id.0)
+            && self
+                .rx_stats
+                .insert(channel_id.0, ChannelRxStats::default())
+                .is_err()
+        {
+            warn!("No room for RX statistics of channel {}", channel_id.0);
+            return;
+        }
+        let Some(stats) = self.rx_stats.get_mut(&channel_id.0) else {
+            return;
+        };
+
+        stats.events += 1;
+        stats.received += len as u64;
+        match outcome {
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            RxOutcome::Delivered(n) => {
+                stats.delivered += n as u64;
+                stats.overflow += (len - n) as u64;
+            }
+            #[cfg(feature = "socket-tcp")]
+            RxOutcome::Staged(n) => {
+                stats.staged += n as u64;
+                stats.paused_overflow += (len - n) as u64;
+            }
+            RxOutcome::Unknown => stats.unknown_channel += len as u64,
         }
     }
 }
+
+/// Connection state of a socket, as relevant to the handle mappings.
+fn socket_mapping(socket: &Socket) -> Option<(bool, Option<PeerHandle>, Option<ChannelId>)> {
+    match socket {
+        #[cfg(feature = "socket-udp")]
+        Socket::Udp(udp) => Some((
+            udp.state() == UdpState::Established,
+            udp.peer_handle,
+            udp.edm_channel,
+        )),
+        #[cfg(feature = "socket-tcp")]
+        Socket::Tcp(tcp) => Some((
+            tcp.state() == TcpState::Established,
+            tcp.peer_handle,
+            tcp.edm_channel,
+        )),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+impl SocketStack {
+    /// Check the socket, peer handle and EDM channel mappings for
+    /// consistency, logging every violation found. Returns the number of
+    /// violations.
+    ///
+    /// Every established socket must be mapped to a peer handle and an EDM
+    /// channel, and no peer handle or EDM channel may be mapped to more than
+    /// one socket.
+    fn check_invariants(&self) -> usize {
+        let mut violations = 0;
+
+        for (i, (handle, socket)) in self.sockets.iter().enumerate() {
+            let Some((established, peer_handle, edm_channel)) = socket_mapping(socket) else {
+                continue;
+            };
+
+            if established && (peer_handle.is_none() || edm_channel.is_none()) {
+                error!(
+                    "Socket {:?} established without mapping (peer: {:?}, channel: {:?})",
+                    handle, peer_handle, edm_channel
+                );
+                violations += 1;
+            }
+
+            for (other, other_socket) in self.sockets.iter().skip(i + 1) {
+                let Some((_, other_peer_handle, other_edm_channel)) = socket_mapping(other_socket)
+                else {
+                    continue;
+                };
+
+                if peer_handle.is_some() && peer_handle == other_peer_handle {
+                    error!(
+                        "Peer handle {:?} mapped to both socket {:?} and {:?}",
+                        peer_handle, handle, other
+                    );
+                    violations += 1;
+                }
+                if edm_channel.is_some() && edm_channel == other_edm_channel {
+                    error!(
+                        "EDM channel {:?} mapped to both socket {:?} and {:?}",
+                        edm_channel, handle, other
+                    );
+                    violations += 1;
+                }
+            }
+        }
+
+        if violations > 0 {
+            self.dump_mappings();
+        }
+
+        violations
+    }
+
+    fn dump_mappings(&self) {
+        for (handle, socket) in self.sockets.iter() {
+            if let Some((established, peer_handle, edm_channel)) = socket_mapping(socket) {
+                error!(
+                    "  socket {:?}: established: {}, peer: {:?}, channel: {:?}",
+                    handle, established, peer_handle, edm_channel
+                );
+            }
+        }
+    }
+}
+
+impl<const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
+    UbloxStack<INGRESS_BUF_SIZE, URC_CAPACITY>
+{
+    pub fn new<const SOCK: usize>(
+        device: Device<'static, INGRESS_BUF_SIZE, URC_CAPACITY>,
+        resources: &'static mut StackResources<SOCK>,
+    ) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = UrcCapacityCheck::<SOCK, URC_CAPACITY>::OK;
+        #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+        #[allow(clippy::let_unit_value)]
+        let () = SocketSetCheck::<SOCK>::OK;
+
+        let sockets = SocketSet::new(&mut resources.sockets[..]);
+
+        let mut socket = SocketStack::new(sockets);
+        socket.dns_table.set_timeout(device.state_ch.timeouts().dns);
+        #[cfg(feature = "socket-tcp")]
+        {
+            socket.connect_timeout = device.state_ch.timeouts().socket_connect;
+        }
+
+        Self {
+            socket: RefCell::new(socket),
+            device,
+            last_tx_socket: AtomicU8::new(0),
+            should_tx: AtomicBool::new(false),
+            socket_capacity: SOCK,
+        }
+    }
+
+    /// Check the size of the socket set against the number of simultaneous
+    /// peer connections supported by the module, waiting for the module to
+    /// be initialized.
+    ///
+    /// Returns [`Error::SocketSetTooLarge`](crate::error::Error::SocketSetTooLarge)
+    /// if the module cannot connect all
+    /// sockets at once. Sockets beyond the limit of the module would
+    /// otherwise fail to connect.
+    pub async fn check_socket_limit(&self) -> Result<(), crate::error::Error> {
+        self.device.state_ch.wait_for_initialized().await;
+
+        match self.device.state_ch.max_peers() {
+            Some(max_peers) if self.socket_capacity > max_peers => {
+                error!(
+                    "Socket set of {} sockets exceeds the {} peers supported by the module",
+                    self.socket_capacity, max_peers
+                );
+                Err(crate::error::Error::SocketSetTooLarge)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Resolve hostnames with the `+UDNSRN` command, for firmware that
+    /// supports it. Disabled by default, so hostnames are resolved through a
+    /// ping of the host.
+    ///
+    /// The command is not part of the u-connectXpress AT commands manual, so
+    /// enable it only for firmware known to answer it. Firmware that does not
+    /// is recognized by the failed command, at the cost of a ping fallback
+    /// for every resolve. See [`ResolveName`].
+    pub fn set_resolve_command(&self, enabled: bool) {
+        self.socket
+            .borrow_mut()
+            .dns_table
+            .set_resolve_command(enabled);
+    }
+
+    pub async fn run(&self) -> ! {
+        let resolver = Self::resolve_names(&self.socket, &self.device.at_client);
+        match select::select(self.run_sockets(), resolver).await {
+            select::Either::First(never) | select::Either::Second(never) => never,
+        }
+    }
+
+    async fn run_sockets(&self) -> ! {
+        let mut tx_buf = [0u8; MAX_EGRESS_SIZE];
+
+        let Device {
+            urc_channel,
+            state_ch,
+            at_client,
+        } = &self.device;
+
+        let mut urcs = UrcLanes::new(
+            urc_channel.subscribe().unwrap(),
+            urc_channel.subscribe().unwrap(),
+        );
+        let mut edm_capabilities = None;
+        let mut link_ups = None;
+
+        loop {
+            Self::follow_link(&self.socket, state_ch, &mut link_ups);
+
+            let capabilities = state_ch.edm_capabilities();
+            if capabilities != edm_capabilities {
+                edm_capabilities = capabilities;
+                if let Some(capabilities) = capabilities {
+                    let max_frame = capabilities.max_payload as usize + PAYLOAD_OVERHEAD;
+                    if capabilities.version != 0 && INGRESS_BUF_SIZE < max_frame {
+                        warn!(
+                            "Ingress buffer of {} bytes is smaller than the EDM frames of up to {} bytes the module may send",
+                            INGRESS_BUF_SIZE, max_frame
+                        );
+                    }
+                    self.socket.borrow_mut().egress_chunk = egress_chunk(capabilities);
+                }
+            }
+
+            if let Some(attach) = state_ch.take_pending_attach() {
+                let mut s = self.socket.borrow_mut();
+                s.pending_attach = Some(attach);
+                s.attach_attempts = 0;
+            }
+
+            // FIXME: It feels like this can be written smarter/simpler?
+            let should_tx = poll_fn(|cx| match self.should_tx.load(Ordering::Relaxed) {
+                true => {
+                    self.should_tx.store(false, Ordering::Relaxed);
+                    Poll::Ready(())
+                }
+                false => {
+                    self.should_tx.store(true, Ordering::Relaxed);
+                    self.socket.borrow_mut().waker.register(cx.waker());
+                    Poll::<()>::Pending
+                }
+            });
+
+            let ticker = Ticker::every(Duration::from_millis(100));
+            futures_util::pin_mut!(ticker);
+
+            let event = select::select3(urcs.next(state_ch), should_tx, ticker.next()).await;
+            self.socket.borrow_mut().ping_active = state_ch.ping_active();
+
+            match event {
+                select::Either3::First(event) => {
+                    Self::socket_rx(event, &self.socket);
+                    self.debug_assert_invariants();
+                }
+                select::Either3::Second(_) | select::Either3::Third(_) => {
+                    if let Some(ev) = Self::tx_event(&self.socket, &mut tx_buf) {
+                        // Hold off pings of the application before the ping
+                        // of a resolve is issued
+                        Self::sync_resolve_ping(&self.socket, state_ch);
+                        Self::socket_tx(ev, &self.socket, &at_client).await;
+                    }
+                }
+            }
+            Self::sync_resolve_ping(&self.socket, state_ch);
+        }
+    }
+
+    /// Number of times the link has come up. Sockets are reset at the start
+    /// of every link epoch, see [`TcpSocket::connect`](tcp::TcpSocket::connect).
+    pub fn link_epoch(&self) -> u32 {
+        self.socket.borrow().link_epoch
+    }
+
+    /// Persist the state of the driver into `buf`, to adopt the module with
+    /// [`Runner::attach`](crate::asynch::Runner::attach) after rebooting the
+    /// host. Returns the number of bytes written, at most
+    /// [`DetachedState::MAX_LEN`].
+    ///
+    /// From then on, the stack leaves the module alone, so its peers survive
+    /// the reboot: no more data is sent, and dropped sockets are not closed.
+    /// Data received until the peers are adopted again is lost.
+    pub fn detach(&self, buf: &mut [u8]) -> Result<usize, crate::error::Error> {
+        let state_ch = &self.device.state_ch;
+        let edm_capabilities = state_ch
+            .edm_capabilities()
+            .ok_or(crate::error::Error::Uninitialized)?;
+
+        let mut s = self.socket.borrow_mut();
+        let state = DetachedState {
+            config_id: state_ch.should_connect().then_some(CONFIG_ID),
+            edm_capabilities,
+            peers: s.detached_peers(),
+        };
+        let len = state.to_bytes(buf)?;
+        s.detached = true;
+
+        Ok(len)
+    }
+
+    /// Outcome of attaching to a running module, see
+    /// [`Runner::attach`](crate::asynch::Runner::attach). `None` until the
+    /// peers of the previous host boot are reconciled with the module.
+    ///
+    /// Alive TCP peers can be adopted with
+    /// [`TcpSocket::adopt`](tcp::TcpSocket::adopt). Call
+    /// [`UbloxStack::release_attached_peers`] to close the others.
+    pub fn attach_report(&self) -> Option<AttachReport> {
+        self.socket.borrow().attach_report.clone()
+    }
+
+    /// Close the alive peers of the previous host boot, that were not adopted
+    /// by a socket.
+    pub fn release_attached_peers(&self) {
+        self.socket.borrow_mut().release_attached_peers()
+    }
+
+    /// Receive statistics of an EDM channel, see [`ChannelRxStats`].
+    pub fn rx_stats(&self, channel_id: ChannelId) -> Option<ChannelRxStats> {
+        self.socket.borrow().rx_stats.get(&channel_id.0).copied()
+    }
+
+    /// Verify that the socket, peer handle and EDM channel mappings are
+    /// consistent, logging a dump of all mappings on violation.
+    ///
+    /// Returns `true` if no violations were found. Compiled out in release
+    /// builds, where it always returns `true`.
+    pub fn debug_assert_invariants(&self) -> bool {
+        #[cfg(debug_assertions)]
+        {
+            self.socket.borrow().check_invariants() == 0
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            true
+        }
+    }
+
+    /// Make a query for a given name and return the corresponding IP addresses.
+    // #[cfg(feature = "dns")]
+    pub async fn dns_query(
+        &self,
+        name: &str,
+        addr_type: embedded_nal_async::AddrType,
+    ) -> Result<IpAddr, dns::Error> {
+        DnsSocket::new(self).query(name, addr_type).await
+    }
+
+    fn socket_rx(event: EdmEvent, socket: &RefCell<SocketStack>) {
+        match event {
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            EdmEvent::IPv4ConnectEvent(ev) => {
+                let endpoint = SocketAddr::new(ev.remote_ip.into(), ev.remote_port);
+                Self::connect_event(ev.channel_id, ev.protocol, endpoint, ev.local_port, socket);
+            }
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            EdmEvent::IPv6ConnectEvent(ev) => {
+                let endpoint = SocketAddr::new(ev.remote_ip.into(), ev.remote_port);
+                Self::connect_event(ev.channel_id, ev.protocol, endpoint, ev.local_port, socket);
+            }
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            EdmEvent::DisconnectEvent(channel_id) => {
+                let mut s = socket.borrow_mut();
+                for (handle, socket) in s.sockets.iter_mut() {
+                    match socket {
+                        #[cfg(feature = "socket-udp")]
+                        Socket::Udp(udp) if udp.edm_channel == Some(channel_id) => {
+                            udp.edm_channel = None;
+                            trace_transition(
+                                SocketTransition::ChannelClosed,
+                                Some(handle),
+                                udp.peer_handle,
+                                Some(channel_id),
+                                None,
+                            );
+                            break;
+                        }
+                        #[cfg(feature = "socket-tcp")]
+                        Socket::Tcp(tcp) if tcp.edm_channel == Some(channel_id) => {
+                            tcp.edm_channel = None;
+                            trace_transition(
+                                SocketTransition::ChannelClosed,
+                                Some(handle),
+                                tcp.peer_handle,
+                                Some(channel_id),
+                                None,
+                            );
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            EdmEvent::DataEvent(DataEvent { channel_id, data }) => {
+                let s = &mut *socket.borrow_mut();
+                #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+                let (outcome, delivered_to) = s.deliver(channel_id, &data);
+                // Without socket support there is nothing to deliver to, the
+                // data is only accounted for
+                #[cfg(not(any(feature = "socket-tcp", feature = "socket-udp")))]
+                let (outcome, delivered_to) = (RxOutcome::Unknown, None);
+
+                trace_transition(
+                    SocketTransition::Data(data.len()),
+                    delivered_to,
+                    None,
+                    Some(channel_id),
+                    None,
+                );
+                s.record_rx(channel_id, data.len(), outcome);
+
+                #[cfg(feature = "socket-tcp")]
+                if let Some(monitor) = delivered_to.and_then(|h| s.half_open.get_mut(&h)) {
+                    monitor.on_rx(Instant::now());
+                }
+            }
+            #[cfg(feature = "socket-tcp")]
+            EdmEvent::ATEvent(Urc::PeerConnected(PeerConnected {
+                handle,
+                protocol: IPProtocol::TCP,
+                ..
+            })) => {
+                let s = &mut *socket.borrow_mut();
+                s.peer_connected(handle);
+
+                for (socket_handle, socket) in s.sockets.iter_mut() {
+                    match ublox_sockets::tcp::Socket::downcast_mut(socket) {
+                        Some(tcp) if tcp.peer_handle == Some(handle) => {
+                            establish(socket_handle, tcp, &mut s.connected_peers);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            #[cfg(feature = "socket-udp")]
+            EdmEvent::ATEvent(Urc::PeerConnected(PeerConnected {
+                handle,
+                protocol: IPProtocol::UDP,
+                local_port,
+                ..
+            })) => {
+                socket.borrow_mut().sender_peer(local_port, handle);
+            }
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            EdmEvent::ATEvent(Urc::PeerDisconnected(PeerDisconnected { handle, reason })) => {
+                let s = &mut *socket.borrow_mut();
+                #[cfg(feature = "socket-tcp")]
+                s.connected_peers.retain(|p| *p != handle);
+                for (socket_handle, socket) in s.sockets.iter_mut() {
+                    match socket {
+                        #[cfg(feature = "socket-udp")]
+                        Socket::Udp(udp) if udp.peer_handle == Some(handle) => {
+                            trace_transition(
+                                SocketTransition::PeerClosed,
+                                Some(socket_handle),
+                                Some(handle),
+                                udp.edm_channel,
+                                None,
+                            );
+                            udp.peer_handle = None;
+                            // FIXME:
+                            // udp.set_state(UdpState::TimeWait);
+                            break;
+                        }
+                        #[cfg(feature = "socket-tcp")]
+                        Socket::Tcp(tcp) if tcp.peer_handle == Some(handle) => {
+                            trace_transition(
+                                SocketTransition::PeerClosed,
+                                Some(socket_handle),
+                                Some(handle),
+                                tcp.edm_channel,
+                                None,
+                            );
+                            // The module drops the peer of a TLS socket
+                            // failing its handshake before it connects
+                            if tcp.state() == TcpState::SynSent
+                                && s.credential_map.contains_key(&socket_handle)
+                            {
+                                warn!(
+                                    "TLS handshake of socket {} failed, reason {:?}",
+                                    socket_handle, reason
+                                );
+                                record_close_reason(
+                                    &mut s.close_reasons,
+                                    socket_handle,
+                                    CloseReason::TlsHandshake(reason),
+                                );
+                            }
+                            tcp.peer_handle = None;
+                            tcp.set_state(TcpState::TimeWait);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            // The module drops all peers along with the link, so stop
+            // accepting data for them right away
+            EdmEvent::ATEvent(Urc::WifiLinkDisconnected(_)) => {
+                socket.borrow_mut().set_link_up(false);
+            }
+            EdmEvent::ATEvent(Urc::NetworkDown(NetworkDown { interface_id }))
+                if interface_id <= 10 =>
+            {
+                socket.borrow_mut().set_link_up(false);
+            }
+            #[cfg(feature = "ap")]
+            EdmEvent::ATEvent(Urc::WifiAPDown(_)) => {
+                socket.borrow_mut().set_link_up(false);
+            }
+            EdmEvent::ATEvent(Urc::PingResponse(PingResponse {
+                ip, hostname, rtt, ..
+            })) => {
+                let state = if rtt == -1 {
+                    // According to AT manual, rtt = -1 means the PING has timed out
+                    DnsState::Error(PingError::Timeout)
+                } else {
+                    DnsState::Resolved(ip)
+                };
+                socket.borrow_mut().dns_table.complete(&hostname, state);
+            }
+            EdmEvent::ATEvent(Urc::PingErrorResponse(PingErrorResponse { error })) => {
+                let mut s = socket.borrow_mut();
+                // The error belongs to the ping of the application, a
+                // resolve still pending then is left to time out
+                if !s.ping_active {
+                    s.dns_table.fail_pending(error);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Follow the link state, last seen with `link_ups`, see
+    /// [`state::Runner::link_ups`].
+    ///
+    /// Only changes of the link state are followed, as the stack may learn
+    /// about a lost link from its URCs before the state does. A link lost and
+    /// regained since it was last seen starts a new link epoch all the same.
+    fn follow_link(
+        socket: &RefCell<SocketStack>,
+        state_ch: &state::Runner<'_>,
+        link_ups: &mut Option<u32>,
+    ) {
+        let state_link_ups = state_ch.link_ups();
+        if state_link_ups == *link_ups {
+            return;
+        }
+
+        let mut s = socket.borrow_mut();
+        if link_ups.is_some() && state_link_ups.is_some() {
+            // Lost in between, if the URCs did not tell already
+            s.set_link_up(false);
+        }
+        s.set_link_up(state_link_ups.is_some());
+        *link_ups = state_link_ups;
+    }
+
+    /// Publish whether the module is pinging a host to resolve its name, see
+    /// [`Control::ping`](crate::asynch::control::Control::ping).
+    fn sync_resolve_ping(socket: &RefCell<SocketStack>, state_ch: &state::Runner<'_>) {
+        let outstanding = socket.borrow().dns_table.ping_outstanding(Instant::now());
+        state_ch.set_resolve_ping(outstanding);
+    }
+
+    fn tx_event<'data>(
+        socket: &RefCell<SocketStack>,
+        buf: &'data mut [u8],
+    ) -> Option<TxEvent<'data>> {
+        let mut s = socket.borrow_mut();
+        if s.detached {
+            return None;
+        }
+
+        if s.pending_attach.is_some() {
+            return Some(TxEvent::ResyncConnections);
+        }
+
+        // Resolves are held back until a ping of the application is done
+        let query = match s.ping_active {
+            true => None,
+            false => s.dns_table.next_query(Instant::now(), ResolveMethod::Ping),
+        };
+        if let Some(query) = query {
+            buf[..query.domain_name.len()].copy_from_slice(query.domain_name.as_bytes());
+            return Some(TxEvent::Dns {
+                hostname: core::str::from_utf8(&buf[..query.domain_name.len()]).unwrap(),
+            });
+        }
+
+        // Handle delayed close-by-drop here
+        if let Some(dropped_peer_handle) = s.dropped_sockets.pop() {
+            warn!("Handling dropped socket {}", dropped_peer_handle);
+            return Some(TxEvent::Close {
+                peer_handle: dropped_peer_handle,
+            });
+        }
+
+        #[cfg(feature = "socket-udp")]
+        if let Some(server_id) = s.stopped_servers.pop() {
+            return Some(TxEvent::Listen {
+                server_id,
+                port: None,
+            });
+        }
+
+        #[cfg(feature = "socket-udp")]
+        for listener in s.udp_listeners.values_mut() {
+            if !listener.active {
+                listener.active = true;
+                return Some(TxEvent::Listen {
+                    server_id: listener.server_id,
+                    port: Some(listener.port),
+                });
+            }
+        }
+
+        #[cfg(feature = "socket-udp")]
+        if let Some(peer_handle) = s.expire_sender_peer(Instant::now()) {
+            return Some(TxEvent::Close { peer_handle });
+        }
+
+        #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+        if let Some(ev) = Self::socket_tx_event(&mut s, buf) {
+            return Some(ev);
+        }
+
+        None
+    }
+
+    /// Next transmission of the sockets, if any.
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    fn socket_tx_event<'data>(s: &mut SocketStack, buf: &'data mut [u8]) -> Option<TxEvent<'data>> {
+        // Make sure to give all sockets an even opportunity to TX
+        // let skip = self
+        //     .last_tx_socket
+        //     .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+        //         let next = v + 1;
+        //         Some(next.rem(s.sockets.sockets.len() as u8))
+        //     })
+        //     .unwrap();
+        let skip = 0;
+
+        let SocketStack {
+            sockets,
+            dns_table,
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            socket_ids,
+            #[cfg(feature = "socket-tcp")]
+            credential_map,
+            #[cfg(feature = "socket-tcp")]
+            socket_options,
+            #[cfg(feature = "socket-udp")]
+            udp_listeners,
+            #[cfg(feature = "socket-tcp")]
+            half_open,
+            egress_chunk,
+            ..
+        } = s;
+
+        for (handle, socket) in sockets.iter_mut().skip(skip as usize) {
+            match socket {
+                #[cfg(feature = "socket-udp")]
+                Socket::Udp(udp) => match udp.state() {
+                    // Bound sockets get their peers from the module server
+                    UdpState::Closed if !udp_listeners.contains_key(&handle) => {
+                        if let (Some(addr), None, Some(socket_id)) = (
+                            udp.endpoint,
+                            udp.peer_handle,
+                            socket_ids.get(&handle).copied(),
+                        ) {
+                            let mut builder = PeerUrlBuilder::new();
+
+                            if let Some(hostname) = dns_table.reverse_lookup(addr.ip()) {
+                                builder.hostname(hostname).port(addr.port())
+                            } else {
+                                builder.address(&addr)
+                            };
+
+                            let url = builder.udp::<128>().unwrap();
+
+                            // FIXME: Write directly into `buf` instead
+                            buf[..url.len()].copy_from_slice(url.as_bytes());
+
+                            return Some(TxEvent::Connect {
+                                socket_handle: handle,
+                                socket_id,
+                                url: core::str::from_utf8(&buf[..url.len()]).unwrap(),
+                            });
+                        }
+                    }
+                    UdpState::Established => {
+                        if let Some(edm_channel) = udp.edm_channel {
+                            return udp.tx_dequeue(|payload| {
+                                let len = core::cmp::min(payload.len(), *egress_chunk);
+                                let res = if len != 0 {
+                                    buf[..len].copy_from_slice(&payload[..len]);
+                                    Some(TxEvent::Send {
+                                        edm_channel,
+                                        data: &buf[..len],
+                                    })
+                                } else {
+                                    None
+                                };
+
+                                (len, res)
+                            });
+                        }
+                    }
+                    #[allow(unreachable_patterns)]
+                    _ => {}
+                },
+                #[cfg(feature = "socket-tcp")]
+                Socket::Tcp(tcp) => {
+                    tcp.poll();
+
+                    match tcp.state() {
+                        TcpState::Closed => {
+                            if let (Some(addr), Some(socket_id)) =
+                                (tcp.remote_endpoint(), socket_ids.get(&handle).copied())
+                            {
+                                let creds = credential_map.get(&handle);
+                                if let Some(creds) = creds {
+                                    info!("Found credentials {} for {}", creds, handle);
+                                }
+
+                                let url = tcp_peer_url(
+                                    dns_table,
+                                    addr,
+                                    tcp.local_port,
+                                    creds,
+                                    socket_options.get(&handle),
+                                )
+                                .unwrap();
+
+                                // FIXME: Write directly into `buf` instead
+                                buf[..url.len()].copy_from_slice(url.as_bytes());
+
+                                return Some(TxEvent::Connect {
+                                    socket_handle: handle,
+                                    socket_id,
+                                    url: core::str::from_utf8(&buf[..url.len()]).unwrap(),
+                                });
+                            }
+                        }
+                        // We transmit data in all states where we may have data in the buffer,
+                        // or the transmit half of the connection is still open.
+                        TcpState::Established | TcpState::CloseWait | TcpState::LastAck => {
+                            if let Some(idle) = socket_options
+                                .get(&handle)
+                                .and_then(|options| options.half_open_check)
+                            {
+                                if !half_open.contains_key(&handle)
+                                    && half_open
+                                        .insert(handle, HalfOpenMonitor::new(Instant::now()))
+                                        .is_err()
+                                {
+                                    // Room for every socket of the set, see
+                                    // `SocketSetCheck`
+                                    error!("No room to monitor socket {} for half-open", handle);
+                                }
+
+                                if let (Some(monitor), Some(peer_handle)) =
+                                    (half_open.get_mut(&handle), tcp.peer_handle)
+                                {
+                                    if monitor.poll_check(Instant::now(), idle) {
+                                        return Some(TxEvent::PeerStatus {
+                                            socket_handle: handle,
+                                            peer_handle,
+                                        });
+                                    }
+                                }
+                            }
+
+                            if let Some(edm_channel) = tcp.edm_channel {
+                                let ev = tcp.tx_dequeue(|payload| {
+                                    let len = core::cmp::min(payload.len(), *egress_chunk);
+                                    let res = if len != 0 {
+                                        buf[..len].copy_from_slice(&payload[..len]);
+                                        Some(TxEvent::Send {
+                                            edm_channel,
+                                            data: &buf[..len],
+                                        })
+                                    } else {
+                                        None
+                                    };
+
+                                    (len, res)
+                                });
+
+                                if ev.is_some() {
+                                    if let Some(monitor) = half_open.get_mut(&handle) {
+                                        monitor.on_tx();
+                                    }
+                                }
+                                return ev;
+                            }
+                        }
+                        TcpState::FinWait1 => {
+                            // Lingering sockets hand their queued data to the
+                            // module before closing the connection.
+                            let linger = socket_options
+                                .get(&handle)
+                                .is_some_and(|options| options.linger.is_some());
+
+                            if let (true, Some(edm_channel)) = (linger, tcp.edm_channel) {
+                                if tcp.send_queue() > 0 {
+                                    return tcp.tx_dequeue(|payload| {
+                                        let len = core::cmp::min(payload.len(), *egress_chunk);
+                                        buf[..len].copy_from_slice(&payload[..len]);
+                                        (
+                                            len,
+                                            Some(TxEvent::Send {
+                                                edm_channel,
+                                                data: &buf[..len],
+                                            }),
+                                        )
+                                    });
+                                }
+                            }
+
+                            return Some(TxEvent::Close {
+                                peer_handle: tcp.peer_handle.unwrap(),
+                            });
+                        }
+                        TcpState::Listen => todo!(),
+                        TcpState::SynReceived => todo!(),
+                        _ => {}
+                    };
+                }
+                _ => {}
+            };
+        }
+
+        None
+    }
+
+    async fn socket_tx<'data>(
+        ev: TxEvent<'data>,
+        socket: &RefCell<SocketStack>,
+        at_client: &RefCell<ProxyClient<'_, INGRESS_BUF_SIZE>>,
+    ) {
+        use atat::asynch::AtatClient;
+
+        // Shared with the resolver, see `resolve_names`
+        let at_client = at_client.borrow();
+        let mut at = &*at_client;
+        match ev {
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            TxEvent::Connect {
+                socket_handle,
+                socket_id,
+                url,
+            } => {
+                let peer_handle = match at
+                    .send_retry(&EdmAtCmdWrapper(ConnectPeer { url: &url }))
+                    .await
+                {
+                    Ok(ConnectPeerResponse { peer_handle }) => Some(peer_handle),
+                    Err(e) => {
+                        error!("Failed to connect?! {}", e);
+                        None
+                    }
+                };
+                socket
+                    .borrow_mut()
+                    .connect_response(socket_handle, socket_id, peer_handle);
+            }
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            TxEvent::Send { edm_channel, data } => {
+                warn!("Sending {} bytes on {}", data.len(), edm_channel);
+                at.send_retry(&EdmDataCommand {
+                    channel: edm_channel,
+                    data,
+                })
+                .await
+                .ok();
+            }
+            TxEvent::Close { peer_handle } => {
+                trace_transition(SocketTransition::Close, None, Some(peer_handle), None, None);
+                at.send_retry(&EdmAtCmdWrapper(ClosePeerConnection { peer_handle }))
+                    .await
+                    .ok();
+            }
+            #[cfg(feature = "socket-udp")]
+            TxEvent::Listen { server_id, port } => {
+                let server_config = match port {
+                    Some(port) => ServerType::UDP(port, UDPBehaviour::AutoConnect, IPVersion::IPv4),
+                    None => ServerType::Disabled,
+                };
+                if let Err(e) = at
+                    .send_retry(&EdmAtCmdWrapper(ServerConfiguration {
+                        id: server_id,
+                        server_config,
+                    }))
+                    .await
+                {
+                    error!("Failed to configure UDP server {}: {}", server_id, e);
+                    if port.is_some() {
+                        socket.borrow_mut().listen_failed(server_id);
+                    }
+                }
+            }
+            #[cfg(feature = "socket-tcp")]
+            TxEvent::PeerStatus {
+                socket_handle,
+                peer_handle,
+            } => match at.send_retry(&EdmAtCmdWrapper(PeerList)).await {
+                Ok(PeerListResponse { peers }) => {
+                    let peer_listed = peers.iter().any(|peer| peer.peer_handle == peer_handle);
+                    socket
+                        .borrow_mut()
+                        .peer_status(socket_handle, peer_handle, peer_listed);
+                }
+                Err(e) => {
+                    error!("Failed to query peer status of {}: {}", socket_handle, e);
+                }
+            },
+            TxEvent::ResyncConnections => {
+                let module_reset = socket
+                    .borrow()
+                    .pending_attach
+                    .as_ref()
+                    .is_some_and(|attach| attach.module_reset);
+
+                // Peers do not survive a reset, so there is nothing to list
+                let listed: heapless::Vec<PeerHandle, 8> = if module_reset {
+                    heapless::Vec::new()
+                } else {
+                    match at.send_retry(&EdmAtCmdWrapper(PeerList)).await {
+                        Ok(PeerListResponse { peers }) => {
+                            peers.iter().map(|peer| peer.peer_handle).collect()
+                        }
+                        Err(e) => {
+                            error!("Failed to list peers to attach: {}", e);
+                            socket.borrow_mut().resync_connections_failed();
+                            return;
+                        }
+                    }
+                };
+
+                socket.borrow_mut().resync_connections(&listed);
+            }
+            TxEvent::Dns { hostname } => {
+                // The module resolves the host for the ping, reporting the
+                // address with the `+UUPING` URC of the echo reply
+                match at
+                    .send_retry(&EdmAtCmdWrapper(Ping {
+                        hostname: &hostname,
+                        retry_num: 1,
+                    }))
+                    .await
+                {
+                    Ok(_) => {}
+                    Err(_) => {
+                        socket
+                            .borrow_mut()
+                            .dns_table
+                            .complete(&hostname, DnsState::Error(PingError::Other));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Resolve the hostnames submitted while the resolution command is
+    /// enabled, see [`UbloxStack::set_resolve_command`].
+    ///
+    /// Runs next to the socket loop, so URCs and data are processed while
+    /// the module resolves a name. The command is given up on after half the
+    /// timeout of the resolve, leaving the other half to the ping fallback.
+    async fn resolve_names(
+        socket: &RefCell<SocketStack>,
+        at_client: &RefCell<ProxyClient<'_, INGRESS_BUF_SIZE>>,
+    ) -> ! {
+        loop {
+            let (hostname, timeout) = poll_fn(|cx| {
+                let mut s = socket.borrow_mut();
+                let timeout = s.dns_table.timeout() / 2;
+                match s
+                    .dns_table
+                    .next_query(Instant::now(), ResolveMethod::Command)
+                {
+                    Some(query) => Poll::Ready((query.domain_name.clone(), timeout)),
+                    None => {
+                        s.waker.register(cx.waker());
+                        Poll::Pending
+                    }
+                }
+            })
+            .await;
+
+            let res = at_client
+                .borrow()
+                .send_abortable(
+                    &EdmAtCmdWrapper(ResolveName {
+                        resolution_type: ResolutionType::DomainToIp,
+                        domain_ip_string: &hostname,
+                    }),
+                    timeout,
+                    core::future::pending::<()>(),
+                )
+                .await;
+
+            let mut s = socket.borrow_mut();
+            match res {
+                Ok(Some(ResolveNameResponse { ip_address })) => {
+                    s.dns_table
+                        .complete(&hostname, DnsState::Resolved(ip_address));
+                }
+                Ok(None) => unreachable!("resolves are never aborted"),
+                Err(e) => {
+                    warn!(
+                        "DNS resolution of {} failed, falling back to ping: {}",
+                        hostname.as_str(),
+                        e
+                    );
+                    s.dns_table.fall_back(&hostname);
+                    s.waker.wake();
+                }
+            }
+        }
+    }
+
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    #[cfg_attr(not(feature = "socket-udp"), allow(unused_variables))]
+    fn connect_event(
+        channel_id: ChannelId,
+        protocol: Protocol,
+        endpoint: SocketAddr,
+        local_port: u16,
+        socket: &RefCell<SocketStack>,
+    ) {
+        let mut s = socket.borrow_mut();
+        let SocketStack {
+            sockets,
+            #[cfg(feature = "socket-udp")]
+            udp_listeners,
+            #[cfg(feature = "socket-tcp")]
+            connected_peers,
+            ..
+        } = s.deref_mut();
+        for (handle, socket) in sockets.iter_mut() {
+            match protocol {
+                #[cfg(feature = "socket-tcp")]
+                Protocol::TCP => match ublox_sockets::tcp::Socket::downcast_mut(socket) {
+                    // Only connecting sockets, a socket closed by its peer
+                    // may still be waiting for the application
+                    Some(tcp)
+                        if tcp.remote_endpoint == Some(endpoint)
+                            && tcp.edm_channel.is_none()
+                            && matches!(tcp.state(), TcpState::Closed | TcpState::SynSent) =>
+                    {
+                        tcp.edm_channel = Some(channel_id);
+                        establish(handle, tcp, connected_peers);
+                        break;
+                    }
+                    _ => {}
+                },
+                #[cfg(feature = "socket-udp")]
+                Protocol::UDP => match ublox_sockets::udp::Socket::downcast_mut(socket) {
+                    Some(udp)
+                        if udp.endpoint == Some(endpoint)
+                            || udp_listeners
+                                .get(&handle)
+                                .is_some_and(|l| l.port == local_port) =>
+                    {
+                        // A bound socket talks to the most recent sender
+                        udp.endpoint = Some(endpoint);
+                        udp.edm_channel = Some(channel_id);
+                        udp.set_state(UdpState::Established);
+                        trace_transition(
+                            SocketTransition::Connected,
+                            Some(handle),
+                            udp.peer_handle,
+                            Some(channel_id),
+                            Some(endpoint),
+                        );
+                        break;
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+// TODO: This extra data clone step can probably be avoided by adding a
+// waker/context based API to ATAT.
+enum TxEvent<'data> {
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    Connect {
+        socket_handle: SocketHandle,
+        socket_id: SocketId,
+        url: &'data str,
+    },
+    #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+    Send {
+        edm_channel: ChannelId,
+        data: &'data [u8],
+    },
+    Close {
+        peer_handle: PeerHandle,
+    },
+    /// Configure a UDP server listening on `port`, or disable it if `None`.
+    #[cfg(feature = "socket-udp")]
+    Listen {
+        server_id: u8,
+        port: Option<u16>,
+    },
+    /// Resolve `hostname` through a ping of the host.
+    Dns {
+        hostname: &'data str,
+    },
+    /// Query the peers of the module, to reconcile them with the pending
+    /// attach.
+    ResyncConnections,
+    /// Query the peers of the module, to check for a half-open connection on
+    /// `socket_handle`.
+    #[cfg(feature = "socket-tcp")]
+    PeerStatus {
+        socket_handle: SocketHandle,
+        peer_handle: PeerHandle,
+    },
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for TxEvent<'_> {
+    fn format(&self, fmt: defmt::Formatter) {
+        match self {
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            TxEvent::Connect { .. } => defmt::write!(fmt, "TxEvent::Connect"),
+            #[cfg(any(feature = "socket-tcp", feature = "socket-udp"))]
+            TxEvent::Send { .. } => defmt::write!(fmt, "TxEvent::Send"),
+            TxEvent::Close { .. } => defmt::write!(fmt, "TxEvent::Close"),
+            #[cfg(feature = "socket-udp")]
+            TxEvent::Listen { .. } => defmt::write!(fmt, "TxEvent::Listen"),
+            TxEvent::Dns { .. } => defmt::write!(fmt, "TxEvent::Dns"),
+            TxEvent::ResyncConnections => defmt::write!(fmt, "TxEvent::ResyncConnections"),
+            #[cfg(feature = "socket-tcp")]
+            TxEvent::PeerStatus { .. } => defmt::write!(fmt, "TxEvent::PeerStatus"),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "socket-tcp"))]
+mod test {
+    use super::dns::{DnsTableEntry, DNS_TIMEOUT};
+    #[cfg(feature = "socket-udp")]
+    use super::fixture::udp_socket;
+    use super::fixture::{socket_stack, tcp_socket, tcp_socket_with};
+    use super::*;
+    use ublox_sockets::tcp;
+
+    type Stack = UbloxStack<256, 8>;
+
+    fn data_event(channel_id: u8, len: usize) -> EdmEvent {
+        data_event_from(channel_id, &vec![0x42; len])
+    }
+
+    fn data_event_from(channel_id: u8, data: &[u8]) -> EdmEvent {
+        EdmEvent::DataEvent(DataEvent {
+            channel_id: ChannelId(channel_id),
+            data: heapless::Vec::from_slice(data).unwrap(),
+        })
+    }
+
+    #[test]
+    fn rx_losses_are_attributed() {
+        let stack = RefCell::new(socket_stack::<2>());
+
+        // A receive window smaller than the data event
+        let handle = tcp_socket_with(&mut stack.borrow_mut(), 4, 4);
+        {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            tcp.edm_channel = Some(ChannelId(1));
+            tcp.set_state(TcpState::Established);
+        }
+
+        Stack::socket_rx(data_event(1, 10), &stack);
+        Stack::socket_rx(data_event(2, 3), &stack);
+
+        let s = stack.borrow();
+        let stats = s.rx_stats.get(&1).unwrap();
+        assert_eq!(
+            *stats,
+            ChannelRxStats {
+                events: 1,
+                received: 10,
+                delivered: 4,
+                overflow: 6,
+                unknown_channel: 0,
+                staged: 0,
+                paused_overflow: 0,
+            }
+        );
+
+        let stats = s.rx_stats.get(&2).unwrap();
+        assert_eq!(stats.unknown_channel, 3);
+    }
+
+    #[test]
+    fn link_down_overtakes_data_backlog() {
+        use crate::asynch::state;
+        use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+        use embassy_sync::pubsub::PubSubChannel;
+
+        static CHANNEL: PubSubChannel<CriticalSectionRawMutex, EdmEvent, 128, 2, 1> =
+            PubSubChannel::new();
+
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let stack = RefCell::new(socket_stack::<2>());
+        stack.borrow_mut().set_link_up(true);
+
+        let publisher = CHANNEL.publisher().unwrap();
+        let mut urcs = UrcLanes::new(CHANNEL.subscriber().unwrap(), CHANNEL.subscriber().unwrap());
+        for _ in 0..100 {
+            publisher.publish_immediate(data_event(1, 8));
+        }
+        publisher.publish_immediate(EdmEvent::ATEvent(Urc::NetworkDown(NetworkDown {
+            interface_id: 0,
+        })));
+
+        let mut delivered = 0;
+        while stack.borrow().link_up {
+            let event = embassy_futures::block_on(urcs.next(&ch));
+            if matches!(event, EdmEvent::DataEvent(_)) {
+                delivered += 1;
+            }
+            Stack::socket_rx(event, &stack);
+        }
+        assert_eq!(delivered, 0);
+
+        // The backlog is still delivered, in order
+        for _ in 0..100 {
+            let event = embassy_futures::block_on(urcs.next(&ch));
+            assert!(matches!(event, EdmEvent::DataEvent(_)));
+            Stack::socket_rx(event, &stack);
+        }
+        assert_eq!(stack.borrow().rx_stats.get(&1).unwrap().events, 100);
+        assert_eq!(ch.urc_stats().lost, 0);
+    }
+
+    #[test]
+    fn link_lost_reported_for_every_socket() {
+        let stack = RefCell::new(socket_stack::<MAX_SOCKET_IDS>());
+        stack.borrow_mut().set_link_up(true);
+
+        let handles: Vec<_> = (0..MAX_SOCKET_IDS)
+            .map(|i| {
+                let mut s = stack.borrow_mut();
+                let handle = tcp_socket(&mut s);
+                let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+                tcp.peer_handle = Some(PeerHandle(i as u8));
+                tcp.edm_channel = Some(ChannelId(i as u8));
+                tcp.set_state(TcpState::Established);
+                handle
+            })
+            .collect();
+
+        stack.borrow_mut().set_link_up(false);
+
+        let s = stack.borrow();
+        for handle in handles {
+            assert_eq!(s.close_reasons.get(&handle), Some(&CloseReason::LinkLost));
+        }
+    }
+
+    #[test]
+    fn link_flap_followed() {
+        use crate::asynch::state;
+        use crate::connection::WiFiState;
+        use crate::test_util::harness;
+
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut link_ups = None;
+
+        let flap = || {
+            ch.update_connection_with(|con| con.wifi_state = WiFiState::NotConnected);
+            ch.update_connection_with(|con| harness::connect(con, Default::default(), 6));
+        };
+
+        ch.mark_initialized();
+        ch.update_connection_with(|con| harness::connect(con, Default::default(), 6));
+        Stack::follow_link(&stack, &ch, &mut link_ups);
+        assert!(stack.borrow().link_up);
+        let epoch = stack.borrow().link_epoch;
+
+        // Lost as told by the URCs, and regained before the link state is
+        // looked at again
+        Stack::socket_rx(
+            EdmEvent::ATEvent(Urc::NetworkDown(NetworkDown { interface_id: 0 })),
+            &stack,
+        );
+        assert!(!stack.borrow().link_up);
+        flap();
+        Stack::follow_link(&stack, &ch, &mut link_ups);
+        assert!(stack.borrow().link_up);
+        assert_ne!(stack.borrow().link_epoch, epoch);
+
+        // Lost and regained without the URCs telling
+        let handle = {
+            let mut s = stack.borrow_mut();
+            let handle = tcp_socket(&mut s);
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            tcp.peer_handle = Some(PeerHandle(0));
+            tcp.edm_channel = Some(ChannelId(0));
+            tcp.set_state(TcpState::Established);
+            handle
+        };
+        flap();
+        Stack::follow_link(&stack, &ch, &mut link_ups);
+
+        let s = stack.borrow();
+        assert!(s.link_up);
+        assert_eq!(s.close_reasons.get(&handle), Some(&CloseReason::LinkLost));
+    }
+
+    #[test]
+    #[cfg(feature = "ap")]
+    fn ap_down_resets_sockets() {
+        use crate::command::wifi::urc::WifiAPDown;
+
+        let stack = RefCell::new(socket_stack::<1>());
+        stack.borrow_mut().set_link_up(true);
+
+        let handle = tcp_socket(&mut stack.borrow_mut());
+        {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            tcp.peer_handle = Some(PeerHandle(3));
+            tcp.edm_channel = Some(ChannelId(1));
+            tcp.set_state(TcpState::Established);
+        }
+
+        Stack::socket_rx(
+            EdmEvent::ATEvent(Urc::WifiAPDown(WifiAPDown { connection_id: 0 })),
+            &stack,
+        );
+
+        let mut s = stack.borrow_mut();
+        assert!(!s.link_up);
+        assert_eq!(s.close_reasons.get(&handle), Some(&CloseReason::LinkLost));
+        let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+        assert_eq!(tcp.state(), TcpState::TimeWait);
+        assert_eq!(tcp.edm_channel, None);
+    }
+
+    #[test]
+    fn paused_rx() {
+        let stack = RefCell::new(socket_stack::<2>());
+
+        let handle = tcp_socket(&mut stack.borrow_mut());
+        {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            tcp.edm_channel = Some(ChannelId(1));
+            tcp.set_state(TcpState::Established);
+        }
+
+        Stack::socket_rx(data_event_from(1, b"ab"), &stack);
+        stack.borrow_mut().pause_rx(handle, 4).unwrap();
+
+        // Within the staging limit
+        Stack::socket_rx(data_event_from(1, b"cd"), &stack);
+        assert_eq!(stack.borrow().rx_stats.get(&1).unwrap().staged, 2);
+        assert_eq!(
+            stack
+                .borrow_mut()
+                .sockets
+                .get_mut::<tcp::Socket>(handle)
+                .recv_queue(),
+            2
+        );
+
+        // Beyond the staging limit
+        Stack::socket_rx(data_event_from(1, b"efg"), &stack);
+
+        assert_eq!(
+            stack.borrow_mut().resume_rx(handle),
+            Err(super::tcp::Error::PausedOverflow)
+        );
+        assert!(!stack.borrow().paused_rx.contains_key(&handle));
+        // Not paused, so nothing to report
+        assert_eq!(stack.borrow_mut().resume_rx(handle), Ok(()));
+
+        Stack::socket_rx(data_event_from(1, b"h"), &stack);
+
+        let mut s = stack.borrow_mut();
+        let stats = *s.rx_stats.get(&1).unwrap();
+        assert_eq!(stats.received, 8);
+        assert_eq!(stats.delivered, 7);
+        assert_eq!(stats.staged, 0);
+        assert_eq!(stats.paused_overflow, 1);
+
+        let mut buf = [0u8; 16];
+        let n = s
+            .sockets
+            .get_mut::<tcp::Socket>(handle)
+            .recv_slice(&mut buf)
+            .unwrap();
+        assert_eq!(&buf[..n], b"abcdefh");
+    }
+
+    #[test]
+    fn resume_rx_keeps_staged_data() {
+        let stack = RefCell::new(socket_stack::<1>());
+
+        let handle = tcp_socket(&mut stack.borrow_mut());
+        {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            tcp.edm_channel = Some(ChannelId(1));
+            tcp.set_state(TcpState::Established);
+        }
+        let recv = |len| {
+            let mut buf = [0u8; 16];
+            let n = stack
+                .borrow_mut()
+                .sockets
+                .get_mut::<tcp::Socket>(handle)
+                .recv_slice(&mut buf[..len])
+                .unwrap();
+            buf[..n].to_vec()
+        };
+
+        Stack::socket_rx(data_event_from(1, b"abcdefghijklmn"), &stack);
+        stack.borrow_mut().pause_rx(handle, 8).unwrap();
+        Stack::socket_rx(data_event_from(1, b"opqr"), &stack);
+
+        // Only 2 bytes of room for the 4 staged
+        assert_eq!(
+            stack.borrow_mut().resume_rx(handle),
+            Err(super::tcp::Error::RxBufferFull)
+        );
+        assert!(stack.borrow().paused_rx.contains_key(&handle));
+
+        assert_eq!(recv(2), b"ab");
+        assert_eq!(stack.borrow_mut().resume_rx(handle), Ok(()));
+        assert!(!stack.borrow().paused_rx.contains_key(&handle));
+        assert_eq!(recv(16), b"cdefghijklmnopqr");
+        assert_eq!(stack.borrow().rx_stats.get(&1).unwrap().delivered, 18);
+
+        // Staged data the socket can no longer receive is reported lost
+        stack.borrow_mut().pause_rx(handle, 8).unwrap();
+        Stack::socket_rx(data_event_from(1, b"st"), &stack);
+        stack
+            .borrow_mut()
+            .sockets
+            .get_mut::<tcp::Socket>(handle)
+            .set_state(TcpState::Closed);
+        assert_eq!(
+            stack.borrow_mut().resume_rx(handle),
+            Err(super::tcp::Error::PausedOverflow)
+        );
+        assert_eq!(stack.borrow().rx_stats.get(&1).unwrap().overflow, 2);
+    }
+
+    #[test]
+    fn connect_waits_for_both_events() {
+        use crate::command::data_mode::types::ConnectionType;
+        use crate::command::edm::types::IPv4ConnectEvent;
+        use atat::heapless_bytes::Bytes;
+
+        let connect_event = |channel_id| {
+            EdmEvent::IPv4ConnectEvent(IPv4ConnectEvent {
+                channel_id: ChannelId(channel_id),
+                protocol: Protocol::TCP,
+                remote_ip: "192.168.0.2".parse().unwrap(),
+                remote_port: 5000,
+                local_ip: "192.168.0.1".parse().unwrap(),
+                local_port: 4000,
+            })
+        };
+        let peer_connected = |handle| {
+            EdmEvent::ATEvent(Urc::PeerConnected(PeerConnected {
+                handle: PeerHandle(handle),
+                connection_type: ConnectionType::IPv4,
+                protocol: IPProtocol::TCP,
+                local_address: Bytes::from_slice(b"192.168.0.1").unwrap(),
+                local_port: 4000,
+                remote_address: Bytes::from_slice(b"192.168.0.2").unwrap(),
+                remote_port: 5000,
+            }))
+        };
+
+        let stack = RefCell::new(socket_stack::<1>());
+        let handle = tcp_socket(&mut stack.borrow_mut());
+        let connecting = |peer_handle| {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            tcp.remote_endpoint = Some("192.168.0.2:5000".parse().unwrap());
+            tcp.peer_handle = Some(PeerHandle(peer_handle));
+            tcp.edm_channel = None;
+            tcp.set_state(TcpState::SynSent);
+        };
+        let state = || stack.borrow().sockets.get::<tcp::Socket>(handle).state();
+
+        // EDM connect event first
+        connecting(1);
+        Stack::socket_rx(connect_event(1), &stack);
+        assert_eq!(state(), TcpState::SynSent);
+        Stack::socket_rx(peer_connected(1), &stack);
+        assert_eq!(state(), TcpState::Established);
+
+        // Peer connected URC first, for another peer
+        connecting(2);
+        Stack::socket_rx(peer_connected(3), &stack);
+        Stack::socket_rx(peer_connected(2), &stack);
+        assert_eq!(state(), TcpState::SynSent);
+        Stack::socket_rx(connect_event(2), &stack);
+        assert_eq!(state(), TcpState::Established);
+        assert_eq!(stack.borrow().connected_peers, [PeerHandle(3)]);
+
+        Stack::socket_rx(
+            EdmEvent::ATEvent(Urc::PeerDisconnected(PeerDisconnected {
+                handle: PeerHandle(3),
+                reason: None,
+            })),
+            &stack,
+        );
+        assert!(stack.borrow().connected_peers.is_empty());
+    }
+
+    #[test]
+    fn awaited_peer_not_evicted() {
+        use crate::command::data_mode::types::ConnectionType;
+        use crate::command::edm::types::IPv4ConnectEvent;
+        use atat::heapless_bytes::Bytes;
+
+        let peer_connected = |handle| {
+            EdmEvent::ATEvent(Urc::PeerConnected(PeerConnected {
+                handle: PeerHandle(handle),
+                connection_type: ConnectionType::IPv4,
+                protocol: IPProtocol::TCP,
+                local_address: Bytes::from_slice(b"192.168.0.1").unwrap(),
+                local_port: 4000,
+                remote_address: Bytes::from_slice(b"192.168.0.2").unwrap(),
+                remote_port: 5000,
+            }))
+        };
+
+        let stack = RefCell::new(socket_stack::<1>());
+        let handle = tcp_socket(&mut stack.borrow_mut());
+        {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            tcp.remote_endpoint = Some("192.168.0.2:5000".parse().unwrap());
+            tcp.peer_handle = Some(PeerHandle(1));
+            tcp.set_state(TcpState::SynSent);
+        }
+
+        // The peer of the connecting socket is the oldest, when more peers
+        // show up than there is room for
+        for peer_handle in 1..=MAX_SOCKET_IDS + 1 {
+            Stack::socket_rx(peer_connected(peer_handle as u8), &stack);
+        }
+        {
+            let s = stack.borrow();
+            assert_eq!(s.connected_peers.len(), MAX_SOCKET_IDS);
+            assert!(s.connected_peers.contains(&PeerHandle(1)));
+            assert!(!s.connected_peers.contains(&PeerHandle(2)));
+        }
+
+        Stack::socket_rx(
+            EdmEvent::IPv4ConnectEvent(IPv4ConnectEvent {
+                channel_id: ChannelId(1),
+                protocol: Protocol::TCP,
+                remote_ip: "192.168.0.2".parse().unwrap(),
+                remote_port: 5000,
+                local_ip: "192.168.0.1".parse().unwrap(),
+                local_port: 4000,
+            }),
+            &stack,
+        );
+        assert_eq!(
+            stack.borrow().sockets.get::<tcp::Socket>(handle).state(),
+            TcpState::Established
+        );
+    }
+
+    #[test]
+    fn peer_handle_reused() {
+        use crate::command::data_mode::types::ConnectionType;
+        use crate::command::edm::types::IPv4ConnectEvent;
+        use atat::heapless_bytes::Bytes;
+
+        let connect_event = |channel_id| {
+            EdmEvent::IPv4ConnectEvent(IPv4ConnectEvent {
+                channel_id: ChannelId(channel_id),
+                protocol: Protocol::TCP,
+                remote_ip: "192.168.0.2".parse().unwrap(),
+                remote_port: 5000,
+                local_ip: "192.168.0.1".parse().unwrap(),
+                local_port: 4000,
+            })
+        };
+        let peer_connected = EdmEvent::ATEvent(Urc::PeerConnected(PeerConnected {
+            handle: PeerHandle(0),
+            connection_type: ConnectionType::IPv4,
+            protocol: IPProtocol::TCP,
+            local_address: Bytes::from_slice(b"192.168.0.1").unwrap(),
+            local_port: 4000,
+            remote_address: Bytes::from_slice(b"192.168.0.2").unwrap(),
+            remote_port: 5000,
+        }));
+
+        let stack = RefCell::new(socket_stack::<2>());
+        let (a, b) = {
+            let mut s = stack.borrow_mut();
+            (tcp_socket(&mut s), tcp_socket(&mut s))
+        };
+        let connect = |handle| {
+            let mut s = stack.borrow_mut();
+            s.sockets.get_mut::<tcp::Socket>(handle).remote_endpoint =
+                Some("192.168.0.2:5000".parse().unwrap());
+            s.bind_peer(handle, PeerHandle(0));
+        };
+
+        // Socket A connects, receives data and is closed by the remote
+        connect(a);
+        Stack::socket_rx(connect_event(1), &stack);
+        Stack::socket_rx(peer_connected.clone(), &stack);
+        Stack::socket_rx(data_event_from(1, b"a"), &stack);
+        Stack::socket_rx(EdmEvent::DisconnectEvent(ChannelId(1)), &stack);
+
+        // Socket B connects before the close reached A, and gets the same
+        // peer handle
+        connect(b);
+        Stack::socket_rx(connect_event(2), &stack);
+        Stack::socket_rx(peer_connected, &stack);
+        Stack::socket_rx(data_event_from(2, b"b"), &stack);
+
+        {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(b);
+            assert_eq!(tcp.state(), TcpState::Established);
+            assert_eq!(tcp.peer_handle, Some(PeerHandle(0)));
+            assert_eq!(tcp.edm_channel, Some(ChannelId(2)));
+            assert_eq!(tcp.recv_queue(), 1);
+            tcp.send_slice(b"c").unwrap();
+
+            // A is left to drain its data
+            let tcp = s.sockets.get_mut::<tcp::Socket>(a);
+            assert_eq!(tcp.state(), TcpState::TimeWait);
+            assert_eq!(tcp.peer_handle, None);
+            assert_eq!(tcp.recv_queue(), 1);
+        }
+
+        let mut buf = [0u8; 16];
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut buf),
+            Some(TxEvent::Send {
+                edm_channel: ChannelId(2),
+                data: b"c",
+            })
+        ));
+    }
+
+    #[test]
+    fn connect_unconnected_sockets_in_any_order() {
+        use crate::command::data_mode::types::ConnectionType;
+        use crate::command::edm::types::IPv4ConnectEvent;
+        use atat::heapless_bytes::Bytes;
+
+        let connect_event = |channel_id, remote_port| {
+            EdmEvent::IPv4ConnectEvent(IPv4ConnectEvent {
+                channel_id: ChannelId(channel_id),
+                protocol: Protocol::TCP,
+                remote_ip: "192.168.0.2".parse().unwrap(),
+                remote_port,
+                local_ip: "192.168.0.1".parse().unwrap(),
+                local_port: 4000,
+            })
+        };
+        let peer_connected = |handle, remote_port| {
+            EdmEvent::ATEvent(Urc::PeerConnected(PeerConnected {
+                handle: PeerHandle(handle),
+                connection_type: ConnectionType::IPv4,
+                protocol: IPProtocol::TCP,
+                local_address: Bytes::from_slice(b"192.168.0.1").unwrap(),
+                local_port: 4000,
+                remote_address: Bytes::from_slice(b"192.168.0.2").unwrap(),
+                remote_port,
+            }))
+        };
+
+        // Open the full capacity of sockets up front
+        let stack = RefCell::new(socket_stack::<4>());
+        let handles: Vec<_> = (0..4)
+            .map(|_| tcp_socket(&mut stack.borrow_mut()))
+            .collect();
+
+        // Connect them in reverse order
+        for (i, &handle) in handles.iter().enumerate().rev() {
+            let port = 5000 + i as u16;
+            {
+                let mut s = stack.borrow_mut();
+                s.sockets.get_mut::<tcp::Socket>(handle).remote_endpoint =
+                    Some(SocketAddr::new("192.168.0.2".parse().unwrap(), port));
+                s.bind_peer(handle, PeerHandle(i as u8));
+            }
+            Stack::socket_rx(connect_event(10 + i as u8, port), &stack);
+            Stack::socket_rx(peer_connected(i as u8, port), &stack);
+        }
+
+        for i in 0..4 {
+            Stack::socket_rx(data_event(10 + i as u8, i + 1), &stack);
+        }
+
+        let mut s = stack.borrow_mut();
+        for (i, &handle) in handles.iter().enumerate() {
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            assert_eq!(tcp.state(), TcpState::Established);
+            assert_eq!(tcp.peer_handle, Some(PeerHandle(i as u8)));
+            assert_eq!(tcp.edm_channel, Some(ChannelId(10 + i as u8)));
+            assert_eq!(tcp.recv_queue(), i + 1);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "socket-udp")]
+    fn dynamic_ports() {
+        let mut s = socket_stack::<2>();
+        let handle = udp_socket(&mut s);
+        s.udp_listeners
+            .insert(
+                handle,
+                UdpListener {
+                    port: 49153,
+                    server_id: UDP_SERVER_IDS[0],
+                    active: true,
+                    last_rx: Instant::now(),
+                },
+            )
+            .ok();
+
+        #[cfg(feature = "socket-tcp")]
+        {
+            let handle = tcp_socket(&mut s);
+            s.sockets.get_mut::<tcp::Socket>(handle).local_port = Some(49154);
+        }
+
+        assert_eq!(s.get_local_port(), 49152);
+        // Skips the ports in use
+        #[cfg(feature = "socket-tcp")]
+        assert_eq!(s.get_local_port(), 49155);
+        #[cfg(not(feature = "socket-tcp"))]
+        assert_eq!(s.get_local_port(), 49154);
+
+        s.next_local_port = 65535;
+        assert_eq!(s.get_local_port(), 65535);
+        assert_eq!(s.get_local_port(), 49152);
+    }
+
+    #[test]
+    fn half_open_detected() {
+        let stack = RefCell::new(socket_stack::<1>());
+
+        let handle = tcp_socket(&mut stack.borrow_mut());
+        {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            tcp.peer_handle = Some(PeerHandle(3));
+            tcp.edm_channel = Some(ChannelId(1));
+            tcp.set_state(TcpState::Established);
+            s.half_open
+                .insert(handle, HalfOpenMonitor::new(Instant::from_secs(0)))
+                .ok();
+        }
+
+        // The module still lists the peer, but nothing is received
+        stack.borrow_mut().peer_status(handle, PeerHandle(3), true);
+        assert_eq!(
+            stack
+                .borrow_mut()
+                .sockets
+                .get_mut::<tcp::Socket>(handle)
+                .state(),
+            TcpState::Established
+        );
+        assert_eq!(stack.borrow().close_reasons.get(&handle), None);
+
+        stack.borrow_mut().peer_status(handle, PeerHandle(3), true);
+        let mut s = stack.borrow_mut();
+        assert_eq!(
+            s.close_reasons.get(&handle),
+            Some(&CloseReason::HalfOpenDetected)
+        );
+        assert_eq!(s.dropped_sockets, [PeerHandle(3)]);
+        let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+        assert_eq!(tcp.state(), TcpState::TimeWait);
+        assert_eq!(tcp.peer_handle, None);
+    }
+
+    #[test]
+    fn dns_resolve_does_not_block_close() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut buf = [0u8; MAX_EGRESS_SIZE];
+
+        stack
+            .borrow_mut()
+            .dns_table
+            .upsert(DnsTableEntry::new(
+                heapless::String::try_from("unresponsive.example.com").unwrap(),
+            ))
+            .unwrap();
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut buf),
+            Some(TxEvent::Dns {
+                hostname: "unresponsive.example.com"
+            })
+        ));
+
+        // A socket is dropped while the resolve is pending
+        stack.borrow_mut().dropped_sockets.insert(PeerHandle(2));
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut buf),
+            Some(TxEvent::Close {
+                peer_handle: PeerHandle(2)
+            })
+        ));
+        assert!(Stack::tx_event(&stack, &mut buf).is_none());
+
+        let clock = crate::test_util::ManualClock::new();
+        clock.advance_by(DNS_TIMEOUT);
+        let mut s = stack.borrow_mut();
+        s.dns_table.expire(clock.now());
+        assert!(
+            s.dns_table.get("unresponsive.example.com").unwrap().state
+                == DnsState::Error(PingError::Timeout)
+        );
+    }
+
+    #[test]
+    fn dns_resolve_held_back_by_ping() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut buf = [0u8; MAX_EGRESS_SIZE];
+
+        stack
+            .borrow_mut()
+            .dns_table
+            .upsert(DnsTableEntry::new(
+                heapless::String::try_from("example.com").unwrap(),
+            ))
+            .unwrap();
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut buf),
+            Some(TxEvent::Dns {
+                hostname: "example.com"
+            })
+        ));
+
+        // The application starts a ping while the resolve is pending
+        stack.borrow_mut().ping_active = true;
+        stack
+            .borrow_mut()
+            .dns_table
+            .upsert(DnsTableEntry::new(
+                heapless::String::try_from("other.example.com").unwrap(),
+            ))
+            .unwrap();
+
+        // The error of the ping is not taken for that of the resolve
+        Stack::socket_rx(
+            EdmEvent::ATEvent(Urc::PingErrorResponse(PingErrorResponse {
+                error: PingError::CannotResolveHost,
+            })),
+            &stack,
+        );
+        assert!(stack.borrow().dns_table.get("example.com").unwrap().state == DnsState::Pending);
+
+        Stack::socket_rx(
+            EdmEvent::ATEvent(Urc::PingResponse(PingResponse {
+                retrynum: 1,
+                ping_size: 32,
+                hostname: heapless::String::try_from("example.com").unwrap(),
+                ip: no_std_net::Ipv4Addr::new(93, 184, 216, 34).into(),
+                ttl: 52,
+                rtt: 20,
+            })),
+            &stack,
+        );
+        assert!(Stack::tx_event(&stack, &mut buf).is_none());
+
+        stack.borrow_mut().ping_active = false;
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut buf),
+            Some(TxEvent::Dns {
+                hostname: "other.example.com"
+            })
+        ));
+    }
+
+    #[test]
+    fn ping_held_back_by_resolve() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut buf = [0u8; MAX_EGRESS_SIZE];
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+
+        stack
+            .borrow_mut()
+            .dns_table
+            .upsert(DnsTableEntry::new(
+                heapless::String::try_from("example.com").unwrap(),
+            ))
+            .unwrap();
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut buf),
+            Some(TxEvent::Dns {
+                hostname: "example.com"
+            })
+        ));
+        Stack::sync_resolve_ping(&stack, &ch);
+
+        // The ping of the application waits for that of the resolve
+        let mut ping = core::pin::pin!(ch.start_ping());
+        assert!(embassy_futures::poll_once(ping.as_mut()).is_pending());
+
+        Stack::socket_rx(
+            EdmEvent::ATEvent(Urc::PingResponse(PingResponse {
+                retrynum: 1,
+                ping_size: 32,
+                hostname: heapless::String::try_from("example.com").unwrap(),
+                ip: no_std_net::Ipv4Addr::new(93, 184, 216, 34).into(),
+                ttl: 52,
+                rtt: 20,
+            })),
+            &stack,
+        );
+        Stack::sync_resolve_ping(&stack, &ch);
+        assert_eq!(embassy_futures::poll_once(ping.as_mut()), Poll::Ready(true));
+    }
+
+    /// Resolve `hostname` on `stack` with the resolution command, until
+    /// `done` holds for its DNS table entry, with `module` serving the
+    /// commands.
+    fn resolve_with(
+        module: &mut crate::test_util::MockUbloxModule,
+        stack: &RefCell<SocketStack>,
+        hostname: &str,
+        done: impl Fn(&SocketStack) -> bool,
+    ) {
+        let harness = crate::test_util::Harness::new();
+        let client = RefCell::new(harness.client());
+
+        stack.borrow_mut().dns_table.set_resolve_command(true);
+        stack
+            .borrow_mut()
+            .dns_table
+            .upsert(DnsTableEntry::new(
+                heapless::String::try_from(hostname).unwrap(),
+            ))
+            .unwrap();
+
+        let resolved = poll_fn(|cx| {
+            let mut s = stack.borrow_mut();
+            match done(&s) {
+                true => Poll::Ready(()),
+                false => {
+                    s.waker.register(cx.waker());
+                    s.dns_table
+                        .get_mut(hostname)
+                        .unwrap()
+                        .waker
+                        .register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        });
+        harness.serve(
+            module,
+            select::select(Stack::resolve_names(stack, &client), resolved),
+        );
+    }
+
+    #[test]
+    fn dns_resolve_command() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut buf = [0u8; MAX_EGRESS_SIZE];
+
+        let mut module = crate::test_util::MockUbloxModule::new();
+        module.respond("AT+UDNSRN=0,\"example.com\"", "+UDNSRN:\"93.184.216.34\"");
+        resolve_with(&mut module, &stack, "example.com", |s| {
+            s.dns_table.get("example.com").unwrap().state != DnsState::Pending
+        });
+
+        assert!(
+            stack.borrow().dns_table.get("example.com").unwrap().state
+                == DnsState::Resolved(no_std_net::Ipv4Addr::new(93, 184, 216, 34).into())
+        );
+        // No ping of the host
+        assert!(Stack::tx_event(&stack, &mut buf).is_none());
+    }
+
+    #[test]
+    fn dns_resolve_command_falls_back_to_ping() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut buf = [0u8; MAX_EGRESS_SIZE];
+
+        // Firmware without the resolution command
+        let mut module = crate::test_util::MockUbloxModule::new();
+        module.fail("AT+UDNSRN", atat::Error::Error);
+        resolve_with(&mut module, &stack, "example.com", |s| {
+            s.dns_table.get("example.com").unwrap().state == DnsState::New
+        });
+
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut buf),
+            Some(TxEvent::Dns {
+                hostname: "example.com"
+            })
+        ));
+    }
+
+    #[test]
+    fn dns_resolve_command_disabled_by_default() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut buf = [0u8; MAX_EGRESS_SIZE];
+
+        stack
+            .borrow_mut()
+            .dns_table
+            .upsert(DnsTableEntry::new(
+                heapless::String::try_from("example.com").unwrap(),
+            ))
+            .unwrap();
+        assert!(stack
+            .borrow_mut()
+            .dns_table
+            .next_query(Instant::now(), ResolveMethod::Command)
+            .is_none());
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut buf),
+            Some(TxEvent::Dns {
+                hostname: "example.com"
+            })
+        ));
+    }
+
+    #[test]
+    fn egress_clamped_to_advertised_payload() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut buf = [0u8; MAX_EGRESS_SIZE];
+
+        assert_eq!(egress_chunk(EdmCapabilities::DEFAULT), MAX_EGRESS_SIZE);
+        for max_payload in [0, 1, 2] {
+            let capabilities = EdmCapabilities {
+                version: 1,
+                max_payload,
+            };
+            assert_eq!(egress_chunk(capabilities), 1);
+        }
+        stack.borrow_mut().egress_chunk = egress_chunk(EdmCapabilities {
+            version: 1,
+            max_payload: 1001,
+        });
+
+        let handle = tcp_socket_with(&mut stack.borrow_mut(), 4, 2500);
+        {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            tcp.edm_channel = Some(ChannelId(1));
+            tcp.set_state(TcpState::Established);
+            assert_eq!(tcp.send_slice(&[0x42; 2500]).unwrap(), 2500);
+        }
+
+        let mut chunks = vec![];
+        while let Some(ev) = Stack::tx_event(&stack, &mut buf) {
+            match ev {
+                TxEvent::Send { edm_channel, data } => {
+                    assert!(edm_channel == ChannelId(1));
+                    assert!(data.iter().all(|b| *b == 0x42));
+                    chunks.push(data.len());
+                }
+                _ => panic!("unexpected tx event"),
+            }
+        }
+        assert_eq!(chunks, [1000, 1000, 500]);
+    }
+
+    #[test]
+    fn mapping_invariants() {
+        let stack = RefCell::new(socket_stack::<2>());
+
+        let mut handles = [None; 2];
+        for handle in handles.iter_mut() {
+            *handle = Some(tcp_socket_with(&mut stack.borrow_mut(), 4, 4));
+        }
+        let [Some(first), Some(second)] = handles else {
+            unreachable!()
+        };
+        assert_eq!(stack.borrow().check_invariants(), 0);
+
+        {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(first);
+            tcp.peer_handle = Some(PeerHandle(0));
+            tcp.edm_channel = Some(ChannelId(1));
+            tcp.set_state(TcpState::Established);
+        }
+        assert_eq!(stack.borrow().check_invariants(), 0);
+
+        // Established without a channel
+        stack
+            .borrow_mut()
+            .sockets
+            .get_mut::<tcp::Socket>(second)
+            .set_state(TcpState::Established);
+        assert_eq!(stack.borrow().check_invariants(), 1);
+
+        // Sharing the channel of the first socket
+        {
+            let mut s = stack.borrow_mut();
+            let tcp = s.sockets.get_mut::<tcp::Socket>(second);
+            tcp.peer_handle = Some(PeerHandle(1));
+            tcp.edm_channel = Some(ChannelId(1));
+        }
+        assert_eq!(stack.borrow().check_invariants(), 1);
+    }
+
+    #[test]
+    fn reopened_socket_does_not_alias() {
+        let stack = RefCell::new(socket_stack::<2>());
+        let open = || {
+            let mut s = stack.borrow_mut();
+            let handle = tcp_socket(&mut s);
+            s.add_socket_id(handle);
+            s.sockets.get_mut::<tcp::Socket>(handle).remote_endpoint =
+                Some("192.168.0.2:5000".parse().unwrap());
+            handle
+        };
+        let state = |handle| stack.borrow().sockets.get::<tcp::Socket>(handle).state();
+
+        // Socket A connects, and is closed by the remote
+        let a = open();
+        let Some(TxEvent::Connect {
+            socket_handle,
+            socket_id: a_id,
+            ..
+        }) = Stack::tx_event(&stack, &mut [0u8; 128])
+        else {
+            panic!("expected a connect");
+        };
+        assert_eq!(socket_handle, a);
+        stack
+            .borrow_mut()
+            .connect_response(a, a_id, Some(PeerHandle(0)));
+        Stack::socket_rx(
+            EdmEvent::ATEvent(Urc::PeerDisconnected(PeerDisconnected {
+                handle: PeerHandle(0),
+                reason: None,
+            })),
+            &stack,
+        );
+        assert_eq!(state(a), TcpState::TimeWait);
+
+        // Socket B opens while A lingers
+        let b = open();
+        let b_id = stack.borrow().socket_id(b).unwrap();
+        assert_ne!(b_id, a_id);
+
+        // A is dropped, and socket C immediately takes over its handle
+        {
+            let mut s = stack.borrow_mut();
+            s.remove_socket_id(a);
+            s.sockets.remove(a);
+        }
+        let c = open();
+        assert_eq!(c, a);
+        let c_id = stack.borrow().socket_id(c).unwrap();
+        assert_ne!(c_id, a_id);
+        assert_ne!(c_id, b_id);
+
+        // A late response to a connect of A is not taken for C
+        stack
+            .borrow_mut()
+            .connect_response(a, a_id, Some(PeerHandle(1)));
+        assert_eq!(state(c), TcpState::Closed);
+        assert_eq!(
+            stack.borrow().sockets.get::<tcp::Socket>(c).peer_handle,
+            None
+        );
+        assert_eq!(stack.borrow().dropped_sockets, [PeerHandle(1)]);
+
+        stack
+            .borrow_mut()
+            .connect_response(c, c_id, Some(PeerHandle(1)));
+        assert_eq!(state(c), TcpState::SynSent);
+    }
+
+    #[test]
+    fn socket_ids_wrap() {
+        let mut s = socket_stack::<3>();
+
+        s.next_socket_id = u16::MAX;
+        let a = tcp_socket(&mut s);
+        s.add_socket_id(a);
+        let b = tcp_socket(&mut s);
+        s.add_socket_id(b);
+        assert_eq!(s.socket_id(a), Some(SocketId(u16::MAX)));
+        assert_eq!(s.socket_id(b), Some(SocketId(0)));
+
+        // Ids still in use are skipped
+        s.next_socket_id = u16::MAX;
+        let c = tcp_socket(&mut s);
+        s.add_socket_id(c);
+        assert_eq!(s.socket_id(c), Some(SocketId(1)));
+    }
+
+    #[test]
+    fn socket_without_id_never_connects() {
+        let stack = RefCell::new(socket_stack::<{ MAX_SOCKET_IDS + 1 }>());
+        let handles: Vec<_> = (0..=MAX_SOCKET_IDS)
+            .map(|_| {
+                let mut s = stack.borrow_mut();
+                let handle = tcp_socket(&mut s);
+                s.add_socket_id(handle);
+                handle
+            })
+            .collect();
+        let last = *handles.last().unwrap();
+        assert!(!stack.borrow().has_socket_id(last));
+
+        stack
+            .borrow_mut()
+            .sockets
+            .get_mut::<tcp::Socket>(last)
+            .remote_endpoint = Some("192.168.0.2:5000".parse().unwrap());
+        assert!(Stack::tx_event(&stack, &mut [0u8; 128]).is_none());
+
+        // A response for another socket is not taken for it
+        let id = stack.borrow().socket_id(handles[0]).unwrap();
+        stack
+            .borrow_mut()
+            .connect_response(last, id, Some(PeerHandle(1)));
+        assert_eq!(
+            stack.borrow().sockets.get::<tcp::Socket>(last).peer_handle,
+            None
+        );
+        assert_eq!(stack.borrow().dropped_sockets, [PeerHandle(1)]);
+    }
+
+    #[test]
+    fn dropped_peers_never_overflow() {
+        let stack = RefCell::new(socket_stack::<1>());
+        for peer in 0..32 {
+            stack.borrow_mut().dropped_sockets.insert(PeerHandle(peer));
+        }
+
+        for peer in 0..32 {
+            assert!(matches!(
+                Stack::tx_event(&stack, &mut [0u8; 64]),
+                Some(TxEvent::Close { peer_handle }) if peer_handle == PeerHandle(peer)
+            ));
+        }
+        assert!(Stack::tx_event(&stack, &mut [0u8; 64]).is_none());
+    }
+
+    #[test]
+    fn attach_with_vanished_peer() {
+        // Detach with two connected sockets
+        let stack = RefCell::new(socket_stack::<2>());
+        for (peer_handle, port) in [(1, 5001), (2, 5002)] {
+            let mut s = stack.borrow_mut();
+            let handle = tcp_socket(&mut s);
+            let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+            tcp.remote_endpoint = Some(SocketAddr::new("192.168.0.2".parse().unwrap(), port));
+            tcp.peer_handle = Some(PeerHandle(peer_handle));
+            tcp.edm_channel = Some(ChannelId(peer_handle));
+            tcp.set_state(TcpState::Established);
+        }
+
+        let state = DetachedState {
+            config_id: Some(CONFIG_ID),
+            edm_capabilities: EdmCapabilities::DEFAULT,
+            peers: stack.borrow().detached_peers(),
+        };
+        let mut buf = [0u8; DetachedState::MAX_LEN];
+        let len = state.to_bytes(&mut buf).unwrap();
+
+        // Nothing is sent to the module after detaching
+        stack.borrow_mut().detached = true;
+        stack.borrow_mut().dropped_sockets.insert(PeerHandle(1));
+        assert!(Stack::tx_event(&stack, &mut [0u8; 64]).is_none());
+
+        // After the reboot, peer 2 is gone, and the module lists a peer
+        // unknown to the previous boot
+        let mut s = socket_stack::<1>();
+        s.pending_attach = Some(PendingAttach {
+            previous: DetachedState::from_bytes(&buf[..len]).unwrap(),
+            module_reset: false,
+        });
+        s.resync_connections(&[PeerHandle(1), PeerHandle(5)]);
+
+        let report = s.attach_report.clone().unwrap();
+        assert_eq!(report.peers.len(), 2);
+        assert_eq!(report.status(PeerHandle(1)), Some(PeerStatus::Alive));
+        assert_eq!(
+            report.status(PeerHandle(2)),
+            Some(PeerStatus::Closed(AttachCloseReason::ClosedWhileDetached))
+        );
+        assert_eq!(report.status(PeerHandle(5)), None);
+        assert_eq!(s.dropped_sockets, [PeerHandle(5)]);
+
+        // Only the alive peer can be adopted
+        let handle = tcp_socket(&mut s);
+        assert!(matches!(
+            s.adopt(handle, PeerHandle(2)),
+            Err(crate::error::Error::SocketNotFound)
+        ));
+        s.adopt(handle, PeerHandle(1)).unwrap();
+
+        let tcp = s.sockets.get::<tcp::Socket>(handle);
+        assert_eq!(tcp.state(), TcpState::Established);
+        assert_eq!(tcp.edm_channel, Some(ChannelId(1)));
+        assert_eq!(
+            tcp.remote_endpoint,
+            Some("192.168.0.2:5001".parse().unwrap())
+        );
+        assert!(s.attached_peers.is_empty());
+        assert_eq!(s.check_invariants(), 0);
+    }
+
+    #[test]
+    fn attach_after_module_reset() {
+        let mut s = socket_stack::<1>();
+
+        let mut peers = heapless::Vec::new();
+        peers
+            .push(DetachedPeer {
+                peer_handle: PeerHandle(1),
+                channel_id: Some(ChannelId(1)),
+                protocol: IPProtocol::TCP,
+                remote: "192.168.0.2:5001".parse().unwrap(),
+            })
+            .unwrap();
+        s.pending_attach = Some(PendingAttach {
+            previous: DetachedState {
+                config_id: None,
+                edm_capabilities: EdmCapabilities::DEFAULT,
+                peers,
+            },
+            module_reset: true,
+        });
+        s.resync_connections(&[]);
+
+        assert_eq!(
+            s.attach_report.unwrap().status(PeerHandle(1)),
+            Some(PeerStatus::Closed(AttachCloseReason::ModuleReset))
+        );
+        assert!(s.attached_peers.is_empty());
+        assert!(s.dropped_sockets.is_empty());
+    }
+
+    /// Run the pending attach of `stack` through the peer list of `module`,
+    /// `attempts` times.
+    fn resync_with(
+        module: &mut crate::test_util::MockUbloxModule,
+        stack: &RefCell<SocketStack>,
+        attempts: usize,
+    ) {
+        let harness = crate::test_util::Harness::new();
+        let client = RefCell::new(harness.client());
+
+        let resync = async {
+            for _ in 0..attempts {
+                assert!(matches!(
+                    Stack::tx_event(stack, &mut [0u8; 64]),
+                    Some(TxEvent::ResyncConnections)
+                ));
+                Stack::socket_tx(TxEvent::ResyncConnections, stack, &client).await;
+            }
+        };
+        harness.serve(module, resync);
+    }
+
+    fn pending_attach(peer_handles: &[u8]) -> RefCell<SocketStack> {
+        let mut s = socket_stack::<1>();
+
+        let mut peers = heapless::Vec::new();
+        for &peer_handle in peer_handles {
+            peers
+                .push(DetachedPeer {
+                    peer_handle: PeerHandle(peer_handle),
+                    channel_id: Some(ChannelId(peer_handle)),
+                    protocol: IPProtocol::TCP,
+                    remote: format!("192.168.0.2:{}", 5000 + u16::from(peer_handle))
+                        .parse()
+                        .unwrap(),
+                })
+                .unwrap();
+        }
+        s.pending_attach = Some(PendingAttach {
+            previous: DetachedState {
+                config_id: Some(CONFIG_ID),
+                edm_capabilities: EdmCapabilities::DEFAULT,
+                peers,
+            },
+            module_reset: false,
+        });
+        RefCell::new(s)
+    }
+
+    #[test]
+    fn attach_through_peer_list() {
+        let mut module = crate::test_util::MockUbloxModule::new();
+        module.respond(
+            "AT+UDLP?",
+            "+UDLP:1,\"tcp\",\"192.168.0.10:49153\",\"192.168.0.2:5001\"\r\n\
+             +UDLP:3,\"tcp\",\"192.168.0.10:49154\",\"192.168.0.2:5003\"",
+        );
+
+        let stack = pending_attach(&[1, 2]);
+        resync_with(&mut module, &stack, 1);
+
+        let s = stack.borrow();
+        assert!(s.pending_attach.is_none());
+        let report = s.attach_report.clone().unwrap();
+        assert_eq!(report.status(PeerHandle(1)), Some(PeerStatus::Alive));
+        assert_eq!(
+            report.status(PeerHandle(2)),
+            Some(PeerStatus::Closed(AttachCloseReason::ClosedWhileDetached))
+        );
+        assert_eq!(s.dropped_sockets, [PeerHandle(3)]);
+        assert_eq!(
+            module.sent_commands().collect::<std::vec::Vec<_>>(),
+            [b"AT+UDLP?\r\n".as_slice()]
+        );
+    }
+
+    #[test]
+    fn attach_without_peer_list() {
+        let mut module = crate::test_util::MockUbloxModule::new();
+        module.fail("AT+UDLP?", atat::Error::Error);
+
+        let stack = pending_attach(&[1, 2]);
+
+        // Retried on the next transmission
+        resync_with(&mut module, &stack, 1);
+        assert!(stack.borrow().pending_attach.is_some());
+        assert!(stack.borrow().attach_report.is_none());
+
+        // Until out of attempts, closing the peers of the previous boot
+        resync_with(&mut module, &stack, usize::from(MAX_ATTACH_ATTEMPTS) - 1);
+        let mut s = stack.borrow_mut();
+        assert!(s.pending_attach.is_none());
+        let report = s.attach_report.clone().unwrap();
+        for peer_handle in [PeerHandle(1), PeerHandle(2)] {
+            assert_eq!(
+                report.status(peer_handle),
+                Some(PeerStatus::Closed(AttachCloseReason::PeerListFailed))
+            );
+        }
+        assert_eq!(s.dropped_sockets, [PeerHandle(1), PeerHandle(2)]);
+        assert!(s.attached_peers.is_empty());
+        assert_eq!(
+            module.sent_commands().count(),
+            usize::from(MAX_ATTACH_ATTEMPTS)
+        );
+
+        // Nothing is left to resync
+        s.dropped_sockets.clear();
+        drop(s);
+        assert!(Stack::tx_event(&stack, &mut [0u8; 64]).is_none());
+    }
+}
+
+#[cfg(all(test, not(any(feature = "socket-tcp", feature = "socket-udp"))))]
+mod socketless_test {
+    use super::fixture::socket_stack;
+    use super::*;
+
+    type Stack = UbloxStack<256, 8>;
+
+    #[test]
+    fn data_event_is_counted() {
+        let stack = RefCell::new(socket_stack::<1>());
+
+        let event = EdmEvent::DataEvent(DataEvent {
+            channel_id: ChannelId(1),
+            data: heapless::Vec::from_slice(&[0x42; 10]).unwrap(),
+        });
+        Stack::socket_rx(event.clone(), &stack);
+        Stack::socket_rx(event, &stack);
+
+        let stats = *stack.borrow().rx_stats.get(&1).unwrap();
+        assert_eq!(stats.events, 2);
+        assert_eq!(stats.received, 20);
+        assert_eq!(stats.unknown_channel, 20);
+    }
+}
@@ -0,0 +1,89 @@
+//! Pausing the receive path of a socket.
+//!
+//! Applications running their own flow control with the remote peer may need
+//! to stop data from piling up in the receive buffer of a socket, without
+//! reading it out. While a socket is paused, received data is staged up to a
+//! small limit instead, and handed to the socket in order on resume. Data
+//! beyond the limit is dropped, and reported as
+//! [`Error::PausedOverflow`](super::tcp::Error::PausedOverflow) on resume.
+use heapless::Vec;
+
+use ublox_sockets::ChannelId;
+
+/// Maximum number of bytes staged for a paused socket.
+pub const MAX_STAGED_RX: usize = 256;
+
+/// Receive path state of a paused socket.
+#[derive(Debug)]
+pub(crate) struct PausedRx {
+    staged: Vec<u8, MAX_STAGED_RX>,
+    limit: usize,
+    /// Channel the staged data was received on.
+    channel_id: Option<ChannelId>,
+    /// Bytes dropped because the staging limit was reached.
+    dropped: usize,
+}
+
+impl PausedRx {
+    /// Start staging up to `limit` bytes, capped at [`MAX_STAGED_RX`].
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            staged: Vec::new(),
+            limit: limit.min(MAX_STAGED_RX),
+            channel_id: None,
+            dropped: 0,
+        }
+    }
+
+    /// Stage the data of a data event, returning the number of bytes staged.
+    /// The rest is dropped.
+    pub(crate) fn stage(&mut self, channel_id: ChannelId, data: &[u8]) -> usize {
+        let n = data.len().min(self.limit - self.staged.len());
+        // Cannot fail, as the limit is capped at the capacity
+        self.staged.extend_from_slice(&data[..n]).ok();
+        self.dropped += data.len() - n;
+        self.channel_id = Some(channel_id);
+        n
+    }
+
+    pub(crate) fn staged(&self) -> &[u8] {
+        &self.staged
+    }
+
+    pub(crate) fn channel_id(&self) -> Option<ChannelId> {
+        self.channel_id
+    }
+
+    pub(crate) fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stage_up_to_limit() {
+        let mut paused = PausedRx::new(5);
+
+        assert_eq!(paused.stage(ChannelId(1), b"abc"), 3);
+        assert_eq!(paused.stage(ChannelId(1), b"def"), 2);
+        assert_eq!(paused.stage(ChannelId(1), b"ghi"), 0);
+
+        assert_eq!(paused.staged(), b"abcde");
+        assert_eq!(paused.dropped(), 4);
+        assert_eq!(paused.channel_id(), Some(ChannelId(1)));
+    }
+
+    #[test]
+    fn limit_capped() {
+        let mut paused = PausedRx::new(usize::MAX);
+
+        assert_eq!(
+            paused.stage(ChannelId(1), &[0; MAX_STAGED_RX + 1]),
+            MAX_STAGED_RX
+        );
+        assert_eq!(paused.dropped(), 1);
+    }
+}
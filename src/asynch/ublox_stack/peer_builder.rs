@@ -1,8 +1,19 @@
 use crate::error::Error;
+#[cfg(feature = "socket-tcp")]
+use crate::options::CredentialNamespace;
 use core::fmt::Write;
+#[cfg(feature = "socket-tcp")]
+use core::ops::RangeInclusive;
+#[cfg(feature = "socket-tcp")]
+use embassy_time::Duration;
 use heapless::String;
 use no_std_net::{IpAddr, SocketAddr};
 
+/// TCP maximum segment sizes accepted by the module.
+#[cfg(feature = "socket-tcp")]
+pub const TCP_MSS_RANGE: RangeInclusive<u16> = 536..=1460;
+
+#[cfg(feature = "socket-tcp")]
 #[derive(Debug, Clone, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct SecurityCredentials {
@@ -11,12 +22,127 @@ pub struct SecurityCredentials {
     pub c_key_name: heapless::String<16>,
 }
 
+#[cfg(feature = "socket-tcp")]
+impl SecurityCredentials {
+    /// Credentials referencing the certificates and private key imported as
+    /// `ca_cert_name`, `c_cert_name` and `c_key_name` in `namespace`.
+    pub fn namespaced(
+        namespace: &CredentialNamespace,
+        ca_cert_name: &str,
+        c_cert_name: &str,
+        c_key_name: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            ca_cert_name: namespace.apply(ca_cert_name)?,
+            c_cert_name: namespace.apply(c_cert_name)?,
+            c_key_name: namespace.apply(c_key_name)?,
+        })
+    }
+}
+
+/// Options applied to a socket, when the peer connection is established.
+#[cfg(feature = "socket-tcp")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SocketOptions {
+    /// Interval of TCP keep-alive packets. `None` uses the module default.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub keep_alive: Option<Duration>,
+    /// Transmit data immediately, rather than waiting to fill a segment.
+    pub flush_tx: Option<bool>,
+    /// Time to wait for the module to confirm the close of the connection,
+    /// see [`TcpSocket::close_linger`](super::tcp::TcpSocket::close_linger).
+    /// `None` closes immediately.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub linger: Option<Duration>,
+    /// Time without received data, despite data being sent, after which the
+    /// module is queried for the state of the peer, to detect half-open
+    /// connections. `None` disables the detection.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub half_open_check: Option<Duration>,
+    /// TCP maximum segment size, within [`TCP_MSS_RANGE`]. `None` uses the
+    /// module default.
+    pub mss: Option<u16>,
+    /// Server name sent in the TLS handshake. `None` sends the hostname the
+    /// socket connects to, if it was resolved from one. Only applies to
+    /// sockets with [`SecurityCredentials`].
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub sni: Option<String<64>>,
+    /// Verify that the server certificate matches the server name. `None`
+    /// uses the module default. Only applies to sockets with
+    /// [`SecurityCredentials`].
+    pub verify_hostname: Option<bool>,
+}
+
+#[cfg(feature = "socket-tcp")]
+impl SocketOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+
+    pub fn flush_tx(mut self, flush_tx: bool) -> Self {
+        self.flush_tx = Some(flush_tx);
+        self
+    }
+
+    pub fn linger(mut self, timeout: Duration) -> Self {
+        self.linger = Some(timeout);
+        self
+    }
+
+    pub fn half_open_check(mut self, idle: Duration) -> Self {
+        self.half_open_check = Some(idle);
+        self
+    }
+
+    pub fn mss(mut self, mss: u16) -> Self {
+        self.mss = Some(mss);
+        self
+    }
+
+    pub fn sni(mut self, server_name: String<64>) -> Self {
+        self.sni = Some(server_name);
+        self
+    }
+
+    pub fn verify_hostname(mut self, verify: bool) -> Self {
+        self.verify_hostname = Some(verify);
+        self
+    }
+
+    /// Check the options against the ranges supported by the module.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.mss.is_some_and(|mss| !TCP_MSS_RANGE.contains(&mss)) {
+            return Err(Error::InvalidSocketOption);
+        }
+        Ok(())
+    }
+}
+
+/// Builder of the URL of a peer, as passed to the module on connect.
+///
+/// Query parameters are written in a fixed order, and IP addresses in their
+/// normalized form, regardless of the order the builder is set up in. Equal
+/// configurations thus give equal URLs. A URL exceeding the capacity of the
+/// string fails with [`Error::UrlTooLong`], rather than being truncated.
 #[derive(Default)]
 pub(crate) struct PeerUrlBuilder<'a> {
     hostname: Option<&'a str>,
     ip_addr: Option<IpAddr>,
     port: Option<u16>,
+    #[cfg(feature = "socket-tcp")]
     creds: Option<&'a SecurityCredentials>,
+    #[cfg(feature = "socket-tcp")]
+    options: Option<&'a SocketOptions>,
+    #[cfg(feature = "socket-tcp")]
+    sni: Option<&'a str>,
+    #[cfg(feature = "socket-tcp")]
+    verify_hostname: Option<bool>,
     local_port: Option<u16>,
 }
 
@@ -28,26 +154,25 @@ impl<'a> PeerUrlBuilder<'a> {
 
     fn write_domain<const N: usize>(&self, s: &mut String<N>) -> Result<(), Error> {
         let port = self.port.ok_or(Error::Network)?;
-        let addr = self
-            .ip_addr
-            .and_then(|ip| write!(s, "{}/", SocketAddr::new(ip, port)).ok());
-        let host = self
-            .hostname
-            .and_then(|host| write!(s, "{}:{}/", host, port).ok());
-
-        addr.xor(host).ok_or(Error::Network)
+        match (self.ip_addr, self.hostname) {
+            (Some(ip), None) => write!(s, "{}/", SocketAddr::new(ip, port)),
+            (None, Some(host)) => write!(s, "{}:{}/", host, port),
+            _ => return Err(Error::Network),
+        }
+        .map_err(|_| Error::UrlTooLong)
     }
 
+    #[cfg(feature = "socket-udp")]
     pub fn udp<const N: usize>(&self) -> Result<String<N>, Error> {
         let mut s = String::new();
-        write!(&mut s, "udp://").map_err(|_| Error::Overflow)?;
+        write!(&mut s, "udp://").map_err(|_| Error::UrlTooLong)?;
         self.write_domain(&mut s)?;
 
         // Start writing query parameters
-        write!(&mut s, "?").map_err(|_| Error::Overflow)?;
+        write!(&mut s, "?").map_err(|_| Error::UrlTooLong)?;
 
         if let Some(v) = self.local_port {
-            write!(&mut s, "local_port={}&", v).map_err(|_| Error::Overflow)?;
+            write!(&mut s, "local_port={}&", v).map_err(|_| Error::UrlTooLong)?;
         }
 
         // Remove trailing '&' or '?' if no query.
@@ -56,22 +181,57 @@ impl<'a> PeerUrlBuilder<'a> {
         Ok(s)
     }
 
+    #[cfg(feature = "socket-tcp")]
     pub fn tcp<const N: usize>(&mut self) -> Result<String<N>, Error> {
         let mut s = String::new();
-        write!(&mut s, "tcp://").map_err(|_| Error::Overflow)?;
+        write!(&mut s, "tcp://").map_err(|_| Error::UrlTooLong)?;
         self.write_domain(&mut s)?;
 
         // Start writing query parameters
-        write!(&mut s, "?").map_err(|_| Error::Overflow)?;
+        write!(&mut s, "?").map_err(|_| Error::UrlTooLong)?;
 
         if let Some(v) = self.local_port {
-            write!(&mut s, "local_port={}&", v).map_err(|_| Error::Overflow)?;
+            write!(&mut s, "local_port={}&", v).map_err(|_| Error::UrlTooLong)?;
+        }
+
+        if let Some(options) = self.options {
+            options.validate()?;
+
+            if let Some(keep_alive) = options.keep_alive {
+                write!(&mut s, "keepAlive={}&", keep_alive.as_millis())
+                    .map_err(|_| Error::UrlTooLong)?;
+            }
+
+            if let Some(flush_tx) = options.flush_tx {
+                write!(&mut s, "flush_tx={}&", flush_tx as u8).map_err(|_| Error::UrlTooLong)?;
+            }
+
+            if let Some(mss) = options.mss {
+                write!(&mut s, "mss={}&", mss).map_err(|_| Error::UrlTooLong)?;
+            }
         }
 
         if let Some(creds) = self.creds.as_ref() {
-            write!(&mut s, "ca={}&", creds.ca_cert_name).map_err(|_| Error::Overflow)?;
-            write!(&mut s, "cert={}&", creds.c_cert_name).map_err(|_| Error::Overflow)?;
-            write!(&mut s, "privKey={}&", creds.c_key_name).map_err(|_| Error::Overflow)?;
+            write!(&mut s, "ca={}&", creds.ca_cert_name).map_err(|_| Error::UrlTooLong)?;
+            write!(&mut s, "cert={}&", creds.c_cert_name).map_err(|_| Error::UrlTooLong)?;
+            write!(&mut s, "privKey={}&", creds.c_key_name).map_err(|_| Error::UrlTooLong)?;
+
+            // Default to the hostname connected to, never to an IP address
+            let sni = self
+                .sni
+                .or(self.options.and_then(|o| o.sni.as_deref()))
+                .or(self.hostname);
+            if let Some(sni) = sni {
+                write!(&mut s, "sni={}&", sni).map_err(|_| Error::UrlTooLong)?;
+            }
+
+            let verify_hostname = self
+                .verify_hostname
+                .or(self.options.and_then(|o| o.verify_hostname));
+            if let Some(verify) = verify_hostname {
+                write!(&mut s, "verifyHostname={}&", verify as u8)
+                    .map_err(|_| Error::UrlTooLong)?;
+            }
         };
 
         // Remove trailing '&' or '?' if no query.
@@ -116,11 +276,32 @@ impl<'a> PeerUrlBuilder<'a> {
         self
     }
 
+    #[cfg(feature = "socket-tcp")]
     pub fn creds(&mut self, creds: &'a SecurityCredentials) -> &mut Self {
         self.creds.replace(creds);
         self
     }
 
+    #[cfg(feature = "socket-tcp")]
+    pub fn options(&mut self, options: &'a SocketOptions) -> &mut Self {
+        self.options.replace(options);
+        self
+    }
+
+    /// Server name sent in the TLS handshake, overriding the hostname and
+    /// [`SocketOptions::sni`].
+    #[cfg(feature = "socket-tcp")]
+    pub fn sni(&mut self, server_name: &'a str) -> &mut Self {
+        self.sni.replace(server_name);
+        self
+    }
+
+    #[cfg(feature = "socket-tcp")]
+    pub fn verify_hostname(&mut self, verify: bool) -> &mut Self {
+        self.verify_hostname.replace(verify);
+        self
+    }
+
     pub fn local_port(&mut self, local_port: u16) -> &mut Self {
         self.local_port.replace(local_port);
         self
@@ -137,6 +318,7 @@ mod test {
     use super::*;
 
     #[test]
+    #[cfg(feature = "socket-udp")]
     fn udp_ipv4_url() {
         let address = "192.168.0.1:8080".parse().unwrap();
         let url = PeerUrlBuilder::new()
@@ -147,6 +329,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(feature = "socket-udp")]
     fn udp_ipv6_url() {
         let address = "[FE80:0000:0000:0000:0202:B3FF:FE1E:8329]:8080"
             .parse()
@@ -159,6 +342,7 @@ mod test {
     }
 
     #[test]
+    #[cfg(feature = "socket-udp")]
     fn udp_hostname_url() {
         let url = PeerUrlBuilder::new()
             .hostname("example.org")
@@ -170,6 +354,27 @@ mod test {
     }
 
     #[test]
+    #[cfg(feature = "socket-tcp")]
+    fn tcp_ip_addr_url() {
+        use no_std_net::{Ipv4Addr, Ipv6Addr};
+
+        let url = PeerUrlBuilder::new()
+            .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)))
+            .port(443)
+            .tcp::<128>()
+            .unwrap();
+        assert_eq!(url, "tcp://192.168.0.1:443/");
+
+        let url = PeerUrlBuilder::new()
+            .ip_addr(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)))
+            .port(443)
+            .tcp::<128>()
+            .unwrap();
+        assert_eq!(url, "tcp://[2001:db8::1]:443/");
+    }
+
+    #[test]
+    #[cfg(feature = "socket-tcp")]
     fn tcp_certs() {
         let url = PeerUrlBuilder::new()
             .hostname("example.org")
@@ -184,7 +389,217 @@ mod test {
 
         assert_eq!(
             url,
-            "tcp://example.org:2000/?ca=ca.crt&cert=client.crt&privKey=client.key"
+            "tcp://example.org:2000/?ca=ca.crt&cert=client.crt&privKey=client.key&sni=example.org"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "socket-tcp")]
+    fn tcp_namespaced_certs() {
+        let namespace = CredentialNamespace::new("app_");
+
+        // Names as imported by `Control::import_credentials`
+        let existing = ["boot_ca"];
+        let ca = namespace.import_name("ca", existing, false).unwrap();
+        assert_eq!(ca, "app_ca");
+
+        let creds = SecurityCredentials::namespaced(&namespace, "ca", "cert", "key").unwrap();
+        assert_eq!(creds.ca_cert_name, ca);
+
+        let url = PeerUrlBuilder::new()
+            .hostname("example.org")
+            .port(2000)
+            .creds(&creds)
+            .tcp::<128>()
+            .unwrap();
+
+        assert_eq!(
+            url,
+            "tcp://example.org:2000/?ca=app_ca&cert=app_cert&privKey=app_key&sni=example.org"
         );
     }
+
+    #[test]
+    #[cfg(feature = "socket-tcp")]
+    fn tcp_socket_options() {
+        let options = SocketOptions::new()
+            .keep_alive(Duration::from_secs(30))
+            .flush_tx(true);
+
+        let url = PeerUrlBuilder::new()
+            .hostname("example.org")
+            .port(2000)
+            .options(&options)
+            .tcp::<128>()
+            .unwrap();
+
+        assert_eq!(url, "tcp://example.org:2000/?keepAlive=30000&flush_tx=1");
+    }
+
+    #[test]
+    #[cfg(feature = "socket-tcp")]
+    fn tcp_canonical_url() {
+        let creds = SecurityCredentials {
+            c_cert_name: heapless::String::try_from("client.crt").unwrap(),
+            ca_cert_name: heapless::String::try_from("ca.crt").unwrap(),
+            c_key_name: heapless::String::try_from("client.key").unwrap(),
+        };
+        let options = SocketOptions::new()
+            .mss(1200)
+            .flush_tx(true)
+            .keep_alive(Duration::from_secs(30));
+
+        let a = PeerUrlBuilder::new()
+            .address(&"[FE80::0202:B3FF:FE1E:8329]:2000".parse().unwrap())
+            .local_port(2001)
+            .creds(&creds)
+            .options(&options)
+            .tcp::<128>()
+            .unwrap();
+        let b = PeerUrlBuilder::new()
+            .options(&options)
+            .creds(&creds)
+            .local_port(2001)
+            .port(2000)
+            .ip_addr("fe80:0:0:0:202:b3ff:fe1e:8329".parse().unwrap())
+            .tcp::<128>()
+            .unwrap();
+
+        assert_eq!(a, b);
+        assert_eq!(
+            a,
+            "tcp://[fe80::202:b3ff:fe1e:8329]:2000/?local_port=2001&keepAlive=30000&flush_tx=1&mss=1200&ca=ca.crt&cert=client.crt&privKey=client.key"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "socket-tcp")]
+    fn tcp_mss() {
+        let options = SocketOptions::new().mss(1200);
+        let url = PeerUrlBuilder::new()
+            .hostname("example.org")
+            .port(2000)
+            .options(&options)
+            .tcp::<128>()
+            .unwrap();
+        assert_eq!(url, "tcp://example.org:2000/?mss=1200");
+
+        for mss in [*TCP_MSS_RANGE.start() - 1, *TCP_MSS_RANGE.end() + 1] {
+            let options = SocketOptions::new().mss(mss);
+            assert!(matches!(
+                options.validate(),
+                Err(Error::InvalidSocketOption)
+            ));
+            assert!(PeerUrlBuilder::new()
+                .hostname("example.org")
+                .port(2000)
+                .options(&options)
+                .tcp::<128>()
+                .is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "socket-tcp")]
+    fn tcp_sni() {
+        let creds = SecurityCredentials {
+            c_cert_name: heapless::String::try_from("client.crt").unwrap(),
+            ca_cert_name: heapless::String::try_from("ca.crt").unwrap(),
+            c_key_name: heapless::String::try_from("client.key").unwrap(),
+        };
+
+        // No server name to send when connecting to an IP address
+        let url = PeerUrlBuilder::new()
+            .address(&"93.184.216.34:443".parse().unwrap())
+            .creds(&creds)
+            .tcp::<128>()
+            .unwrap();
+        assert_eq!(
+            url,
+            "tcp://93.184.216.34:443/?ca=ca.crt&cert=client.crt&privKey=client.key"
+        );
+
+        let url = PeerUrlBuilder::new()
+            .hostname("example.org")
+            .port(443)
+            .creds(&creds)
+            .tcp::<128>()
+            .unwrap();
+        assert_eq!(
+            url,
+            "tcp://example.org:443/?ca=ca.crt&cert=client.crt&privKey=client.key&sni=example.org"
+        );
+
+        let options = SocketOptions::new()
+            .sni(heapless::String::try_from("api.example.org").unwrap())
+            .verify_hostname(true);
+        let url = PeerUrlBuilder::new()
+            .address(&"93.184.216.34:443".parse().unwrap())
+            .creds(&creds)
+            .options(&options)
+            .tcp::<128>()
+            .unwrap();
+        assert_eq!(
+            url,
+            "tcp://93.184.216.34:443/?ca=ca.crt&cert=client.crt&privKey=client.key&sni=api.example.org&verifyHostname=1"
+        );
+
+        let url = PeerUrlBuilder::new()
+            .hostname("example.org")
+            .port(443)
+            .creds(&creds)
+            .options(&options)
+            .sni("cdn.example.org")
+            .verify_hostname(false)
+            .tcp::<128>()
+            .unwrap();
+        assert_eq!(
+            url,
+            "tcp://example.org:443/?ca=ca.crt&cert=client.crt&privKey=client.key&sni=cdn.example.org&verifyHostname=0"
+        );
+
+        // TLS only options are left out of plain TCP connections
+        let url = PeerUrlBuilder::new()
+            .hostname("example.org")
+            .port(80)
+            .options(&options)
+            .tcp::<128>()
+            .unwrap();
+        assert_eq!(url, "tcp://example.org:80/");
+    }
+
+    #[test]
+    #[cfg(feature = "socket-tcp")]
+    fn tcp_url_too_long() {
+        let creds = SecurityCredentials {
+            c_cert_name: heapless::String::try_from("client.crt").unwrap(),
+            ca_cert_name: heapless::String::try_from("ca.crt").unwrap(),
+            c_key_name: heapless::String::try_from("client.key").unwrap(),
+        };
+        let hostname = "a-rather-long-subdomain.of-an-even-longer-domain.example.org";
+
+        let url = PeerUrlBuilder::new()
+            .hostname(hostname)
+            .port(443)
+            .tcp::<128>()
+            .unwrap();
+        assert_eq!(url.len(), 71);
+
+        // The hostname is repeated as server name
+        assert!(matches!(
+            PeerUrlBuilder::new()
+                .hostname(hostname)
+                .port(443)
+                .creds(&creds)
+                .tcp::<128>(),
+            Err(Error::UrlTooLong)
+        ));
+        assert!(matches!(
+            PeerUrlBuilder::new()
+                .hostname(hostname)
+                .port(443)
+                .tcp::<32>(),
+            Err(Error::UrlTooLong)
+        ));
+    }
 }
@@ -0,0 +1,95 @@
+//! Set of module peers, with room for every peer handle.
+//!
+//! Peers to be closed in the module are queued as a set, so a burst of
+//! dropped sockets cannot overflow the queue and leak peers in the module.
+use ublox_sockets::PeerHandle;
+
+#[derive(Clone, PartialEq, Eq, Default)]
+pub(crate) struct PeerSet {
+    bits: [u32; 8],
+}
+
+impl PeerSet {
+    pub(crate) const fn new() -> Self {
+        Self { bits: [0; 8] }
+    }
+
+    /// Add `peer_handle`, returning whether it was not in the set yet.
+    pub(crate) fn insert(&mut self, peer_handle: PeerHandle) -> bool {
+        let (word, bit) = Self::position(peer_handle);
+        let added = self.bits[word] & bit == 0;
+        self.bits[word] |= bit;
+        added
+    }
+
+    pub(crate) fn contains(&self, peer_handle: PeerHandle) -> bool {
+        let (word, bit) = Self::position(peer_handle);
+        self.bits[word] & bit != 0
+    }
+
+    /// Remove and return the lowest peer handle in the set.
+    pub(crate) fn pop(&mut self) -> Option<PeerHandle> {
+        let peer_handle = self.iter().next()?;
+        let (word, bit) = Self::position(peer_handle);
+        self.bits[word] &= !bit;
+        Some(peer_handle)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bits.iter().all(|word| *word == 0)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.bits = [0; 8];
+    }
+
+    /// The peer handles in the set, lowest first.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = PeerHandle> + '_ {
+        (0..=u8::MAX)
+            .map(PeerHandle)
+            .filter(|peer_handle| self.contains(*peer_handle))
+    }
+
+    fn position(peer_handle: PeerHandle) -> (usize, u32) {
+        let handle = usize::from(peer_handle.0);
+        (handle / 32, 1 << (handle % 32))
+    }
+}
+
+impl core::fmt::Debug for PeerSet {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+/// Compares the peer handles in the set, lowest first.
+impl<const N: usize> PartialEq<[PeerHandle; N]> for PeerSet {
+    fn eq(&self, other: &[PeerHandle; N]) -> bool {
+        self.iter().eq(other.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_peer_handle() {
+        let mut set = PeerSet::new();
+        assert!(set.is_empty());
+
+        for handle in (0..=u8::MAX).rev() {
+            assert!(set.insert(PeerHandle(handle)));
+        }
+        // Already queued
+        assert!(!set.insert(PeerHandle(7)));
+        assert_eq!(set.iter().count(), 256);
+
+        for handle in 0..=u8::MAX {
+            assert_eq!(set.pop(), Some(PeerHandle(handle)));
+        }
+        assert_eq!(set.pop(), None);
+        assert!(set.is_empty());
+    }
+}
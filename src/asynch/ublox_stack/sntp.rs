@@ -0,0 +1,158 @@
+//! Wall-clock time from an SNTP server.
+use embassy_time::{with_timeout, Instant};
+use embedded_nal_async::{AddrType, SocketAddr};
+
+use super::udp::{RecvError, SendError, UdpSocket};
+use super::{dns, UbloxStack};
+
+/// Port of the NTP service.
+pub const NTP_PORT: u16 = 123;
+
+const PACKET_LEN: usize = 48;
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Errors returned by [`UbloxStack::ntp_time`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    Dns(dns::Error),
+    Send(SendError),
+    Recv(RecvError),
+    /// The server did not respond in time.
+    Timeout,
+    /// The response is not a server response to our request.
+    InvalidResponse,
+    /// The server refused to serve the request (kiss-o'-death).
+    Refused,
+}
+
+/// Build an SNTP client request, identified by `nonce`.
+fn request(nonce: u64) -> [u8; PACKET_LEN] {
+    let mut packet = [0u8; PACKET_LEN];
+    // LI = 0, VN = 4, Mode = 3 (client)
+    packet[0] = 0x23;
+    // The server echoes the transmit timestamp as originate timestamp
+    packet[40..48].copy_from_slice(&nonce.to_be_bytes());
+    packet
+}
+
+/// Parse the SNTP server response to the request identified by `nonce`, into
+/// a Unix timestamp in seconds.
+fn parse_response(packet: &[u8], nonce: u64) -> Result<u64, Error> {
+    if packet.len() < PACKET_LEN {
+        return Err(Error::InvalidResponse);
+    }
+
+    // Mode = 4 (server)
+    if packet[0] & 0x07 != 4 || packet[24..32] != nonce.to_be_bytes() {
+        return Err(Error::InvalidResponse);
+    }
+
+    // Stratum 0 is a kiss-o'-death message
+    if packet[1] == 0 {
+        return Err(Error::Refused);
+    }
+
+    let seconds = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as u64;
+
+    // Timestamps before 1970 are taken to be in NTP era 1, which starts in
+    // 2036.
+    Ok(if seconds >= NTP_UNIX_OFFSET {
+        seconds - NTP_UNIX_OFFSET
+    } else {
+        seconds + (1 << 32) - NTP_UNIX_OFFSET
+    })
+}
+
+impl<const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>
+    UbloxStack<INGRESS_BUF_SIZE, URC_CAPACITY>
+{
+    /// Query the SNTP server `server` for the current time, as a Unix
+    /// timestamp in seconds.
+    ///
+    /// `server` is either a hostname or an IP address. The response is
+    /// awaited for [`Timeouts::ntp`](crate::timeouts::Timeouts::ntp).
+    pub async fn ntp_time(&self, server: &str) -> Result<u64, Error> {
+        let addr = self
+            .dns_query(server, AddrType::IPv4)
+            .await
+            .map_err(Error::Dns)?;
+
+        let mut rx_buffer = [0u8; PACKET_LEN];
+        let mut tx_buffer = [0u8; PACKET_LEN];
+        let mut socket = UdpSocket::new(self, &mut rx_buffer, &mut tx_buffer);
+
+        let nonce = Instant::now().as_ticks();
+        socket
+            .send_to(&request(nonce), SocketAddr::new(addr, NTP_PORT))
+            .await
+            .map_err(Error::Send)?;
+
+        let recv_fut = async {
+            let mut packet = [0u8; PACKET_LEN];
+            let (n, _) = socket.recv_from(&mut packet).await.map_err(Error::Recv)?;
+            parse_response(&packet[..n], nonce)
+        };
+
+        with_timeout(self.device.state_ch.timeouts().ntp, recv_fut)
+            .await
+            .map_err(|_| Error::Timeout)?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(nonce: u64, seconds: u32) -> [u8; PACKET_LEN] {
+        let mut packet = [0u8; PACKET_LEN];
+        // LI = 0, VN = 4, Mode = 4 (server)
+        packet[0] = 0x24;
+        packet[1] = 2;
+        packet[24..32].copy_from_slice(&nonce.to_be_bytes());
+        packet[40..44].copy_from_slice(&seconds.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn client_request() {
+        let packet = request(0x0102030405060708);
+        assert_eq!(packet[0], 0x23);
+        assert_eq!(packet[40..48], [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert!(packet[1..40].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn server_response() {
+        // 2024-01-01T00:00:00Z
+        let packet = response(42, 3_913_056_000);
+        assert_eq!(parse_response(&packet, 42), Ok(1_704_067_200));
+    }
+
+    #[test]
+    fn era_rollover() {
+        // 2036-02-07T06:28:16Z starts NTP era 1
+        let packet = response(42, 0);
+        assert_eq!(parse_response(&packet, 42), Ok(2_085_978_496));
+    }
+
+    #[test]
+    fn invalid_response() {
+        let packet = response(42, 3_913_056_000);
+        assert_eq!(parse_response(&packet, 43), Err(Error::InvalidResponse));
+        assert_eq!(
+            parse_response(&packet[..40], 42),
+            Err(Error::InvalidResponse)
+        );
+
+        let mut client = packet;
+        client[0] = 0x23;
+        assert_eq!(parse_response(&client, 42), Err(Error::InvalidResponse));
+
+        let mut kiss = packet;
+        kiss[1] = 0;
+        assert_eq!(parse_response(&kiss, 42), Err(Error::Refused));
+    }
+}
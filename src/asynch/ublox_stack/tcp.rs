@@ -1,13 +1,15 @@
 use core::cell::RefCell;
 use core::future::poll_fn;
 use core::mem;
-use core::task::Poll;
+use core::task::{Context, Poll};
 
-use embassy_time::Duration;
+use embassy_time::{with_timeout, Duration};
 use embedded_nal_async::SocketAddr;
-use ublox_sockets::{tcp, SocketHandle, TcpState};
+use ublox_sockets::{tcp, PeerHandle, SocketHandle, TcpState};
 
-use super::{SocketStack, UbloxStack};
+use super::{
+    tcp_peer_url, trace_transition, SocketOptions, SocketStack, SocketTransition, UbloxStack,
+};
 
 /// Error returned by TcpSocket read/write functions.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -17,13 +19,27 @@ pub enum Error {
     ///
     /// This can happen on receiving a RST packet, or on timeout.
     ConnectionReset,
+    /// The network link is not up.
+    NotConnected,
+    /// Data was dropped while the receive path was paused, as the staging
+    /// limit was reached, or the socket could no longer receive the staged
+    /// data on resume, see [`TcpSocket::pause_rx`].
+    PausedOverflow,
+    /// The data staged while the receive path was paused does not fit the
+    /// receive buffer yet. The socket stays paused, see
+    /// [`TcpSocket::resume_rx`].
+    RxBufferFull,
+    /// The module did not confirm the close within the linger time, see
+    /// [`TcpSocket::close_linger`].
+    TimedOut,
 }
 
 /// Error returned by [`TcpSocket::connect`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ConnectError {
-    /// The socket is already connected or listening.
+    /// The socket is already connected or listening, or was created with
+    /// more sockets in the stack than it supports.
     InvalidState,
     /// The remote host rejected the connection with a RST packet.
     ConnectionReset,
@@ -31,6 +47,14 @@ pub enum ConnectError {
     TimedOut,
     /// No route to host.
     NoRoute,
+    /// The network link is not up.
+    NotConnected,
+    /// The URL of the peer, including the hostname, credentials and socket
+    /// options, is too long to pass to the module.
+    UrlTooLong,
+    /// The TLS handshake failed, e.g. for an untrusted or expired server
+    /// certificate, with the reason reported by the module, if any.
+    TlsHandshake(Option<u8>),
 }
 
 /// Error returned by [`TcpSocket::accept`].
@@ -45,6 +69,52 @@ pub enum AcceptError {
     ConnectionReset,
 }
 
+/// Reason the stack closed a connection on its own accord, see
+/// [`TcpSocket::close_reason`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum CloseReason {
+    /// The remote host stopped responding without closing the connection,
+    /// see [`SocketOptions::half_open_check`].
+    HalfOpenDetected,
+    /// The network link went down, and the module dropped the connection.
+    LinkLost,
+    /// The module dropped the connection of a TLS socket before it was
+    /// established, as the TLS handshake failed, see
+    /// [`ConnectError::TlsHandshake`].
+    TlsHandshake(Option<u8>),
+}
+
+/// Error returned by [`TcpSocket::send_stream`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SendStreamError<E> {
+    /// Offset into the source, up to which all data has been handed off to
+    /// the module. Pass this as `offset` to resume the transfer.
+    ///
+    /// The module does not report which data the remote host acknowledged,
+    /// so this is an upper bound of the data received by the remote host.
+    /// Data handed off right before the connection dropped may be lost, which
+    /// only the application protocol can tell.
+    pub delivered: u64,
+    pub kind: SendStreamErrorKind<E>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SendStreamErrorKind<E> {
+    /// Reading from the source failed.
+    Read(E),
+    /// The source ended before reaching the resume offset.
+    UnexpectedEof,
+    /// Writing to the socket failed.
+    Socket(Error),
+}
+
+/// Size of the chunks read from the source in [`TcpSocket::send_stream`].
+const STREAM_CHUNK_SIZE: usize = 512;
+
 /// A TCP socket.
 pub struct TcpSocket<'a> {
     pub(crate) io: TcpIo<'a>,
@@ -64,7 +134,12 @@ impl<'a> TcpReader<'a> {
     /// Read data from the socket.
     ///
     /// Returns how many bytes were read, or an error. If no data is available, it waits
-    /// until there is at least one byte available.
+    /// until there is at least one byte available. Reading into an empty buffer
+    /// returns `Ok(0)` immediately.
+    ///
+    /// Once the remote host closed the connection and all received data was
+    /// read, returns `Ok(0)` (EOF). Fails with [`Error::ConnectionReset`] if
+    /// the socket was never connected, or its connection attempt was aborted.
     pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
         self.io.read(buf).await
     }
@@ -84,13 +159,26 @@ impl<'a> TcpReader<'a> {
     pub fn recv_capacity(&self) -> usize {
         self.io.recv_capacity()
     }
+
+    /// Return the number of bytes that can be read without waiting.
+    pub fn recv_available(&self) -> usize {
+        self.io.recv_available()
+    }
+
+    /// Wait until at least `n` bytes can be read without waiting.
+    ///
+    /// See [`TcpSocket::wait_read_ready`].
+    pub async fn wait_read_ready(&mut self, n: usize) -> Result<(), Error> {
+        self.io.wait_read_ready(n).await
+    }
 }
 
 impl<'a> TcpWriter<'a> {
     /// Write data to the socket.
     ///
     /// Returns how many bytes were written, or an error. If the socket is not ready to
-    /// accept data, it waits until it is.
+    /// accept data, it waits until it is. Writing an empty buffer returns `Ok(0)`
+    /// immediately, without any traffic to the module.
     pub async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
         self.io.write(buf).await
     }
@@ -118,6 +206,18 @@ impl<'a> TcpWriter<'a> {
     pub fn send_capacity(&self) -> usize {
         self.io.send_capacity()
     }
+
+    /// Return the number of bytes that can be written without waiting.
+    pub fn send_available(&self) -> usize {
+        self.io.send_available()
+    }
+
+    /// Wait until at least `n` bytes can be written without waiting.
+    ///
+    /// See [`TcpSocket::wait_write_ready`].
+    pub async fn wait_write_ready(&mut self, n: usize) -> Result<(), Error> {
+        self.io.wait_write_ready(n).await
+    }
 }
 
 impl<'a> TcpSocket<'a> {
@@ -127,19 +227,26 @@ impl<'a> TcpSocket<'a> {
         rx_buffer: &'a mut [u8],
         tx_buffer: &'a mut [u8],
     ) -> Self {
-        let s = &mut *stack.socket.borrow_mut();
+        Self::new_in(&stack.socket, rx_buffer, tx_buffer)
+    }
+
+    fn new_in(
+        stack: &'a RefCell<SocketStack>,
+        rx_buffer: &'a mut [u8],
+        tx_buffer: &'a mut [u8],
+    ) -> Self {
+        let s = &mut *stack.borrow_mut();
         let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
         let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
         let handle = s.sockets.add(tcp::Socket::new(
             tcp::SocketBuffer::new(rx_buffer),
             tcp::SocketBuffer::new(tx_buffer),
         ));
+        s.add_socket_id(handle);
+        trace_transition(SocketTransition::Create, Some(handle), None, None, None);
 
         Self {
-            io: TcpIo {
-                stack: &stack.socket,
-                handle,
-            },
+            io: TcpIo { stack, handle },
         }
     }
 
@@ -153,6 +260,39 @@ impl<'a> TcpSocket<'a> {
         self.io.send_capacity()
     }
 
+    /// Return the number of bytes that can be read without waiting.
+    ///
+    /// This is a local query, without any traffic to the module.
+    pub fn recv_available(&self) -> usize {
+        self.io.recv_available()
+    }
+
+    /// Return the number of bytes that can be written without waiting.
+    ///
+    /// This is a local query, without any traffic to the module. Producers
+    /// can use it to pace writes to the rate at which the module drains the
+    /// transmit buffer.
+    pub fn send_available(&self) -> usize {
+        self.io.send_available()
+    }
+
+    /// Wait until at least `n` bytes can be read without waiting.
+    ///
+    /// `n` is capped at the receive buffer size. If the remote host closes
+    /// the connection, this completes with less data available, and fails
+    /// with [`Error::ConnectionReset`] once no data is left.
+    pub async fn wait_read_ready(&mut self, n: usize) -> Result<(), Error> {
+        self.io.wait_read_ready(n).await
+    }
+
+    /// Wait until at least `n` bytes can be written without waiting.
+    ///
+    /// `n` is capped at the transmit buffer size. Fails with
+    /// [`Error::ConnectionReset`] if the connection can no longer send.
+    pub async fn wait_write_ready(&mut self, n: usize) -> Result<(), Error> {
+        self.io.wait_write_ready(n).await
+    }
+
     /// Call `f` with the largest contiguous slice of octets in the transmit buffer,
     /// and enqueue the amount of elements returned by `f`.
     ///
@@ -181,28 +321,126 @@ impl<'a> TcpSocket<'a> {
     }
 
     /// Connect to a remote host.
+    ///
+    /// Completes once the module reported the peer connected, and assigned
+    /// it a data channel, so data can be sent right away.
+    ///
+    /// Sockets can be created regardless of the link state, but connecting
+    /// requires the link to be up, and fails with
+    /// [`ConnectError::NotConnected`] otherwise. If the link drops before the
+    /// connection is established, the connect fails with
+    /// [`ConnectError::ConnectionReset`] once the link is back, and the socket
+    /// can be connected again.
+    ///
+    /// Fails with [`ConnectError::TimedOut`] if the connection is not
+    /// established within the
+    /// [`socket_connect`](crate::timeouts::Timeouts::socket_connect) timeout,
+    /// see [`connect_with_timeout`](TcpSocket::connect_with_timeout).
+    ///
+    /// For TLS sockets, the server name is the hostname `remote_endpoint` was
+    /// resolved from, unless overridden by [`SocketOptions::sni`].
     pub async fn connect<T>(&mut self, remote_endpoint: T) -> Result<(), ConnectError>
     where
         T: Into<SocketAddr>,
     {
+        let timeout = self.io.stack.borrow().connect_timeout;
+        self.connect_with_timeout(remote_endpoint, timeout).await
+    }
+
+    /// Connect to a remote host, failing with [`ConnectError::TimedOut`] if
+    /// the connection is not established within `timeout`.
+    ///
+    /// On timeout, the peer is closed, if the module assigned one already,
+    /// and the socket is left closed and ready to connect again.
+    pub async fn connect_with_timeout<T>(
+        &mut self,
+        remote_endpoint: T,
+        timeout: Duration,
+    ) -> Result<(), ConnectError>
+    where
+        T: Into<SocketAddr>,
+    {
+        self.start_connect(remote_endpoint)?;
+
+        match with_timeout(timeout, poll_fn(|cx| self.poll_connect(cx))).await {
+            Ok(res) => res,
+            Err(_) => {
+                self.io.stack.borrow_mut().abort_connect(self.io.handle);
+                Err(ConnectError::TimedOut)
+            }
+        }
+    }
+
+    /// Start connecting to a remote host, without waiting for the module.
+    ///
+    /// The connect is driven by [`poll_connect`](TcpSocket::poll_connect),
+    /// so a single task can connect many sockets without one slow handshake
+    /// holding up the others. Fails like [`connect`](TcpSocket::connect).
+    pub fn start_connect<T>(&mut self, remote_endpoint: T) -> Result<(), ConnectError>
+    where
+        T: Into<SocketAddr>,
+    {
+        if !self.io.stack.borrow().link_up {
+            return Err(ConnectError::NotConnected);
+        }
+        if !self.io.stack.borrow().has_socket_id(self.io.handle) {
+            return Err(ConnectError::InvalidState);
+        }
+
+        // Fail up front, rather than when the stack hands the URL to the module
+        let remote_endpoint = remote_endpoint.into();
+        {
+            let s = &*self.io.stack.borrow();
+            let local_port = s.sockets.get::<tcp::Socket>(self.io.handle).local_port;
+            if let Err(crate::error::Error::UrlTooLong) = tcp_peer_url(
+                &s.dns_table,
+                remote_endpoint,
+                local_port,
+                s.credential_map.get(&self.io.handle),
+                s.socket_options.get(&self.io.handle),
+            ) {
+                return Err(ConnectError::UrlTooLong);
+            }
+        }
+
         match { self.io.with_mut(|s| s.connect(remote_endpoint, None)) } {
             Ok(()) => {}
             Err(_) => return Err(ConnectError::InvalidState),
             // Err(tcp::ConnectError::Unaddressable) => return Err(ConnectError::NoRoute),
         }
 
-        poll_fn(|cx| {
-            self.io.with_mut(|s| match s.state() {
-                tcp::State::TimeWait => Poll::Ready(Err(ConnectError::ConnectionReset)),
-                tcp::State::Listen => unreachable!(),
-                tcp::State::Closed | tcp::State::SynSent | tcp::State::SynReceived => {
-                    s.register_send_waker(cx.waker());
-                    Poll::Pending
-                }
-                _ => Poll::Ready(Ok(())),
-            })
+        let mut stack = self.io.stack.borrow_mut();
+        stack.close_reasons.remove(&self.io.handle);
+        stack.half_open.remove(&self.io.handle);
+        Ok(())
+    }
+
+    /// Poll a connect started by [`start_connect`](TcpSocket::start_connect),
+    /// registering the waker of `cx` until the connection is established.
+    ///
+    /// A connect the module rejects fails with
+    /// [`ConnectError::ConnectionReset`], leaving the socket closed and ready
+    /// to connect again. A TLS socket failing its handshake fails with
+    /// [`ConnectError::TlsHandshake`] instead.
+    pub fn poll_connect(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ConnectError>> {
+        let close_reason = self.close_reason();
+        self.io.with_mut(|s| match s.state() {
+            tcp::State::TimeWait => Poll::Ready(Err(match close_reason {
+                Some(CloseReason::TlsHandshake(reason)) => ConnectError::TlsHandshake(reason),
+                _ => ConnectError::ConnectionReset,
+            })),
+            // Rejected by the module, or reset at the start of a new link
+            // epoch
+            tcp::State::Closed if s.remote_endpoint.is_none() => {
+                Poll::Ready(Err(ConnectError::ConnectionReset))
+            }
+            tcp::State::Listen => unreachable!(),
+            tcp::State::Closed | tcp::State::SynSent | tcp::State::SynReceived => {
+                s.register_send_waker(cx.waker());
+                Poll::Pending
+            }
+            _ => Poll::Ready(Ok(())),
         })
-        .await
     }
 
     // /// Accept a connection from a remote host.
@@ -234,7 +472,12 @@ impl<'a> TcpSocket<'a> {
     /// Read data from the socket.
     ///
     /// Returns how many bytes were read, or an error. If no data is available, it waits
-    /// until there is at least one byte available.
+    /// until there is at least one byte available. Reading into an empty buffer
+    /// returns `Ok(0)` immediately.
+    ///
+    /// Once the remote host closed the connection and all received data was
+    /// read, returns `Ok(0)` (EOF). Fails with [`Error::ConnectionReset`] if
+    /// the socket was never connected, or its connection attempt was aborted.
     pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
         self.io.read(buf).await
     }
@@ -242,7 +485,8 @@ impl<'a> TcpSocket<'a> {
     /// Write data to the socket.
     ///
     /// Returns how many bytes were written, or an error. If the socket is not ready to
-    /// accept data, it waits until it is.
+    /// accept data, it waits until it is. Writing an empty buffer returns `Ok(0)`
+    /// immediately, without any traffic to the module.
     pub async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
         self.io.write(buf).await
     }
@@ -255,6 +499,90 @@ impl<'a> TcpSocket<'a> {
         self.io.flush().await
     }
 
+    /// Send everything from `reader` on the socket, starting at `offset`.
+    ///
+    /// The first `offset` bytes of `reader` are skipped, which allows resuming
+    /// a transfer after a connection drop, by re-establishing the connection
+    /// and passing the `delivered` count of the returned error, see
+    /// [`SendStreamError::delivered`]. `progress` is called with the number
+    /// of bytes handed off to the module so far, not necessarily received by
+    /// the remote host.
+    ///
+    /// Returns the total number of bytes sent, including `offset`. The
+    /// transfer can be cancelled at any point by dropping the future.
+    pub async fn send_stream<R: embedded_io_async::Read>(
+        &mut self,
+        reader: &mut R,
+        offset: u64,
+        mut progress: impl FnMut(u64),
+    ) -> Result<u64, SendStreamError<R::Error>> {
+        let mut buf = [0u8; STREAM_CHUNK_SIZE];
+
+        // Skip the part of the source that was delivered before.
+        let mut skipped = 0;
+        while skipped < offset {
+            let len = core::cmp::min(offset - skipped, buf.len() as u64) as usize;
+            match reader.read(&mut buf[..len]).await {
+                Ok(0) => {
+                    return Err(SendStreamError {
+                        delivered: offset,
+                        kind: SendStreamErrorKind::UnexpectedEof,
+                    })
+                }
+                Ok(n) => skipped += n as u64,
+                Err(e) => {
+                    return Err(SendStreamError {
+                        delivered: offset,
+                        kind: SendStreamErrorKind::Read(e),
+                    })
+                }
+            }
+        }
+
+        let mut written = offset;
+        loop {
+            let n = match reader.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    return Err(SendStreamError {
+                        delivered: written - self.io.send_queue() as u64,
+                        kind: SendStreamErrorKind::Read(e),
+                    })
+                }
+            };
+
+            let mut chunk = &buf[..n];
+            while !chunk.is_empty() {
+                match self.io.write(chunk).await {
+                    Ok(n) => {
+                        chunk = &chunk[n..];
+                        written += n as u64;
+                    }
+                    Err(e) => {
+                        return Err(SendStreamError {
+                            delivered: written - self.io.send_queue() as u64,
+                            kind: SendStreamErrorKind::Socket(e),
+                        })
+                    }
+                }
+            }
+
+            progress(written - self.io.send_queue() as u64);
+        }
+
+        if let Err(e) = self.io.flush().await {
+            return Err(SendStreamError {
+                delivered: written - self.io.send_queue() as u64,
+                kind: SendStreamErrorKind::Socket(e),
+            });
+        }
+
+        progress(written);
+
+        Ok(written)
+    }
+
     /// Set the timeout for the socket.
     ///
     /// If the timeout is set, the socket will be closed if no data is received for the
@@ -270,10 +598,142 @@ impl<'a> TcpSocket<'a> {
     /// the specified duration of inactivity.
     ///
     /// If not set, the socket will not send keep-alive packets.
-    pub fn set_keep_alive(&mut self, _interval: Option<Duration>) {
-        todo!()
-        // self.io
-        //     .with_mut(|s| s.set_keep_alive(interval.map(duration_to_smoltcp)))
+    ///
+    /// Must be called before [`connect()`](TcpSocket::connect) to take effect.
+    pub fn set_keep_alive(
+        &mut self,
+        interval: Option<Duration>,
+    ) -> Result<(), crate::error::Error> {
+        let mut options = self.socket_options();
+        options.keep_alive = interval;
+        self.set_socket_options(options)
+    }
+
+    /// Set the linger time of the socket.
+    ///
+    /// With a linger time set, [`close_linger()`](TcpSocket::close_linger)
+    /// flushes queued data and waits up to this long for the module to
+    /// confirm the connection is closed. `None` closes immediately, which is
+    /// the default.
+    pub fn set_linger(&mut self, linger: Option<Duration>) -> Result<(), crate::error::Error> {
+        let mut options = self.socket_options();
+        options.linger = linger;
+        self.set_socket_options(options)
+    }
+
+    /// Enable detection of half-open connections, see
+    /// [`SocketOptions::half_open_check`].
+    ///
+    /// A connection detected as half-open is closed, with
+    /// [`close_reason()`](TcpSocket::close_reason) reporting
+    /// [`CloseReason::HalfOpenDetected`].
+    pub fn set_half_open_check(
+        &mut self,
+        idle: Option<Duration>,
+    ) -> Result<(), crate::error::Error> {
+        let mut options = self.socket_options();
+        options.half_open_check = idle;
+        self.set_socket_options(options)
+    }
+
+    /// Adopt a TCP peer that survived a reboot of the host, as reported
+    /// [`Alive`](crate::asynch::detach::PeerStatus::Alive) by
+    /// [`UbloxStack::attach_report`].
+    ///
+    /// The socket must be closed. Fails with
+    /// [`SocketNotFound`](crate::error::Error::SocketNotFound) if the peer is
+    /// not alive, not a TCP peer, or already adopted.
+    pub fn adopt(&mut self, peer_handle: PeerHandle) -> Result<(), crate::error::Error> {
+        self.io
+            .stack
+            .borrow_mut()
+            .adopt(self.io.handle, peer_handle)
+    }
+
+    /// Pause the receive path of the socket.
+    ///
+    /// While paused, received data is not enqueued in the receive buffer, but
+    /// staged, up to `stage_limit` bytes capped at
+    /// [`MAX_STAGED_RX`](super::MAX_STAGED_RX). Data beyond the limit is
+    /// dropped, and reported by [`resume_rx()`](TcpSocket::resume_rx).
+    pub fn pause_rx(&mut self, stage_limit: usize) -> Result<(), crate::error::Error> {
+        self.io
+            .stack
+            .borrow_mut()
+            .pause_rx(self.io.handle, stage_limit)
+    }
+
+    /// Resume the receive path of the socket, enqueueing the staged data in
+    /// the order it was received.
+    ///
+    /// Fails with [`Error::RxBufferFull`] if the staged data does not fit
+    /// the receive buffer yet, in which case the socket stays paused: read
+    /// from the socket, and resume again. Fails with
+    /// [`Error::PausedOverflow`] if data was dropped while paused, in which
+    /// case the data staged is enqueued nonetheless.
+    pub fn resume_rx(&mut self) -> Result<(), Error> {
+        self.io.stack.borrow_mut().resume_rx(self.io.handle)
+    }
+
+    /// Whether the receive path of the socket is paused, see
+    /// [`pause_rx()`](TcpSocket::pause_rx).
+    pub fn paused(&self) -> bool {
+        self.io
+            .stack
+            .borrow()
+            .paused_rx
+            .contains_key(&self.io.handle)
+    }
+
+    /// Set the TCP maximum segment size of the connection, within
+    /// [`TCP_MSS_RANGE`](super::TCP_MSS_RANGE). `None` uses the module
+    /// default.
+    ///
+    /// Must be called before [`connect()`](TcpSocket::connect) to take effect.
+    pub fn set_mss(&mut self, mss: Option<u16>) -> Result<(), crate::error::Error> {
+        let mut options = self.socket_options();
+        options.mss = mss;
+        self.set_socket_options(options)
+    }
+
+    /// Get the reason the stack closed the connection, if it did.
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        self.io
+            .stack
+            .borrow()
+            .close_reasons
+            .get(&self.io.handle)
+            .copied()
+    }
+
+    /// Get the options currently configured for the socket.
+    pub fn socket_options(&self) -> SocketOptions {
+        self.io
+            .stack
+            .borrow()
+            .socket_options
+            .get(&self.io.handle)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Configure multiple socket options in one go.
+    ///
+    /// The options are applied when the peer connection is established, so
+    /// this must be called before [`connect()`](TcpSocket::connect) to take
+    /// effect.
+    pub fn set_socket_options(
+        &mut self,
+        options: SocketOptions,
+    ) -> Result<(), crate::error::Error> {
+        options.validate()?;
+        self.io
+            .stack
+            .borrow_mut()
+            .socket_options
+            .insert(self.io.handle, options)
+            .map_err(|_| crate::error::Error::SocketMapMemory)?;
+        Ok(())
     }
 
     // /// Set the hop limit field in the IP header of sent packets.
@@ -312,6 +772,27 @@ impl<'a> TcpSocket<'a> {
         self.io.with_mut(|s| s.close())
     }
 
+    /// Close the socket, lingering until the module confirms the connection
+    /// is closed.
+    ///
+    /// Data queued in the socket is handed to the module before the
+    /// connection is closed, so it is not dropped along with the socket. Waits
+    /// for the linger time set with [`set_linger()`](TcpSocket::set_linger)
+    /// at most, returning [`Error::TimedOut`] if the close was not confirmed
+    /// in time. Without a linger time this is equivalent to
+    /// [`close()`](TcpSocket::close).
+    pub async fn close_linger(&mut self) -> Result<(), Error> {
+        self.close();
+
+        let Some(linger) = self.socket_options().linger else {
+            return Ok(());
+        };
+
+        with_timeout(linger, self.io.wait_closed())
+            .await
+            .map_err(|_| Error::TimedOut)
+    }
+
     /// Forcibly close the socket.
     ///
     /// This instantly closes both the read and write halves of the socket. Any pending data
@@ -355,11 +836,22 @@ impl<'a> Drop for TcpSocket<'a> {
                     .stack
                     .borrow_mut()
                     .dropped_sockets
-                    .push(peer_handle)
-                    .ok();
+                    .insert(peer_handle);
             }
         }
+        trace_transition(
+            SocketTransition::Drop,
+            Some(self.io.handle),
+            self.io.with(|s| s.peer_handle),
+            self.io.with(|s| s.edm_channel),
+            None,
+        );
         let mut stack = self.io.stack.borrow_mut();
+        stack.socket_options.remove(&self.io.handle);
+        stack.half_open.remove(&self.io.handle);
+        stack.close_reasons.remove(&self.io.handle);
+        stack.paused_rx.remove(&self.io.handle);
+        stack.remove_socket_id(self.io.handle);
         stack.sockets.remove(self.io.handle);
         stack.waker.wake();
     }
@@ -389,36 +881,61 @@ impl<'d> TcpIo<'d> {
     }
 
     async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        // embedded_io_async::Read's contract is to not block if buf is empty. While
+        // this function is not a direct implementor of the trait method, we still don't
+        // want our future to never resolve.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
         poll_fn(move |cx| {
+            let aborted = self.stack.borrow().close_reasons.contains_key(&self.handle);
+
             // CAUTION: smoltcp semantics around EOF are different to what you'd expect
             // from posix-like IO, so we have to tweak things here.
             self.with_mut(|s| match s.recv_slice(buf) {
-                // No data ready
-                Ok(0) if buf.is_empty() => {
-                    // embedded_io_async::Read's contract is to not block if buf is empty. While
-                    // this function is not a direct implementor of the trait method, we still don't
-                    // want our future to never resolve.
-                    Poll::Ready(Ok(0))
-                }
-                // No data ready
-                Ok(0) => {
+                // Data ready!
+                Ok(n) if n > 0 => Poll::Ready(Ok(n)),
+                // No data ready, but the connection is live
+                _ if s.may_recv() => {
                     s.register_recv_waker(cx.waker());
                     Poll::Pending
                 }
-                // Data ready!
-                Ok(n) => Poll::Ready(Ok(n)),
-                // EOF
-                Err(_) => Poll::Ready(Ok(0)),
-                // FIXME:
-                // Err(tcp::RecvError::Finished) => Poll::Ready(Ok(0)),
-                // Connection reset. TODO: this can also be timeouts etc, investigate.
-                // Err(tcp::RecvError::InvalidState) => Poll::Ready(Err(Error::ConnectionReset)),
+                // The stack closed the connection, not the remote host
+                _ if aborted => Poll::Ready(Err(Error::ConnectionReset)),
+                // EOF: the buffer is drained, and the remote host closed the
+                // connection
+                _ if matches!(
+                    s.state(),
+                    TcpState::CloseWait | TcpState::LastAck | TcpState::TimeWait
+                ) =>
+                {
+                    Poll::Ready(Ok(0))
+                }
+                // Never connected, or the connection attempt was aborted
+                _ => Poll::Ready(Err(Error::ConnectionReset)),
             })
         })
         .await
     }
 
+    /// Make sure the network link is up, before operating on the socket.
+    async fn ensure_network(&mut self) -> Result<(), Error> {
+        if !self.stack.borrow().link_up {
+            return Err(Error::NotConnected);
+        }
+        Ok(())
+    }
+
     async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        // Some protocols use empty writes as a liveness check. There is nothing
+        // to hand to the module, and the send buffer would never accept it.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.ensure_network().await?;
+
         poll_fn(move |cx| {
             self.with_mut(|s| match s.send_slice(buf) {
                 // Not ready to send (no space in the tx buffer)
@@ -441,6 +958,8 @@ impl<'d> TcpIo<'d> {
     where
         F: FnOnce(&mut [u8]) -> (usize, R),
     {
+        self.ensure_network().await?;
+
         let mut f = Some(f);
 
         poll_fn(move |cx| {
@@ -474,7 +993,9 @@ impl<'d> TcpIo<'d> {
         let mut f = Some(f);
         poll_fn(move |cx| {
             self.with_mut(|s| {
-                if !s.can_recv() {
+                // Data already received is handed out even once the
+                // connection is closed, as by `read`
+                if s.recv_queue() == 0 {
                     if s.may_recv() {
                         // socket buffer is empty wait until it has atleast one byte has arrived
                         s.register_recv_waker(cx.waker());
@@ -515,10 +1036,74 @@ impl<'d> TcpIo<'d> {
         .await
     }
 
+    /// Wait for the peer connection to be closed by the module.
+    async fn wait_closed(&mut self) {
+        poll_fn(move |cx| {
+            self.with_mut(|s| match s.state() {
+                TcpState::Closed | TcpState::TimeWait => Poll::Ready(()),
+                _ => {
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    async fn wait_read_ready(&mut self, n: usize) -> Result<(), Error> {
+        poll_fn(move |cx| {
+            self.with_mut(|s| {
+                if s.recv_queue() >= n.min(s.recv_capacity()) {
+                    Poll::Ready(Ok(()))
+                } else if !s.may_recv() {
+                    // Nothing more will arrive, hand out what is left
+                    if s.recv_queue() > 0 {
+                        Poll::Ready(Ok(()))
+                    } else {
+                        Poll::Ready(Err(Error::ConnectionReset))
+                    }
+                } else {
+                    s.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
+    async fn wait_write_ready(&mut self, n: usize) -> Result<(), Error> {
+        poll_fn(move |cx| {
+            self.with_mut(|s| {
+                if !s.may_send() {
+                    Poll::Ready(Err(Error::ConnectionReset))
+                } else if s.send_capacity() - s.send_queue() >= n.min(s.send_capacity()) {
+                    Poll::Ready(Ok(()))
+                } else {
+                    // Woken as octets are dequeued from the send buffer
+                    s.register_send_waker(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+
     fn recv_capacity(&self) -> usize {
         self.with(|s| s.recv_capacity())
     }
 
+    fn recv_available(&self) -> usize {
+        self.with(|s| s.recv_queue())
+    }
+
+    fn send_available(&self) -> usize {
+        self.with(|s| s.send_capacity() - s.send_queue())
+    }
+
+    fn send_queue(&self) -> usize {
+        self.with(|s| s.send_queue())
+    }
+
     fn send_capacity(&self) -> usize {
         self.with(|s| s.send_capacity())
     }
@@ -533,7 +1118,10 @@ mod embedded_io_impls {
                 ConnectError::ConnectionReset => embedded_io_async::ErrorKind::ConnectionReset,
                 ConnectError::TimedOut => embedded_io_async::ErrorKind::TimedOut,
                 ConnectError::NoRoute => embedded_io_async::ErrorKind::NotConnected,
+                ConnectError::NotConnected => embedded_io_async::ErrorKind::NotConnected,
                 ConnectError::InvalidState => embedded_io_async::ErrorKind::Other,
+                ConnectError::UrlTooLong => embedded_io_async::ErrorKind::InvalidInput,
+                ConnectError::TlsHandshake(_) => embedded_io_async::ErrorKind::ConnectionRefused,
             }
         }
     }
@@ -542,6 +1130,10 @@ mod embedded_io_impls {
         fn kind(&self) -> embedded_io_async::ErrorKind {
             match self {
                 Error::ConnectionReset => embedded_io_async::ErrorKind::ConnectionReset,
+                Error::NotConnected => embedded_io_async::ErrorKind::NotConnected,
+                Error::PausedOverflow => embedded_io_async::ErrorKind::OutOfMemory,
+                Error::RxBufferFull => embedded_io_async::ErrorKind::OutOfMemory,
+                Error::TimedOut => embedded_io_async::ErrorKind::TimedOut,
             }
         }
     }
@@ -618,13 +1210,28 @@ mod embedded_io_impls {
 /// TCP client compatible with `embedded-nal-async` traits.
 pub mod client {
     use core::cell::{Cell, UnsafeCell};
-    use core::mem::MaybeUninit;
+    use core::mem::{ManuallyDrop, MaybeUninit};
     use core::ptr::NonNull;
 
     use crate::asynch::ublox_stack::dns::DnsSocket;
+    use crate::asynch::ublox_stack::peer_builder::SecurityCredentials;
+    use crate::asynch::ublox_stack::tcp_peer_url;
 
     use super::*;
 
+    /// Error returned by [`TcpClient::connect_dedup`], with the error of the
+    /// step that failed.
+    #[derive(Debug)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum DedupError {
+        /// All connection buffers of the client are in use.
+        NoFreeBuffer,
+        /// The socket options are invalid, or the peer URL is too long.
+        Config(crate::error::Error),
+        /// The connect failed.
+        Connect(ConnectError),
+    }
+
     /// TCP client connection pool compatible with `embedded-nal-async` traits.
     ///
     /// The pool is capable of managing up to N concurrent connections with tx and rx buffers according to TX_SZ and RX_SZ.
@@ -662,10 +1269,10 @@ pub mod client {
 
         async fn get_host_by_address(
             &self,
-            _addr: no_std_net::IpAddr,
-            _result: &mut [u8],
+            addr: no_std_net::IpAddr,
+            result: &mut [u8],
         ) -> Result<usize, Self::Error> {
-            unimplemented!()
+            DnsSocket::new(self.stack).reverse_query(addr, result)
         }
     }
 
@@ -685,6 +1292,44 @@ pub mod client {
         ) -> Self {
             Self { stack, state }
         }
+
+        /// Connect to `remote` with `options`, sharing an equivalent live
+        /// connection, if there is one.
+        ///
+        /// Connections are equivalent if their peer URLs are, covering the
+        /// remote host, credentials and options, see
+        /// [`SocketOptions`]. Only connections opened with this method are
+        /// shared, and only once established: concurrent calls may still open
+        /// a peer each. All connections sharing a peer read from and write to
+        /// the same socket, and see the same failures, such as a link loss or
+        /// a [`close_reason`](TcpSocket::close_reason). The peer is closed
+        /// once the last of them is dropped.
+        pub async fn connect_dedup<'a>(
+            &'a self,
+            remote: SocketAddr,
+            options: &SocketOptions,
+        ) -> Result<TcpConnection<'a, N, TX_SZ, RX_SZ>, DedupError> {
+            if let Some(connection) =
+                TcpConnection::share(&self.stack.socket, self.state, remote, None, options)
+                    .map_err(DedupError::Config)?
+            {
+                return Ok(connection);
+            }
+
+            let mut connection = TcpConnection::new(&self.stack.socket, self.state)
+                .map_err(|_| DedupError::NoFreeBuffer)?;
+            connection
+                .socket
+                .set_socket_options(options.clone())
+                .map_err(DedupError::Config)?;
+            connection
+                .socket
+                .connect((remote.ip(), remote.port()))
+                .await
+                .map_err(DedupError::Connect)?;
+            connection.set_shared();
+            Ok(connection)
+        }
     }
 
     impl<
@@ -705,7 +1350,7 @@ pub mod client {
             remote: SocketAddr,
         ) -> Result<Self::Connection<'a>, Self::Error> {
             let remote_endpoint = (remote.ip(), remote.port());
-            let mut socket = TcpConnection::new(self.stack, self.state)?;
+            let mut socket = TcpConnection::new(&self.stack.socket, self.state)?;
             socket
                 .socket
                 .connect(remote_endpoint)
@@ -717,7 +1362,8 @@ pub mod client {
 
     /// Opened TCP connection in a [`TcpClient`].
     pub struct TcpConnection<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize> {
-        socket: TcpSocket<'d>,
+        /// Dropped along with the last connection sharing the socket.
+        pub(super) socket: ManuallyDrop<TcpSocket<'d>>,
         state: &'d TcpClientState<N, TX_SZ, RX_SZ>,
         bufs: NonNull<([u8; TX_SZ], [u8; RX_SZ])>,
     }
@@ -725,27 +1371,107 @@ pub mod client {
     impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize>
         TcpConnection<'d, N, TX_SZ, RX_SZ>
     {
-        fn new<const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>(
-            stack: &'d UbloxStack<INGRESS_BUF_SIZE, URC_CAPACITY>,
+        pub(super) fn new(
+            stack: &'d RefCell<SocketStack>,
             state: &'d TcpClientState<N, TX_SZ, RX_SZ>,
         ) -> Result<Self, Error> {
             let mut bufs = state.pool.alloc().ok_or(Error::ConnectionReset)?;
+            let socket =
+                unsafe { TcpSocket::new_in(stack, &mut bufs.as_mut().1, &mut bufs.as_mut().0) };
+            let slot = state.pool.index(bufs);
+            state.handles[slot].set(Some(socket.io.handle));
+            state.refs[slot].set(0);
             Ok(Self {
-                socket: unsafe {
-                    TcpSocket::new(stack, &mut bufs.as_mut().1, &mut bufs.as_mut().0)
-                },
+                socket: ManuallyDrop::new(socket),
                 state,
                 bufs,
             })
         }
+
+        /// Share the established, shared connection equivalent to `remote`
+        /// with `credentials` and `options`, if there is one.
+        ///
+        /// Fails if the peer URL of `remote` is too long, in which case no
+        /// connection could be equivalent.
+        pub(super) fn share(
+            stack: &'d RefCell<SocketStack>,
+            state: &'d TcpClientState<N, TX_SZ, RX_SZ>,
+            remote: SocketAddr,
+            credentials: Option<&SecurityCredentials>,
+            options: &SocketOptions,
+        ) -> Result<Option<Self>, crate::error::Error> {
+            let s = &*stack.borrow();
+            let url = tcp_peer_url(&s.dns_table, remote, None, credentials, Some(options))?;
+
+            let Some(slot) = (0..N).find(|&n| {
+                let Some(handle) = state.handles[n].get().filter(|_| state.refs[n].get() > 0)
+                else {
+                    return false;
+                };
+                let socket = s.sockets.get::<tcp::Socket>(handle);
+                let Some(endpoint) = socket.remote_endpoint() else {
+                    return false;
+                };
+
+                socket.state() == TcpState::Established
+                    && s.credential_map.get(&handle) == credentials
+                    && s.socket_options.get(&handle).cloned().unwrap_or_default() == *options
+                    && tcp_peer_url(
+                        &s.dns_table,
+                        endpoint,
+                        socket.local_port,
+                        s.credential_map.get(&handle),
+                        s.socket_options.get(&handle),
+                    )
+                    .is_ok_and(|existing| existing == url)
+            }) else {
+                return Ok(None);
+            };
+
+            let (Some(handle), Some(refs)) = (
+                state.handles[slot].get(),
+                state.refs[slot].get().checked_add(1),
+            ) else {
+                return Ok(None);
+            };
+            state.refs[slot].set(refs);
+            Ok(Some(Self {
+                socket: ManuallyDrop::new(TcpSocket {
+                    io: TcpIo { stack, handle },
+                }),
+                state,
+                bufs: state.pool.get(slot),
+            }))
+        }
+
+        /// Allow the connection to be shared, see
+        /// [`TcpClient::connect_dedup`].
+        pub(super) fn set_shared(&self) {
+            self.state.refs[self.state.pool.index(self.bufs)].set(1);
+        }
+
+        /// Handle of the socket of the connection, shared by all connections
+        /// sharing its peer.
+        pub fn handle(&self) -> SocketHandle {
+            self.socket.io.handle
+        }
     }
 
     impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize> Drop
         for TcpConnection<'d, N, TX_SZ, RX_SZ>
     {
         fn drop(&mut self) {
+            let refs = &self.state.refs[self.state.pool.index(self.bufs)];
+            if refs.get() > 1 {
+                // Other connections still share the socket
+                refs.set(refs.get() - 1);
+                return;
+            }
+            refs.set(0);
+
             unsafe {
                 self.socket.close();
+                ManuallyDrop::drop(&mut self.socket);
                 self.state.pool.free(self.bufs);
             }
         }
@@ -780,12 +1506,24 @@ pub mod client {
     /// State for TcpClient
     pub struct TcpClientState<const N: usize, const TX_SZ: usize, const RX_SZ: usize> {
         pub(crate) pool: Pool<([u8; TX_SZ], [u8; RX_SZ]), N>,
+        /// Socket of each pool slot.
+        handles: [Cell<Option<SocketHandle>>; N],
+        /// Number of connections sharing the socket of each pool slot. Zero
+        /// if the socket is not shared, see [`TcpClient::connect_dedup`].
+        refs: [Cell<u8>; N],
     }
 
     impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize> TcpClientState<N, TX_SZ, RX_SZ> {
+        const NO_HANDLE: Cell<Option<SocketHandle>> = Cell::new(None);
+        const NO_REFS: Cell<u8> = Cell::new(0);
+
         /// Create a new `TcpClientState`.
         pub const fn new() -> Self {
-            Self { pool: Pool::new() }
+            Self {
+                pool: Pool::new(),
+                handles: [Self::NO_HANDLE; N],
+                refs: [Self::NO_REFS; N],
+            }
         }
     }
 
@@ -821,11 +1559,788 @@ pub mod client {
 
         /// safety: p must be a pointer obtained from self.alloc that hasn't been freed yet.
         pub(crate) unsafe fn free(&self, p: NonNull<T>) {
+            self.used[self.index(p)].set(false);
+        }
+
+        /// Index of the slot `p` points to.
+        pub(crate) fn index(&self, p: NonNull<T>) -> usize {
             let origin = self.data.as_ptr() as *mut T;
-            let n = p.as_ptr().offset_from(origin);
+            let n = unsafe { p.as_ptr().offset_from(origin) };
             assert!(n >= 0);
             assert!((n as usize) < N);
-            self.used[n as usize].set(false);
+            n as usize
         }
+
+        /// Pointer to the slot at `n`.
+        pub(crate) fn get(&self, n: usize) -> NonNull<T> {
+            unsafe { NonNull::new_unchecked(self.data[n].get() as *mut T) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::cell::Cell;
+    use core::pin::pin;
+    use embassy_futures::select::{select, select3, Either};
+
+    use super::super::fixture::{socket_stack, tcp_socket, tcp_socket_with};
+    use crate::asynch::control::ProxyClient;
+    use crate::test_util::{harness, Harness, MockUbloxModule};
+
+    type Stack = UbloxStack<{ harness::INGRESS_BUF_SIZE }, 8>;
+
+    fn closed_socket() -> (&'static RefCell<SocketStack>, SocketHandle) {
+        let stack = Box::leak(Box::new(RefCell::new(socket_stack::<1>())));
+        let handle = tcp_socket(&mut stack.borrow_mut());
+        stack.borrow_mut().add_socket_id(handle);
+        (stack, handle)
+    }
+
+    #[test]
+    fn empty_write() {
+        let (stack, handle) = closed_socket();
+        let mut io = TcpIo { stack, handle };
+
+        // The link is down, so any real write would fail
+        assert_eq!(embassy_futures::block_on(io.write(&[])), Ok(0));
+        assert_eq!(io.send_queue(), 0);
+    }
+
+    #[test]
+    fn allocate_before_join() {
+        let (stack, handle) = closed_socket();
+        let mut socket = TcpSocket {
+            io: TcpIo { stack, handle },
+        };
+        let remote = "192.168.0.1:8080".parse::<SocketAddr>().unwrap();
+
+        assert_eq!(
+            embassy_futures::block_on(socket.connect(remote)),
+            Err(ConnectError::NotConnected)
+        );
+
+        // A fresh socket is left alone when the link comes up
+        stack.borrow_mut().set_link_up(true);
+        assert_eq!(stack.borrow().link_epoch, 1);
+        assert_eq!(socket.state(), tcp::State::Closed);
+    }
+
+    #[test]
+    fn options_for_every_socket() {
+        use super::super::MAX_SOCKET_IDS;
+
+        let stack: &RefCell<SocketStack> =
+            Box::leak(Box::new(RefCell::new(socket_stack::<MAX_SOCKET_IDS>())));
+        let mut sockets: std::vec::Vec<_> = (0..MAX_SOCKET_IDS)
+            .map(|_| {
+                let handle = tcp_socket(&mut stack.borrow_mut());
+                stack.borrow_mut().add_socket_id(handle);
+                TcpSocket {
+                    io: TcpIo { stack, handle },
+                }
+            })
+            .collect();
+
+        for socket in sockets.iter_mut() {
+            socket.set_linger(Some(Duration::from_secs(1))).unwrap();
+            socket.pause_rx(16).unwrap();
+        }
+        for socket in sockets.iter() {
+            assert_eq!(socket.socket_options().linger, Some(Duration::from_secs(1)));
+            assert!(socket.paused());
+        }
+    }
+
+    #[test]
+    fn connect_interrupted_by_link_loss() {
+        let (stack, handle) = closed_socket();
+        let mut socket = TcpSocket {
+            io: TcpIo { stack, handle },
+        };
+        let remote = "192.168.0.1:8080".parse::<SocketAddr>().unwrap();
+        stack.borrow_mut().set_link_up(true);
+
+        let res = embassy_futures::block_on(embassy_futures::select::select(
+            socket.connect(remote),
+            async {
+                // The module accepted the connect, then the link dropped
+                {
+                    let mut s = stack.borrow_mut();
+                    let tcp = s.sockets.get_mut::<tcp::Socket>(handle);
+                    tcp.peer_handle = Some(ublox_sockets::PeerHandle(0));
+                    tcp.set_state(tcp::State::SynSent);
+                }
+                stack.borrow_mut().set_link_up(false);
+                stack.borrow_mut().set_link_up(true);
+                core::future::pending::<()>().await
+            },
+        ));
+        assert!(matches!(
+            res,
+            embassy_futures::select::Either::First(Err(ConnectError::ConnectionReset))
+        ));
+        assert_eq!(stack.borrow().link_epoch, 2);
+
+        // The socket is ready to connect again
+        assert_eq!(socket.state(), tcp::State::Closed);
+        assert!(socket.io.with(|s| s.peer_handle.is_none()));
+        assert!(socket.io.with_mut(|s| s.connect(remote, None)).is_ok());
+    }
+
+    #[test]
+    fn connect_rejected() {
+        let (stack, handle) = closed_socket();
+        let mut socket = TcpSocket {
+            io: TcpIo { stack, handle },
+        };
+        let remote = "192.168.0.1:8080".parse::<SocketAddr>().unwrap();
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+        stack.borrow_mut().set_link_up(true);
+
+        assert_eq!(socket.start_connect(remote), Ok(()));
+        assert_eq!(socket.poll_connect(&mut cx), Poll::Pending);
+        assert_eq!(
+            socket.start_connect(remote),
+            Err(ConnectError::InvalidState)
+        );
+
+        // The module rejects the connect
+        stack.borrow_mut().connect_failed(handle);
+        assert_eq!(
+            socket.poll_connect(&mut cx),
+            Poll::Ready(Err(ConnectError::ConnectionReset))
+        );
+
+        // The socket is ready to connect again, and is connected once the
+        // module reports the peer
+        assert_eq!(socket.start_connect(remote), Ok(()));
+        socket.io.with_mut(|s| s.set_state(tcp::State::Established));
+        assert_eq!(socket.poll_connect(&mut cx), Poll::Ready(Ok(())));
+    }
+
+    #[test]
+    fn tls_handshake_failed() {
+        use crate::asynch::ublox_stack::peer_builder::SecurityCredentials;
+        use crate::command::{edm::urc::EdmEvent, Urc};
+        use atat::AtatUrc;
+
+        let remote = "192.168.0.1:8443".parse::<SocketAddr>().unwrap();
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+
+        for tls in [true, false] {
+            let (stack, handle) = closed_socket();
+            let mut socket = TcpSocket {
+                io: TcpIo { stack, handle },
+            };
+            stack.borrow_mut().set_link_up(true);
+            if tls {
+                let creds = SecurityCredentials {
+                    ca_cert_name: heapless::String::try_from("ca.crt").unwrap(),
+                    c_cert_name: heapless::String::try_from("client.crt").unwrap(),
+                    c_key_name: heapless::String::try_from("client.key").unwrap(),
+                };
+                stack
+                    .borrow_mut()
+                    .credential_map
+                    .insert(handle, creds)
+                    .unwrap();
+            }
+
+            assert_eq!(socket.start_connect(remote), Ok(()));
+            stack.borrow_mut().bind_peer(handle, PeerHandle(2));
+            assert_eq!(socket.poll_connect(&mut cx), Poll::Pending);
+
+            // The module drops the peer, e.g. for an untrusted certificate
+            let urc = Urc::parse(b"+UUDPD:2,3").unwrap();
+            UbloxStack::<256, 8>::socket_rx(EdmEvent::ATEvent(urc), stack);
+
+            if tls {
+                assert_eq!(
+                    socket.poll_connect(&mut cx),
+                    Poll::Ready(Err(ConnectError::TlsHandshake(Some(3))))
+                );
+                assert_eq!(
+                    socket.close_reason(),
+                    Some(CloseReason::TlsHandshake(Some(3)))
+                );
+            } else {
+                assert_eq!(
+                    socket.poll_connect(&mut cx),
+                    Poll::Ready(Err(ConnectError::ConnectionReset))
+                );
+                assert_eq!(socket.close_reason(), None);
+            }
+        }
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let (stack, handle) = closed_socket();
+        let mut socket = TcpSocket {
+            io: TcpIo { stack, handle },
+        };
+        let remote = "192.168.0.1:8080".parse::<SocketAddr>().unwrap();
+        stack.borrow_mut().set_link_up(true);
+
+        // The module does not answer in time
+        assert_eq!(
+            embassy_futures::block_on(socket.connect_with_timeout(remote, Duration::from_ticks(0))),
+            Err(ConnectError::TimedOut)
+        );
+        assert_eq!(socket.state(), tcp::State::Closed);
+        assert_eq!(socket.remote_endpoint(), None);
+
+        // The peer of the late answer is closed right away
+        stack.borrow_mut().bind_peer(handle, PeerHandle(2));
+        assert_eq!(stack.borrow().dropped_sockets, [PeerHandle(2)]);
+        assert!(socket.io.with(|s| s.peer_handle.is_none()));
+
+        // The module assigned a peer, but never reports it connected
+        assert_eq!(socket.start_connect(remote), Ok(()));
+        stack.borrow_mut().bind_peer(handle, PeerHandle(3));
+        assert_eq!(socket.state(), tcp::State::SynSent);
+        stack.borrow_mut().abort_connect(handle);
+        assert_eq!(
+            stack.borrow().dropped_sockets,
+            [PeerHandle(2), PeerHandle(3)]
+        );
+        assert_eq!(socket.state(), tcp::State::Closed);
+        assert!(socket.io.with(|s| s.peer_handle.is_none()));
+
+        // Plain connects time out after the timeout of the stack
+        stack.borrow_mut().connect_timeout = Duration::from_ticks(0);
+        assert_eq!(
+            embassy_futures::block_on(socket.connect(remote)),
+            Err(ConnectError::TimedOut)
+        );
+        assert_eq!(socket.start_connect(remote), Ok(()));
+    }
+
+    #[test]
+    fn close_linger() {
+        let (stack, handle) = closed_socket();
+        let mut socket = TcpSocket {
+            io: TcpIo { stack, handle },
+        };
+        socket.io.with_mut(|s| s.set_state(tcp::State::Established));
+
+        // Without a linger time, the close is not awaited
+        assert_eq!(embassy_futures::block_on(socket.close_linger()), Ok(()));
+        assert_eq!(socket.state(), tcp::State::FinWait1);
+
+        socket.io.with_mut(|s| s.set_state(tcp::State::Established));
+        socket.set_linger(Some(Duration::from_secs(10))).unwrap();
+
+        let res = embassy_futures::block_on(embassy_futures::select::select(
+            socket.close_linger(),
+            async {
+                // The module confirms the close
+                stack
+                    .borrow_mut()
+                    .sockets
+                    .get_mut::<tcp::Socket>(handle)
+                    .set_state(tcp::State::TimeWait);
+                core::future::pending::<()>().await
+            },
+        ));
+        assert!(matches!(
+            res,
+            embassy_futures::select::Either::First(Ok(()))
+        ));
+
+        // The module does not confirm the close in time
+        socket.io.with_mut(|s| s.set_state(tcp::State::Established));
+        socket.set_linger(Some(Duration::from_millis(100))).unwrap();
+        let clock = crate::test_util::ManualClock::new();
+        let mut close = pin!(socket.close_linger());
+        assert!(embassy_futures::poll_once(close.as_mut()).is_pending());
+        clock.advance(100);
+        assert_eq!(
+            embassy_futures::poll_once(close.as_mut()),
+            Poll::Ready(Err(Error::TimedOut))
+        );
+    }
+
+    #[test]
+    fn readiness() {
+        let (stack, handle) = closed_socket();
+        let mut socket = TcpSocket {
+            io: TcpIo { stack, handle },
+        };
+        socket.io.with_mut(|s| s.set_state(tcp::State::Established));
+        assert_eq!(socket.send_available(), 16);
+        assert_eq!(socket.recv_available(), 0);
+
+        // Fill the transmit buffer, and let the module drain it
+        assert_eq!(
+            socket.io.with_mut(|s| s.send_slice(&[0; 12]).ok()),
+            Some(12)
+        );
+        assert_eq!(socket.send_available(), 4);
+        {
+            let mut ready = pin!(socket.wait_write_ready(8));
+            assert!(embassy_futures::poll_once(ready.as_mut()).is_pending());
+
+            let drain = |len| {
+                stack
+                    .borrow_mut()
+                    .sockets
+                    .get_mut::<tcp::Socket>(handle)
+                    .tx_dequeue(|_| (len, ()))
+            };
+            drain(3);
+            assert!(embassy_futures::poll_once(ready.as_mut()).is_pending());
+            drain(1);
+            assert_eq!(
+                embassy_futures::poll_once(ready.as_mut()),
+                Poll::Ready(Ok(()))
+            );
+        }
+        assert_eq!(socket.send_available(), 8);
+
+        // Requests beyond the buffer size complete once it is empty
+        {
+            let mut ready = pin!(socket.wait_write_ready(100));
+            assert!(embassy_futures::poll_once(ready.as_mut()).is_pending());
+            stack
+                .borrow_mut()
+                .sockets
+                .get_mut::<tcp::Socket>(handle)
+                .tx_dequeue(|payload| (payload.len(), ()));
+            assert_eq!(
+                embassy_futures::poll_once(ready.as_mut()),
+                Poll::Ready(Ok(()))
+            );
+        }
+
+        // Data arrives from the module, and is read
+        let enqueue = |data: &[u8]| {
+            stack
+                .borrow_mut()
+                .sockets
+                .get_mut::<tcp::Socket>(handle)
+                .rx_enqueue_slice(data)
+        };
+        {
+            let mut ready = pin!(socket.wait_read_ready(6));
+            enqueue(&[0x42; 4]);
+            assert!(embassy_futures::poll_once(ready.as_mut()).is_pending());
+            enqueue(&[0x42; 2]);
+            assert_eq!(
+                embassy_futures::poll_once(ready.as_mut()),
+                Poll::Ready(Ok(()))
+            );
+        }
+        assert_eq!(socket.recv_available(), 6);
+
+        let mut buf = [0; 4];
+        assert_eq!(embassy_futures::block_on(socket.read(&mut buf)), Ok(4));
+        assert_eq!(socket.recv_available(), 2);
+
+        // The remote host closed the connection, with data left
+        socket.io.with_mut(|s| s.set_state(tcp::State::CloseWait));
+        assert_eq!(embassy_futures::block_on(socket.wait_read_ready(6)), Ok(()));
+        assert_eq!(embassy_futures::block_on(socket.read(&mut buf)), Ok(2));
+        assert_eq!(
+            embassy_futures::block_on(socket.wait_read_ready(1)),
+            Err(Error::ConnectionReset)
+        );
+    }
+
+    #[test]
+    fn read_eof() {
+        let (stack, handle) = closed_socket();
+        let mut socket = TcpSocket {
+            io: TcpIo { stack, handle },
+        };
+        let mut buf = [0; 4];
+
+        // Not connected
+        assert_eq!(
+            embassy_futures::block_on(socket.read(&mut buf)),
+            Err(Error::ConnectionReset)
+        );
+
+        // Connected, no data yet
+        socket.io.with_mut(|s| s.set_state(tcp::State::Established));
+        {
+            let mut read = pin!(socket.read(&mut buf));
+            assert!(embassy_futures::poll_once(read.as_mut()).is_pending());
+        }
+
+        // The remote host sends data, and closes the connection
+        socket.io.with_mut(|s| {
+            s.rx_enqueue_slice(&[0x42; 6]);
+            s.set_state(tcp::State::TimeWait);
+        });
+        assert_eq!(embassy_futures::block_on(socket.read(&mut buf)), Ok(4));
+        assert_eq!(embassy_futures::block_on(socket.read(&mut buf)), Ok(2));
+        assert_eq!(embassy_futures::block_on(socket.read(&mut buf)), Ok(0));
+        assert_eq!(embassy_futures::block_on(socket.read(&mut buf)), Ok(0));
+    }
+
+    #[test]
+    fn read_with_after_close() {
+        let (stack, handle) = closed_socket();
+        let mut socket = TcpSocket {
+            io: TcpIo { stack, handle },
+        };
+        let take = |n: usize| move |data: &mut [u8]| (n.min(data.len()), data[0]);
+
+        // The remote host sends data, and closes the connection
+        socket.io.with_mut(|s| {
+            s.set_state(tcp::State::Established);
+            s.rx_enqueue_slice(&[0x42; 6]);
+            s.set_state(tcp::State::TimeWait);
+        });
+        assert_eq!(
+            embassy_futures::block_on(socket.read_with(take(4))),
+            Ok(0x42)
+        );
+        assert_eq!(
+            embassy_futures::block_on(socket.read_with(take(4))),
+            Ok(0x42)
+        );
+        assert_eq!(socket.recv_available(), 0);
+        assert_eq!(
+            embassy_futures::block_on(socket.read_with(take(4))),
+            Err(Error::ConnectionReset)
+        );
+
+        // Received before the link dropped
+        stack.borrow_mut().set_link_up(true);
+        socket.io.with_mut(|s| {
+            s.peer_handle = Some(ublox_sockets::PeerHandle(0));
+            s.edm_channel = Some(ublox_sockets::ChannelId(1));
+            s.set_state(tcp::State::Established);
+            s.rx_enqueue_slice(&[0x43; 2]);
+        });
+        stack.borrow_mut().set_link_up(false);
+        assert_eq!(
+            embassy_futures::block_on(socket.read_with(take(4))),
+            Ok(0x43)
+        );
+    }
+
+    #[test]
+    fn read_interrupted_by_link_loss() {
+        let (stack, handle) = closed_socket();
+        let mut socket = TcpSocket {
+            io: TcpIo { stack, handle },
+        };
+        let mut buf = [0; 4];
+        stack.borrow_mut().set_link_up(true);
+        socket.io.with_mut(|s| {
+            s.peer_handle = Some(ublox_sockets::PeerHandle(0));
+            s.edm_channel = Some(ublox_sockets::ChannelId(1));
+            s.set_state(tcp::State::Established);
+            s.rx_enqueue_slice(&[0x42; 2]);
+        });
+
+        assert_eq!(embassy_futures::block_on(socket.read(&mut buf)), Ok(2));
+
+        // Waiting for data when the link drops
+        {
+            let mut read = pin!(socket.read(&mut buf));
+            assert!(embassy_futures::poll_once(read.as_mut()).is_pending());
+            stack.borrow_mut().set_link_up(false);
+            assert_eq!(
+                embassy_futures::poll_once(read.as_mut()),
+                Poll::Ready(Err(Error::ConnectionReset))
+            );
+        }
+
+        assert_eq!(socket.state(), tcp::State::TimeWait);
+        assert_eq!(socket.close_reason(), Some(CloseReason::LinkLost));
+        assert!(socket.io.with(|s| s.edm_channel.is_none()));
+        assert_eq!(stack.borrow().link_epoch, 1);
+    }
+
+    #[test]
+    fn connection_dedup() {
+        use client::{TcpClientState, TcpConnection};
+
+        let stack: &RefCell<SocketStack> = Box::leak(Box::new(RefCell::new(socket_stack::<3>())));
+        let state: &TcpClientState<3, 16, 16> = Box::leak(Box::new(TcpClientState::new()));
+
+        let remote: SocketAddr = "192.168.0.1:8883".parse().unwrap();
+        let options = SocketOptions::new().keep_alive(Duration::from_secs(30));
+        let establish = |connection: &mut TcpConnection<'_, 3, 16, 16>, options: &SocketOptions| {
+            connection
+                .socket
+                .set_socket_options(options.clone())
+                .unwrap();
+            connection.socket.io.with_mut(|s| {
+                s.connect(remote, None).unwrap();
+                s.set_state(tcp::State::Established);
+            });
+        };
+
+        // Connections not opened for sharing are not shared
+        let mut exclusive = TcpConnection::new(stack, state).unwrap();
+        establish(&mut exclusive, &options);
+        assert!(TcpConnection::share(stack, state, remote, None, &options)
+            .unwrap()
+            .is_none());
+        drop(exclusive);
+
+        let mut first = TcpConnection::new(stack, state).unwrap();
+        establish(&mut first, &options);
+        first.set_shared();
+
+        // Equivalent connections share the socket, others do not
+        let second = TcpConnection::share(stack, state, remote, None, &options)
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.handle(), first.handle());
+        assert!(
+            TcpConnection::share(stack, state, remote, None, &SocketOptions::new())
+                .unwrap()
+                .is_none()
+        );
+        let other: SocketAddr = "192.168.0.1:1883".parse().unwrap();
+        assert!(TcpConnection::share(stack, state, other, None, &options)
+            .unwrap()
+            .is_none());
+
+        // The socket is closed once the last connection is dropped
+        let handle = first.handle();
+        drop(first);
+        assert_eq!(second.socket.state(), tcp::State::Established);
+        assert!(TcpConnection::share(stack, state, remote, None, &options)
+            .unwrap()
+            .is_some_and(|third| third.handle() == handle));
+        drop(second);
+        assert!(TcpConnection::share(stack, state, remote, None, &options)
+            .unwrap()
+            .is_none());
+        for _ in 0..3 {
+            core::mem::forget(TcpConnection::new(stack, state).unwrap());
+        }
+    }
+
+    #[test]
+    fn connection_dedup_credentials() {
+        use crate::asynch::ublox_stack::peer_builder::SecurityCredentials;
+        use client::{TcpClientState, TcpConnection};
+
+        let stack: &RefCell<SocketStack> = Box::leak(Box::new(RefCell::new(socket_stack::<1>())));
+        let state: &TcpClientState<1, 16, 16> = Box::leak(Box::new(TcpClientState::new()));
+
+        let remote: SocketAddr = "192.168.0.1:8883".parse().unwrap();
+        let options = SocketOptions::new();
+        let credentials = |ca: &str| SecurityCredentials {
+            ca_cert_name: heapless::String::try_from(ca).unwrap(),
+            c_cert_name: heapless::String::try_from("cert").unwrap(),
+            c_key_name: heapless::String::try_from("key").unwrap(),
+        };
+
+        let first = TcpConnection::new(stack, state).unwrap();
+        stack
+            .borrow_mut()
+            .credential_map
+            .insert(first.handle(), credentials("ca"))
+            .unwrap();
+        first.socket.io.with_mut(|s| {
+            s.connect(remote, None).unwrap();
+            s.set_state(tcp::State::Established);
+        });
+        first.set_shared();
+
+        // Only shared with the same credentials
+        let share = |credentials: Option<&SecurityCredentials>| {
+            TcpConnection::share(stack, state, remote, credentials, &options).unwrap()
+        };
+        assert!(share(None).is_none());
+        assert!(share(Some(&credentials("other"))).is_none());
+        let second = share(Some(&credentials("ca"))).unwrap();
+        assert_eq!(second.handle(), first.handle());
+
+        // The error of the lookup is kept
+        let long = SecurityCredentials {
+            ca_cert_name: heapless::String::try_from("a".repeat(16).as_str()).unwrap(),
+            c_cert_name: heapless::String::try_from("b".repeat(16).as_str()).unwrap(),
+            c_key_name: heapless::String::try_from("c".repeat(16).as_str()).unwrap(),
+        };
+        let sni = heapless::String::try_from("s".repeat(64).as_str()).unwrap();
+        assert!(matches!(
+            TcpConnection::share(
+                stack,
+                state,
+                remote,
+                Some(&long),
+                &SocketOptions::new().sni(sni)
+            ),
+            Err(crate::error::Error::UrlTooLong)
+        ));
+
+        drop(second);
+        drop(first);
+    }
+
+    #[test]
+    fn connection_dedup_link_loss() {
+        use client::{TcpClientState, TcpConnection};
+
+        let stack: &RefCell<SocketStack> = Box::leak(Box::new(RefCell::new(socket_stack::<2>())));
+        let state: &TcpClientState<2, 16, 16> = Box::leak(Box::new(TcpClientState::new()));
+
+        let remote: SocketAddr = "192.168.0.1:8883".parse().unwrap();
+        let options = SocketOptions::new();
+        let mut first = TcpConnection::new(stack, state).unwrap();
+        first.socket.io.with_mut(|s| {
+            s.connect(remote, None).unwrap();
+            s.set_state(tcp::State::Established);
+        });
+        first.set_shared();
+        let mut second = TcpConnection::share(stack, state, remote, None, &options)
+            .unwrap()
+            .unwrap();
+
+        // All connections sharing the socket see the failure, and a new peer
+        // is needed
+        stack.borrow_mut().reset_stale_sockets();
+        for connection in [&mut first, &mut second] {
+            assert_eq!(connection.socket.state(), tcp::State::TimeWait);
+            assert_eq!(
+                embassy_futures::block_on(connection.socket.read(&mut [0; 4])),
+                Err(Error::ConnectionReset)
+            );
+        }
+        assert!(TcpConnection::share(stack, state, remote, None, &options)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn empty_read() {
+        let (stack, handle) = closed_socket();
+        let mut io = TcpIo { stack, handle };
+
+        assert_eq!(embassy_futures::block_on(io.read(&mut [])), Ok(0));
+    }
+
+    /// Run the transmissions of `stack` through `client`, and feed the events
+    /// of the module back to `stack`, as the runner does. Never returns.
+    async fn run_stack(
+        stack: &RefCell<SocketStack>,
+        harness: &Harness,
+        client: &RefCell<ProxyClient<'_, { harness::INGRESS_BUF_SIZE }>>,
+    ) -> ! {
+        let mut events = harness.urc_channel.subscribe().unwrap();
+        let mut buf = [0u8; super::super::MAX_EGRESS_SIZE];
+        loop {
+            while let Some(ev) = Stack::tx_event(stack, &mut buf) {
+                Stack::socket_tx(ev, stack, client).await;
+            }
+
+            let mut registered = false;
+            let woken = poll_fn(|cx| {
+                if registered {
+                    return Poll::Ready(());
+                }
+                stack.borrow_mut().waker.register(cx.waker());
+                registered = true;
+                Poll::Pending
+            });
+            if let Either::First(event) = select(events.next_message_pure(), woken).await {
+                Stack::socket_rx(event, stack);
+            }
+        }
+    }
+
+    #[test]
+    fn send_stream_resumed() {
+        const LEN: usize = 64 * 1024;
+        let source: std::vec::Vec<u8> = (0..LEN).map(|i| (i % 251) as u8).collect();
+        let remote = "192.168.0.1:8080".parse::<SocketAddr>().unwrap();
+
+        let stack: &RefCell<SocketStack> = Box::leak(Box::new(RefCell::new(socket_stack::<2>())));
+        let (first, second) = {
+            let mut s = stack.borrow_mut();
+            s.set_link_up(true);
+            // Data commands fitting a single request to the module
+            s.egress_chunk = 128;
+            let first = tcp_socket_with(&mut s, 16, 1024);
+            let second = tcp_socket_with(&mut s, 16, 1024);
+            s.add_socket_id(first);
+            s.add_socket_id(second);
+            (first, second)
+        };
+
+        let harness = Harness::new();
+        let client = RefCell::new(harness.client());
+        let mut module = MockUbloxModule::new();
+        let mut running = pin!(run_stack(stack, &harness, &client));
+
+        let mut socket = TcpSocket {
+            io: TcpIo {
+                stack,
+                handle: first,
+            },
+        };
+        match harness.serve(
+            &mut module,
+            select(socket.connect(remote), running.as_mut()),
+        ) {
+            Either::First(res) => res.unwrap(),
+            Either::Second(never) => never,
+        }
+
+        // The connection drops half way through the transfer
+        let progress = Cell::new(0);
+        let mut reader = source.as_slice();
+        let mut stream = pin!(socket.send_stream(&mut reader, 0, |n| progress.set(n)));
+        let halfway = poll_fn(|_| {
+            if progress.get() >= LEN as u64 / 2 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        });
+        harness.serve(
+            &mut module,
+            select3(stream.as_mut(), running.as_mut(), halfway),
+        );
+        let channel_id = module.channel_id(PeerHandle(1)).unwrap();
+        module.inject_disconnect_event(channel_id);
+        module.inject_urc("+UUDPD:1");
+
+        let delivered = match harness.serve(&mut module, select(stream.as_mut(), running.as_mut()))
+        {
+            Either::First(Err(e)) => {
+                assert_eq!(e.kind, SendStreamErrorKind::Socket(Error::ConnectionReset));
+                e.delivered
+            }
+            Either::First(Ok(_)) => panic!("transfer completed despite the drop"),
+            Either::Second(never) => never,
+        };
+        assert!((LEN as u64 / 2..LEN as u64).contains(&delivered));
+
+        // Resumed on a new connection
+        let mut socket = TcpSocket {
+            io: TcpIo {
+                stack,
+                handle: second,
+            },
+        };
+        let resumed = async {
+            socket.connect(remote).await.unwrap();
+            socket
+                .send_stream(&mut source.as_slice(), delivered, |_| {})
+                .await
+        };
+        match harness.serve(&mut module, select(resumed, running.as_mut())) {
+            Either::First(res) => assert_eq!(res.unwrap(), LEN as u64),
+            Either::Second(never) => never,
+        }
+
+        // Each byte sent exactly once
+        let delivered = delivered as usize;
+        assert_eq!(module.sent_data(channel_id), Some(&source[..delivered]));
+        let channel_id = module.channel_id(PeerHandle(2)).unwrap();
+        assert_eq!(module.sent_data(channel_id), Some(&source[delivered..]));
     }
 }
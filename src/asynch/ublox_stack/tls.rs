@@ -6,7 +6,7 @@ use super::peer_builder::SecurityCredentials;
 
 use super::{
     tcp::{ConnectError, Error, TcpIo, TcpReader, TcpSocket, TcpWriter},
-    UbloxStack,
+    SocketOptions, UbloxStack,
 };
 
 pub struct TlsSocket<'a> {
@@ -145,10 +145,28 @@ impl<'a> TlsSocket<'a> {
     /// the specified duration of inactivity.
     ///
     /// If not set, the socket will not send keep-alive packets.
-    pub fn set_keep_alive(&mut self, interval: Option<Duration>) {
+    pub fn set_keep_alive(
+        &mut self,
+        interval: Option<Duration>,
+    ) -> Result<(), crate::error::Error> {
         self.inner.set_keep_alive(interval)
     }
 
+    /// Get the options currently configured for the socket.
+    pub fn socket_options(&self) -> SocketOptions {
+        self.inner.socket_options()
+    }
+
+    /// Configure multiple socket options in one go.
+    ///
+    /// Must be called before [`connect()`](TlsSocket::connect) to take effect.
+    pub fn set_socket_options(
+        &mut self,
+        options: SocketOptions,
+    ) -> Result<(), crate::error::Error> {
+        self.inner.set_socket_options(options)
+    }
+
     // /// Set the hop limit field in the IP header of sent packets.
     // pub fn set_hop_limit(&mut self, hop_limit: Option<u8>) {
     //     self.inner.set_hop_limit()
@@ -307,10 +325,10 @@ pub mod client {
 
         async fn get_host_by_address(
             &self,
-            _addr: no_std_net::IpAddr,
-            _result: &mut [u8],
+            addr: no_std_net::IpAddr,
+            result: &mut [u8],
         ) -> Result<usize, Self::Error> {
-            unimplemented!()
+            DnsSocket::new(self.stack).reverse_query(addr, result)
         }
     }
 
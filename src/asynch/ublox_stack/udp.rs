@@ -1,12 +1,16 @@
 //! UDP sockets.
 use core::cell::RefCell;
-
+use core::future::poll_fn;
 use core::mem;
+use core::task::{Context, Poll};
 
+use embassy_time::Instant;
 use embedded_nal_async::SocketAddr;
 use ublox_sockets::{udp, SocketHandle, UdpState};
 
-use super::{SocketStack, UbloxStack};
+use super::{
+    trace_transition, SocketStack, SocketTransition, UbloxStack, UdpListener, UDP_SERVER_IDS,
+};
 
 /// Error returned by [`UdpSocket::bind`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -16,6 +20,10 @@ pub enum BindError {
     InvalidState,
     /// No route to host.
     NoRoute,
+    /// Another socket is bound to the port.
+    AddrInUse,
+    /// All module servers reserved for bound sockets are in use.
+    NoFreeServer,
 }
 
 /// Error returned by [`UdpSocket::recv_from`] and [`UdpSocket::send_to`].
@@ -26,20 +34,57 @@ pub enum SendError {
     NoRoute,
     /// Socket not bound to an outgoing port.
     SocketNotBound,
+    /// The socket is in [`UdpMode::Connected`], and connected to a different
+    /// remote endpoint.
+    WrongEndpoint,
+    /// The module rejected the peer of the remote endpoint, and the data
+    /// queued for it was dropped. The socket can be connected again.
+    ///
+    /// Also returned by [`UdpSocket::connect`] for a socket created with more
+    /// sockets in the stack than it supports.
+    ConnectFailed,
+    /// The datagram is larger than the send buffer of the socket, see
+    /// [`UdpSocket::poll_send_to`].
+    PacketTooLarge,
 }
 
 /// Error returned by [`UdpSocket::recv_from`] and [`UdpSocket::send_to`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum RecvError {
-    /// Provided buffer was smaller than the received packet.
+    /// Provided buffer was smaller than the received packet, which was
+    /// dropped.
     Truncated,
 }
 
+/// Addressing mode of a [`UdpSocket`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UdpMode {
+    /// The socket talks to a single remote endpoint, set by the first
+    /// [`UdpSocket::connect`] or [`UdpSocket::send_to`].
+    #[default]
+    Connected,
+    /// Every [`UdpSocket::send_to`] may pick a new remote endpoint.
+    ///
+    /// The module has no unconnected UDP peers, so changing the destination
+    /// closes the peer of the previous destination, and opens a new peer
+    /// once the pending data has been sent. Data is only received from the
+    /// most recent destination.
+    Datagram,
+}
+
 /// An UDP socket.
+///
+/// The socket keeps its slot in the socket set until it is dropped. Quiet
+/// sockets are never reclaimed by the stack, regardless of how long they
+/// have been idle.
 pub struct UdpSocket<'a> {
     stack: &'a RefCell<SocketStack>,
     handle: SocketHandle,
+    mode: UdpMode,
+    /// Size of the send buffer.
+    send_capacity: usize,
 }
 
 impl<'a> UdpSocket<'a> {
@@ -50,38 +95,335 @@ impl<'a> UdpSocket<'a> {
         tx_buffer: &'a mut [u8],
     ) -> Self {
         let s = &mut *stack.socket.borrow_mut();
+        let send_capacity = tx_buffer.len();
         let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
         let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
         let handle = s.sockets.add(udp::Socket::new(
             udp::SocketBuffer::new(rx_buffer),
             udp::SocketBuffer::new(tx_buffer),
         ));
+        s.add_socket_id(handle);
+        trace_transition(SocketTransition::Create, Some(handle), None, None, None);
 
         Self {
             stack: &stack.socket,
             handle,
+            mode: UdpMode::default(),
+            send_capacity,
         }
     }
 
-    // /// Bind the socket to a local endpoint.
-    // pub fn bind<T>(&mut self, endpoint: T) -> Result<(), BindError>
-    // where
-    //     T: Into<IpListenEndpoint>,
-    // {
-    //     let mut endpoint = endpoint.into();
+    /// Returns the addressing mode of the socket.
+    pub fn mode(&self) -> UdpMode {
+        self.mode
+    }
 
-    //     if endpoint.port == 0 {
-    //         // If user didn't specify port allocate a dynamic port.
-    //         endpoint.port = self.stack.borrow_mut().get_local_port();
-    //     }
+    /// Set the addressing mode of the socket, see [`UdpMode`].
+    pub fn set_mode(&mut self, mode: UdpMode) {
+        self.mode = mode;
+    }
 
-    //     match self.with_mut(|s| s.bind(endpoint)) {
-    //         Ok(()) => Ok(()),
-    //         // Err(udp::BindError::InvalidState) => Err(BindError::InvalidState),
-    //         // Err(udp::BindError::Unaddressable) => Err(BindError::NoRoute),
-    //         Err(_) => Err(BindError::InvalidState),
-    //     }
-    // }
+    /// Connect the socket to a remote endpoint.
+    ///
+    /// The peer is opened by the stack in the background. In
+    /// [`UdpMode::Connected`], the remote endpoint cannot be changed once set.
+    pub fn connect(&mut self, remote: SocketAddr) -> Result<(), SendError> {
+        match self.with(|s| s.endpoint) {
+            Some(endpoint) if endpoint == remote => Ok(()),
+            Some(_) if self.mode == UdpMode::Connected => Err(SendError::WrongEndpoint),
+            Some(_) => {
+                self.retarget(remote);
+                Ok(())
+            }
+            None if !self.stack.borrow().has_socket_id(self.handle) => {
+                Err(SendError::ConnectFailed)
+            }
+            None => {
+                self.stack
+                    .borrow_mut()
+                    .udp_connect_failed
+                    .remove(&self.handle);
+                self.with_mut(|s| s.endpoint = Some(remote));
+                Ok(())
+            }
+        }
+    }
+
+    /// Send a datagram to the connected remote endpoint.
+    ///
+    /// This method will wait until the datagram has been enqueued in the send
+    /// buffer.
+    ///
+    /// Fails with [`SendError::ConnectFailed`] if the module rejects the peer
+    /// of the remote endpoint.
+    pub async fn send(&self, buf: &[u8]) -> Result<(), SendError> {
+        self.connect_result()?;
+        if self.with(|s| s.endpoint).is_none() {
+            return Err(SendError::SocketNotBound);
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+        if !self.stack.borrow().link_up {
+            return Err(SendError::NoRoute);
+        }
+
+        let mut sent = 0;
+        poll_fn(move |cx| {
+            self.connect_result()?;
+            self.with_mut(|s| match s.send_slice(&buf[sent..]) {
+                Ok(n) => {
+                    sent += n;
+                    if sent == buf.len() {
+                        Poll::Ready(Ok(()))
+                    } else {
+                        s.register_send_waker(cx.waker());
+                        Poll::Pending
+                    }
+                }
+                Err(_) => Poll::Ready(Err(SendError::NoRoute)),
+            })
+        })
+        .await
+    }
+
+    /// Send a datagram to the specified remote endpoint.
+    ///
+    /// In [`UdpMode::Datagram`], sending to a new remote endpoint first waits
+    /// for the data pending for the previous one to be sent. In
+    /// [`UdpMode::Connected`], sending to anything but the connected remote
+    /// endpoint fails with [`SendError::WrongEndpoint`].
+    ///
+    /// Fails with [`SendError::ConnectFailed`] if the module rejected the
+    /// peer of the previous remote endpoint with data still pending for it,
+    /// or rejects the peer of `remote`.
+    pub async fn send_to(&mut self, buf: &[u8], remote: SocketAddr) -> Result<(), SendError> {
+        if self.mode == UdpMode::Datagram && self.with(|s| s.endpoint.is_some_and(|e| e != remote))
+        {
+            self.flush().await?;
+        }
+        self.connect(remote)?;
+        self.send(buf).await
+    }
+
+    /// Send a datagram to the specified remote endpoint.
+    ///
+    /// When the send buffer has no room for the whole datagram, or the data
+    /// pending for the previous remote endpoint is not sent yet, this method
+    /// returns `Poll::Pending` and registers the current task to be notified
+    /// when data was handed to the module, see [`send_to`](Self::send_to).
+    ///
+    /// Unlike [`send_to`](Self::send_to), a datagram is never enqueued in
+    /// parts, and fails with [`SendError::PacketTooLarge`] if it does not fit
+    /// the send buffer.
+    pub fn poll_send_to(
+        &mut self,
+        buf: &[u8],
+        remote: SocketAddr,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), SendError>> {
+        if self.mode == UdpMode::Datagram && self.with(|s| s.endpoint.is_some_and(|e| e != remote))
+        {
+            match self.poll_flush(cx) {
+                Poll::Ready(Ok(())) => {}
+                res => return res,
+            }
+        }
+        self.connect(remote)?;
+        self.connect_result()?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        if buf.len() > self.send_capacity {
+            return Poll::Ready(Err(SendError::PacketTooLarge));
+        }
+        if !self.stack.borrow().link_up {
+            return Poll::Ready(Err(SendError::NoRoute));
+        }
+
+        let send_capacity = self.send_capacity;
+        self.with_mut(|s| {
+            if send_capacity - s.send_queue() < buf.len() {
+                s.register_send_waker(cx.waker());
+                return Poll::Pending;
+            }
+            match s.send_slice(buf) {
+                Ok(_) => Poll::Ready(Ok(())),
+                Err(_) => Poll::Ready(Err(SendError::NoRoute)),
+            }
+        })
+    }
+
+    /// Receive a datagram.
+    ///
+    /// This method will wait until data is received. Data is only ever
+    /// received from the remote endpoint the socket is connected to, which is
+    /// returned along with the number of bytes received.
+    ///
+    /// Fails with [`RecvError::Truncated`] if the datagram is larger than
+    /// `buf`, including an empty `buf`, dropping the datagram.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, SocketAddr), RecvError> {
+        poll_fn(move |cx| self.poll_recv_from(buf, cx)).await
+    }
+
+    /// Receive a datagram.
+    ///
+    /// When no data is available, this method returns `Poll::Pending` and
+    /// registers the current task to be notified when data is received, see
+    /// [`recv_from`](Self::recv_from).
+    pub fn poll_recv_from(
+        &self,
+        buf: &mut [u8],
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(usize, SocketAddr), RecvError>> {
+        let SocketStack {
+            sockets,
+            udp_datagrams,
+            waker,
+            ..
+        } = &mut *self.stack.borrow_mut();
+        let udp = sockets.get_mut::<udp::Socket>(self.handle);
+        let Some(endpoint) = udp.endpoint.filter(|_| udp.can_recv()) else {
+            // Lengths of datagrams no longer buffered, e.g. after a close
+            if let Some(lengths) = udp_datagrams.get_mut(&self.handle) {
+                lengths.clear();
+            }
+            udp.register_recv_waker(cx.waker());
+            return Poll::Pending;
+        };
+
+        // Data without a recorded length is taken as a single datagram
+        let len = udp_datagrams
+            .get_mut(&self.handle)
+            .and_then(|lengths| lengths.pop_front())
+            .unwrap_or(buf.len());
+        let n = udp.recv_slice(&mut buf[..len.min(buf.len())]).unwrap_or(0);
+
+        // Drop the rest of a datagram larger than `buf`
+        let mut rest = len - n;
+        let mut scratch = [0u8; 32];
+        while rest > 0 {
+            match udp.recv_slice(&mut scratch[..rest.min(scratch.len())]) {
+                Ok(m) if m > 0 => rest -= m,
+                _ => break,
+            }
+        }
+        waker.wake();
+
+        if len > buf.len() {
+            Poll::Ready(Err(RecvError::Truncated))
+        } else {
+            Poll::Ready(Ok((n, endpoint)))
+        }
+    }
+
+    /// Wait until the send buffer is empty, including while the stack opens
+    /// the peer of the remote endpoint.
+    async fn flush(&self) -> Result<(), SendError> {
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    /// Poll variant of [`flush`](Self::flush).
+    fn poll_flush(&self, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        self.connect_result()?;
+        let s = &mut *self.stack.borrow_mut();
+        // Bound sockets do not open peers of their own
+        let bound = s.udp_listeners.contains_key(&self.handle);
+        let udp = s.sockets.get_mut::<udp::Socket>(self.handle);
+        if udp.send_queue() > 0 && (udp.edm_channel.is_some() || (udp.endpoint.is_some() && !bound))
+        {
+            udp.register_send_waker(cx.waker());
+            Poll::Pending
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Fails with [`SendError::ConnectFailed`] if the module rejected the
+    /// peer of the remote endpoint.
+    fn connect_result(&self) -> Result<(), SendError> {
+        match self
+            .stack
+            .borrow()
+            .udp_connect_failed
+            .contains(&self.handle)
+        {
+            true => Err(SendError::ConnectFailed),
+            false => Ok(()),
+        }
+    }
+
+    /// Close the peer of the current remote endpoint, and point the socket to
+    /// `remote`, for the stack to open a new peer.
+    fn retarget(&mut self, remote: SocketAddr) {
+        let peer_handle = self.with_mut(|s| {
+            s.edm_channel = None;
+            s.set_state(UdpState::Closed);
+            s.endpoint = Some(remote);
+            s.peer_handle.take()
+        });
+
+        if let Some(peer_handle) = peer_handle {
+            self.stack.borrow_mut().dropped_sockets.insert(peer_handle);
+        }
+    }
+
+    /// Bind the socket to the local `port`, to receive datagrams sent to it.
+    /// Port 0 binds the socket to a dynamic port, see
+    /// [`local_port`](Self::local_port).
+    ///
+    /// The module is configured to listen on the port in the background. The
+    /// module opens a peer for every sender, and the socket receives from and
+    /// sends to the most recent sender, as returned by
+    /// [`recv_from`](Self::recv_from). The peer of the previous sender is
+    /// closed as soon as a new sender shows up, and the peer of the most
+    /// recent sender once nothing was received from it for a minute.
+    pub fn bind(&mut self, port: u16) -> Result<(), BindError> {
+        if self.with(|s| s.endpoint.is_some() || s.state() != UdpState::Closed) {
+            return Err(BindError::InvalidState);
+        }
+
+        let s = &mut *self.stack.borrow_mut();
+        if s.udp_listeners.contains_key(&self.handle) {
+            return Err(BindError::InvalidState);
+        }
+        if s.udp_listeners.values().any(|l| l.port == port) {
+            return Err(BindError::AddrInUse);
+        }
+
+        let server_id = UDP_SERVER_IDS
+            .into_iter()
+            .find(|id| {
+                !s.udp_listeners.values().any(|l| l.server_id == *id)
+                    && !s.stopped_servers.contains(id)
+            })
+            .ok_or(BindError::NoFreeServer)?;
+        let port = if port == 0 { s.get_local_port() } else { port };
+
+        s.udp_listeners
+            .insert(
+                self.handle,
+                UdpListener {
+                    port,
+                    server_id,
+                    active: false,
+                    last_rx: Instant::now(),
+                },
+            )
+            .map_err(|_| BindError::NoFreeServer)?;
+        s.waker.wake();
+
+        Ok(())
+    }
+
+    /// Returns the local port the socket is bound to, if any.
+    pub fn local_port(&self) -> Option<u16> {
+        self.stack
+            .borrow()
+            .udp_listeners
+            .get(&self.handle)
+            .map(|l| l.port)
+    }
 
     fn with<R>(&self, f: impl FnOnce(&udp::Socket) -> R) -> R {
         let s = &*self.stack.borrow();
@@ -97,87 +439,6 @@ impl<'a> UdpSocket<'a> {
         res
     }
 
-    // /// Receive a datagram.
-    // ///
-    // /// This method will wait until a datagram is received.
-    // ///
-    // /// Returns the number of bytes received and the remote endpoint.
-    // pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), RecvError> {
-    //     poll_fn(move |cx| self.poll_recv_from(buf, cx)).await
-    // }
-
-    // /// Receive a datagram.
-    // ///
-    // /// When no datagram is available, this method will return `Poll::Pending` and
-    // /// register the current task to be notified when a datagram is received.
-    // ///
-    // /// When a datagram is received, this method will return `Poll::Ready` with the
-    // /// number of bytes received and the remote endpoint.
-    // pub fn poll_recv_from(
-    //     &self,
-    //     buf: &mut [u8],
-    //     cx: &mut Context<'_>,
-    // ) -> Poll<Result<(usize, IpEndpoint), RecvError>> {
-    //     self.with_mut(|s| match s.recv_slice(buf) {
-    //         Ok((n, meta)) => Poll::Ready(Ok((n, meta.endpoint))),
-    //         // No data ready
-    //         // Err(udp::RecvError::Truncated) => Poll::Ready(Err(RecvError::Truncated)),
-    //         // Err(udp::RecvError::Exhausted) => {
-    //         Err(_) => {
-    //             s.register_recv_waker(cx.waker());
-    //             Poll::Pending
-    //         }
-    //     })
-    // }
-
-    // /// Send a datagram to the specified remote endpoint.
-    // ///
-    // /// This method will wait until the datagram has been sent.
-    // ///
-    // /// When the remote endpoint is not reachable, this method will return `Err(SendError::NoRoute)`
-    // pub async fn send_to<T>(&self, buf: &[u8], remote_endpoint: T) -> Result<(), SendError>
-    // where
-    //     T: Into<IpEndpoint>,
-    // {
-    //     let remote_endpoint: IpEndpoint = remote_endpoint.into();
-    //     poll_fn(move |cx| self.poll_send_to(buf, remote_endpoint, cx)).await
-    // }
-
-    // /// Send a datagram to the specified remote endpoint.
-    // ///
-    // /// When the datagram has been sent, this method will return `Poll::Ready(Ok())`.
-    // ///
-    // /// When the socket's send buffer is full, this method will return `Poll::Pending`
-    // /// and register the current task to be notified when the buffer has space available.
-    // ///
-    // /// When the remote endpoint is not reachable, this method will return `Poll::Ready(Err(Error::NoRoute))`.
-    // pub fn poll_send_to<T>(
-    //     &self,
-    //     buf: &[u8],
-    //     remote_endpoint: T,
-    //     cx: &mut Context<'_>,
-    // ) -> Poll<Result<(), SendError>>
-    // where
-    //     T: Into<IpEndpoint>,
-    // {
-    //     self.with_mut(|s| match s.send_slice(buf, remote_endpoint) {
-    //         // Entire datagram has been sent
-    //         Ok(()) => Poll::Ready(Ok(())),
-    //         Err(udp::SendError::BufferFull) => {
-    //             s.register_send_waker(cx.waker());
-    //             Poll::Pending
-    //         }
-    //         Err(udp::SendError::Unaddressable) => {
-    //             // If no sender/outgoing port is specified, there is not really "no route"
-    //             if s.endpoint().port == 0 {
-    //                 Poll::Ready(Err(SendError::SocketNotBound))
-    //             } else {
-    //                 Poll::Ready(Err(SendError::NoRoute))
-    //             }
-    //         }
-    //     })
-    // }
-
     /// Returns the local endpoint of the socket.
     pub fn endpoint(&self) -> Option<SocketAddr> {
         self.with(|s| s.endpoint())
@@ -231,17 +492,425 @@ impl<'a> UdpSocket<'a> {
 
 impl<'a> Drop for UdpSocket<'a> {
     fn drop(&mut self) {
-        if matches!(self.with(|s| s.state()), UdpState::Established) {
-            if let Some(peer_handle) = self.with(|s| s.peer_handle) {
-                self.stack
-                    .borrow_mut()
-                    .dropped_sockets
-                    .push(peer_handle)
-                    .ok();
-            }
+        // Also the peer of a sender to a bound socket, which may not be
+        // established yet
+        if let Some(peer_handle) = self.with(|s| s.peer_handle) {
+            self.stack.borrow_mut().dropped_sockets.insert(peer_handle);
         }
+        trace_transition(
+            SocketTransition::Drop,
+            Some(self.handle),
+            self.with(|s| s.peer_handle),
+            self.with(|s| s.edm_channel),
+            None,
+        );
         let mut stack = self.stack.borrow_mut();
+        if let Some(listener) = stack.udp_listeners.remove(&self.handle) {
+            stack.stopped_servers.push(listener.server_id).ok();
+        }
+        stack.udp_connect_failed.remove(&self.handle);
+        stack.udp_datagrams.remove(&self.handle);
+        stack.remove_socket_id(self.handle);
         stack.sockets.remove(self.handle);
         stack.waker.wake();
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::pin::pin;
+    use ublox_sockets::PeerHandle;
+
+    use super::super::fixture::{self, socket_stack};
+    use super::super::TxEvent;
+
+    type Stack = UbloxStack<256, 8>;
+
+    fn udp_socket(stack: &RefCell<SocketStack>) -> UdpSocket<'_> {
+        let handle = {
+            let s = &mut *stack.borrow_mut();
+            let handle = fixture::udp_socket(s);
+            s.add_socket_id(handle);
+            s.set_link_up(true);
+            handle
+        };
+        UdpSocket {
+            stack,
+            handle,
+            mode: UdpMode::default(),
+            // As created by `fixture::udp_socket`
+            send_capacity: 16,
+        }
+    }
+
+    /// Let the stack issue the connect of `socket`, and answer it with
+    /// `peer_handle`, or reject it if `None`.
+    fn connect_response(socket: &UdpSocket<'_>, peer_handle: Option<PeerHandle>) {
+        let mut buf = [0u8; 128];
+        let Some(TxEvent::Connect {
+            socket_handle,
+            socket_id,
+            ..
+        }) = Stack::tx_event(socket.stack, &mut buf)
+        else {
+            panic!("No connect issued");
+        };
+        socket
+            .stack
+            .borrow_mut()
+            .connect_response(socket_handle, socket_id, peer_handle);
+    }
+
+    #[test]
+    fn connect_rejected() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut socket = udp_socket(&stack);
+        let remote = "192.168.0.2:5000".parse().unwrap();
+
+        socket.connect(remote).unwrap();
+        assert_eq!(
+            embassy_futures::poll_once(pin!(socket.send(b"hello"))),
+            Poll::Ready(Ok(()))
+        );
+        connect_response(&socket, None);
+
+        // The connect is not retried, and the data is dropped
+        assert!(Stack::tx_event(&stack, &mut [0u8; 128]).is_none());
+        assert_eq!(socket.with(|s| s.send_queue()), 0);
+        assert_eq!(
+            embassy_futures::poll_once(pin!(socket.send(b"hello"))),
+            Poll::Ready(Err(SendError::ConnectFailed))
+        );
+
+        // Connecting again starts over
+        socket.connect(remote).unwrap();
+        connect_response(&socket, Some(PeerHandle(1)));
+        assert_eq!(
+            embassy_futures::poll_once(pin!(socket.send(b"hello"))),
+            Poll::Ready(Ok(()))
+        );
+    }
+
+    #[test]
+    fn send_to_waits_for_rejected_peer() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut socket = udp_socket(&stack);
+        socket.set_mode(UdpMode::Datagram);
+        let first = "192.168.0.2:5000".parse().unwrap();
+        let second = "192.168.0.3:5000".parse().unwrap();
+
+        assert_eq!(
+            embassy_futures::poll_once(pin!(socket.send_to(b"hello", first))),
+            Poll::Ready(Ok(()))
+        );
+
+        {
+            // The data for the first endpoint is pending while its peer is
+            // opened
+            let mut send_to = pin!(socket.send_to(b"world", second));
+            assert!(embassy_futures::poll_once(send_to.as_mut()).is_pending());
+
+            let mut buf = [0u8; 128];
+            let Some(TxEvent::Connect {
+                socket_handle,
+                socket_id,
+                ..
+            }) = Stack::tx_event(&stack, &mut buf)
+            else {
+                panic!("No connect issued");
+            };
+            stack
+                .borrow_mut()
+                .connect_response(socket_handle, socket_id, None);
+
+            assert_eq!(
+                embassy_futures::poll_once(send_to.as_mut()),
+                Poll::Ready(Err(SendError::ConnectFailed))
+            );
+        }
+
+        // Nothing is sent to the second endpoint until asked again
+        assert!(Stack::tx_event(&stack, &mut [0u8; 128]).is_none());
+        assert_eq!(
+            embassy_futures::poll_once(pin!(socket.send_to(b"world", second))),
+            Poll::Ready(Ok(()))
+        );
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut [0u8; 128]),
+            Some(TxEvent::Connect { .. })
+        ));
+    }
+
+    /// The peer the module opens for a sender to the local `port`.
+    fn sender_connected(stack: &RefCell<SocketStack>, peer_handle: u8, port: u16) {
+        use crate::command::data_mode::types::{ConnectionType, IPProtocol};
+        use crate::command::data_mode::urc::PeerConnected;
+        use crate::command::edm::urc::EdmEvent;
+        use crate::command::Urc;
+        use atat::heapless_bytes::Bytes;
+
+        Stack::socket_rx(
+            EdmEvent::ATEvent(Urc::PeerConnected(PeerConnected {
+                handle: PeerHandle(peer_handle),
+                connection_type: ConnectionType::IPv4,
+                protocol: IPProtocol::UDP,
+                local_address: Bytes::from_slice(b"192.168.0.1").unwrap(),
+                local_port: port,
+                remote_address: Bytes::from_slice(b"192.168.0.2").unwrap(),
+                remote_port: 5000 + u16::from(peer_handle),
+            })),
+            stack,
+        );
+    }
+
+    #[test]
+    fn bind_and_rebind() {
+        let stack = RefCell::new(socket_stack::<2>());
+
+        let mut first = udp_socket(&stack);
+        first.bind(4000).unwrap();
+        assert_eq!(first.bind(4001), Err(BindError::InvalidState));
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut [0u8; 128]),
+            Some(TxEvent::Listen {
+                server_id: 5,
+                port: Some(4000)
+            })
+        ));
+
+        let mut second = udp_socket(&stack);
+        assert_eq!(second.bind(4000), Err(BindError::AddrInUse));
+
+        // The server of a dropped socket is disabled, and the port can be
+        // bound again
+        drop(first);
+        second.bind(4000).unwrap();
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut [0u8; 128]),
+            Some(TxEvent::Listen {
+                server_id: 5,
+                port: None
+            })
+        ));
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut [0u8; 128]),
+            Some(TxEvent::Listen {
+                server_id: 6,
+                port: Some(4000)
+            })
+        ));
+        assert!(Stack::tx_event(&stack, &mut [0u8; 128]).is_none());
+        assert_eq!(second.local_port(), Some(4000));
+    }
+
+    #[test]
+    fn listen_failure_retried() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut socket = udp_socket(&stack);
+        socket.bind(4000).unwrap();
+
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut [0u8; 128]),
+            Some(TxEvent::Listen {
+                server_id: 5,
+                port: Some(4000)
+            })
+        ));
+        assert!(Stack::tx_event(&stack, &mut [0u8; 128]).is_none());
+
+        stack.borrow_mut().listen_failed(5);
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut [0u8; 128]),
+            Some(TxEvent::Listen {
+                server_id: 5,
+                port: Some(4000)
+            })
+        ));
+    }
+
+    #[test]
+    fn poll_recv_from_sender() {
+        use crate::command::edm::types::{DataEvent, IPv4ConnectEvent, Protocol};
+        use crate::command::edm::urc::EdmEvent;
+        use ublox_sockets::ChannelId;
+
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut socket = udp_socket(&stack);
+        socket.bind(4000).unwrap();
+        Stack::tx_event(&stack, &mut [0u8; 128]);
+
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+        let mut buf = [0u8; 16];
+        assert_eq!(socket.poll_recv_from(&mut buf, &mut cx), Poll::Pending);
+
+        // Data of a sender to the bound port
+        Stack::socket_rx(
+            EdmEvent::IPv4ConnectEvent(IPv4ConnectEvent {
+                channel_id: ChannelId(1),
+                protocol: Protocol::UDP,
+                remote_ip: "192.168.0.2".parse().unwrap(),
+                remote_port: 5000,
+                local_ip: "192.168.0.1".parse().unwrap(),
+                local_port: 4000,
+            }),
+            &stack,
+        );
+        assert_eq!(socket.poll_recv_from(&mut buf, &mut cx), Poll::Pending);
+        Stack::socket_rx(
+            EdmEvent::DataEvent(DataEvent {
+                channel_id: ChannelId(1),
+                data: heapless::Vec::from_slice(b"hello").unwrap(),
+            }),
+            &stack,
+        );
+
+        assert_eq!(
+            socket.poll_recv_from(&mut buf, &mut cx),
+            Poll::Ready(Ok((5, "192.168.0.2:5000".parse().unwrap())))
+        );
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(socket.poll_recv_from(&mut buf, &mut cx), Poll::Pending);
+    }
+
+    #[test]
+    fn poll_recv_from_truncated() {
+        use crate::command::edm::types::{DataEvent, IPv4ConnectEvent, Protocol};
+        use crate::command::edm::urc::EdmEvent;
+        use ublox_sockets::ChannelId;
+
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut socket = udp_socket(&stack);
+        socket.bind(4000).unwrap();
+        Stack::tx_event(&stack, &mut [0u8; 128]);
+
+        Stack::socket_rx(
+            EdmEvent::IPv4ConnectEvent(IPv4ConnectEvent {
+                channel_id: ChannelId(1),
+                protocol: Protocol::UDP,
+                remote_ip: "192.168.0.2".parse().unwrap(),
+                remote_port: 5000,
+                local_ip: "192.168.0.1".parse().unwrap(),
+                local_port: 4000,
+            }),
+            &stack,
+        );
+        for data in [&b"hello"[..], b"world!", b"x"] {
+            Stack::socket_rx(
+                EdmEvent::DataEvent(DataEvent {
+                    channel_id: ChannelId(1),
+                    data: heapless::Vec::from_slice(data).unwrap(),
+                }),
+                &stack,
+            );
+        }
+
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+        let mut buf = [0u8; 16];
+        assert_eq!(
+            socket.poll_recv_from(&mut buf[..4], &mut cx),
+            Poll::Ready(Err(RecvError::Truncated))
+        );
+
+        // The rest of the truncated datagram is dropped
+        assert_eq!(
+            socket.poll_recv_from(&mut buf, &mut cx),
+            Poll::Ready(Ok((6, "192.168.0.2:5000".parse().unwrap())))
+        );
+        assert_eq!(&buf[..6], b"world!");
+
+        assert_eq!(
+            socket.poll_recv_from(&mut [], &mut cx),
+            Poll::Ready(Err(RecvError::Truncated))
+        );
+        assert_eq!(socket.poll_recv_from(&mut buf, &mut cx), Poll::Pending);
+    }
+
+    #[test]
+    fn poll_send_to_whole_datagrams() {
+        use crate::command::edm::types::{IPv4ConnectEvent, Protocol};
+        use crate::command::edm::urc::EdmEvent;
+        use ublox_sockets::ChannelId;
+
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut socket = udp_socket(&stack);
+        let remote = "192.168.0.2:5000".parse().unwrap();
+        let mut cx = Context::from_waker(futures_util::task::noop_waker_ref());
+
+        assert_eq!(
+            socket.poll_send_to(b"hello", remote, &mut cx),
+            Poll::Ready(Ok(()))
+        );
+        assert_eq!(
+            socket.poll_send_to(&[0x42; 17], remote, &mut cx),
+            Poll::Ready(Err(SendError::PacketTooLarge))
+        );
+        // No room for the whole datagram, nothing is enqueued
+        assert_eq!(
+            socket.poll_send_to(&[0x42; 12], remote, &mut cx),
+            Poll::Pending
+        );
+        assert_eq!(socket.with(|s| s.send_queue()), 5);
+
+        // Room once the pending datagram is handed to the module
+        connect_response(&socket, Some(PeerHandle(1)));
+        Stack::socket_rx(
+            EdmEvent::IPv4ConnectEvent(IPv4ConnectEvent {
+                channel_id: ChannelId(1),
+                protocol: Protocol::UDP,
+                remote_ip: "192.168.0.2".parse().unwrap(),
+                remote_port: 5000,
+                local_ip: "192.168.0.1".parse().unwrap(),
+                local_port: 4000,
+            }),
+            &stack,
+        );
+        assert!(matches!(
+            Stack::tx_event(&stack, &mut [0u8; 128]),
+            Some(TxEvent::Send { data, .. }) if data == b"hello"
+        ));
+        assert_eq!(
+            socket.poll_send_to(&[0x42; 12], remote, &mut cx),
+            Poll::Ready(Ok(()))
+        );
+        assert_eq!(socket.with(|s| s.send_queue()), 12);
+    }
+
+    #[test]
+    fn sender_peers_released() {
+        let stack = RefCell::new(socket_stack::<1>());
+        let mut socket = udp_socket(&stack);
+        socket.bind(4000).unwrap();
+        Stack::tx_event(&stack, &mut [0u8; 128]);
+
+        // A peer to another port is not for the socket
+        sender_connected(&stack, 1, 4001);
+        assert_eq!(socket.with(|s| s.peer_handle), None);
+
+        // The peer of the previous sender is closed right away
+        sender_connected(&stack, 2, 4000);
+        sender_connected(&stack, 3, 4000);
+        assert_eq!(socket.with(|s| s.peer_handle), Some(PeerHandle(3)));
+        assert_eq!(stack.borrow().dropped_sockets, [PeerHandle(2)]);
+
+        // The peer of the current sender once idle
+        let last_rx = stack.borrow().udp_listeners[&socket.handle].last_rx;
+        assert_eq!(stack.borrow_mut().expire_sender_peer(last_rx), None);
+        assert_eq!(
+            stack
+                .borrow_mut()
+                .expire_sender_peer(last_rx + super::super::UDP_SENDER_IDLE_TIMEOUT),
+            Some(PeerHandle(3))
+        );
+        assert_eq!(socket.with(|s| s.peer_handle), None);
+        assert!(socket.local_port().is_some());
+
+        // And on drop
+        sender_connected(&stack, 4, 4000);
+        drop(socket);
+        assert_eq!(
+            stack.borrow().dropped_sockets,
+            [PeerHandle(2), PeerHandle(4)]
+        );
+    }
+}
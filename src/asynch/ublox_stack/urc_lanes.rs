@@ -0,0 +1,257 @@
+//! Dispatch of URCs to the network stack, giving control events priority
+//! over socket data.
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::pubsub::{Subscriber, WaitResult};
+
+use crate::asynch::state;
+use crate::command::edm::urc::EdmEvent;
+use crate::command::Urc;
+
+/// Two subscriptions to the URC channel, one for control events and one for
+/// socket data.
+///
+/// Both lanes see every URC, and skip those of the other lane. Control
+/// events, such as connects, disconnects and link changes, are dispatched as
+/// soon as they arrive, ahead of a backlog of data events, so the stack
+/// learns about a lost link without first working through the data of a
+/// burst.
+///
+/// Disconnects never overtake data: a disconnect is held back until all data
+/// received before it is dispatched, and the data lane does not pass a held
+/// back disconnect. This keeps the data of every channel ahead of its close,
+/// at the cost of also delaying the disconnect behind the data of other
+/// channels.
+pub(crate) struct UrcLanes<'a, M: RawMutex, const CAP: usize, const SUBS: usize, const PUBS: usize>
+{
+    control: Subscriber<'a, M, EdmEvent, CAP, SUBS, PUBS>,
+    data: Subscriber<'a, M, EdmEvent, CAP, SUBS, PUBS>,
+    /// Next control event, along with its position in the URC stream.
+    pending: Option<(u64, EdmEvent)>,
+    /// Position in the URC stream of the next URC of each lane.
+    control_pos: u64,
+    data_pos: u64,
+}
+
+impl<'a, M: RawMutex, const CAP: usize, const SUBS: usize, const PUBS: usize>
+    UrcLanes<'a, M, CAP, SUBS, PUBS>
+{
+    pub(crate) fn new(
+        control: Subscriber<'a, M, EdmEvent, CAP, SUBS, PUBS>,
+        data: Subscriber<'a, M, EdmEvent, CAP, SUBS, PUBS>,
+    ) -> Self {
+        Self {
+            control,
+            data,
+            pending: None,
+            control_pos: 0,
+            data_pos: 0,
+        }
+    }
+
+    /// Wait for the next URC to dispatch, recording the backlog of data and
+    /// any lost URCs in the statistics of `ch`.
+    pub(crate) async fn next(&mut self, ch: &state::Runner<'_>) -> EdmEvent {
+        loop {
+            if let Some(event) = self.try_next(ch) {
+                return event;
+            }
+
+            // Nothing to dispatch, wait for the next URC. The data lane sees
+            // every URC, so control events wake it too.
+            let result = self.data.next_message().await;
+            if let Some(event) = self.on_data(ch, result) {
+                return event;
+            }
+        }
+    }
+
+    fn try_next(&mut self, ch: &state::Runner<'_>) -> Option<EdmEvent> {
+        if self.pending.is_none() {
+            while let Some(result) = self.control.try_next_message() {
+                match result {
+                    // Recorded by the data lane, which loses the same URCs
+                    WaitResult::Lagged(n) => self.control_pos += n,
+                    WaitResult::Message(event) => {
+                        self.control_pos += 1;
+                        if !is_data(&event) {
+                            self.pending = Some((self.control_pos - 1, event));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        loop {
+            match &self.pending {
+                Some((pos, event)) if !is_close(event) || self.data_pos > *pos => {
+                    return self.pending.take().map(|(_, event)| event);
+                }
+                _ => {}
+            }
+
+            let result = self.data.try_next_message()?;
+            if let Some(event) = self.on_data(ch, result) {
+                return Some(event);
+            }
+        }
+    }
+
+    fn on_data(
+        &mut self,
+        ch: &state::Runner<'_>,
+        result: WaitResult<EdmEvent>,
+    ) -> Option<EdmEvent> {
+        match result {
+            WaitResult::Lagged(n) => {
+                warn!("URC channel full, lost {} URCs", n);
+                ch.record_urc_lost(n);
+                self.data_pos += n;
+                None
+            }
+            WaitResult::Message(event) => {
+                self.data_pos += 1;
+                if is_data(&event) {
+                    ch.record_urc_backlog(self.data.available() as usize + 1);
+                    Some(event)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+fn is_data(event: &EdmEvent) -> bool {
+    matches!(event, EdmEvent::DataEvent(_))
+}
+
+/// Events closing a channel, which must not overtake the data of the channel.
+fn is_close(event: &EdmEvent) -> bool {
+    matches!(
+        event,
+        EdmEvent::DisconnectEvent(_) | EdmEvent::ATEvent(Urc::PeerDisconnected(_))
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::edm::types::DataEvent;
+    use crate::command::network::urc::{NetworkDown, NetworkUp};
+    use embassy_futures::block_on;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::pubsub::PubSubChannel;
+    use ublox_sockets::ChannelId;
+
+    fn data(channel_id: u8, data: &[u8]) -> EdmEvent {
+        EdmEvent::DataEvent(DataEvent {
+            channel_id: ChannelId(channel_id),
+            data: heapless::Vec::from_slice(data).unwrap(),
+        })
+    }
+
+    #[test]
+    fn close_does_not_overtake_data() {
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+
+        let channel = PubSubChannel::<NoopRawMutex, EdmEvent, 8, 2, 1>::new();
+        let publisher = channel.publisher().unwrap();
+        let mut lanes = UrcLanes::new(channel.subscriber().unwrap(), channel.subscriber().unwrap());
+
+        publisher.publish_immediate(data(1, b"a"));
+        publisher.publish_immediate(data(2, b"b"));
+        publisher.publish_immediate(EdmEvent::DisconnectEvent(ChannelId(1)));
+        publisher.publish_immediate(EdmEvent::ATEvent(Urc::NetworkUp(NetworkUp {
+            interface_id: 0,
+        })));
+        publisher.publish_immediate(data(1, b"c"));
+
+        let mut next = || block_on(lanes.next(&ch));
+        assert!(matches!(next(), EdmEvent::DataEvent(ev) if ev.data == b"a"[..]));
+        assert!(matches!(next(), EdmEvent::DataEvent(ev) if ev.data == b"b"[..]));
+        assert!(matches!(next(), EdmEvent::DisconnectEvent(ChannelId(1))));
+        // Control events behind the close are not overtaken by data either
+        assert!(matches!(next(), EdmEvent::ATEvent(Urc::NetworkUp(_))));
+        assert!(matches!(next(), EdmEvent::DataEvent(ev) if ev.data == b"c"[..]));
+        assert_eq!(ch.urc_stats().lost, 0);
+    }
+
+    #[test]
+    fn control_overtakes_data() {
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+
+        let channel = PubSubChannel::<NoopRawMutex, EdmEvent, 8, 2, 1>::new();
+        let publisher = channel.publisher().unwrap();
+        let mut lanes = UrcLanes::new(channel.subscriber().unwrap(), channel.subscriber().unwrap());
+
+        publisher.publish_immediate(data(1, b"a"));
+        publisher.publish_immediate(data(1, b"b"));
+        publisher.publish_immediate(EdmEvent::ATEvent(Urc::NetworkUp(NetworkUp {
+            interface_id: 0,
+        })));
+
+        let mut next = || block_on(lanes.next(&ch));
+        assert!(matches!(next(), EdmEvent::ATEvent(Urc::NetworkUp(_))));
+        assert!(matches!(next(), EdmEvent::DataEvent(ev) if ev.data == b"a"[..]));
+        assert!(matches!(next(), EdmEvent::DataEvent(ev) if ev.data == b"b"[..]));
+        assert_eq!(ch.urc_stats().high_water, 3);
+    }
+
+    fn network_down() -> EdmEvent {
+        EdmEvent::ATEvent(Urc::NetworkDown(NetworkDown { interface_id: 0 }))
+    }
+
+    #[test]
+    fn link_loss_overtakes_data_burst() {
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+
+        let channel = PubSubChannel::<NoopRawMutex, EdmEvent, 128, 2, 1>::new();
+        let publisher = channel.publisher().unwrap();
+        let mut lanes = UrcLanes::new(channel.subscriber().unwrap(), channel.subscriber().unwrap());
+
+        for i in 0..100 {
+            publisher.publish_immediate(data(1, &[i]));
+        }
+        publisher.publish_immediate(network_down());
+
+        let mut next = || block_on(lanes.next(&ch));
+        assert!(matches!(next(), EdmEvent::ATEvent(Urc::NetworkDown(_))));
+        for i in 0u8..100 {
+            assert!(matches!(next(), EdmEvent::DataEvent(ev) if ev.data == [i][..]));
+        }
+        assert_eq!(ch.urc_stats().lost, 0);
+        assert_eq!(ch.urc_stats().high_water, 101);
+    }
+
+    #[test]
+    fn link_loss_overtakes_full_data_lane() {
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+
+        let channel = PubSubChannel::<NoopRawMutex, EdmEvent, 8, 2, 1>::new();
+        let publisher = channel.publisher().unwrap();
+        let mut lanes = UrcLanes::new(channel.subscriber().unwrap(), channel.subscriber().unwrap());
+
+        // The burst overflows the channel, and the link loss pushes out
+        // another data event
+        for i in 0..100 {
+            publisher.publish_immediate(data(1, &[i]));
+        }
+        publisher.publish_immediate(network_down());
+
+        let mut next = || block_on(lanes.next(&ch));
+        assert!(matches!(next(), EdmEvent::ATEvent(Urc::NetworkDown(_))));
+        for i in 93u8..100 {
+            assert!(matches!(next(), EdmEvent::DataEvent(ev) if ev.data == [i][..]));
+        }
+        assert_eq!(ch.urc_stats().lost, 93);
+
+        // Nothing is left, or dispatched twice
+        let mut pending = core::pin::pin!(lanes.next(&ch));
+        assert!(embassy_futures::poll_once(pending.as_mut()).is_pending());
+    }
+}
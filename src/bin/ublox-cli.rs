@@ -0,0 +1,121 @@
+//! Host tool for exercising a module over a serial port.
+//!
+//! ```text
+//! ublox-cli <port> [--baud <rate>] <command>
+//! ```
+//!
+//! Build with `cargo run --features tools,std-time,<module> --bin ublox-cli`.
+use std::process::ExitCode;
+
+use embassy_time::Duration;
+use ublox_short_range::{
+    command::{security::types::SecurityDataType, wifi::types::WifiStatusVal},
+    tools::{SerialTransport, Session},
+    DEFAULT_BAUD_RATE,
+};
+
+const USAGE: &str = "\
+usage: ublox-cli <port> [--baud <rate>] <command>
+
+commands:
+    scan                                 scan for networks
+    status                               show firmware version and station status
+    import-cert <ca|cert|key> <name> <file>
+                                         import a certificate or private key
+    raw-at <line>                        send a raw AT command line
+    monitor-urcs                         print URCs as they arrive";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match embassy_futures::block_on(run(&args)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: &[String]) -> Result<(), String> {
+    let Some((port, rest)) = args.split_first() else {
+        return Err(USAGE.into());
+    };
+
+    let (baud_rate, command) = match rest {
+        [flag, rate, command @ ..] if flag == "--baud" => (
+            rate.parse()
+                .map_err(|_| format!("invalid baud rate: {}", rate))?,
+            command,
+        ),
+        command => (DEFAULT_BAUD_RATE as u32, command),
+    };
+
+    let transport = SerialTransport::open(port, baud_rate, false)
+        .map_err(|e| format!("failed to open {}: {}", port, e))?;
+    let mut session = Session::new(transport);
+
+    let version = session
+        .init()
+        .await
+        .map_err(|e| format!("module did not respond: {:?}", e))?;
+
+    match command {
+        [cmd] if cmd == "scan" => {
+            let networks = session.scan().await.map_err(|e| format!("{:?}", e))?;
+            println!("{:<32} {:>7} {:>5}  BSSID", "SSID", "CHANNEL", "RSSI");
+            for network in networks {
+                println!(
+                    "{:<32} {:>7} {:>5}  {}",
+                    network.ssid, network.channel, network.rssi, network.bssid
+                );
+            }
+        }
+        [cmd] if cmd == "status" => {
+            let status = session
+                .wifi_status()
+                .await
+                .map_err(|e| format!("{:?}", e))?;
+            println!("firmware: {:?}", version);
+            println!(
+                "station:  {}",
+                match status {
+                    WifiStatusVal::Disabled => "disabled",
+                    WifiStatusVal::Disconnected => "disconnected",
+                    WifiStatusVal::Connected => "connected",
+                }
+            );
+        }
+        [cmd, data_type, name, file] if cmd == "import-cert" => {
+            let data_type = match data_type.as_str() {
+                "ca" => SecurityDataType::TrustedRootCA,
+                "cert" => SecurityDataType::ClientCertificate,
+                "key" => SecurityDataType::ClientPrivateKey,
+                _ => return Err(format!("invalid credential type: {}", data_type)),
+            };
+            let data =
+                std::fs::read(file).map_err(|e| format!("failed to read {}: {}", file, e))?;
+
+            session
+                .import_credentials(data_type, name, &data)
+                .await
+                .map_err(|e| format!("{:?}", e))?;
+            println!("imported {} bytes as {}", data.len(), name);
+        }
+        [cmd, line] if cmd == "raw-at" => {
+            let response = session
+                .raw_at(line, Duration::from_secs(10))
+                .await
+                .map_err(|e| format!("{:?}", e))?;
+            println!("{}", String::from_utf8_lossy(&response));
+            println!("OK");
+        }
+        [cmd] if cmd == "monitor-urcs" => loop {
+            let urc = session.next_urc().await.map_err(|e| format!("{:?}", e))?;
+            println!("{:?}", urc);
+        },
+        _ => return Err(USAGE.into()),
+    }
+
+    Ok(())
+}
@@ -4,7 +4,7 @@ use crate::command::edm::{
 };
 use atat::{helpers::LossyStr, DigestResult, Digester, InternalError};
 
-use super::edm::types::{AUTOCONNECTMESSAGE, STARTUPMESSAGE};
+use super::edm::types::STARTUPMESSAGE;
 
 /// Digester for EDM context
 #[derive(Debug, Default)]
@@ -35,7 +35,10 @@ impl Digester for EdmDigester {
                         DigestResult::Urc(&buf[..STARTUPMESSAGE.len()]),
                         STARTUPMESSAGE.len(),
                     );
-                } else if len == AUTOCONNECTMESSAGE.len() || len == AUTOCONNECTMESSAGE.len() + 1 {
+                } else if buf[2] == b'+' {
+                    // AT mode URCs (e.g. autoconnect events) can interleave
+                    // with the switch to EDM. Pass them on rather than
+                    // letting them get in the way of the `StartEvent`.
                     return (DigestResult::Urc(&buf[..len]), len);
                 } else {
                     return (DigestResult::None, len);
@@ -106,6 +109,82 @@ impl Digester for EdmDigester {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::{
+        edm::{edm_frame, urc::EdmEvent, EdmAtCmdWrapper, SwitchToEdmCommand},
+        network::urc::NetworkUp,
+        system::{types::EchoOn, SetEcho},
+        Urc,
+    };
+    use atat::{AtatCmd, AtatUrc};
+
+    /// Switching to EDM right after boot, while the module is autoconnecting
+    /// to a stored network.
+    #[test]
+    fn enter_edm_with_interleaved_autoconnect() {
+        let capture: &[u8] =
+            b"\r\n+UUWLE:0,D4CA6DF5F2F0,1\r\n\r\nOK\r\n\r\n+UUNU:0\r\n\xAA\x00\x02\x00\x71\x55";
+        let mut digester = EdmDigester::new();
+        let mut buf = capture;
+        let mut urcs = 0;
+
+        let response = loop {
+            let (res, consumed) = digester.digest(buf);
+            assert_ne!(consumed, 0, "digester stalled on {:?}", LossyStr(buf));
+            buf = &buf[consumed..];
+            match res {
+                DigestResult::Urc(urc) => {
+                    assert!(matches!(EdmEvent::parse(urc), Some(EdmEvent::ATEvent(_))));
+                    urcs += 1;
+                }
+                DigestResult::Response(resp) => break resp,
+                _ => {}
+            }
+        };
+
+        assert_eq!(urcs, 2);
+        assert!(buf.is_empty());
+        assert!(SwitchToEdmCommand.parse(response).is_ok());
+    }
+
+    /// Echo is disabled during initialization, as the digester does not
+    /// handle it. Should the module still echo a command line, the echoed
+    /// bytes are skipped rather than parsed as part of the response.
+    #[test]
+    fn echoed_command_is_skipped() {
+        let mut packet = [0u8; 16];
+        let len = edm_frame(PayloadType::ATConfirmation, b"\r\nOK\r\n", &mut packet);
+        let mut capture = b"ATE0\r\n".to_vec();
+        capture.extend_from_slice(&packet[..len]);
+
+        let mut digester = EdmDigester::new();
+        let (res, consumed) = digester.digest(&capture);
+        assert!(matches!(res, DigestResult::None));
+        assert_eq!(consumed, b"ATE0\r\n".len());
+
+        let (res, consumed) = digester.digest(&capture[consumed..]);
+        assert_eq!(consumed, len);
+        let DigestResult::Response(response) = res else {
+            panic!("no response digested");
+        };
+        assert!(EdmAtCmdWrapper(SetEcho { on: EchoOn::Off })
+            .parse(response)
+            .is_ok());
+    }
+
+    #[test]
+    fn parse_at_mode_urc() {
+        assert_eq!(
+            EdmEvent::parse(b"\r\n+UUNU:0\r\n"),
+            Some(EdmEvent::ATEvent(Urc::NetworkUp(NetworkUp {
+                interface_id: 0
+            })))
+        );
+    }
+}
+
 // #[cfg(test)]
 // mod test {
 //     use super::*;
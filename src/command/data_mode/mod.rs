@@ -168,3 +168,30 @@ pub struct SoftwareUpdate {
     #[at_arg(position = 1)]
     pub channel_id: u8,
 }
+
+#[cfg(all(test, feature = "internal-network-stack"))]
+mod test {
+    use super::*;
+    use atat::AtatCmd;
+    use ublox_sockets::PeerHandle;
+
+    /// The `+UDLP` response the half-open check and the attach rely on. The
+    /// EDM framed response is covered by `attach_through_peer_list` of the
+    /// network stack.
+    #[test]
+    fn peer_list() {
+        let response = PeerList
+            .parse(Ok(
+                b"+UDLP:1,\"tcp\",\"192.168.0.10:49153\",\"192.168.0.2:5001\"\r\n\
+                        +UDLP:3,\"udp\",\"192.168.0.10:49154\",\"192.168.0.2:5003\"",
+            ))
+            .unwrap();
+
+        assert_eq!(response.peers.len(), 2);
+        assert_eq!(response.peers[0].peer_handle, PeerHandle(1));
+        assert_eq!(response.peers[0].protocol, "tcp");
+        assert_eq!(response.peers[0].remote_address, "192.168.0.2:5001");
+        assert_eq!(response.peers[1].peer_handle, PeerHandle(3));
+        assert_eq!(response.peers[1].local_address, "192.168.0.10:49154");
+    }
+}
@@ -14,13 +14,7 @@ pub struct ConnectPeerResponse {
 #[derive(Clone, AtatResp)]
 pub struct PeerListResponse {
     #[at_arg(position = 0)]
-    pub peer_handle: ublox_sockets::PeerHandle,
-    #[at_arg(position = 1)]
-    pub protocol: heapless::String<64>,
-    #[at_arg(position = 2)]
-    pub local_address: heapless::String<64>,
-    #[at_arg(position = 3)]
-    pub remote_address: heapless::String<64>,
+    pub peers: heapless::Vec<super::types::PeerListEntry, 8>,
 }
 
 /// 5.12 Bind +UDBIND
@@ -254,3 +254,13 @@ pub enum IPProtocol {
     TCP = 0,
     UDP = 1,
 }
+
+/// A peer connection listed by [`PeerList`](super::PeerList).
+#[cfg(feature = "internal-network-stack")]
+#[derive(Clone, PartialEq, serde::Deserialize)]
+pub struct PeerListEntry {
+    pub peer_handle: ublox_sockets::PeerHandle,
+    pub protocol: String<64>,
+    pub local_address: String<64>,
+    pub remote_address: String<64>,
+}
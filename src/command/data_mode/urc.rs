@@ -1,6 +1,8 @@
 //! Unsolicited responses for Data mode Commands
 #[allow(unused_imports)]
 use super::types::*;
+#[cfg(feature = "internal-network-stack")]
+use no_std_net::{IpAddr, SocketAddr};
 
 /// 5.10 Peer connected +UUDPC
 #[cfg(feature = "internal-network-stack")]
@@ -29,10 +31,99 @@ pub struct PeerConnected {
     pub remote_port: u16,
 }
 
+#[cfg(feature = "internal-network-stack")]
+impl PeerConnected {
+    /// Local address of the connection, or `None` if the module reported an
+    /// address that does not parse.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        parse_socket_addr(&self.local_address, self.local_port)
+    }
+
+    /// Remote address of the connection, or `None` if the module reported an
+    /// address that does not parse.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        parse_socket_addr(&self.remote_address, self.remote_port)
+    }
+}
+
+/// Parse an IPv4 or IPv6 address in textual form, IPv6 addresses with or
+/// without brackets.
+#[cfg(feature = "internal-network-stack")]
+fn parse_socket_addr(addr: &[u8], port: u16) -> Option<SocketAddr> {
+    let addr = core::str::from_utf8(addr).ok()?;
+    let addr = addr
+        .strip_prefix('[')
+        .and_then(|addr| addr.strip_suffix(']'))
+        .unwrap_or(addr);
+
+    Some(SocketAddr::new(addr.parse::<IpAddr>().ok()?, port))
+}
+
 /// 5.11 Peer disconnected +UUDPD
 #[cfg(feature = "internal-network-stack")]
 #[derive(Debug, PartialEq, Clone, atat::atat_derive::AtatResp)]
 pub struct PeerDisconnected {
     #[at_arg(position = 0)]
     pub handle: ublox_sockets::PeerHandle,
+    /// Reason of the disconnect, appended by firmware versions reporting
+    /// failed TLS handshakes.
+    #[at_arg(position = 1)]
+    pub reason: Option<u8>,
+}
+
+#[cfg(all(test, feature = "internal-network-stack"))]
+mod test {
+    use crate::command::Urc;
+    use atat::AtatUrc;
+
+    fn peer_connected(urc: &[u8]) -> super::PeerConnected {
+        match Urc::parse(urc) {
+            Some(Urc::PeerConnected(urc)) => urc,
+            _ => panic!("expected PeerConnected"),
+        }
+    }
+
+    #[test]
+    fn ipv4_addresses() {
+        let urc = peer_connected(b"+UUDPC:2,2,1,192.168.0.10,49152,162.159.200.1,123");
+        assert_eq!(
+            urc.local_addr(),
+            Some("192.168.0.10:49152".parse().unwrap())
+        );
+        assert_eq!(
+            urc.remote_addr(),
+            Some("162.159.200.1:123".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn ipv6_addresses() {
+        let urc = peer_connected(b"+UUDPC:3,3,0,fe80::1,49152,[2001:db8::1],443");
+        assert_eq!(urc.local_addr(), Some("[fe80::1]:49152".parse().unwrap()));
+        assert_eq!(
+            urc.remote_addr(),
+            Some("[2001:db8::1]:443".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn disconnect_reason() {
+        let Some(Urc::PeerDisconnected(urc)) = Urc::parse(b"+UUDPD:3") else {
+            panic!("expected PeerDisconnected");
+        };
+        assert_eq!(urc.handle, ublox_sockets::PeerHandle(3));
+        assert_eq!(urc.reason, None);
+
+        let Some(Urc::PeerDisconnected(urc)) = Urc::parse(b"+UUDPD:3,5") else {
+            panic!("expected PeerDisconnected");
+        };
+        assert_eq!(urc.reason, Some(5));
+    }
+
+    #[test]
+    fn invalid_address() {
+        let mut urc = peer_connected(b"+UUDPC:2,2,1,192.168.0.10,49152,162.159.200.1,123");
+        urc.remote_address = atat::heapless_bytes::Bytes::from_slice(b"example.org").unwrap();
+        assert_eq!(urc.remote_addr(), None);
+    }
 }
@@ -0,0 +1,57 @@
+//! ### DNS Commands
+pub mod responses;
+pub mod types;
+
+use atat::atat_derive::AtatCmd;
+use responses::*;
+use types::*;
+
+/// DNS resolution +UDNSRN
+///
+/// Translates a domain name to an IP address, using the DNS servers of the
+/// network interface. Unlike resolving a host through +UPING, no ICMP echo
+/// request is sent to the host, so it resolves hosts that do not answer
+/// pings.
+///
+/// The response is returned once the DNS servers have answered, or have
+/// given up on the query.
+///
+/// The command is not part of the u-connectXpress AT commands manual. The
+/// syntax is that of the `+UDNSRN` command of the u-blox cellular modules, and
+/// firmware that does not implement it answers with `ERROR`. The stack only
+/// issues it when enabled with `UbloxStack::set_resolve_command`.
+#[derive(Clone, AtatCmd)]
+#[at_cmd("+UDNSRN", ResolveNameResponse, timeout_ms = 10000)]
+pub struct ResolveName<'a> {
+    #[at_arg(position = 0)]
+    pub resolution_type: ResolutionType,
+    /// Domain name or IP address (dotted decimal representation) to resolve.
+    /// - Maximum length: 128 characters
+    #[at_arg(position = 1, len = 128)]
+    pub domain_ip_string: &'a str,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use atat::AtatCmd;
+    use no_std_net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn resolve_name() {
+        let cmd = ResolveName {
+            resolution_type: ResolutionType::DomainToIp,
+            domain_ip_string: "example.com",
+        };
+
+        let mut buf = [0u8; ResolveName::MAX_LEN];
+        let len = cmd.write(&mut buf);
+        assert_eq!(&buf[..len], b"AT+UDNSRN=0,\"example.com\"\r\n");
+
+        let response = cmd.parse(Ok(&b"+UDNSRN:\"93.184.216.34\""[..])).unwrap();
+        assert_eq!(
+            response.ip_address,
+            IpAddr::V4(Ipv4Addr::new(93, 184, 216, 34))
+        );
+    }
+}
@@ -0,0 +1,11 @@
+//! Responses for DNS Commands
+use atat::atat_derive::AtatResp;
+use no_std_net::IpAddr;
+
+/// DNS resolution +UDNSRN
+#[derive(Debug, PartialEq, Clone, AtatResp)]
+pub struct ResolveNameResponse {
+    /// IP address the domain name resolved to.
+    #[at_arg(position = 0)]
+    pub ip_address: IpAddr,
+}
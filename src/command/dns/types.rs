@@ -0,0 +1,14 @@
+//! Argument and parameter types used by DNS Commands and Responses
+
+use atat::atat_derive::AtatEnum;
+
+/// Direction of a DNS resolution. Only resolving domain names is supported,
+/// as [`ResolveNameResponse`](super::responses::ResolveNameResponse) holds
+/// an IP address.
+#[derive(Debug, PartialEq, Clone, Copy, AtatEnum)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum ResolutionType {
+    /// Domain name to IP address
+    DomainToIp = 0,
+}
@@ -16,6 +16,60 @@ use ublox_sockets::ChannelId;
 pub(crate) fn calc_payload_len(resp: &[u8]) -> usize {
     (u16::from_be_bytes(resp[1..3].try_into().unwrap()) & EDM_FULL_SIZE_FILTER) as usize
 }
+
+/// Length of an EDM packet carrying `payload_len` bytes of payload, or `None`
+/// if the payload does not fit the length field of the packet, or the packet
+/// does not fit a buffer of `buf_len` bytes.
+pub(crate) fn edm_packet_len(payload_len: usize, buf_len: usize) -> Option<usize> {
+    let packet_len = AT_COMMAND_POSITION + payload_len + 1;
+    (payload_len <= MAX_PAYLOAD_LEN && packet_len <= buf_len).then_some(packet_len)
+}
+
+/// Write `payload` framed as an EDM packet of type `payload_type` to `buf`,
+/// returning the length of the packet.
+///
+/// Nothing is written, and 0 is returned, if the packet does not fit, see
+/// [`edm_packet_len`].
+pub(crate) fn edm_frame(payload_type: PayloadType, payload: &[u8], buf: &mut [u8]) -> usize {
+    if edm_packet_len(payload.len(), buf.len()).is_none() {
+        error!("EDM payload of {} bytes does not fit", payload.len());
+        return 0;
+    }
+
+    buf[AT_COMMAND_POSITION..AT_COMMAND_POSITION + payload.len()].copy_from_slice(payload);
+    edm_frame_in_place(payload_type, payload.len(), buf)
+}
+
+/// Frame the `payload_len` bytes of payload, already written to `buf` at
+/// [`AT_COMMAND_POSITION`], as an EDM packet of type `payload_type`,
+/// returning the length of the packet.
+///
+/// Returns 0 if the packet does not fit, see [`edm_packet_len`].
+pub(crate) fn edm_frame_in_place(
+    payload_type: PayloadType,
+    payload_len: usize,
+    buf: &mut [u8],
+) -> usize {
+    let Some(packet_len) = edm_packet_len(payload_len, buf.len()) else {
+        error!("EDM payload of {} bytes does not fit", payload_len);
+        return 0;
+    };
+
+    // The length field covers the payload type as well
+    let len = (payload_len + 2) as u16;
+
+    buf[0..AT_COMMAND_POSITION].copy_from_slice(&[
+        STARTBYTE,
+        (len >> 8) as u8 & EDM_SIZE_FILTER,
+        (len & 0xffu16) as u8,
+        0x00,
+        payload_type as u8,
+    ]);
+    buf[AT_COMMAND_POSITION + payload_len] = ENDBYTE;
+
+    packet_len
+}
+
 /// EDM wrapper for AT-Commands
 // Note:
 // The AT+UMRS command to change serial settings does not work exactly the same as in command
@@ -33,20 +87,8 @@ impl<T: AtatCmd> atat::AtatCmd for EdmAtCmdWrapper<T> {
     const MAX_TIMEOUT_MS: u32 = T::MAX_TIMEOUT_MS;
 
     fn write(&self, buf: &mut [u8]) -> usize {
-        let at_len = self.0.write(&mut buf[5..]);
-        let payload_len = (at_len + 2) as u16;
-
-        buf[0..5].copy_from_slice(&[
-            STARTBYTE,
-            (payload_len >> 8) as u8 & EDM_SIZE_FILTER,
-            (payload_len & 0xffu16) as u8,
-            0x00,
-            PayloadType::ATRequest as u8,
-        ]);
-
-        buf[5 + at_len] = ENDBYTE;
-
-        5 + at_len + 1
+        let at_len = self.0.write(&mut buf[AT_COMMAND_POSITION..]);
+        edm_frame_in_place(PayloadType::ATRequest, at_len, buf)
     }
 
     fn parse(
@@ -107,20 +149,15 @@ impl<'a> atat::AtatCmd for EdmDataCommand<'a> {
     }
 
     fn write(&self, buf: &mut [u8]) -> usize {
-        let payload_len = (self.data.len() + 3) as u16;
-        buf[0..6].copy_from_slice(&[
-            STARTBYTE,
-            (payload_len >> 8) as u8 & EDM_SIZE_FILTER,
-            (payload_len & 0xffu16) as u8,
-            0x00,
-            PayloadType::DataCommand as u8,
-            self.channel.0,
-        ]);
-
-        buf[6..6 + self.data.len()].copy_from_slice(self.data);
-        buf[6 + self.data.len()] = ENDBYTE;
+        if edm_packet_len(self.data.len() + 1, buf.len()).is_none() {
+            error!("EDM data packet of {} bytes does not fit", self.data.len());
+            return 0;
+        }
 
-        6 + self.data.len() + 1
+        buf[AT_COMMAND_POSITION] = self.channel.0;
+        buf[AT_COMMAND_POSITION + 1..AT_COMMAND_POSITION + 1 + self.data.len()]
+            .copy_from_slice(self.data);
+        edm_frame_in_place(PayloadType::DataCommand, self.data.len() + 1, buf)
     }
 }
 
@@ -133,16 +170,7 @@ impl atat::AtatCmd for EdmResendConnectEventsCommand {
     const MAX_LEN: usize = 6;
 
     fn write(&self, buf: &mut [u8]) -> usize {
-        buf[0..6].copy_from_slice(&[
-            STARTBYTE,
-            0x00,
-            0x02,
-            0x00,
-            PayloadType::ResendConnectEventsCommand as u8,
-            ENDBYTE,
-        ]);
-
-        6
+        edm_frame(PayloadType::ResendConnectEventsCommand, &[], buf)
     }
 
     fn parse(
@@ -157,7 +185,7 @@ impl atat::AtatCmd for EdmResendConnectEventsCommand {
 pub struct SwitchToEdmCommand;
 
 impl atat::AtatCmd for SwitchToEdmCommand {
-    type Response = NoResponse;
+    type Response = EdmCapabilities;
 
     const MAX_LEN: usize = 6;
 
@@ -175,12 +203,28 @@ impl atat::AtatCmd for SwitchToEdmCommand {
         resp: Result<&[u8], atat::InternalError>,
     ) -> core::result::Result<Self::Response, atat::Error> {
         let resp = resp?;
-        // Parse EDM startup command
-        let correct = &[0xAA, 0x00, 0x02, 0x00, 0x71, 0x55];
-        if resp.len() != correct.len() || resp[..correct.len()] != *correct {
-            return Err(atat::Error::InvalidResponse);
-        }
-        Ok(NoResponse)
+        // Parse EDM startup command. Left over AT mode output may precede
+        // the start event, so look for it anywhere in the response.
+        (0..resp.len())
+            .filter_map(|start| {
+                let packet = &resp[start..];
+                if packet.len() < PAYLOAD_OVERHEAD
+                    || packet[0] != STARTBYTE
+                    || packet[3] != 0x00
+                    || packet[4] != PayloadType::StartEvent as u8
+                {
+                    return None;
+                }
+
+                let payload_len = calc_payload_len(packet);
+                if payload_len < 2 || packet.get(payload_len + EDM_OVERHEAD - 1) != Some(&ENDBYTE) {
+                    return None;
+                }
+
+                EdmCapabilities::parse(&packet[AT_COMMAND_POSITION..PAYLOAD_POSITION + payload_len])
+            })
+            .next()
+            .ok_or(atat::Error::InvalidResponse)
     }
 }
 
@@ -387,6 +431,86 @@ mod test {
         );
     }
 
+    #[test]
+    fn frame_payload() {
+        let mut buf = [0u8; 16];
+        let len = edm_frame(PayloadType::ATRequest, b"AT\r\n", &mut buf);
+        assert_eq!(
+            buf[..len],
+            [0xAA, 0x00, 0x06, 0x00, 0x44, 0x41, 0x54, 0x0D, 0x0a, 0x55]
+        );
+    }
+
+    #[test]
+    fn frame_data_command() {
+        let mut buf = [0u8; EdmDataCommand::MAX_LEN];
+        let len = EdmDataCommand {
+            channel: ChannelId(3),
+            data: b"abc",
+        }
+        .write(&mut buf);
+        assert_eq!(
+            buf[..len],
+            [0xAA, 0x00, 0x06, 0x00, 0x36, 0x03, 0x61, 0x62, 0x63, 0x55]
+        );
+    }
+
+    #[test]
+    fn frame_resend_connect_events() {
+        let mut buf = [0u8; EdmResendConnectEventsCommand::MAX_LEN];
+        let len = EdmResendConnectEventsCommand.write(&mut buf);
+        assert_eq!(buf[..len], [0xAA, 0x00, 0x02, 0x00, 0x56, 0x55]);
+    }
+
+    #[test]
+    fn frame_large_payload() {
+        let data = [0x42; 300];
+        let mut buf = [0u8; EdmDataCommand::MAX_LEN];
+        let len = EdmDataCommand {
+            channel: ChannelId(0),
+            data: &data,
+        }
+        .write(&mut buf);
+
+        assert_eq!(len, data.len() + 7);
+        // 300 + channel + payload type = 0x012F
+        assert_eq!(buf[..6], [0xAA, 0x01, 0x2F, 0x00, 0x36, 0x00]);
+        assert_eq!(calc_payload_len(&buf), data.len() + 3);
+        assert_eq!(buf[len - 1], 0x55);
+    }
+
+    #[test]
+    fn frame_max_payload() {
+        let data = [0x42; MAX_PAYLOAD_LEN - 1];
+        let mut buf = [0u8; MAX_PAYLOAD_LEN + 6];
+        let len = EdmDataCommand {
+            channel: ChannelId(0),
+            data: &data,
+        }
+        .write(&mut buf);
+
+        assert_eq!(len, buf.len());
+        assert_eq!(buf[1..3], [0x0F, 0xFF]);
+    }
+
+    #[test]
+    fn frame_oversized_payload() {
+        // Does not fit the length field
+        let data = [0x42; MAX_PAYLOAD_LEN];
+        let mut buf = [0u8; MAX_PAYLOAD_LEN + 16];
+        let len = EdmDataCommand {
+            channel: ChannelId(0),
+            data: &data,
+        }
+        .write(&mut buf);
+        assert_eq!(len, 0);
+
+        // Does not fit the buffer
+        let mut buf = [0u8; 8];
+        assert_eq!(edm_frame(PayloadType::ATRequest, b"AT\r\n", &mut buf), 0);
+        assert_eq!(buf, [0u8; 8]);
+    }
+
     #[test]
     fn change_to_edm_cmd() {
         let resp = &[0xAA, 0x00, 0x02, 0x00, 0x71, 0x55];
@@ -396,6 +520,43 @@ mod test {
         let len = SwitchToEdmCommand.write(&mut buf);
 
         assert_eq!(buf[..len], correct);
-        assert_eq!(SwitchToEdmCommand.parse(Ok(resp)).unwrap(), NoResponse);
+        assert_eq!(
+            SwitchToEdmCommand.parse(Ok(resp)).unwrap(),
+            EdmCapabilities::DEFAULT
+        );
+    }
+
+    #[test]
+    fn change_to_edm_with_leading_output() {
+        let resp = b"\r\nOK\r\n\xAA\x00\x02\x00\x71\x55";
+        assert_eq!(
+            SwitchToEdmCommand.parse(Ok(resp)).unwrap(),
+            EdmCapabilities::DEFAULT
+        );
+
+        let resp = b"\r\nOK\r\n\xAA\x00\x02\x00\x70\x55";
+        assert_eq!(
+            SwitchToEdmCommand.parse(Ok(resp)),
+            Err(Error::InvalidResponse)
+        );
+    }
+
+    #[test]
+    fn change_to_edm_with_capabilities() {
+        let resp = b"\r\nOK\r\n\xAA\x00\x05\x00\x71\x01\x03\xEC\x55";
+        assert_eq!(
+            SwitchToEdmCommand.parse(Ok(resp)).unwrap(),
+            EdmCapabilities {
+                version: 1,
+                max_payload: 1004,
+            }
+        );
+
+        // Truncated capabilities
+        let resp = b"\xAA\x00\x03\x00\x71\x01\x55";
+        assert_eq!(
+            SwitchToEdmCommand.parse(Ok(resp)),
+            Err(Error::InvalidResponse)
+        );
     }
 }
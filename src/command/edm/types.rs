@@ -13,6 +13,9 @@ pub const EDM_SIZE_FILTER: u8 = 0x0F;
 pub const EDM_FULL_SIZE_FILTER: u16 = 0x0FFF;
 pub const EDM_OVERHEAD: usize = 4;
 pub const PAYLOAD_OVERHEAD: usize = 6;
+/// Largest payload that fits the length field of an EDM packet, which also
+/// covers the two bytes of the payload type.
+pub const MAX_PAYLOAD_LEN: usize = EDM_FULL_SIZE_FILTER as usize - 2;
 /// Index in packet at which AT-command starts
 pub const AT_COMMAND_POSITION: usize = 5;
 /// Index in packet at which payload starts
@@ -73,6 +76,43 @@ impl From<u8> for PayloadType {
     }
 }
 
+/// Capabilities advertised by the module in the payload of the EDM start
+/// event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EdmCapabilities {
+    /// Version of the EDM protocol. Version 0 denotes a start event without
+    /// payload, sent by firmware not advertising its capabilities.
+    pub version: u8,
+    /// Maximum payload of a single EDM packet, in either direction. The
+    /// payload of a data packet includes its channel id.
+    pub max_payload: u16,
+}
+
+impl EdmCapabilities {
+    /// Capabilities of firmware not advertising any, limited only by the
+    /// length field of the EDM packet.
+    pub const DEFAULT: Self = Self {
+        version: 0,
+        max_payload: MAX_PAYLOAD_LEN as u16,
+    };
+
+    /// Parse the payload of a start event, consisting of the protocol
+    /// version followed by the big endian maximum payload size.
+    pub(crate) fn parse(payload: &[u8]) -> Option<Self> {
+        match payload {
+            [] => Some(Self::DEFAULT),
+            [version, hi, lo, ..] => Some(Self {
+                version: *version,
+                max_payload: u16::from_be_bytes([*hi, *lo]),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl atat::AtatResp for EdmCapabilities {}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct BluetoothConnectEvent {
     pub channel_id: ChannelId,
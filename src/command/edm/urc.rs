@@ -39,15 +39,10 @@ impl AtatUrc for EdmEvent {
         trace!("[Parse URC] {:?}", LossyStr(resp));
         // Startup message?
         // TODO: simplify mayby no packet check.
-        if resp.len() >= STARTUPMESSAGE.len()
-            && resp[..2] == *b"\r\n"
-            && resp[resp.len() - 2..] == *b"\r\n"
-        {
+        if resp.len() > 4 && resp[..2] == *b"\r\n" && resp[resp.len() - 2..] == *b"\r\n" {
             if resp == STARTUPMESSAGE {
                 return EdmEvent::ATEvent(Urc::StartUp).into();
-            } else if resp.len() == AUTOCONNECTMESSAGE.len()
-                || resp.len() == AUTOCONNECTMESSAGE.len() + 1
-            {
+            } else if resp[2] == b'+' {
                 let mut urc = resp;
                 if let Some(i) = urc.iter().position(|x| !x.is_ascii_whitespace()) {
                     urc = &urc[i..];
@@ -4,6 +4,7 @@
 #[cfg(feature = "edm")]
 pub mod custom_digest;
 pub mod data_mode;
+pub mod dns;
 #[cfg(feature = "edm")]
 pub mod edm;
 pub mod ethernet;
@@ -44,15 +45,19 @@ pub enum Urc {
     #[at_urc("+UUWLD")]
     WifiLinkDisconnected(wifi::urc::WifiLinkDisconnected),
     /// 7.17 Wi-Fi Access point up +UUWAPU
+    #[cfg(feature = "ap")]
     #[at_urc("+UUWAPU")]
     WifiAPUp(wifi::urc::WifiAPUp),
     /// 7.18 Wi-Fi Access point down +UUWAPD
+    #[cfg(feature = "ap")]
     #[at_urc("+UUWAPD")]
     WifiAPDown(wifi::urc::WifiAPDown),
     /// 7.19 Wi-Fi Access point station connected +UUWAPSTAC
+    #[cfg(feature = "ap")]
     #[at_urc("+UUWAPSTAC")]
     WifiAPStationConnected(wifi::urc::WifiAPStationConnected),
     /// 7.20 Wi-Fi Access point station disconnected +UUWAPSTAD
+    #[cfg(feature = "ap")]
     #[at_urc("+UUWAPSTAD")]
     WifiAPStationDisconnected(wifi::urc::WifiAPStationDisconnected),
     /// 8.3 Ethernet link up +UUETHLU
@@ -12,6 +12,7 @@ use super::NoResponse;
 /// 7.10 Wi-Fi Acess point status +UWAPSTAT
 ///
 /// Read status of Wi-Fi interface id.
+#[cfg(feature = "ap")]
 #[derive(Clone, AtatCmd)]
 #[at_cmd("+UWAPSTAT", APStatusResponse, attempts = 3, timeout_ms = 1000)]
 pub struct GetAPStatus {
@@ -1,10 +1,12 @@
 //! Responses for Network Commands
+#[cfg(feature = "ap")]
 use crate::command::wifi::types::AccessPointStatus;
 
 use super::types::*;
 use atat::atat_derive::AtatResp;
 
 /// 7.10 WiFi AP status +UWAPSTAT
+#[cfg(feature = "ap")]
 #[derive(Clone, AtatResp)]
 pub struct APStatusResponse {
     pub status_val: AccessPointStatus,
@@ -141,7 +141,7 @@ pub enum NetworkStatusParameter {
     IPv6Address3 = 212,
 }
 
-#[derive(Clone, PartialEq, AtatEnum)]
+#[derive(Debug, Clone, PartialEq, Eq, AtatEnum)]
 #[repr(u8)]
 pub enum InterfaceType {
     Unknown = 0,
@@ -300,7 +300,8 @@ pub enum Timing {
     AnnounceInterval(u16),
 }
 
-#[derive(Debug, Clone, PartialEq, AtatEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AtatEnum)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum ErrorType {
     IpAddressConflict = 128,
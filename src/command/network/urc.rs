@@ -18,6 +18,7 @@ pub struct NetworkDown {
 
 /// 10.8 Network error +UUNERR
 #[derive(Debug, PartialEq, Clone, AtatResp)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct NetworkError {
     #[at_arg(position = 0)]
     pub interface_id: u8,
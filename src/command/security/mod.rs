@@ -9,6 +9,10 @@ use types::*;
 
 use super::NoResponse;
 
+/// Largest certificate or private key accepted by
+/// [`PrepareSecurityDataImport`].
+pub const MAX_SECURITY_DATA_SIZE: usize = 8192;
+
 /// 11.1 SSL/TLS certificates and private keys manager +USECMNG
 ///
 /// Manages the X.509 certificates and private keys with the following functionalities:
@@ -66,21 +70,24 @@ pub struct RemoveSecurityData<'a> {
     pub name: &'a str,
 }
 
-// /// TODO: Implement response for this
-// /// 11.1 SSL/TLS certificates and private keys manager +USECMNG
-// ///
-// /// Manages the X.509 certificates and private keys with the following functionalities:
-// /// - Validation and import of certificates and private keys
-// /// - List and information retrieval of the imported certificates and private keys
-// /// - Removal of the certificates and private keys
-// /// - MD5 calculation of the imported certificate or private key
-// #[derive(Clone, AtatCmd)]
-// #[at_cmd("+USECMNG=3,", NoResponse, value_sep = false, timeout_ms = 1000)]
-// // #[at_cmd("+USECMNG=3,", ListSecurityDataResponse, value_sep = false, timeout_ms = 1000)]
-// pub struct ListSecurityData {
-//     #[at_arg(position = 0)]
-//     pub types: SecurityDataType,
-// }
+/// 11.1 SSL/TLS certificates and private keys manager +USECMNG
+///
+/// Manages the X.509 certificates and private keys with the following functionalities:
+/// - Validation and import of certificates and private keys
+/// - List and information retrieval of the imported certificates and private keys
+/// - Removal of the certificates and private keys
+/// - MD5 calculation of the imported certificate or private key
+#[derive(Clone, AtatCmd)]
+#[at_cmd(
+    "+USECMNG=3,",
+    ListSecurityDataResponse,
+    value_sep = false,
+    timeout_ms = 1000
+)]
+pub struct ListSecurityData {
+    #[at_arg(position = 0)]
+    pub types: SecurityDataType,
+}
 
 /// 11.1 SSL/TLS certificates and private keys manager +USECMNG
 ///
@@ -97,3 +104,71 @@ pub struct GetSecurityDataMD5 {
     #[at_arg(position = 1)]
     pub name: String<32>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use atat::AtatCmd;
+
+    fn write<Cmd: AtatCmd>(cmd: &Cmd) -> std::vec::Vec<u8> {
+        let mut buf = vec![0; Cmd::MAX_LEN];
+        let len = cmd.write(&mut buf);
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn remove() {
+        let cmd = RemoveSecurityData {
+            types: SecurityDataType::ClientCertificate,
+            name: "app_cert",
+        };
+        assert_eq!(write(&cmd), b"AT+USECMNG=2,1,\"app_cert\"\r\n");
+    }
+
+    #[test]
+    fn list() {
+        let cmd = ListSecurityData {
+            types: SecurityDataType::TrustedRootCA,
+        };
+        assert_eq!(write(&cmd), b"AT+USECMNG=3,0\r\n");
+
+        let response = cmd.parse(Ok(&b"+USECMNG:0,\"app_ca\""[..])).unwrap();
+        assert_eq!(response.entries.len(), 1);
+        assert_eq!(response.entries[0].internal_name, "app_ca");
+    }
+
+    #[test]
+    fn list_many() {
+        let response = ListSecurityData {
+            types: SecurityDataType::ClientCertificate,
+        }
+        .parse(Ok(b"+USECMNG:1,\"app_cert\"\r\n\
+                +USECMNG:1,\"boot_cert\"\r\n\
+                +USECMNG:1,\"app_cert2\""))
+        .unwrap();
+
+        assert_eq!(response.entries.len(), 3);
+        assert!(response.entries[0].data_type == SecurityDataType::ClientCertificate);
+        assert_eq!(response.entries[0].internal_name, "app_cert");
+        assert_eq!(response.entries[1].internal_name, "boot_cert");
+        assert_eq!(response.entries[2].internal_name, "app_cert2");
+    }
+
+    #[test]
+    fn md5() {
+        let cmd = GetSecurityDataMD5 {
+            types: SecurityDataType::ClientPrivateKey,
+            name: String::try_from("app_key").unwrap(),
+        };
+        assert_eq!(write(&cmd), b"AT+USECMNG=4,2,\"app_key\"\r\n");
+
+        let response = cmd
+            .parse(Ok(
+                &b"+USECMNG:4,2,\"app_key\",\"0e4d4b7d7b2ab0b5a3b6c4e1c86f0e73\""[..],
+            ))
+            .unwrap();
+        assert!(response.data_type == SecurityDataType::ClientPrivateKey);
+        assert_eq!(response.internal_name, "app_key");
+        assert_eq!(response.md5_string, "0e4d4b7d7b2ab0b5a3b6c4e1c86f0e73");
+    }
+}
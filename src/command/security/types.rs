@@ -1,6 +1,8 @@
 //! Argument and parameter types used by Security Commands and Responses
 
 use atat::atat_derive::AtatEnum;
+use heapless::String;
+use serde::Deserialize;
 
 #[derive(Clone, PartialEq, AtatEnum)]
 #[repr(u8)]
@@ -23,3 +25,12 @@ pub enum SecurityDataType {
     ClientCertificate = 1,
     ClientPrivateKey = 2,
 }
+
+/// An imported certificate or private key.
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct SecurityDataEntry {
+    /// Type of the security data
+    pub data_type: SecurityDataType,
+    /// Unique identifier of the imported certificate or private key.
+    pub internal_name: String<32>,
+}
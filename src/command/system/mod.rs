@@ -38,7 +38,7 @@ pub struct ResetToFactoryDefaults;
 /// (logical 1 on UART_DSR signal) states.
 /// The DTR line is connected to the DSR pin on the module.
 #[derive(Debug, PartialEq, Clone, AtatCmd)]
-#[at_cmd("&D", NoResponse, timeout_ms = 1000)]
+#[at_cmd("&D", NoResponse, timeout_ms = 1000, value_sep = false)]
 pub struct SetDTRBehavior {
     #[at_arg(position = 0)]
     pub mode: DTRMode,
@@ -51,7 +51,7 @@ pub struct SetDTRBehavior {
 /// signal UART_DTR) states.
 /// The DSR line is connected to the DTR pin on the module.
 #[derive(Debug, PartialEq, Clone, AtatCmd)]
-#[at_cmd("&S", NoResponse, timeout_ms = 1000)]
+#[at_cmd("&S", NoResponse, timeout_ms = 1000, value_sep = false)]
 pub struct SetDSROverride {
     #[at_arg(position = 0)]
     pub mode: DSRAssertMode,
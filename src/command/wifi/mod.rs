@@ -154,13 +154,91 @@ pub struct WifiScan<'a> {
 ///   for the current region.
 /// - Any DFS channel will be disabled for active use until an appropriate authoritative source has been found
 ///   for clearing each specific channel.
-#[derive(Clone, AtatCmd)]
-#[at_cmd("+UWCL", WifiScanResponse, timeout_ms = 1000)]
+///
+/// Serialized by hand, as the channels are written as separate parameters.
+#[derive(Clone)]
 pub struct SetChannelList {
-    #[at_arg(position = 0)]
+    /// Channels to scan, restoring the default channel list if empty.
     pub channels: Vec<u8, 10>,
 }
 
+impl atat::AtatCmd for SetChannelList {
+    type Response = NoResponse;
+
+    // `AT+UWCL`, up to 10 channels of up to 3 digits with their separator,
+    // and the termination
+    const MAX_LEN: usize = 7 + 10 * 4 + 2;
+
+    const MAX_TIMEOUT_MS: u32 = 1000;
+
+    fn write(&self, buf: &mut [u8]) -> usize {
+        use core::fmt::Write as _;
+
+        let mut cmd = heapless::String::<{ Self::MAX_LEN }>::new();
+        cmd.push_str("AT+UWCL").unwrap();
+        for (i, channel) in self.channels.iter().enumerate() {
+            let sep = if i == 0 { '=' } else { ',' };
+            write!(cmd, "{}{}", sep, channel).unwrap();
+        }
+        cmd.push_str("\r\n").unwrap();
+
+        buf[..cmd.len()].copy_from_slice(cmd.as_bytes());
+        cmd.len()
+    }
+
+    fn parse(
+        &self,
+        resp: Result<&[u8], atat::InternalError>,
+    ) -> core::result::Result<Self::Response, atat::Error> {
+        resp.map(|_| NoResponse).map_err(atat::Error::from)
+    }
+}
+
+/// 7.4 Channel list +UWCL
+///
+/// Reads the channel list for station mode, as set with [`SetChannelList`].
+/// Example response: +UWCL:1,6,11
+///
+/// Parsed by hand, as the channels are written as separate parameters.
+#[derive(Clone)]
+pub struct GetChannelList;
+
+impl atat::AtatCmd for GetChannelList {
+    type Response = ChannelListResponse;
+
+    const MAX_LEN: usize = 10;
+
+    const MAX_TIMEOUT_MS: u32 = 1000;
+
+    fn write(&self, buf: &mut [u8]) -> usize {
+        let cmd = b"AT+UWCL?\r\n";
+        buf[..cmd.len()].copy_from_slice(cmd);
+        cmd.len()
+    }
+
+    fn parse(
+        &self,
+        resp: Result<&[u8], atat::InternalError>,
+    ) -> core::result::Result<Self::Response, atat::Error> {
+        let resp = resp.map_err(atat::Error::from)?;
+        let list = core::str::from_utf8(resp)
+            .map_err(|_| atat::Error::Parse)?
+            .trim();
+
+        let mut channels = Vec::new();
+        if let Some(list) = list.strip_prefix("+UWCL:") {
+            for channel in list.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                let channel = channel.parse().map_err(|_| atat::Error::Parse)?;
+                channels.push(channel).map_err(|_| atat::Error::Parse)?;
+            }
+        } else if !list.is_empty() {
+            return Err(atat::Error::Parse);
+        }
+
+        Ok(ChannelListResponse { channels })
+    }
+}
+
 /// 7.5 Wi-Fi station status +UWSSTAT
 ///
 /// Writes the required channel list for station mode.
@@ -211,6 +289,7 @@ pub struct GetWatchdogConfig {
 /// be activated (Wi-Fi Access Point Configuration Action +UWAPCA) before using.
 /// The command will generate an error if the configuration id is active. See "Wi-Fi Access Point Configuration
 /// Action +UWAPCA" for instructions on how to deactivate a configuration.
+#[cfg(feature = "ap")]
 #[derive(Clone)]
 // #[at_cmd("+UWAPC", NoResponse, timeout_ms = 1000)]
 pub struct SetWifiAPConfig<'a> {
@@ -221,13 +300,16 @@ pub struct SetWifiAPConfig<'a> {
 }
 
 // FIXME:
+#[cfg(feature = "ap")]
 #[automatically_derived]
 impl<'a> atat::AtatLen for SetWifiAPConfig<'a> {
     const LEN: usize =
         <AccessPointConfig<'a> as atat::AtatLen>::LEN + <u8 as atat::AtatLen>::LEN + 1usize;
 }
+#[cfg(feature = "ap")]
 const ATAT_SETWIFIAPCONFIG_LEN: usize =
     <AccessPointConfig<'_> as atat::AtatLen>::LEN + <u8 as atat::AtatLen>::LEN + 1usize;
+#[cfg(feature = "ap")]
 #[automatically_derived]
 impl<'a> atat::AtatCmd for SetWifiAPConfig<'a> {
     type Response = NoResponse;
@@ -264,6 +346,7 @@ impl<'a> atat::AtatCmd for SetWifiAPConfig<'a> {
         }
     }
 }
+#[cfg(feature = "ap")]
 #[automatically_derived]
 impl<'a> atat::serde_at::serde::Serialize for SetWifiAPConfig<'a> {
     #[inline]
@@ -296,6 +379,7 @@ impl<'a> atat::serde_at::serde::Serialize for SetWifiAPConfig<'a> {
 /// be activated (Wi-Fi Access Point Configuration Action +UWAPCA) before using.
 /// The command will generate an error if the configuration id is active. See "Wi-Fi Access Point Configuration
 /// Action +UWAPCA" for instructions on how to deactivate a configuration.
+#[cfg(feature = "ap")]
 #[derive(Clone, AtatCmd)]
 #[at_cmd("+UWAPC", WifiAPConfigResponse, timeout_ms = 1000)]
 pub struct GetWifiAPConfig {
@@ -308,6 +392,7 @@ pub struct GetWifiAPConfig {
 /// 7.9 Wi-Fi Access point configuration action +UWAPCA
 ///
 /// Executes an action for the Wi-Fi network.
+#[cfg(feature = "ap")]
 #[derive(Clone, AtatCmd)]
 #[at_cmd("+UWAPCA", NoResponse, timeout_ms = 1000)]
 pub struct WifiAPAction {
@@ -320,6 +405,7 @@ pub struct WifiAPAction {
 /// 7.10 Wi-Fi Access point status +UWAPSTAT
 ///
 /// Reads current status of the Wi-Fi interface.
+#[cfg(feature = "ap")]
 #[derive(Clone, AtatCmd)]
 #[at_cmd("+UWAPSTAT", WifiAPStatusResponse, timeout_ms = 1000)]
 pub struct WifiAPStatus {
@@ -330,6 +416,7 @@ pub struct WifiAPStatus {
 /// 7.11 Wi-Fi Access point station list +UWAPSTALIST
 ///
 /// Lists all the stations connected to the Wireless access point.
+#[cfg(feature = "ap")]
 #[derive(Clone, AtatCmd)]
 #[at_cmd("+UWAPSTALIST?", WiFiAPStationListResponse, timeout_ms = 1000)]
 pub struct WiFiAPStationList;
@@ -340,3 +427,118 @@ pub struct WiFiAPStationList;
 #[derive(Clone, AtatCmd)]
 #[at_cmd("+UWAPMACADDR", WifiMacResponse, timeout_ms = 1000)]
 pub struct GetWifiMac;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use atat::AtatCmd;
+
+    fn write(cmd: &SetChannelList) -> std::vec::Vec<u8> {
+        let mut buf = [0u8; SetChannelList::MAX_LEN];
+        let len = cmd.write(&mut buf);
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn channel_list() {
+        let cmd = SetChannelList {
+            channels: Vec::from_slice(&[1, 6, 11, 165]).unwrap(),
+        };
+        assert_eq!(write(&cmd), b"AT+UWCL=1,6,11,165\r\n");
+
+        let cmd = SetChannelList {
+            channels: Vec::from_slice(&[165; 10]).unwrap(),
+        };
+        assert_eq!(write(&cmd).len(), SetChannelList::MAX_LEN);
+    }
+
+    #[test]
+    fn read_channel_list() {
+        let mut buf = [0u8; GetChannelList::MAX_LEN];
+        let len = GetChannelList.write(&mut buf);
+        assert_eq!(&buf[..len], b"AT+UWCL?\r\n");
+
+        let response = GetChannelList.parse(Ok(&b"+UWCL:1,6,11,165"[..])).unwrap();
+        assert_eq!(response.channels, [1, 6, 11, 165]);
+
+        let response = GetChannelList.parse(Ok(&b"+UWCL:"[..])).unwrap();
+        assert!(response.channels.is_empty());
+
+        assert!(GetChannelList.parse(Ok(&b"+UWCL:1,x"[..])).is_err());
+    }
+
+    #[test]
+    fn default_channel_list() {
+        let cmd = SetChannelList {
+            channels: Vec::new(),
+        };
+        assert_eq!(write(&cmd), b"AT+UWCL\r\n");
+    }
+
+    #[test]
+    fn wpa3_authentication() {
+        let write = |authentication| {
+            let cmd = SetWifiStationConfig {
+                config_id: 0,
+                config_param: WifiStationConfig::Authentication(authentication),
+            };
+            let mut buf = vec![0; SetWifiStationConfig::MAX_LEN];
+            let len = cmd.write(&mut buf);
+            buf[..len].to_vec()
+        };
+
+        assert_eq!(write(Authentication::WpaWpa2Psk), b"AT+UWSC=0,5,2\r\n");
+        assert_eq!(write(Authentication::Wpa2Wpa3Psk), b"AT+UWSC=0,5,6\r\n");
+        assert_eq!(write(Authentication::Wpa3Psk), b"AT+UWSC=0,5,7\r\n");
+    }
+
+    #[test]
+    fn enterprise_config() {
+        let write = |config_param| {
+            let cmd = SetWifiStationConfig {
+                config_id: 1,
+                config_param,
+            };
+            let mut buf = vec![0; SetWifiStationConfig::MAX_LEN];
+            let len = cmd.write(&mut buf);
+            buf[..len].to_vec()
+        };
+
+        assert_eq!(
+            write(WifiStationConfig::Authentication(Authentication::PEAP)),
+            b"AT+UWSC=1,5,4\r\n"
+        );
+        assert_eq!(
+            write(WifiStationConfig::Authentication(Authentication::EAPTLS)),
+            b"AT+UWSC=1,5,5\r\n"
+        );
+        assert_eq!(
+            write(WifiStationConfig::EAPPassword("secret")),
+            b"AT+UWSC=1,9,\"secret\"\r\n"
+        );
+        assert_eq!(
+            write(WifiStationConfig::UserName("alice")),
+            b"AT+UWSC=1,10,\"alice\"\r\n"
+        );
+        assert_eq!(
+            write(WifiStationConfig::DomainName("example.com")),
+            b"AT+UWSC=1,11,\"example.com\"\r\n"
+        );
+        assert_eq!(
+            write(WifiStationConfig::ClientCertificateName("app_cert")),
+            b"AT+UWSC=1,12,\"app_cert\"\r\n"
+        );
+        assert_eq!(
+            write(WifiStationConfig::ClientPrivateKey("app_key")),
+            b"AT+UWSC=1,13,\"app_key\"\r\n"
+        );
+        assert_eq!(
+            write(WifiStationConfig::CACertificateName("app_ca")),
+            b"AT+UWSC=1,14,\"app_ca\"\r\n"
+        );
+        assert_eq!(
+            write(WifiStationConfig::ValidateCACertificate(OnOff::Off)),
+            b"AT+UWSC=1,15,0\r\n"
+        );
+    }
+}
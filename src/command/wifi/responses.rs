@@ -13,11 +13,24 @@ pub struct GetWifiStationConfigResponse {
     pub parameter: WifiStationConfigR,
 }
 
+/// Most networks parsed from a single +UWSCAN response.
+pub const MAX_SCAN_RESULTS: usize = 64;
+
 /// 7.3 Scan +UWSCAN
 #[derive(Clone, AtatResp)]
 pub struct WifiScanResponse {
     #[at_arg(position = 0)]
-    pub network_list: Vec<ScannedWifiNetwork, 32>,
+    pub network_list: Vec<ScannedWifiNetwork, MAX_SCAN_RESULTS>,
+}
+
+/// Most channels parsed from a single +UWCL read response.
+pub const MAX_CHANNEL_LIST: usize = 48;
+
+/// 7.4 Channel list +UWCL
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelListResponse {
+    /// Channels in the list of the module.
+    pub channels: Vec<u8, MAX_CHANNEL_LIST>,
 }
 
 /// 7.5 Wi-Fi station status +UWSSTAT
@@ -35,6 +48,7 @@ pub struct WifiConfigResponse {
 }
 
 /// 7.8 Wi-Fi Access point configuration +UWAPC
+#[cfg(feature = "ap")]
 #[derive(Clone, AtatResp)]
 pub struct WifiAPConfigResponse {
     #[at_arg(position = 0)]
@@ -44,6 +58,7 @@ pub struct WifiAPConfigResponse {
 }
 
 /// 7.10 Wi-Fi Access point status +UWAPSTAT
+#[cfg(feature = "ap")]
 #[derive(Clone, AtatResp)]
 pub struct WifiAPStatusResponse {
     #[at_arg(position = 0)]
@@ -51,14 +66,11 @@ pub struct WifiAPStatusResponse {
 }
 
 /// 7.11 Wi-Fi Access point station list +UWAPSTALIST
+#[cfg(feature = "ap")]
 #[derive(Clone, AtatResp)]
 pub struct WiFiAPStationListResponse {
     #[at_arg(position = 0)]
-    pub id: u32,
-    #[at_arg(position = 1)]
-    pub mac_addr: Bytes<12>,
-    #[at_arg(position = 2)]
-    pub rssi: i32,
+    pub stations: Vec<ListedStation, 8>,
 }
 
 /// 7.11 Wi-Fi Access point station list +UWAPSTALIST
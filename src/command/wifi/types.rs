@@ -1,11 +1,13 @@
 //! Argument and parameter types used by WiFi Commands and Responses
 
 use crate::command::OnOff;
+use crate::error::WifiError;
+use crate::hex::from_hex;
 use atat::atat_derive::AtatEnum;
 use atat::heapless_bytes::Bytes;
 use heapless::{String, Vec};
 use no_std_net::{Ipv4Addr, Ipv6Addr};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
 #[derive(Clone, PartialEq, AtatEnum)]
 #[repr(u16)]
@@ -134,6 +136,8 @@ pub enum WifiStationConfig<'a> {
     /// - 3: LEAP
     /// - 4: PEAP
     /// - 5: EAP-TLS
+    /// - 6: WPA2/WPA3 PSK
+    /// - 7: WPA3 PSK
     #[at_arg(value = 5)]
     Authentication(Authentication),
     /// WEP Keys - <param_val1>...<param_val4> are the WEP encryption keys. A
@@ -404,6 +408,10 @@ pub enum Authentication {
     LEAP = 3,
     PEAP = 4,
     EAPTLS = 5,
+    /// WPA2/WPA3 PSK mixed mode. Supported software versions 4.0.0 onwards
+    Wpa2Wpa3Psk = 6,
+    /// WPA3 SAE. Supported software versions 4.0.0 onwards
+    Wpa3Psk = 7,
 }
 
 #[derive(Clone, PartialEq, AtatEnum)]
@@ -464,9 +472,108 @@ pub enum StatusId {
     Region = 8,
 }
 
+/// BSSID of a Wi-Fi network, or MAC address of a Wi-Fi station.
+///
+/// The module reports these in several textual forms, depending on the
+/// firmware version, which all parse to the same value:
+/// - Colon separated, as in `D4:CA:6D:F5:F2:F0`
+/// - Plain hexadecimal, as in `D4CA6DF5F2F0`
+/// - With an interface suffix, as in `D4CA6DF5F2F0p`
+///
+/// Formatted colon separated, in upper case.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Bssid(pub [u8; 6]);
+
+impl Bssid {
+    /// Parse any of the textual forms reported by the module, failing with
+    /// [`WifiError::InvalidBssid`] on any other input.
+    pub fn parse(s: &[u8]) -> Result<Self, WifiError> {
+        let s = match s.split_last() {
+            Some((suffix, digits))
+                if digits.len() == 12
+                    && suffix.is_ascii_alphabetic()
+                    && !suffix.is_ascii_hexdigit() =>
+            {
+                digits
+            }
+            _ => s,
+        };
+
+        let mut digits = [0u8; 12];
+        match s.len() {
+            12 => digits.copy_from_slice(s),
+            17 => {
+                for (i, octet) in s.chunks(3).enumerate() {
+                    if octet.len() == 3 && octet[2] != b':' {
+                        return Err(WifiError::InvalidBssid);
+                    }
+                    digits[2 * i..2 * i + 2].copy_from_slice(&octet[..2]);
+                }
+            }
+            _ => return Err(WifiError::InvalidBssid),
+        }
+
+        let mut octets = [0u8; 6];
+        octets.copy_from_slice(from_hex(&mut digits).map_err(|_| WifiError::InvalidBssid)?);
+        Ok(Self(octets))
+    }
+}
+
+impl core::str::FromStr for Bssid {
+    type Err = WifiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s.as_bytes())
+    }
+}
+
+impl core::fmt::Display for Bssid {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl core::fmt::Debug for Bssid {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Bssid({})", self)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Bssid {
+    fn format(&self, fmt: defmt::Formatter) {
+        let [a, b, c, d, e, g] = self.0;
+        defmt::write!(
+            fmt,
+            "{=u8:02X}:{=u8:02X}:{=u8:02X}:{=u8:02X}:{=u8:02X}:{=u8:02X}",
+            a,
+            b,
+            c,
+            d,
+            e,
+            g
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for Bssid {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = Bytes::<20>::deserialize(deserializer)?;
+        Self::parse(&s).map_err(|_| serde::de::Error::custom("invalid BSSID"))
+    }
+}
+
 #[derive(Clone, PartialEq, Deserialize)]
 pub struct ScannedWifiNetwork {
-    pub bssid: Bytes<20>,
+    pub bssid: Bssid,
     pub op_mode: OperationMode,
     pub ssid: String<64>,
     pub channel: u8,
@@ -481,6 +588,15 @@ pub struct ScannedWifiNetwork {
     pub group_ciphers: u8,
 }
 
+/// Station attached to the access point, as listed by +UWAPSTALIST.
+#[cfg(feature = "ap")]
+#[derive(Clone, PartialEq, Deserialize)]
+pub struct ListedStation {
+    pub station_id: u32,
+    pub mac_addr: Bssid,
+    pub rssi: i32,
+}
+
 #[derive(Clone, PartialEq, AtatEnum)]
 pub enum WifiStatus {
     #[at_arg(value = 0)]
@@ -499,7 +615,7 @@ pub enum WifiStatus {
     /// The <status_val> is the RSSI value of the current connection; will
     /// return-32768, if not connected.
     #[at_arg(value = 6)]
-    Rssi(u32),
+    Rssi(i32),
     /// The <status_val> is the mobility domain of the last or current
     /// connection This tag is supported by ODIN-W2 from software version 6.0.0
     /// onwards only.
@@ -839,7 +955,7 @@ pub enum WifiMode {
     Auto = 2,
 }
 
-#[derive(Clone, PartialEq, AtatEnum)]
+#[derive(Debug, Clone, PartialEq, AtatEnum)]
 #[repr(u8)]
 pub enum PowerSaveMode {
     ActiveMode = 0,
@@ -1434,7 +1550,8 @@ pub enum AccessPointStatusValue {
     String(String<64>),
 }
 
-#[derive(Debug, Clone, PartialEq, AtatEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AtatEnum)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[repr(u8)]
 pub enum DisconnectReason {
     Unknown = 0,
@@ -1444,3 +1561,71 @@ pub enum DisconnectReason {
     SecurityProblems = 4,
     NetworkDisabled = 5,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BSSID: Bssid = Bssid([0xD4, 0xCA, 0x6D, 0xF5, 0xF2, 0xF0]);
+
+    #[test]
+    fn bssid_colon_separated() {
+        assert_eq!(Bssid::parse(b"D4:CA:6D:F5:F2:F0").unwrap(), BSSID);
+        assert_eq!(Bssid::parse(b"d4:ca:6d:f5:f2:f0").unwrap(), BSSID);
+    }
+
+    #[test]
+    fn bssid_plain() {
+        assert_eq!(Bssid::parse(b"D4CA6DF5F2F0").unwrap(), BSSID);
+        assert_eq!(Bssid::parse(b"d4ca6df5f2f0").unwrap(), BSSID);
+    }
+
+    #[test]
+    fn bssid_interface_suffix() {
+        assert_eq!(Bssid::parse(b"D4CA6DF5F2F0p").unwrap(), BSSID);
+        assert_eq!(Bssid::parse(b"D4CA6DF5F2F0r").unwrap(), BSSID);
+    }
+
+    #[test]
+    fn bssid_garbled() {
+        for garbled in [
+            &b""[..],
+            b"D4CA6D",
+            b"D4CA6DF5F2F0F",
+            b"D4CA6DF5F2F01",
+            b"D4CA6DF5F2FG",
+            b"D4:CA:6D:F5:F2",
+            b"D4-CA-6D-F5-F2-F0",
+            b"D4:CA:6DF5:F2:F0:",
+            b"XXXXXXXXXXXX",
+        ] {
+            assert!(
+                matches!(Bssid::parse(garbled), Err(WifiError::InvalidBssid)),
+                "{:?}",
+                garbled
+            );
+        }
+    }
+
+    #[test]
+    fn rssi_status() {
+        use atat::AtatCmd;
+
+        let cmd = super::super::GetWifiStatus {
+            status_id: StatusId::Rssi,
+        };
+        let response = cmd.parse(Ok(&b"+UWSSTAT:6,-55"[..])).unwrap();
+        assert!(matches!(response.status_id, WifiStatus::Rssi(-55)));
+
+        let response = cmd.parse(Ok(&b"+UWSSTAT:6,-32768"[..])).unwrap();
+        assert!(matches!(response.status_id, WifiStatus::Rssi(-32768)));
+    }
+
+    #[test]
+    fn bssid_display() {
+        let mut s = String::<32>::new();
+        core::fmt::write(&mut s, format_args!("{}", BSSID)).unwrap();
+        assert_eq!(s, "D4:CA:6D:F5:F2:F0");
+        assert_eq!(s.parse::<Bssid>().unwrap(), BSSID);
+    }
+}
@@ -1,7 +1,6 @@
 //! Unsolicited responses for WiFi Commands
 use super::types::*;
 use atat::atat_derive::AtatResp;
-use atat::heapless_bytes::Bytes;
 
 /// 7.15 Wi-Fi Link connected +UUWLE
 #[derive(Debug, PartialEq, Clone, AtatResp)]
@@ -12,7 +11,7 @@ pub struct WifiLinkConnected {
     #[at_arg(position = 0)]
     pub connection_id: u32,
     #[at_arg(position = 1)]
-    pub bssid: Bytes<20>,
+    pub bssid: Bssid,
     #[at_arg(position = 2)]
     pub channel: u8,
 }
@@ -27,6 +26,7 @@ pub struct WifiLinkDisconnected {
 }
 
 /// 7.17 Wi-Fi Access point up +UUWAPU
+#[cfg(feature = "ap")]
 #[derive(Debug, PartialEq, Clone, AtatResp)]
 pub struct WifiAPUp {
     #[at_arg(position = 0)]
@@ -34,6 +34,7 @@ pub struct WifiAPUp {
 }
 
 /// 7.18 Wi-Fi Access point down +UUWAPD
+#[cfg(feature = "ap")]
 #[derive(Debug, PartialEq, Clone, AtatResp)]
 pub struct WifiAPDown {
     #[at_arg(position = 0)]
@@ -41,15 +42,17 @@ pub struct WifiAPDown {
 }
 
 /// 7.19 Wi-Fi Access point station connected +UUWAPSTAC
+#[cfg(feature = "ap")]
 #[derive(Debug, PartialEq, Clone, AtatResp)]
 pub struct WifiAPStationConnected {
     #[at_arg(position = 0)]
     pub station_id: u32,
     #[at_arg(position = 1)]
-    pub mac_addr: Bytes<20>,
+    pub mac_addr: Bssid,
 }
 
 /// 7.20 Wi-Fi Access point station disconnected +UUWAPSTAD
+#[cfg(feature = "ap")]
 #[derive(Debug, PartialEq, Clone, AtatResp)]
 pub struct WifiAPStationDisconnected {
     #[at_arg(position = 0)]
@@ -1,7 +1,14 @@
+use embassy_time::Duration;
 use embedded_hal::digital::OutputPin;
 use embedded_io_async::{Read, Write};
 
-use crate::{command::system::types::BaudRate, DEFAULT_BAUD_RATE};
+use crate::{
+    command::system::types::BaudRate,
+    init_script::InitScript,
+    options::{RoamingConfig, WakeConfig},
+    timeouts::Timeouts,
+    DEFAULT_BAUD_RATE,
+};
 
 pub trait WifiConfig<'a> {
     type ResetPin: OutputPin;
@@ -17,12 +24,36 @@ pub trait WifiConfig<'a> {
     #[cfg(feature = "internal-network-stack")]
     const TLS_OUT_BUFFER_SIZE: Option<u16> = None;
 
+    /// Setup of the UART control lines for waking the host, applied during
+    /// initialization. By default the lines are left as configured on the
+    /// module, and power save is disabled.
+    const WAKE_CONFIG: Option<WakeConfig> = None;
+
+    /// Time allowed for capturing diagnostics after an unexpected restart of
+    /// the module, delaying its initialization. By default nothing is
+    /// captured. See [`restart_capture`](crate::restart_capture).
+    const RESTART_CAPTURE_BUDGET: Option<Duration> = None;
+
+    /// Roaming between the access points of the joined network, see
+    /// [`RoamingConfig`]. By default the station stays with the access point
+    /// it associated with, for as long as the module does.
+    const ROAMING: Option<RoamingConfig> = None;
+
+    /// Timeouts of the waits of the driver, see [`Timeouts`].
+    const TIMEOUTS: Timeouts = Timeouts::DEFAULT;
+
     #[cfg(feature = "ppp")]
     const PPP_CONFIG: embassy_net_ppp::Config<'a>;
 
     fn reset_pin(&mut self) -> Option<&mut Self::ResetPin> {
         None
     }
+
+    /// Commands to run on every module boot, at fixed points of the
+    /// initialization. See [`InitScript`].
+    fn init_script(&self) -> Option<&InitScript> {
+        None
+    }
 }
 
 pub trait Transport: Write + Read {
@@ -1,5 +1,11 @@
-use no_std_net::Ipv4Addr;
+use core::str::FromStr as _;
 
+use no_std_net::{Ipv4Addr, Ipv6Addr};
+
+#[cfg(feature = "ap")]
+use crate::asynch::access_point::AccessPoint;
+use crate::command::network::types::{InterfaceType, NetworkStatus, NetworkStatusParameter};
+use crate::error::Error;
 use crate::network::{WifiMode, WifiNetwork};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -23,7 +29,65 @@ pub struct StaticConfigV4 {
     pub dns_servers: DnsServers,
 }
 
+/// Parse an IPv4 address as reported by the module, treating the
+/// unspecified address as no address.
+pub(crate) fn parse_ipv4(addr: &[u8]) -> Option<Ipv4Addr> {
+    core::str::from_utf8(addr)
+        .ok()
+        .and_then(|s| Ipv4Addr::from_str(s).ok())
+        .filter(|ip| !ip.is_unspecified())
+}
+
+/// Parse an IPv6 address as reported by the module, treating the
+/// unspecified address as no address.
+pub(crate) fn parse_ipv6(addr: &[u8]) -> Option<Ipv6Addr> {
+    core::str::from_utf8(addr)
+        .ok()
+        .and_then(|s| Ipv6Addr::from_str(s).ok())
+        .filter(|ip| !ip.is_unspecified())
+}
+
+/// IP configuration of a network interface, as reported by the module.
 #[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkStatusSummary {
+    pub interface_type: InterfaceType,
+    pub ipv4: Option<Ipv4Addr>,
+    pub gateway: Option<Ipv4Addr>,
+    pub ipv6_link_local: Option<Ipv6Addr>,
+}
+
+impl NetworkStatusSummary {
+    /// Network status parameters to query for a summary.
+    pub(crate) const QUERIES: [NetworkStatusParameter; 4] = [
+        NetworkStatusParameter::InterfaceType,
+        NetworkStatusParameter::IPv4Address,
+        NetworkStatusParameter::Gateway,
+        NetworkStatusParameter::IPv6LinkLocalAddress,
+    ];
+
+    pub(crate) const fn new() -> Self {
+        Self {
+            interface_type: InterfaceType::Unknown,
+            ipv4: None,
+            gateway: None,
+            ipv6_link_local: None,
+        }
+    }
+
+    /// Account for the response to one of the [`Self::QUERIES`].
+    pub(crate) fn update(&mut self, status: NetworkStatus) -> Result<(), Error> {
+        match status {
+            NetworkStatus::InterfaceType(interface_type) => self.interface_type = interface_type,
+            NetworkStatus::IPv4Address(ipv4) => self.ipv4 = parse_ipv4(&ipv4),
+            NetworkStatus::Gateway(gateway) => self.gateway = parse_ipv4(&gateway),
+            NetworkStatus::IPv6LinkLocalAddress(ipv6) => self.ipv6_link_local = parse_ipv6(&ipv6),
+            _ => return Err(Error::Network),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct DnsServers {
     pub primary: Option<Ipv4Addr>,
     pub secondary: Option<Ipv4Addr>,
@@ -36,6 +100,8 @@ pub struct WifiConnection {
     #[cfg(feature = "ipv6")]
     pub ipv6_up: bool,
     pub network: Option<WifiNetwork>,
+    #[cfg(feature = "ap")]
+    pub(crate) ap: AccessPoint,
 }
 
 impl WifiConnection {
@@ -47,6 +113,8 @@ impl WifiConnection {
             ipv4_up: false,
             #[cfg(feature = "ipv6")]
             ipv6_up: false,
+            #[cfg(feature = "ap")]
+            ap: AccessPoint::new(),
         }
     }
 
@@ -83,3 +151,64 @@ impl WifiConnection {
         self.is_config_up() && self.wifi_state == WiFiState::Connected
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use atat::heapless_bytes::Bytes;
+
+    #[test]
+    fn parse_addresses() {
+        assert_eq!(
+            parse_ipv4(b"192.168.1.10"),
+            Some(Ipv4Addr::new(192, 168, 1, 10))
+        );
+        assert_eq!(parse_ipv4(b"0.0.0.0"), None);
+        assert_eq!(parse_ipv4(b""), None);
+
+        assert_eq!(
+            parse_ipv6(b"fe80::1"),
+            Some(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1))
+        );
+        assert_eq!(parse_ipv6(b"::"), None);
+    }
+
+    #[test]
+    fn network_status_summary() {
+        let mut summary = NetworkStatusSummary::new();
+        summary
+            .update(NetworkStatus::InterfaceType(InterfaceType::WifiStation))
+            .unwrap();
+        summary
+            .update(NetworkStatus::IPv4Address(
+                Bytes::from_slice(b"192.168.1.10").unwrap(),
+            ))
+            .unwrap();
+        summary
+            .update(NetworkStatus::Gateway(
+                Bytes::from_slice(b"192.168.1.1").unwrap(),
+            ))
+            .unwrap();
+        summary
+            .update(NetworkStatus::IPv6LinkLocalAddress(
+                Bytes::from_slice(b"::").unwrap(),
+            ))
+            .unwrap();
+
+        assert_eq!(
+            summary,
+            NetworkStatusSummary {
+                interface_type: InterfaceType::WifiStation,
+                ipv4: Some(Ipv4Addr::new(192, 168, 1, 10)),
+                gateway: Some(Ipv4Addr::new(192, 168, 1, 1)),
+                ipv6_link_local: None,
+            }
+        );
+
+        assert!(summary
+            .update(NetworkStatus::PrimaryDNS(
+                Bytes::from_slice(b"192.168.1.1").unwrap(),
+            ))
+            .is_err());
+    }
+}
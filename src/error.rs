@@ -26,14 +26,50 @@ pub enum Error {
     InvalidHex,
     Dns(crate::command::ping::types::PingError),
     DuplicateCredentials,
+    /// A certificate or private key of the same name already exists in the
+    /// module.
+    CredentialNameCollision,
+    /// The name of a certificate or private key is empty, or contains
+    /// characters that cannot be used in AT commands or peer URLs.
+    InvalidCredentialName,
+    /// The CA certificate of an enterprise network has not been imported
+    /// into the module.
+    MissingCaCertificate,
+    /// All station configurations of the module are in use, see
+    /// [`Control::free_config_slot`](crate::asynch::control::Control::free_config_slot).
+    ConfigTableFull,
+    CredentialsMismatch,
+    /// The module refused to import security data, as its credential store
+    /// is full.
+    StorageFull,
     Uninitialized,
     Unimplemented,
     SocketMemory,
     SocketMapMemory,
+    /// The socket set holds more sockets than the module supports
+    /// simultaneous peer connections.
+    SocketSetTooLarge,
     Supplicant,
     Timeout,
+    Cancelled,
     ShadowStoreBug,
     AlreadyConnected,
+    WakeConfig(crate::options::WakeConflict),
+    /// A socket option is outside the range supported by the module.
+    InvalidSocketOption,
+    /// The URL of a peer exceeds the length the driver can pass to the
+    /// module.
+    UrlTooLong,
+    /// A channel is not a valid 2.4 or 5 GHz Wi-Fi channel.
+    InvalidChannel(u8),
+    /// Normal operation of the module is suspended, see
+    /// [`Control::suspend`](crate::asynch::control::Control::suspend).
+    Suspended,
+    /// A persisted driver state is truncated, corrupted, or of an
+    /// incompatible version, see
+    /// [`DetachedState`](crate::asynch::detach::DetachedState).
+    #[cfg(feature = "internal-network-stack")]
+    InvalidDetachedState,
     _Unknown,
 }
 
@@ -97,6 +133,15 @@ pub enum WifiError {
     // AT Error occurred.
     ATError(atat::Error),
     HexError,
+    /// The BSSID reported by the module is not a valid MAC address.
+    InvalidBssid,
+    /// The channel reported by the module is not a valid 2.4 or 5 GHz channel.
+    InvalidChannel(u8),
+    /// The authentication suites reported by the module are not a valid
+    /// hexadecimal value.
+    InvalidAuthenticationSuites,
+    /// The ciphers reported by the module are not a valid hexadecimal value.
+    InvalidCiphers,
     // FIXME: Temp fix!
     // Other,
 }
@@ -0,0 +1,554 @@
+//! Length-prefixed message framing on top of byte streams.
+//!
+//! [`FramedSocket`] wraps any [`embedded_io_async`] stream, such as a TCP
+//! socket, and exchanges whole messages (frames) over it. Each frame is sent
+//! as a big-endian length header, followed by the payload and an optional
+//! big-endian checksum over the payload:
+//!
+//! ```text
+//! +----------------+--------------------+--------------------+
+//! | length (2/4 B) | payload (length B) | checksum (0/2/4 B) |
+//! +----------------+--------------------+--------------------+
+//! ```
+//!
+//! Frames are read directly into the buffer given to
+//! [`FramedSocket::recv_frame`], so no buffering beyond a single frame is
+//! required.
+use embedded_io_async::{Read, ReadExactError, Write};
+
+/// Size of the length header preceding each frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LengthPrefix {
+    /// 2 byte length, allowing frames of up to 65535 bytes.
+    U16,
+    /// 4 byte length.
+    U32,
+}
+
+impl LengthPrefix {
+    const fn len(self) -> usize {
+        match self {
+            LengthPrefix::U16 => 2,
+            LengthPrefix::U32 => 4,
+        }
+    }
+
+    const fn max_payload(self) -> usize {
+        match self {
+            LengthPrefix::U16 => u16::MAX as usize,
+            LengthPrefix::U32 => u32::MAX as usize,
+        }
+    }
+}
+
+/// Checksum appended to each frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Checksum {
+    /// No checksum.
+    None,
+    /// CRC-16/CCITT-FALSE.
+    Crc16,
+    /// CRC-32 (IEEE 802.3).
+    Crc32,
+}
+
+impl Checksum {
+    const fn len(self) -> usize {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc16 => 2,
+            Checksum::Crc32 => 4,
+        }
+    }
+
+    fn compute(self, data: &[u8]) -> u32 {
+        match self {
+            Checksum::None => 0,
+            Checksum::Crc16 => crc16(data) as u32,
+            Checksum::Crc32 => crc32(data),
+        }
+    }
+}
+
+/// How to handle a frame that is oversized or fails the checksum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ErrorPolicy {
+    /// Refuse to receive any further frames, returning
+    /// [`FrameError::Poisoned`], as the stream can no longer be trusted.
+    Poison,
+    /// Discard the offending frame and continue with the next one.
+    Resync,
+}
+
+/// Framing configuration. Both ends of the stream must use the same
+/// configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameConfig {
+    pub length: LengthPrefix,
+    pub checksum: Checksum,
+    pub policy: ErrorPolicy,
+    /// Largest frame expected from the peer. A longer length header is taken
+    /// as a stream out of sync, see [`FrameError::InvalidLength`].
+    pub max_frame: usize,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameConfig {
+    /// 2 byte length header, CRC-16 checksum, poisoning on errors, frames of
+    /// up to 65535 bytes.
+    pub const fn new() -> Self {
+        Self {
+            length: LengthPrefix::U16,
+            checksum: Checksum::Crc16,
+            policy: ErrorPolicy::Poison,
+            max_frame: u16::MAX as usize,
+        }
+    }
+
+    pub const fn length(self, length: LengthPrefix) -> Self {
+        Self { length, ..self }
+    }
+
+    pub const fn checksum(self, checksum: Checksum) -> Self {
+        Self { checksum, ..self }
+    }
+
+    pub const fn policy(self, policy: ErrorPolicy) -> Self {
+        Self { policy, ..self }
+    }
+
+    pub const fn max_frame(self, max_frame: usize) -> Self {
+        Self { max_frame, ..self }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError<E> {
+    /// Error from the underlying stream.
+    Io(E),
+    /// The stream was closed in the middle of a frame.
+    UnexpectedEof,
+    /// The received frame does not fit in the receive buffer.
+    Oversized(usize),
+    /// The length header of the received frame exceeds
+    /// [`FrameConfig::max_frame`]. The stream is out of sync, so it is
+    /// poisoned whatever the [`ErrorPolicy`].
+    InvalidLength(usize),
+    /// The payload to send does not fit in the configured length header.
+    PayloadTooLarge,
+    /// The checksum of the received frame did not match its payload.
+    Checksum,
+    /// A previous error made the stream unusable, see [`ErrorPolicy::Poison`].
+    Poisoned,
+}
+
+impl<E> From<ReadExactError<E>> for FrameError<E> {
+    fn from(e: ReadExactError<E>) -> Self {
+        match e {
+            ReadExactError::UnexpectedEof => FrameError::UnexpectedEof,
+            ReadExactError::Other(e) => FrameError::Io(e),
+        }
+    }
+}
+
+/// Message framing adapter for byte streams, see the [module
+/// documentation](self).
+pub struct FramedSocket<T> {
+    inner: T,
+    config: FrameConfig,
+    poisoned: bool,
+}
+
+impl<T> FramedSocket<T> {
+    pub fn new(inner: T, config: FrameConfig) -> Self {
+        Self {
+            inner,
+            config,
+            poisoned: false,
+        }
+    }
+
+    pub fn config(&self) -> &FrameConfig {
+        &self.config
+    }
+
+    /// Whether a previous error has made the stream unusable.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Write> FramedSocket<T> {
+    /// Send `payload` as a single frame.
+    pub async fn send_frame(&mut self, payload: &[u8]) -> Result<(), FrameError<T::Error>> {
+        if payload.len() > self.config.length.max_payload() {
+            return Err(FrameError::PayloadTooLarge);
+        }
+
+        let header = (payload.len() as u32).to_be_bytes();
+        let header = &header[4 - self.config.length.len()..];
+        self.inner.write_all(header).await.map_err(FrameError::Io)?;
+        self.inner
+            .write_all(payload)
+            .await
+            .map_err(FrameError::Io)?;
+
+        let checksum = self.config.checksum.compute(payload).to_be_bytes();
+        let checksum = &checksum[4 - self.config.checksum.len()..];
+        self.inner
+            .write_all(checksum)
+            .await
+            .map_err(FrameError::Io)?;
+
+        self.inner.flush().await.map_err(FrameError::Io)
+    }
+}
+
+impl<T: Read> FramedSocket<T> {
+    /// Receive a single frame into `buf`, returning the length of the payload.
+    ///
+    /// Frames spanning multiple reads of the underlying stream are
+    /// reassembled. Frames larger than `buf`, or failing the checksum, are
+    /// handled according to the configured [`ErrorPolicy`].
+    pub async fn recv_frame(&mut self, buf: &mut [u8]) -> Result<usize, FrameError<T::Error>> {
+        if self.poisoned {
+            return Err(FrameError::Poisoned);
+        }
+
+        let res = self.recv_frame_inner(buf).await;
+        match res {
+            Err(FrameError::Oversized(_) | FrameError::Checksum)
+                if self.config.policy == ErrorPolicy::Poison =>
+            {
+                self.poisoned = true
+            }
+            Err(FrameError::InvalidLength(_)) => self.poisoned = true,
+            _ => {}
+        }
+        res
+    }
+
+    async fn recv_frame_inner(&mut self, buf: &mut [u8]) -> Result<usize, FrameError<T::Error>> {
+        let header_len = self.config.length.len();
+        let checksum_len = self.config.checksum.len();
+
+        let mut header = [0u8; 4];
+        self.inner.read_exact(&mut header[4 - header_len..]).await?;
+        let len = u32::from_be_bytes(header) as usize;
+
+        // Skipping a corrupted length could drain the stream indefinitely
+        if len > self.config.max_frame {
+            return Err(FrameError::InvalidLength(len));
+        }
+
+        if len > buf.len() {
+            if self.config.policy == ErrorPolicy::Resync {
+                let frame_len = len
+                    .checked_add(checksum_len)
+                    .ok_or(FrameError::InvalidLength(len))?;
+                self.skip(frame_len, buf).await?;
+            }
+            return Err(FrameError::Oversized(len));
+        }
+
+        self.inner.read_exact(&mut buf[..len]).await?;
+
+        let mut checksum = [0u8; 4];
+        self.inner
+            .read_exact(&mut checksum[4 - checksum_len..])
+            .await?;
+
+        if u32::from_be_bytes(checksum) != self.config.checksum.compute(&buf[..len]) {
+            return Err(FrameError::Checksum);
+        }
+
+        Ok(len)
+    }
+
+    /// Discard `len` bytes from the stream, using `scratch` as buffer.
+    async fn skip(
+        &mut self,
+        mut len: usize,
+        scratch: &mut [u8],
+    ) -> Result<(), FrameError<T::Error>> {
+        let mut fallback = [0u8; 16];
+        let scratch = if scratch.is_empty() {
+            &mut fallback[..]
+        } else {
+            scratch
+        };
+
+        while len > 0 {
+            let n = len.min(scratch.len());
+            self.inner.read_exact(&mut scratch[..n]).await?;
+            len -= n;
+        }
+        Ok(())
+    }
+}
+
+/// CRC-16/CCITT-FALSE
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// CRC-32 (IEEE 802.3)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use embassy_futures::block_on;
+
+    /// Stream returning at most `chunk` bytes per read, simulating data
+    /// arriving in many small data events.
+    struct ChunkedStream<'a> {
+        rx: &'a [u8],
+        chunk: usize,
+        tx: heapless::Vec<u8, 256>,
+    }
+
+    impl<'a> ChunkedStream<'a> {
+        fn new(rx: &'a [u8], chunk: usize) -> Self {
+            Self {
+                rx,
+                chunk,
+                tx: heapless::Vec::new(),
+            }
+        }
+    }
+
+    impl embedded_io_async::ErrorType for ChunkedStream<'_> {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for ChunkedStream<'_> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let n = self.rx.len().min(buf.len()).min(self.chunk);
+            buf[..n].copy_from_slice(&self.rx[..n]);
+            self.rx = &self.rx[n..];
+            Ok(n)
+        }
+    }
+
+    impl Write for ChunkedStream<'_> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.tx.extend_from_slice(buf).unwrap();
+            Ok(buf.len())
+        }
+    }
+
+    fn encode(config: FrameConfig, payloads: &[&[u8]]) -> heapless::Vec<u8, 256> {
+        let mut socket = FramedSocket::new(ChunkedStream::new(&[], 1), config);
+        for payload in payloads {
+            block_on(socket.send_frame(payload)).unwrap();
+        }
+        socket.into_inner().tx
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn encode_frame() {
+        let frame = encode(FrameConfig::new(), &[b"123456789"]);
+        assert_eq!(&frame[..], b"\x00\x09123456789\x29\xB1");
+
+        let frame = encode(
+            FrameConfig::new()
+                .length(LengthPrefix::U32)
+                .checksum(Checksum::None),
+            &[b"abc"],
+        );
+        assert_eq!(&frame[..], b"\x00\x00\x00\x03abc");
+    }
+
+    #[test]
+    fn frames_split_across_reads() {
+        let config = FrameConfig::new()
+            .length(LengthPrefix::U32)
+            .checksum(Checksum::Crc32);
+        let data = encode(config, &[b"hello", b"", b"framed world"]);
+
+        for chunk in [1, 2, 3, 7, 64] {
+            let mut socket = FramedSocket::new(ChunkedStream::new(&data, chunk), config);
+            let mut buf = [0u8; 16];
+
+            assert_eq!(block_on(socket.recv_frame(&mut buf)), Ok(5));
+            assert_eq!(&buf[..5], b"hello");
+            assert_eq!(block_on(socket.recv_frame(&mut buf)), Ok(0));
+            assert_eq!(block_on(socket.recv_frame(&mut buf)), Ok(12));
+            assert_eq!(&buf[..12], b"framed world");
+            assert_eq!(
+                block_on(socket.recv_frame(&mut buf)),
+                Err(FrameError::UnexpectedEof)
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let data = encode(FrameConfig::new(), &[b"hello"]);
+        let mut socket = FramedSocket::new(
+            ChunkedStream::new(&data[..data.len() - 1], 2),
+            FrameConfig::new(),
+        );
+        let mut buf = [0u8; 16];
+
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn corrupted_checksum() {
+        let mut data = encode(FrameConfig::new(), &[b"hello", b"world"]);
+        // Corrupt the payload of the first frame
+        data[3] ^= 0x01;
+        let mut buf = [0u8; 16];
+
+        let mut socket = FramedSocket::new(ChunkedStream::new(&data, 3), FrameConfig::new());
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::Checksum)
+        );
+        assert!(socket.is_poisoned());
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::Poisoned)
+        );
+
+        let config = FrameConfig::new().policy(ErrorPolicy::Resync);
+        let mut socket = FramedSocket::new(ChunkedStream::new(&data, 3), config);
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::Checksum)
+        );
+        assert_eq!(block_on(socket.recv_frame(&mut buf)), Ok(5));
+        assert_eq!(&buf[..5], b"world");
+    }
+
+    #[test]
+    fn oversized_length() {
+        let data = encode(FrameConfig::new(), &[b"this frame is too long", b"ok"]);
+        let mut buf = [0u8; 8];
+
+        let mut socket = FramedSocket::new(ChunkedStream::new(&data, 5), FrameConfig::new());
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::Oversized(22))
+        );
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::Poisoned)
+        );
+
+        let config = FrameConfig::new().policy(ErrorPolicy::Resync);
+        let mut socket = FramedSocket::new(ChunkedStream::new(&data, 5), config);
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::Oversized(22))
+        );
+        assert_eq!(block_on(socket.recv_frame(&mut buf)), Ok(2));
+        assert_eq!(&buf[..2], b"ok");
+    }
+
+    #[test]
+    fn invalid_length() {
+        let config = FrameConfig::new()
+            .length(LengthPrefix::U32)
+            .policy(ErrorPolicy::Resync);
+        let mut data = encode(config, &[b"ok"]);
+        // Garbage in place of the length header
+        data[..4].copy_from_slice(&[0xFF; 4]);
+        let mut buf = [0u8; 8];
+
+        // Not skipped, even when resynchronizing
+        let mut socket = FramedSocket::new(ChunkedStream::new(&data, 5), config);
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::InvalidLength(0xFFFF_FFFF))
+        );
+        assert!(socket.is_poisoned());
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::Poisoned)
+        );
+
+        // Nor are frames above the configured maximum
+        let data = encode(config, &[b"this frame is too long", b"ok"]);
+        let mut socket = FramedSocket::new(ChunkedStream::new(&data, 5), config.max_frame(16));
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::InvalidLength(22))
+        );
+
+        // Only those larger than the receive buffer
+        let mut socket = FramedSocket::new(ChunkedStream::new(&data, 5), config.max_frame(32));
+        assert_eq!(
+            block_on(socket.recv_frame(&mut buf)),
+            Err(FrameError::Oversized(22))
+        );
+        assert_eq!(block_on(socket.recv_frame(&mut buf)), Ok(2));
+    }
+
+    #[test]
+    fn payload_too_large() {
+        let payload = [0u8; 70_000];
+        let mut socket = FramedSocket::new(ChunkedStream::new(&[], 1), FrameConfig::new());
+        assert_eq!(
+            block_on(socket.send_frame(&payload)),
+            Err(FrameError::PayloadTooLarge)
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Commands applied by the runner at a fixed point of every module boot.
+//!
+//! Some settings can only be applied in a narrow window right after a reset,
+//! before the driver enters extended data mode, or before the module
+//! autoconnects. An [`InitScript`], provided through
+//! [`WifiConfig::init_script`](crate::WifiConfig::init_script), applies such
+//! settings on every boot, without forking the init sequence. The outcome of
+//! the last run is available from
+//! [`Control::init_report`](crate::asynch::control::Control::init_report).
+use atat::{asynch::AtatClient, AtatCmd};
+use heapless::Vec;
+
+use crate::asynch::runner::MAX_CMD_LEN;
+#[cfg(feature = "edm")]
+use crate::command::edm::EdmAtCmdWrapper;
+use crate::command::NoResponse;
+use crate::error::Error;
+use crate::zeroize::zeroize;
+
+/// Maximum number of commands in an [`InitScript`].
+pub const MAX_INIT_SCRIPT_LEN: usize = 8;
+
+/// Maximum length of a serialized command in an [`InitScript`].
+pub const MAX_INIT_COMMAND_LEN: usize = 64;
+
+/// Time to wait for the response to a command of an [`InitScript`].
+const INIT_COMMAND_TIMEOUT_MS: u32 = 5000;
+
+/// Point of the boot sequence at which a command of an [`InitScript`] runs.
+///
+/// Without the `edm` feature, both stages run back to back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InitStage {
+    /// After the startup message of the module, before entering extended
+    /// data mode.
+    BeforeEdm,
+    /// After entering extended data mode, before the driver configures the
+    /// module. With the `edm` feature, the commands are sent wrapped in EDM
+    /// frames.
+    AfterEdm,
+}
+
+/// What to do if a command of an [`InitScript`] fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OnError {
+    /// Abort the initialization, which is retried from a module reset.
+    Abort,
+    /// Carry on with the next command.
+    Continue,
+}
+
+#[derive(Debug, Clone)]
+struct InitCommand {
+    /// The command, serialized.
+    line: Vec<u8, MAX_INIT_COMMAND_LEN>,
+    stage: InitStage,
+    on_error: OnError,
+}
+
+/// Ordered list of commands, run by the runner on every module boot.
+///
+/// Commands of a stage run in the order they were pushed.
+#[derive(Debug, Clone, Default)]
+pub struct InitScript {
+    commands: Vec<InitCommand, MAX_INIT_SCRIPT_LEN>,
+}
+
+impl InitScript {
+    pub const fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+        }
+    }
+
+    /// Append `cmd`, to run at `stage`.
+    ///
+    /// Fails with [`Error::Overflow`] if the script holds
+    /// [`MAX_INIT_SCRIPT_LEN`] commands already, or if the serialized command
+    /// is longer than [`MAX_INIT_COMMAND_LEN`]. Commands without a response
+    /// code, such as data commands, are rejected with
+    /// [`Error::Unimplemented`].
+    pub fn push<Cmd: AtatCmd>(
+        &mut self,
+        cmd: &Cmd,
+        stage: InitStage,
+        on_error: OnError,
+    ) -> Result<(), Error> {
+        if !Cmd::EXPECTS_RESPONSE_CODE {
+            return Err(Error::Unimplemented);
+        }
+        if self.commands.is_full() || Cmd::MAX_LEN > MAX_CMD_LEN {
+            return Err(Error::Overflow);
+        }
+
+        let mut buf = [0u8; MAX_CMD_LEN];
+        let len = cmd.write(&mut buf);
+        let line = Vec::from_slice(&buf[..len]);
+        // The command may carry credentials, such as a Wi-Fi passphrase
+        zeroize(&mut buf[..len]);
+
+        self.commands
+            .push(InitCommand {
+                line: line.map_err(|_| Error::Overflow)?,
+                stage,
+                on_error,
+            })
+            .map_err(|_| Error::Overflow)
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+/// Outcome of a command of an [`InitScript`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InitCommandResult {
+    /// The command was not sent, as an earlier command aborted the
+    /// initialization.
+    NotRun,
+    Ok,
+    Failed(atat::Error),
+}
+
+/// Outcome of the [`InitScript`] on the last module boot.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InitReport {
+    /// Outcome of each command, by position in the script.
+    pub script: Vec<InitCommandResult, MAX_INIT_SCRIPT_LEN>,
+}
+
+impl InitReport {
+    pub const fn new() -> Self {
+        Self { script: Vec::new() }
+    }
+
+    /// A report for a boot running `script`, with none of its commands run
+    /// yet.
+    pub(crate) fn start(script: Option<&InitScript>) -> Self {
+        let mut report = Self::new();
+        report
+            .script
+            .resize(script.map_or(0, InitScript::len), InitCommandResult::NotRun)
+            .ok();
+        report
+    }
+}
+
+/// A serialized command of an [`InitScript`].
+struct RawCommand<'a>(&'a [u8]);
+
+impl AtatCmd for RawCommand<'_> {
+    type Response = NoResponse;
+
+    const MAX_LEN: usize = MAX_INIT_COMMAND_LEN;
+
+    const MAX_TIMEOUT_MS: u32 = INIT_COMMAND_TIMEOUT_MS;
+
+    fn write(&self, buf: &mut [u8]) -> usize {
+        buf[..self.0.len()].copy_from_slice(self.0);
+        self.0.len()
+    }
+
+    fn parse(
+        &self,
+        resp: Result<&[u8], atat::InternalError>,
+    ) -> core::result::Result<Self::Response, atat::Error> {
+        resp.map(|_| NoResponse).map_err(atat::Error::from)
+    }
+}
+
+/// Run the commands of `script` for `stage`, reporting the outcome of each
+/// command by its position in the script.
+pub(crate) async fn run<A: AtatClient>(
+    at_client: &mut A,
+    script: Option<&InitScript>,
+    stage: InitStage,
+    mut on_result: impl FnMut(usize, InitCommandResult),
+) -> Result<(), Error> {
+    let Some(script) = script else {
+        return Ok(());
+    };
+
+    for (i, cmd) in script.commands.iter().enumerate() {
+        if cmd.stage != stage {
+            continue;
+        }
+
+        // The module only accepts commands wrapped in EDM frames once in EDM
+        #[cfg(feature = "edm")]
+        let res = if stage == InitStage::AfterEdm {
+            at_client
+                .send_retry(&EdmAtCmdWrapper(RawCommand(&cmd.line)))
+                .await
+        } else {
+            at_client.send_retry(&RawCommand(&cmd.line)).await
+        };
+        #[cfg(not(feature = "edm"))]
+        let res = at_client.send_retry(&RawCommand(&cmd.line)).await;
+
+        match res {
+            Ok(_) => on_result(i, InitCommandResult::Ok),
+            Err(e) => {
+                warn!("Init script command {} failed: {:?}", i, e);
+                on_result(i, InitCommandResult::Failed(e));
+                if cmd.on_error == OnError::Abort {
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::{
+        general::SoftwareVersion,
+        system::{types::EchoOn, SetEcho},
+        AT,
+    };
+    use crate::test_util::MockUbloxModule;
+
+    fn script() -> InitScript {
+        let mut script = InitScript::new();
+        script
+            .push(&AT, InitStage::AfterEdm, OnError::Continue)
+            .unwrap();
+        script
+            .push(
+                &SetEcho { on: EchoOn::Off },
+                InitStage::BeforeEdm,
+                OnError::Abort,
+            )
+            .unwrap();
+        script
+            .push(&SoftwareVersion, InitStage::AfterEdm, OnError::Abort)
+            .unwrap();
+        script
+    }
+
+    fn boot(
+        script: &InitScript,
+        mut client: MockUbloxModule,
+    ) -> (Result<(), Error>, MockUbloxModule, InitReport) {
+        let mut report = InitReport::start(Some(script));
+
+        let res = embassy_futures::block_on(async {
+            for stage in [InitStage::BeforeEdm, InitStage::AfterEdm] {
+                run(&mut client, Some(script), stage, |i, r| {
+                    report.script[i] = r
+                })
+                .await?;
+            }
+            Ok::<_, Error>(())
+        });
+        (res, client, report)
+    }
+
+    #[test]
+    fn stages_in_order() {
+        let script = script();
+        let mut client = MockUbloxModule::new();
+        client.fail("AT\r\n", atat::Error::Error);
+        let (res, client, report) = boot(&script, client);
+
+        assert!(res.is_ok());
+        assert_eq!(
+            client.sent_commands().collect::<std::vec::Vec<_>>(),
+            [&b"ATE0\r\n"[..], b"AT\r\n", b"AT+CGMR\r\n"]
+        );
+        // The failing command does not abort the initialization
+        assert_eq!(
+            report.script.as_slice(),
+            &[
+                InitCommandResult::Failed(atat::Error::Error),
+                InitCommandResult::Ok,
+                InitCommandResult::Ok,
+            ]
+        );
+    }
+
+    #[test]
+    fn abort() {
+        let script = script();
+        let mut client = MockUbloxModule::new();
+        client.fail("ATE0", atat::Error::Error);
+        let (res, client, report) = boot(&script, client);
+
+        assert!(matches!(res, Err(Error::AT(atat::Error::Error))));
+        assert_eq!(
+            client.sent_commands().collect::<std::vec::Vec<_>>(),
+            [b"ATE0\r\n"]
+        );
+        assert_eq!(
+            report.script.as_slice(),
+            &[
+                InitCommandResult::NotRun,
+                InitCommandResult::Failed(atat::Error::Error),
+                InitCommandResult::NotRun,
+            ]
+        );
+    }
+
+    #[cfg(feature = "internal-network-stack")]
+    #[test]
+    fn after_edm_framed() {
+        use crate::command::edm::types::STARTBYTE;
+
+        let script = script();
+        let (res, client, _) = boot(&script, MockUbloxModule::new());
+        assert!(res.is_ok());
+
+        // Only the commands before the switch are sent as plain AT
+        let framed: std::vec::Vec<_> = client
+            .sent_raw()
+            .map(|raw| raw.first() == Some(&STARTBYTE))
+            .collect();
+        assert_eq!(framed, [false, true, true]);
+        assert_eq!(
+            client.sent_commands().collect::<std::vec::Vec<_>>(),
+            [&b"ATE0\r\n"[..], b"AT\r\n", b"AT+CGMR\r\n"]
+        );
+    }
+
+    #[test]
+    fn bounded() {
+        let mut script = InitScript::new();
+        for _ in 0..MAX_INIT_SCRIPT_LEN {
+            script
+                .push(&AT, InitStage::BeforeEdm, OnError::Abort)
+                .unwrap();
+        }
+
+        assert!(matches!(
+            script.push(&AT, InitStage::BeforeEdm, OnError::Abort),
+            Err(Error::Overflow)
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 #![allow(async_fn_in_trait)]
 
 #[cfg(all(feature = "ppp", feature = "internal-network-stack"))]
@@ -17,13 +17,29 @@ compile_error!("No module feature activated. You must activate exactly one of th
 mod fmt;
 
 pub mod asynch;
+pub mod init_script;
 pub mod options;
+pub mod restart_capture;
+pub mod timeouts;
 
 mod config;
 mod connection;
-mod network;
+pub mod network;
 
 mod hex;
+mod zeroize;
+
+#[cfg(feature = "framing")]
+pub mod framing;
+
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
+#[cfg(feature = "tools")]
+pub mod tools;
 
 pub use atat;
 
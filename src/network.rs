@@ -3,8 +3,9 @@
 use crate::command::wifi::types::{OperationMode, ScannedWifiNetwork};
 use crate::error::WifiError;
 use crate::hex::from_hex;
-use atat::heapless_bytes::Bytes;
-use heapless::String;
+use heapless::{String, Vec};
+
+pub use crate::command::wifi::types::Bssid;
 
 use core::convert::TryFrom;
 
@@ -15,14 +16,49 @@ pub enum WifiMode {
     AccessPoint,
 }
 
+/// Frequency band a Wi-Fi network is operating in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WifiBand {
+    /// 2.4 GHz band (channels 1-14)
+    Band2_4GHz,
+    /// 5 GHz band (channels 32-177)
+    Band5GHz,
+    /// Channel is outside of the known 2.4 and 5 GHz ranges
+    Unknown,
+}
+
+impl WifiBand {
+    /// Whether `channel` is a 2.4 GHz channel, or a 20 MHz 5 GHz channel.
+    pub fn is_valid_channel(channel: u8) -> bool {
+        match channel {
+            1..=14 => true,
+            36..=64 | 100..=144 => channel % 4 == 0,
+            149..=165 => channel % 4 == 1,
+            _ => false,
+        }
+    }
+
+    /// Derive the frequency band from a Wi-Fi channel number.
+    pub fn from_channel(channel: u8) -> Self {
+        match channel {
+            1..=14 => Self::Band2_4GHz,
+            32..=177 => Self::Band5GHz,
+            _ => Self::Unknown,
+        }
+    }
+}
+
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct WifiNetwork {
-    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
-    pub bssid: Bytes<20>,
+    /// BSSID of the network, all zeros for an access point started by the
+    /// module, which does not report its own.
+    pub bssid: Bssid,
     pub op_mode: OperationMode,
     pub ssid: String<64>,
     pub channel: u8,
+    pub band: WifiBand,
     pub rssi: i32,
     pub authentication_suites: u8,
     pub unicast_ciphers: u8,
@@ -31,12 +67,13 @@ pub struct WifiNetwork {
 }
 
 impl WifiNetwork {
-    pub fn new_station(bssid: Bytes<20>, channel: u8) -> Self {
+    pub fn new_station(bssid: Bssid, channel: u8) -> Self {
         Self {
             bssid,
             op_mode: OperationMode::Infrastructure,
             ssid: String::new(),
             channel,
+            band: WifiBand::from_channel(channel),
             rssi: 1,
             authentication_suites: 0,
             unicast_ciphers: 0,
@@ -45,12 +82,14 @@ impl WifiNetwork {
         }
     }
 
+    #[cfg(feature = "ap")]
     pub fn new_ap() -> Self {
         Self {
-            bssid: Bytes::new(),
+            bssid: Bssid::default(),
             op_mode: OperationMode::Infrastructure,
             ssid: String::new(),
             channel: 0,
+            band: WifiBand::Unknown,
             rssi: 1,
             authentication_suites: 0,
             unicast_ciphers: 0,
@@ -58,24 +97,567 @@ impl WifiNetwork {
             mode: WifiMode::AccessPoint,
         }
     }
+
+    /// Network found by a directed scan for `ssid`.
+    ///
+    /// Hidden networks answer the directed probe without broadcasting their
+    /// SSID, so the module reports them with an empty one.
+    pub(crate) fn from_directed_scan(
+        r: ScannedWifiNetwork,
+        ssid: &String<64>,
+    ) -> Result<Self, WifiError> {
+        let mut network = Self::try_from(r)?;
+        if network.ssid.is_empty() {
+            network.ssid = ssid.clone();
+        }
+        Ok(network)
+    }
+
+    /// Frequency band of the network, derived from its channel.
+    pub fn band(&self) -> WifiBand {
+        self.band
+    }
+
+    /// Whether the network accepts a credential of the given kind, see
+    /// [`Compatibility`].
+    ///
+    /// Mixed-mode networks accept every security they advertise, so a WPA2
+    /// credential is [`Compatibility::Degraded`] on a WPA2/WPA3 transition
+    /// network, while a WPA3 credential is [`Compatibility::Full`].
+    pub fn supports(&self, credential: &CredentialKind) -> Compatibility {
+        let auth = self.authentication_suites;
+        let ciphers = self.unicast_ciphers;
+        let wpa = auth & (auth_suite::WPA | auth_suite::WPA2 | auth_suite::WPA3);
+
+        // Whether the strongest security in use is legacy WPA or TKIP
+        let legacy =
+            wpa & (auth_suite::WPA2 | auth_suite::WPA3) == 0 || ciphers & cipher::CCMP == 0;
+
+        match credential {
+            CredentialKind::Open if auth == 0 && ciphers == 0 => Compatibility::Full,
+            CredentialKind::Wep if wpa == 0 && ciphers & (cipher::WEP64 | cipher::WEP128) != 0 => {
+                Compatibility::Full
+            }
+            CredentialKind::Wpa2Psk
+                if auth & auth_suite::PSK != 0
+                    && wpa & (auth_suite::WPA | auth_suite::WPA2) != 0 =>
+            {
+                if legacy || wpa & auth_suite::WPA3 != 0 {
+                    Compatibility::Degraded
+                } else {
+                    Compatibility::Full
+                }
+            }
+            CredentialKind::Wpa3Sae if wpa & auth_suite::WPA3 != 0 => Compatibility::Full,
+            CredentialKind::Enterprise if auth & auth_suite::EAP != 0 && wpa != 0 => {
+                if legacy {
+                    Compatibility::Degraded
+                } else {
+                    Compatibility::Full
+                }
+            }
+            _ => Compatibility::Incompatible,
+        }
+    }
+
+    /// Whether this scanned network is the given known network, by SSID, or
+    /// by BSSID if the SSID is hidden.
+    fn matches(&self, known: &KnownNetwork<'_>) -> bool {
+        if self.ssid.is_empty() {
+            known.bssid == Some(self.bssid)
+        } else {
+            self.ssid == known.ssid
+        }
+    }
+}
+
+/// Bits of [`WifiNetwork::authentication_suites`].
+pub mod auth_suite {
+    pub const SHARED_SECRET: u8 = 1 << 0;
+    pub const PSK: u8 = 1 << 1;
+    pub const EAP: u8 = 1 << 2;
+    pub const WPA: u8 = 1 << 3;
+    pub const WPA2: u8 = 1 << 4;
+    pub const WPA3: u8 = 1 << 5;
+}
+
+/// Bits of [`WifiNetwork::unicast_ciphers`] and
+/// [`WifiNetwork::group_ciphers`].
+pub mod cipher {
+    pub const WEP64: u8 = 1 << 0;
+    pub const WEP128: u8 = 1 << 1;
+    pub const TKIP: u8 = 1 << 2;
+    pub const CCMP: u8 = 1 << 3;
+}
+
+/// Kind of credential stored for a known network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CredentialKind {
+    /// No credential, for open networks.
+    Open,
+    /// WEP key.
+    Wep,
+    /// WPA/WPA2 personal passphrase or PSK.
+    Wpa2Psk,
+    /// WPA3 personal (SAE) password.
+    Wpa3Sae,
+    /// WPA/WPA2/WPA3 enterprise (EAP) credentials.
+    Enterprise,
+}
+
+/// Verdict of whether a stored credential can be used to join a network,
+/// ordered from worst to best.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Compatibility {
+    /// The network does not accept the credential.
+    Incompatible,
+    /// The network accepts the credential, but only using weaker security
+    /// than it offers, or only using legacy security (WPA, TKIP).
+    Degraded,
+    /// The network accepts the credential with its strongest security.
+    Full,
+}
+
+/// A network for which the application has stored credentials.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KnownNetwork<'a> {
+    /// Station configuration id holding the network.
+    pub config_id: u8,
+    pub ssid: &'a str,
+    /// BSSID of the network, used to recognize it if the SSID is hidden.
+    pub bssid: Option<Bssid>,
+    pub credential: CredentialKind,
+}
+
+/// RSSI penalty, in dB, of a network with [`Compatibility::Degraded`] in
+/// [`best_config_for`].
+pub const DEGRADED_RSSI_PENALTY: i32 = 20;
+
+/// Pick the best known network to join from `scan_results`, returning its
+/// configuration id and the scanned network.
+///
+/// A scanned network matches a known network by SSID, or by BSSID if the SSID
+/// is hidden. Incompatible networks are never picked. Of the remaining
+/// candidates, the one with the highest RSSI wins, where degraded networks
+/// are penalized by [`DEGRADED_RSSI_PENALTY`]. On a tie, the known network
+/// listed first wins.
+pub fn best_config_for<'n>(
+    scan_results: &'n [WifiNetwork],
+    known_networks: &[KnownNetwork<'_>],
+) -> Option<(u8, &'n WifiNetwork)> {
+    let mut best: Option<(i32, u8, &'n WifiNetwork)> = None;
+
+    for known in known_networks {
+        for network in scan_results.iter().filter(|n| n.matches(known)) {
+            let score = match network.supports(&known.credential) {
+                Compatibility::Full => network.rssi,
+                Compatibility::Degraded => network.rssi - DEGRADED_RSSI_PENALTY,
+                Compatibility::Incompatible => continue,
+            };
+
+            if best.map_or(true, |(best_score, _, _)| score > best_score) {
+                best = Some((score, known.config_id, network));
+            }
+        }
+    }
+
+    best.map(|(_, config_id, network)| (config_id, network))
+}
+
+/// Order `networks` by RSSI, strongest first, keeping the strongest `N`.
+///
+/// An access point reported more than once, by BSSID, is kept only with its
+/// strongest RSSI. Networks of equal RSSI keep the order of `networks`.
+pub(crate) fn strongest_networks<const N: usize>(
+    networks: impl IntoIterator<Item = WifiNetwork>,
+) -> Vec<WifiNetwork, N> {
+    let mut strongest: Vec<WifiNetwork, N> = Vec::new();
+
+    for network in networks {
+        if let Some(i) = strongest.iter().position(|n| n.bssid == network.bssid) {
+            if strongest[i].rssi >= network.rssi {
+                continue;
+            }
+            strongest.remove(i);
+        }
+
+        let Some(i) = strongest
+            .iter()
+            .position(|n| n.rssi < network.rssi)
+            .or((!strongest.is_full()).then_some(strongest.len()))
+        else {
+            continue;
+        };
+        if strongest.is_full() {
+            strongest.pop();
+        }
+        // Room was made above
+        let _ = strongest.insert(i, network);
+    }
+
+    strongest
+}
+
+/// The module reports authentication suites and ciphers as hexadecimal values,
+/// which are deserialized as if they were decimal.
+fn decimal_as_hex(value: u8) -> Option<u8> {
+    if value > 99 {
+        return None;
+    }
+    let mut digits = [b'0' + value / 10, b'0' + value % 10];
+    from_hex(&mut digits).ok().map(|v| v[0])
 }
 
 impl TryFrom<ScannedWifiNetwork> for WifiNetwork {
     type Error = WifiError;
 
     fn try_from(r: ScannedWifiNetwork) -> Result<Self, Self::Error> {
+        let band = WifiBand::from_channel(r.channel);
+        if band == WifiBand::Unknown {
+            return Err(WifiError::InvalidChannel(r.channel));
+        }
+
         Ok(WifiNetwork {
             bssid: r.bssid,
             op_mode: r.op_mode,
             ssid: r.ssid,
             channel: r.channel,
+            band,
             rssi: r.rssi,
-            authentication_suites: from_hex(&mut [r.authentication_suites])
-                .map_err(|_| Self::Error::HexError)?[0], // TODO: Better solution
-            unicast_ciphers: from_hex(&mut [r.unicast_ciphers])
-                .map_err(|_| Self::Error::HexError)?[0],
-            group_ciphers: r.group_ciphers,
+            authentication_suites: decimal_as_hex(r.authentication_suites)
+                .ok_or(WifiError::InvalidAuthenticationSuites)?,
+            unicast_ciphers: decimal_as_hex(r.unicast_ciphers).ok_or(WifiError::InvalidCiphers)?,
+            group_ciphers: decimal_as_hex(r.group_ciphers).ok_or(WifiError::InvalidCiphers)?,
             mode: WifiMode::Station,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn scanned(bssid: &[u8], channel: u8) -> ScannedWifiNetwork {
+        ScannedWifiNetwork {
+            bssid: Bssid::parse(bssid).unwrap(),
+            op_mode: OperationMode::Infrastructure,
+            ssid: String::try_from("network").unwrap(),
+            channel,
+            rssi: -60,
+            authentication_suites: 18,
+            unicast_ciphers: 8,
+            group_ciphers: 8,
+        }
+    }
+
+    #[test]
+    fn scanned_network() {
+        let network = WifiNetwork::try_from(scanned(b"D4CA6DF5F2F0", 6)).unwrap();
+
+        assert_eq!(network.ssid, "network");
+        assert_eq!(network.channel, 6);
+        assert_eq!(network.band(), WifiBand::Band2_4GHz);
+        assert_eq!(network.authentication_suites, 0x18);
+        assert_eq!(network.unicast_ciphers, 0x08);
+        assert_eq!(network.group_ciphers, 0x08);
+        assert_eq!(network.mode, WifiMode::Station);
+    }
+
+    #[test]
+    fn scanned_network_5ghz() {
+        let network = WifiNetwork::try_from(scanned(b"D4:CA:6D:F5:F2:F0", 36)).unwrap();
+        assert_eq!(network.band(), WifiBand::Band5GHz);
+    }
+
+    #[test]
+    fn valid_channels() {
+        for channel in [1, 6, 13, 14, 36, 64, 100, 144, 149, 165] {
+            assert!(WifiBand::is_valid_channel(channel), "{}", channel);
+        }
+        for channel in [0, 15, 34, 38, 68, 148, 150, 169] {
+            assert!(!WifiBand::is_valid_channel(channel), "{}", channel);
+        }
+    }
+
+    #[test]
+    fn directed_scan_hidden_network() {
+        let ssid = String::try_from("hidden").unwrap();
+
+        let mut hidden = scanned(b"D4CA6DF5F2F0", 6);
+        hidden.ssid.clear();
+        let network = WifiNetwork::from_directed_scan(hidden, &ssid).unwrap();
+        assert_eq!(network.ssid, "hidden");
+        assert_eq!(network.channel, 6);
+
+        // A broadcast SSID is kept
+        let network = WifiNetwork::from_directed_scan(scanned(b"D4CA6DF5F2F0", 6), &ssid).unwrap();
+        assert_eq!(network.ssid, "network");
+    }
+
+    #[test]
+    fn truncated_bssid() {
+        assert!(atat::serde_at::from_slice::<ScannedWifiNetwork>(
+            b"+UWSCAN:D4CA6D,1,\"network\",6,-60,18,8,8"
+        )
+        .is_err());
+        assert!(atat::serde_at::from_slice::<ScannedWifiNetwork>(
+            b"+UWSCAN:,1,\"network\",6,-60,18,8,8"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn same_bssid_from_scan_and_link_urc() {
+        use crate::command::{wifi::urc::WifiLinkConnected, Urc};
+        use atat::AtatUrc;
+
+        let scanned = atat::serde_at::from_slice::<ScannedWifiNetwork>(
+            b"+UWSCAN:D4CA6DF5F2F0,1,\"network\",6,-60,18,8,8",
+        )
+        .unwrap();
+
+        let Some(Urc::WifiLinkConnected(WifiLinkConnected { bssid, .. })) =
+            Urc::parse(b"+UUWLE:0,D4:CA:6D:F5:F2:F0,6")
+        else {
+            panic!("expected WifiLinkConnected");
+        };
+
+        assert_eq!(scanned.bssid, bssid);
+        assert_eq!(
+            WifiNetwork::try_from(scanned).unwrap().bssid,
+            WifiNetwork::new_station(bssid, 6).bssid
+        );
+    }
+
+    #[test]
+    fn invalid_channel() {
+        assert!(matches!(
+            WifiNetwork::try_from(scanned(b"D4CA6DF5F2F0", 0)),
+            Err(WifiError::InvalidChannel(0))
+        ));
+    }
+
+    fn network(ssid: &str, bssid: &[u8], rssi: i32, auth: u8, ciphers: u8) -> WifiNetwork {
+        let mut network = WifiNetwork::new_station(Bssid::parse(bssid).unwrap(), 6);
+        network.ssid = String::try_from(ssid).unwrap();
+        network.rssi = rssi;
+        network.authentication_suites = auth;
+        network.unicast_ciphers = ciphers;
+        network.group_ciphers = ciphers;
+        network
+    }
+
+    const WPA2_PSK: u8 = auth_suite::PSK | auth_suite::WPA2;
+
+    fn known(config_id: u8, ssid: &str, credential: CredentialKind) -> KnownNetwork<'_> {
+        KnownNetwork {
+            config_id,
+            ssid,
+            bssid: None,
+            credential,
+        }
+    }
+
+    #[test]
+    fn compatibility() {
+        use CredentialKind::*;
+
+        let open = network("n", b"D4CA6DF5F2F0", -60, 0, 0);
+        assert_eq!(open.supports(&Open), Compatibility::Full);
+        assert_eq!(open.supports(&Wpa2Psk), Compatibility::Incompatible);
+
+        let wep = network(
+            "n",
+            b"D4CA6DF5F2F0",
+            -60,
+            auth_suite::SHARED_SECRET,
+            cipher::WEP128,
+        );
+        assert_eq!(wep.supports(&Wep), Compatibility::Full);
+        assert_eq!(wep.supports(&Open), Compatibility::Incompatible);
+
+        let wpa2 = network("n", b"D4CA6DF5F2F0", -60, WPA2_PSK, cipher::CCMP);
+        assert_eq!(wpa2.supports(&Wpa2Psk), Compatibility::Full);
+        assert_eq!(wpa2.supports(&Wpa3Sae), Compatibility::Incompatible);
+        assert_eq!(wpa2.supports(&Enterprise), Compatibility::Incompatible);
+
+        // Mixed WPA/WPA2 with TKIP only
+        let legacy = network(
+            "n",
+            b"D4CA6DF5F2F0",
+            -60,
+            WPA2_PSK | auth_suite::WPA,
+            cipher::TKIP,
+        );
+        assert_eq!(legacy.supports(&Wpa2Psk), Compatibility::Degraded);
+
+        // WPA2/WPA3 transition mode
+        let transition = network(
+            "n",
+            b"D4CA6DF5F2F0",
+            -60,
+            WPA2_PSK | auth_suite::WPA3,
+            cipher::CCMP,
+        );
+        assert_eq!(transition.supports(&Wpa2Psk), Compatibility::Degraded);
+        assert_eq!(transition.supports(&Wpa3Sae), Compatibility::Full);
+
+        // Upgraded to WPA3 only
+        let wpa3 = network("n", b"D4CA6DF5F2F0", -60, auth_suite::WPA3, cipher::CCMP);
+        assert_eq!(wpa3.supports(&Wpa2Psk), Compatibility::Incompatible);
+        assert_eq!(wpa3.supports(&Wpa3Sae), Compatibility::Full);
+
+        let enterprise = network(
+            "n",
+            b"D4CA6DF5F2F0",
+            -60,
+            auth_suite::EAP | auth_suite::WPA2,
+            cipher::CCMP,
+        );
+        assert_eq!(enterprise.supports(&Enterprise), Compatibility::Full);
+        assert_eq!(enterprise.supports(&Wpa2Psk), Compatibility::Incompatible);
+    }
+
+    #[test]
+    fn best_config_ordering() {
+        let scan = [
+            network("home", b"D4CA6DF5F2F0", -70, WPA2_PSK, cipher::CCMP),
+            network("office", b"D4CA6DF5F2F1", -50, WPA2_PSK, cipher::CCMP),
+            network("legacy", b"D4CA6DF5F2F2", -45, WPA2_PSK, cipher::TKIP),
+            network(
+                "upgraded",
+                b"D4CA6DF5F2F3",
+                -30,
+                auth_suite::WPA3,
+                cipher::CCMP,
+            ),
+        ];
+
+        // Strongest compatible network wins
+        let known_networks = [
+            known(0, "home", CredentialKind::Wpa2Psk),
+            known(1, "office", CredentialKind::Wpa2Psk),
+        ];
+        let (config_id, network) = best_config_for(&scan, &known_networks).unwrap();
+        assert_eq!(config_id, 1);
+        assert_eq!(network.ssid, "office");
+
+        // A degraded network is penalized
+        let known_networks = [
+            known(0, "office", CredentialKind::Wpa2Psk),
+            known(1, "legacy", CredentialKind::Wpa2Psk),
+        ];
+        assert_eq!(best_config_for(&scan, &known_networks).unwrap().0, 0);
+
+        // Incompatible networks are never picked
+        let known_networks = [
+            known(0, "upgraded", CredentialKind::Wpa2Psk),
+            known(1, "home", CredentialKind::Wpa2Psk),
+        ];
+        assert_eq!(best_config_for(&scan, &known_networks).unwrap().0, 1);
+
+        let known_networks = [known(0, "upgraded", CredentialKind::Wpa2Psk)];
+        assert!(best_config_for(&scan, &known_networks).is_none());
+
+        // Ties go to the first known network
+        let scan = [network(
+            "home",
+            b"D4CA6DF5F2F0",
+            -60,
+            WPA2_PSK,
+            cipher::CCMP,
+        )];
+        let known_networks = [
+            known(3, "home", CredentialKind::Wpa2Psk),
+            known(4, "home", CredentialKind::Wpa2Psk),
+        ];
+        assert_eq!(best_config_for(&scan, &known_networks).unwrap().0, 3);
+    }
+
+    #[test]
+    fn best_config_hidden_ssid() {
+        let scan = [network(
+            "",
+            b"D4:CA:6D:F5:F2:F0",
+            -60,
+            WPA2_PSK,
+            cipher::CCMP,
+        )];
+
+        let mut hidden = known(2, "hidden", CredentialKind::Wpa2Psk);
+        assert!(best_config_for(&scan, &[hidden]).is_none());
+
+        hidden.bssid = Some(Bssid::parse(b"d4ca6df5f2f0").unwrap());
+        assert_eq!(best_config_for(&scan, &[hidden]).unwrap().0, 2);
+    }
+
+    #[test]
+    fn scan_results_strongest_first() {
+        use crate::command::wifi::WifiScan;
+        use atat::AtatCmd;
+
+        let cmd = WifiScan { ssid: None };
+        let response = cmd
+            .parse(Ok(&b"+UWSCAN:D4CA6DF5F2F0,1,\"home\",6,-70,18,8,8\r\n\
+                +UWSCAN:D4CA6DF5F2F1,1,\"office\",36,-50,18,8,8\r\n\
+                +UWSCAN:D4CA6DF5F2F2,1,\"corp\",11,-60,14,8,8\r\n\
+                +UWSCAN:D4CA6DF5F2F0,1,\"home\",6,-65,18,8,8"[..]))
+            .unwrap();
+        assert_eq!(response.network_list.len(), 4);
+
+        let networks = || {
+            response
+                .network_list
+                .clone()
+                .into_iter()
+                .map(|n| WifiNetwork::try_from(n).unwrap())
+        };
+
+        // The access point seen twice is kept with its strongest RSSI
+        let strongest = strongest_networks::<8>(networks());
+        let ranked: std::vec::Vec<_> = strongest
+            .iter()
+            .map(|n| (n.ssid.as_str(), n.rssi))
+            .collect();
+        assert_eq!(ranked, [("office", -50), ("corp", -60), ("home", -65)]);
+        assert_eq!(
+            strongest[1].authentication_suites,
+            auth_suite::EAP | auth_suite::WPA2
+        );
+        assert_eq!(strongest[1].unicast_ciphers, cipher::CCMP);
+
+        // More networks than fit keep the strongest
+        let strongest = strongest_networks::<2>(networks());
+        let ranked: std::vec::Vec<_> = strongest.iter().map(|n| n.ssid.as_str()).collect();
+        assert_eq!(ranked, ["office", "corp"]);
+    }
+
+    #[test]
+    fn scan_results_equal_rssi() {
+        let strongest = strongest_networks::<4>([
+            network("a", b"D4CA6DF5F2F0", -60, WPA2_PSK, cipher::CCMP),
+            network("b", b"D4CA6DF5F2F1", -60, WPA2_PSK, cipher::CCMP),
+            network("c", b"D4CA6DF5F2F2", -40, WPA2_PSK, cipher::CCMP),
+            // A weaker duplicate does not replace the access point
+            network("a", b"D4CA6DF5F2F0", -80, WPA2_PSK, cipher::CCMP),
+        ]);
+        let ranked: std::vec::Vec<_> = strongest
+            .iter()
+            .map(|n| (n.ssid.as_str(), n.rssi))
+            .collect();
+        assert_eq!(ranked, [("c", -40), ("a", -60), ("b", -60)]);
+    }
+
+    #[test]
+    fn invalid_authentication_suites() {
+        let mut network = scanned(b"D4CA6DF5F2F0", 6);
+        network.authentication_suites = 100;
+        assert!(matches!(
+            WifiNetwork::try_from(network),
+            Err(WifiError::InvalidAuthenticationSuites)
+        ));
+    }
+}
@@ -1,6 +1,25 @@
+use embassy_time::Duration;
 use heapless::Vec;
 use no_std_net::Ipv4Addr;
 
+use crate::command::{
+    data_mode::{types::PeerConfigParameter, SetPeerConfiguration},
+    system::{
+        types::{DSRAssertMode, DTRMode},
+        SetDSROverride, SetDTRBehavior,
+    },
+    wifi::{
+        types::{PowerSaveMode, WifiConfig as WifiConfigParam},
+        SetWifiConfig,
+    },
+    OnOff,
+};
+use crate::connection::DnsServers;
+use crate::error::Error;
+
+/// Default time to wait for the link to come up when joining a network.
+pub const DEFAULT_JOIN_TIMEOUT: Duration = Duration::from_secs(20);
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy)]
 /// Channel to broadcast wireless hotspot on.
@@ -29,6 +48,7 @@ pub enum Band {
     Bg,
 }
 
+#[cfg(feature = "ap")]
 #[derive(Debug, Default)]
 pub struct HotspotOptions {
     pub(crate) channel: Option<Channel>,
@@ -36,6 +56,7 @@ pub struct HotspotOptions {
     pub(crate) dhcp_server: bool,
 }
 
+#[cfg(feature = "ap")]
 impl HotspotOptions {
     pub fn new() -> Self {
         Self {
@@ -50,6 +71,11 @@ impl HotspotOptions {
         self
     }
 
+    /// Band of the access point. The module derives the band from the
+    /// channel, so this picks channel 36 for [`Band::A`] when no channel is
+    /// set, and fails the start of the access point with
+    /// [`Error::InvalidChannel`](crate::error::Error::InvalidChannel) for a
+    /// channel outside of the band.
     pub fn band(mut self, band: Band) -> Self {
         self.band = Some(band);
         self
@@ -61,13 +87,54 @@ impl HotspotOptions {
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
-#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+/// Wi-Fi authentication method.
+///
+/// The `Debug` and `defmt::Format` implementations redact the passphrase, to
+/// avoid leaking credentials to logs.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
 pub enum WifiAuthentication<'a> {
     #[default]
     None,
     Wpa2Passphrase(&'a str),
     // Wpa2Psk(&'a [u8; 32]),
+    /// WPA3 SAE only. The passphrase is limited to
+    /// [`MAX_WPA3_PASSPHRASE_LEN`] characters.
+    Wpa3Passphrase(&'a str),
+    /// WPA2/WPA3 mixed mode, falling back to WPA2 on firmware without WPA3
+    /// support. The passphrase is limited to [`MAX_WPA3_PASSPHRASE_LEN`]
+    /// characters.
+    Wpa2Wpa3Passphrase(&'a str),
+    /// WPA2 enterprise, authenticating with EAP.
+    Enterprise(EnterpriseCredentials<'a>),
+}
+
+/// Longest WPA3 passphrase accepted by the module. Unlike for WPA2, a
+/// 64 character hex PSK cannot be given instead.
+pub const MAX_WPA3_PASSPHRASE_LEN: usize = 63;
+
+impl core::fmt::Debug for WifiAuthentication<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::None => write!(f, "None"),
+            Self::Wpa2Passphrase(_) => write!(f, "Wpa2Passphrase(***)"),
+            Self::Wpa3Passphrase(_) => write!(f, "Wpa3Passphrase(***)"),
+            Self::Wpa2Wpa3Passphrase(_) => write!(f, "Wpa2Wpa3Passphrase(***)"),
+            Self::Enterprise(credentials) => write!(f, "Enterprise({:?})", credentials),
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for WifiAuthentication<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Self::None => defmt::write!(f, "None"),
+            Self::Wpa2Passphrase(_) => defmt::write!(f, "Wpa2Passphrase(***)"),
+            Self::Wpa3Passphrase(_) => defmt::write!(f, "Wpa3Passphrase(***)"),
+            Self::Wpa2Wpa3Passphrase(_) => defmt::write!(f, "Wpa2Wpa3Passphrase(***)"),
+            Self::Enterprise(credentials) => defmt::write!(f, "Enterprise({})", credentials),
+        }
+    }
 }
 
 impl<'a> From<&'a str> for WifiAuthentication<'a> {
@@ -76,6 +143,123 @@ impl<'a> From<&'a str> for WifiAuthentication<'a> {
     }
 }
 
+/// EAP method of a WPA2 enterprise network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EapMethod {
+    /// PEAP, authenticating with a user name and password.
+    Peap,
+    /// EAP-TLS, authenticating with a client certificate.
+    Tls,
+}
+
+/// Longest user name or password accepted for EAP.
+pub const MAX_EAP_CREDENTIAL_LEN: usize = 31;
+
+/// Longest domain name accepted for EAP.
+pub const MAX_EAP_DOMAIN_LEN: usize = 63;
+
+/// Credentials of a WPA2 enterprise network.
+///
+/// Certificates and private keys are referenced by the name they were
+/// imported as with
+/// [`Control::import_credentials`](crate::asynch::control::Control::import_credentials),
+/// within the namespace of the client. Connecting fails with
+/// [`Error::MissingCaCertificate`] if the CA certificate is not imported.
+///
+/// The `Debug` and `defmt::Format` implementations redact the password.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct EnterpriseCredentials<'a> {
+    pub(crate) method: EapMethod,
+    pub(crate) username: Option<&'a str>,
+    pub(crate) password: Option<&'a str>,
+    pub(crate) domain: Option<&'a str>,
+    pub(crate) ca_certificate: &'a str,
+    pub(crate) client_certificate: Option<&'a str>,
+    pub(crate) client_private_key: Option<&'a str>,
+}
+
+impl<'a> EnterpriseCredentials<'a> {
+    /// PEAP with `username` and `password`, validating the server against
+    /// the CA certificate `ca_certificate`.
+    pub fn peap(username: &'a str, password: &'a str, ca_certificate: &'a str) -> Self {
+        Self {
+            method: EapMethod::Peap,
+            username: Some(username),
+            password: Some(password),
+            domain: None,
+            ca_certificate,
+            client_certificate: None,
+            client_private_key: None,
+        }
+    }
+
+    /// EAP-TLS with the client certificate `client_certificate` and its
+    /// private key `client_private_key`, validating the server against the
+    /// CA certificate `ca_certificate`.
+    pub fn tls(
+        ca_certificate: &'a str,
+        client_certificate: &'a str,
+        client_private_key: &'a str,
+    ) -> Self {
+        Self {
+            method: EapMethod::Tls,
+            username: None,
+            password: None,
+            domain: None,
+            ca_certificate,
+            client_certificate: Some(client_certificate),
+            client_private_key: Some(client_private_key),
+        }
+    }
+
+    /// Identity to present, required by some EAP-TLS servers.
+    pub fn username(mut self, username: &'a str) -> Self {
+        self.username = Some(username);
+        self
+    }
+
+    /// Domain name of the user.
+    pub fn domain(mut self, domain: &'a str) -> Self {
+        self.domain = Some(domain);
+        self
+    }
+
+    pub fn method(&self) -> EapMethod {
+        self.method
+    }
+}
+
+impl core::fmt::Debug for EnterpriseCredentials<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EnterpriseCredentials")
+            .field("method", &self.method)
+            .field("username", &self.username)
+            .field("password", &self.password.map(|_| "***"))
+            .field("domain", &self.domain)
+            .field("ca_certificate", &self.ca_certificate)
+            .field("client_certificate", &self.client_certificate)
+            .field("client_private_key", &self.client_private_key)
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for EnterpriseCredentials<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "EnterpriseCredentials {{ method: {}, username: {}, domain: {}, ca_certificate: {}, client_certificate: {}, client_private_key: {} }}",
+            self.method,
+            self.username,
+            self.domain,
+            self.ca_certificate,
+            self.client_certificate,
+            self.client_private_key
+        )
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 
@@ -90,7 +274,17 @@ pub struct ConnectionOptions<'a> {
     #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
     pub gateway: Option<Ipv4Addr>,
     #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
-    pub dns: Vec<Ipv4Addr, 2>,
+    pub dns: DnsServers,
+
+    /// Store the station configuration to persistent memory, once the
+    /// connection has been established.
+    pub persist: bool,
+
+    /// Time to wait for the link to come up, before giving up on joining the
+    /// network. Defaults to
+    /// [`Timeouts::connect`](crate::timeouts::Timeouts::connect).
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub join_timeout: Option<Duration>,
 }
 
 impl<'a> ConnectionOptions<'a> {
@@ -111,6 +305,28 @@ impl<'a> ConnectionOptions<'a> {
         self
     }
 
+    pub fn wpa3_passphrase(mut self, password: &'a str) -> Self {
+        self.auth = WifiAuthentication::Wpa3Passphrase(password);
+        self
+    }
+
+    pub fn wpa2_wpa3_passphrase(mut self, password: &'a str) -> Self {
+        self.auth = WifiAuthentication::Wpa2Wpa3Passphrase(password);
+        self
+    }
+
+    /// Join a WPA2 enterprise network with `credentials`.
+    pub fn enterprise(mut self, credentials: EnterpriseCredentials<'a>) -> Self {
+        self.auth = WifiAuthentication::Enterprise(credentials);
+        self
+    }
+
+    /// Authentication method, overriding any set before.
+    pub fn authentication(mut self, auth: WifiAuthentication<'a>) -> Self {
+        self.auth = auth;
+        self
+    }
+
     pub fn ip_address(mut self, ip_addr: Ipv4Addr) -> Self {
         self.ip = Some(ip_addr);
         self
@@ -129,7 +345,465 @@ impl<'a> ConnectionOptions<'a> {
     }
 
     pub fn dns_server(mut self, dns_serv: Vec<Ipv4Addr, 2>) -> Self {
-        self.dns = dns_serv;
+        self.dns = DnsServers {
+            primary: dns_serv.first().copied(),
+            secondary: dns_serv.get(1).copied(),
+        };
         self
     }
+
+    /// Primary DNS server of a static IP configuration.
+    pub fn dns1(mut self, dns: Ipv4Addr) -> Self {
+        self.dns.primary = Some(dns);
+        self
+    }
+
+    /// Secondary DNS server of a static IP configuration.
+    pub fn dns2(mut self, dns: Ipv4Addr) -> Self {
+        self.dns.secondary = Some(dns);
+        self
+    }
+
+    /// Drop the reference to the passphrase, e.g. once the connection has
+    /// been established, as the module keeps its own copy.
+    ///
+    /// The options only borrow the passphrase, so the buffer holding it is
+    /// not wiped, and should be cleared by its owner.
+    pub fn clear_credentials(&mut self) {
+        self.auth = WifiAuthentication::None;
+    }
+
+    /// Time to wait for the link to come up when joining the network.
+    pub fn join_timeout(mut self, timeout: Duration) -> Self {
+        self.join_timeout = Some(timeout);
+        self
+    }
+
+    /// Persist the station configuration after a successful connection.
+    ///
+    /// See [`Control::persist_station_config`](crate::asynch::control::Control::persist_station_config).
+    pub fn persist(mut self, persist: bool) -> Self {
+        self.persist = persist;
+        self
+    }
+}
+
+/// Setup of the UART control lines, for letting the module wake a sleeping
+/// host.
+///
+/// The module signals the host through its `UART_DTR` output, connected to
+/// the DSR input of the host, and watches its `UART_DSR` input, connected to
+/// the DTR output of the host:
+///
+/// - `dsr` selects when the module asserts the DSR line of the host. Only
+///   [`DSRAssertMode::WhenPeersConnected`] changes while the driver is in
+///   (extended) data mode, asserting the line when a remote peer connects.
+/// - `dtr` selects how the module reacts to the host deasserting its DTR
+///   line, e.g. when going to sleep.
+///
+/// Note that the module can only signal new peer connections, not data on an
+/// already connected peer. Peers that should stay connected while the host
+/// sleeps should use a keep-alive, e.g. `TcpSocket::set_keep_alive`.
+///
+/// The configuration is applied by the runner during initialization, see
+/// [`WifiConfig::WAKE_CONFIG`](crate::WifiConfig::WAKE_CONFIG). To sleep the
+/// host without losing socket state, suspend the runner with
+/// [`Control::suspend`](crate::asynch::control::Control::suspend) and resume it
+/// after waking up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WakeConfig {
+    pub dtr: DTRMode,
+    pub dsr: DSRAssertMode,
+    pub power_save: PowerSaveMode,
+    /// Keep the factory reset on the DTR toggle sequence enabled. Disabled
+    /// by default, as toggling DTR around host sleep could trigger it.
+    pub dtr_reset: bool,
+}
+
+/// Conflicting settings in a [`WakeConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeConflict {
+    /// [`DTRMode::Default`] would make the module leave data mode when the
+    /// host goes to sleep.
+    DtrLeavesDataMode,
+    /// [`DTRMode::DisconnectPeers`] refuses new connections while the host
+    /// sleeps, so the DSR line is never asserted.
+    DtrBlocksPeers,
+    /// The DSR line does not change while in data mode.
+    DsrNeverSignals,
+    /// Power save modes require hardware flow control, as the module may
+    /// miss data while waking up.
+    PowerSaveWithoutFlowControl,
+}
+
+impl WakeConfig {
+    /// Wake the host on peer connections, with the module in sleep mode.
+    pub const fn low_latency() -> Self {
+        Self {
+            dtr: DTRMode::Ignore,
+            dsr: DSRAssertMode::WhenPeersConnected,
+            power_save: PowerSaveMode::SleepMode,
+            dtr_reset: false,
+        }
+    }
+
+    /// Wake the host on peer connections, with the module in deep sleep
+    /// mode. This trades a longer wake up latency for lower power draw.
+    pub const fn low_power() -> Self {
+        Self {
+            power_save: PowerSaveMode::DeepSleepMode,
+            ..Self::low_latency()
+        }
+    }
+
+    pub const fn dtr(mut self, mode: DTRMode) -> Self {
+        self.dtr = mode;
+        self
+    }
+
+    pub const fn dsr(mut self, mode: DSRAssertMode) -> Self {
+        self.dsr = mode;
+        self
+    }
+
+    pub const fn power_save(mut self, mode: PowerSaveMode) -> Self {
+        self.power_save = mode;
+        self
+    }
+
+    pub const fn dtr_reset(mut self, enabled: bool) -> Self {
+        self.dtr_reset = enabled;
+        self
+    }
+
+    /// Check the configuration for conflicting settings, given whether
+    /// hardware flow control is used on the UART.
+    pub fn validate(&self, flow_control: bool) -> Result<(), WakeConflict> {
+        match self.dtr {
+            DTRMode::Default => return Err(WakeConflict::DtrLeavesDataMode),
+            DTRMode::DisconnectPeers => return Err(WakeConflict::DtrBlocksPeers),
+            DTRMode::Ignore => {}
+        }
+
+        if self.dsr != DSRAssertMode::WhenPeersConnected {
+            return Err(WakeConflict::DsrNeverSignals);
+        }
+
+        if self.power_save != PowerSaveMode::ActiveMode && !flow_control {
+            return Err(WakeConflict::PowerSaveWithoutFlowControl);
+        }
+
+        Ok(())
+    }
+
+    /// The commands applying the configuration, in the order they are to be
+    /// sent.
+    pub(crate) fn commands(
+        &self,
+    ) -> (
+        SetPeerConfiguration,
+        SetDTRBehavior,
+        SetDSROverride,
+        SetWifiConfig,
+    ) {
+        (
+            SetPeerConfiguration {
+                parameter: PeerConfigParameter::DTRReset(OnOff::from(self.dtr_reset)),
+            },
+            SetDTRBehavior {
+                mode: self.dtr.clone(),
+            },
+            SetDSROverride {
+                mode: self.dsr.clone(),
+            },
+            SetWifiConfig {
+                config_param: WifiConfigParam::PowerSaveMode(self.power_save.clone()),
+            },
+        )
+    }
+}
+
+/// Roaming between the access points of the network the station is joined
+/// to, set through [`WifiConfig::ROAMING`](crate::WifiConfig::ROAMING).
+///
+/// The runner samples the signal strength of the connection every
+/// `interval`. Once `hysteresis` consecutive samples are below
+/// `rssi_threshold`, it scans for the network, and re-associates with it if
+/// another access point is received stronger than the current one.
+///
+/// The station configuration of the module has no BSSID, so the module cannot
+/// be told which access point to associate with, it picks one itself,
+/// normally the strongest. The scan only keeps the station from dropping a
+/// weak link when there is nothing better in range. If the module associates
+/// with the same access point again, roaming is held off until the station
+/// moves to another access point or disconnects.
+///
+/// Sampling and scanning run next to the processing of URCs, with the scan
+/// given up on if the station connection changes meanwhile.
+///
+/// The [`LinkState`](crate::asynch::LinkState) stays up while re-associating,
+/// and only goes down if the re-association fails within
+/// [`Timeouts::connect`](crate::timeouts::Timeouts::connect). Sockets of the
+/// module are closed by the re-association nevertheless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RoamingConfig {
+    /// Signal strength in dBm below which a sample counts as weak.
+    pub rssi_threshold: i32,
+    /// Time between samples of the signal strength.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub interval: Duration,
+    /// Number of consecutive weak samples before roaming, at least 1.
+    pub hysteresis: u8,
+}
+
+impl RoamingConfig {
+    /// Roam below `rssi_threshold` dBm, sampling every 10 seconds, after 3
+    /// consecutive weak samples.
+    pub const fn new(rssi_threshold: i32) -> Self {
+        Self {
+            rssi_threshold,
+            interval: Duration::from_secs(10),
+            hysteresis: 3,
+        }
+    }
+
+    pub const fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub const fn hysteresis(mut self, samples: u8) -> Self {
+        self.hysteresis = samples;
+        self
+    }
+}
+
+/// Longest name of a certificate or private key, including the namespace
+/// prefix, that can be referenced by a TLS socket.
+pub const MAX_CREDENTIAL_NAME_LEN: usize = 15;
+
+/// Prefix applied to the names of the certificates and private keys imported
+/// into the module.
+///
+/// The module keeps all credentials in a single flat namespace, shared by
+/// everything running on the host, e.g. a bootloader and an application.
+/// Giving each of them a namespace keeps them from overwriting each other's
+/// credentials.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CredentialNamespace(&'static str);
+
+impl CredentialNamespace {
+    pub const fn new(prefix: &'static str) -> Self {
+        Self(prefix)
+    }
+
+    pub fn prefix(&self) -> &'static str {
+        self.0
+    }
+
+    /// Full name of the credential `name` in the module.
+    ///
+    /// Fails with [`Error::InvalidCredentialName`] if the name is empty or
+    /// contains characters other than ASCII alphanumerics, `_`, `-` and `.`,
+    /// as others have a meaning in AT commands or peer URLs. Fails with
+    /// [`Error::BadLength`] if the full name exceeds
+    /// [`MAX_CREDENTIAL_NAME_LEN`].
+    pub fn apply(&self, name: &str) -> Result<heapless::String<16>, Error> {
+        let valid = |s: &str| {
+            s.bytes()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b'.'))
+        };
+        if name.is_empty() || !valid(name) || !valid(self.0) {
+            return Err(Error::InvalidCredentialName);
+        }
+
+        if self.0.len() + name.len() > MAX_CREDENTIAL_NAME_LEN {
+            return Err(Error::BadLength);
+        }
+
+        let mut full_name = heapless::String::new();
+        full_name.push_str(self.0).map_err(|_| Error::BadLength)?;
+        full_name.push_str(name).map_err(|_| Error::BadLength)?;
+        Ok(full_name)
+    }
+
+    /// Name of the credential `full_name` within the namespace, or `None` if
+    /// it belongs to another namespace.
+    pub fn strip<'n>(&self, full_name: &'n str) -> Option<&'n str> {
+        full_name
+            .strip_prefix(self.0)
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Full name to import the credential `name` as, given the `existing`
+    /// full names in the module.
+    ///
+    /// Fails with [`Error::CredentialNameCollision`] if the full name already
+    /// exists, unless `overwrite` is set.
+    pub fn import_name<'e>(
+        &self,
+        name: &str,
+        existing: impl IntoIterator<Item = &'e str>,
+        overwrite: bool,
+    ) -> Result<heapless::String<16>, Error> {
+        let full_name = self.apply(name)?;
+
+        if !overwrite && existing.into_iter().any(|e| e == full_name.as_str()) {
+            return Err(Error::CredentialNameCollision);
+        }
+
+        Ok(full_name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use atat::AtatCmd;
+
+    fn written<Cmd: AtatCmd>(cmd: Cmd) -> String {
+        let mut buf = [0u8; 64];
+        let len = cmd.write(&mut buf);
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    fn command_sequence(config: &WakeConfig) -> [String; 4] {
+        let (dtr_reset, dtr, dsr, power_save) = config.commands();
+        [
+            written(dtr_reset),
+            written(dtr),
+            written(dsr),
+            written(power_save),
+        ]
+    }
+
+    #[test]
+    fn low_latency_profile() {
+        let config = WakeConfig::low_latency();
+        assert_eq!(config.validate(true), Ok(()));
+        assert_eq!(
+            command_sequence(&config),
+            [
+                "AT+UDCFG=1,0\r\n",
+                "AT&D0\r\n",
+                "AT&S2\r\n",
+                "AT+UWCFG=1,1\r\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn low_power_profile() {
+        let config = WakeConfig::low_power();
+        assert_eq!(config.validate(true), Ok(()));
+        assert_eq!(
+            command_sequence(&config),
+            [
+                "AT+UDCFG=1,0\r\n",
+                "AT&D0\r\n",
+                "AT&S2\r\n",
+                "AT+UWCFG=1,2\r\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn wake_conflicts() {
+        assert_eq!(
+            WakeConfig::low_latency().validate(false),
+            Err(WakeConflict::PowerSaveWithoutFlowControl)
+        );
+        assert_eq!(
+            WakeConfig::low_latency()
+                .power_save(PowerSaveMode::ActiveMode)
+                .validate(false),
+            Ok(())
+        );
+        assert_eq!(
+            WakeConfig::low_latency()
+                .dtr(DTRMode::Default)
+                .validate(true),
+            Err(WakeConflict::DtrLeavesDataMode)
+        );
+        assert_eq!(
+            WakeConfig::low_latency()
+                .dtr(DTRMode::DisconnectPeers)
+                .validate(true),
+            Err(WakeConflict::DtrBlocksPeers)
+        );
+        assert_eq!(
+            WakeConfig::low_latency()
+                .dsr(DSRAssertMode::DataMode)
+                .validate(true),
+            Err(WakeConflict::DsrNeverSignals)
+        );
+    }
+
+    #[test]
+    fn redacted_passphrase() {
+        let options = ConnectionOptions::new("network")
+            .wpa2_passphrase("secret passphrase")
+            .ip_address(Ipv4Addr::new(192, 168, 1, 10));
+
+        let debug = format!("{:?}", options);
+        assert!(!debug.contains("secret passphrase"));
+        assert!(debug.contains("Wpa2Passphrase(***)"));
+        assert!(debug.contains("network"));
+        assert!(debug.contains("192.168.1.10"));
+    }
+
+    #[test]
+    fn credential_namespace() {
+        let ns = CredentialNamespace::new("app_");
+        assert_eq!(ns.apply("ca").unwrap(), "app_ca");
+        assert_eq!(ns.strip("app_ca"), Some("ca"));
+        assert_eq!(ns.strip("boot_ca"), None);
+        assert_eq!(ns.strip("app_"), None);
+
+        let ns = CredentialNamespace::default();
+        assert_eq!(ns.apply("ca").unwrap(), "ca");
+        assert_eq!(ns.strip("boot_ca"), Some("boot_ca"));
+    }
+
+    #[test]
+    fn invalid_credential_names() {
+        let ns = CredentialNamespace::new("app_");
+        assert!(matches!(ns.apply(""), Err(Error::InvalidCredentialName)));
+        assert!(matches!(
+            ns.apply("ca&x=1"),
+            Err(Error::InvalidCredentialName)
+        ));
+        assert!(matches!(
+            ns.apply("c\"a"),
+            Err(Error::InvalidCredentialName)
+        ));
+        assert!(matches!(ns.apply("long_client.crt"), Err(Error::BadLength)));
+
+        let ns = CredentialNamespace::new("app,");
+        assert!(matches!(ns.apply("ca"), Err(Error::InvalidCredentialName)));
+    }
+
+    #[test]
+    fn credential_name_collision() {
+        let ns = CredentialNamespace::new("app_");
+        let existing = ["boot_ca", "app_ca"];
+
+        // Same name in another namespace
+        assert_eq!(ns.import_name("cert", existing, false).unwrap(), "app_cert");
+        assert_eq!(
+            CredentialNamespace::new("boot_")
+                .import_name("cert", existing, false)
+                .unwrap(),
+            "boot_cert"
+        );
+
+        assert!(matches!(
+            ns.import_name("ca", existing, false),
+            Err(Error::CredentialNameCollision)
+        ));
+        assert_eq!(ns.import_name("ca", existing, true).unwrap(), "app_ca");
+    }
 }
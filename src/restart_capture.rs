@@ -0,0 +1,179 @@
+//! Capture of module diagnostics after an unexpected restart.
+//!
+//! When a module restarts on its own, u-blox support asks for its status
+//! right after the restart, which is gone by the time anyone looks into it.
+//! With [`WifiConfig::RESTART_CAPTURE_BUDGET`](crate::WifiConfig::RESTART_CAPTURE_BUDGET)
+//! set, the runner queries the module as soon as it reports an unexpected
+//! restart, before initializing it again, and keeps the raw responses. The
+//! capture of the last restart is available from
+//! [`Control::last_restart`](crate::asynch::control::Control::last_restart).
+use atat::{asynch::AtatClient, AtatCmd, AtatResp};
+use embassy_time::{with_timeout, Duration, Instant};
+use heapless::Vec;
+
+/// Maximum size of a [`RestartCapture`].
+pub const MAX_RESTART_CAPTURE_LEN: usize = 256;
+
+/// Diagnostic queries issued after an unexpected restart: the uptime and the
+/// settings status of the module, and its firmware version.
+const DIAGNOSTIC_QUERIES: [&[u8]; 3] = [b"AT+UMSTAT=0\r\n", b"AT+UMSTAT=1\r\n", b"AT+CGMR\r\n"];
+
+/// Time to wait for the response to a single diagnostic query.
+const DIAGNOSTIC_TIMEOUT_MS: u32 = 1000;
+
+/// Diagnostics captured after an unexpected restart of the module.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RestartCapture {
+    /// Time the restart was reported.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub timestamp: Instant,
+    /// Each diagnostic query, followed by its raw response, or by `ERROR` if
+    /// the query failed.
+    pub data: Vec<u8, MAX_RESTART_CAPTURE_LEN>,
+    /// The capture was cut short, as it did not fit
+    /// [`MAX_RESTART_CAPTURE_LEN`], or ran out of time.
+    pub truncated: bool,
+}
+
+impl RestartCapture {
+    fn new(timestamp: Instant) -> Self {
+        Self {
+            timestamp,
+            data: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    /// Append `bytes`, as much as fits. Returns `false` if the capture is
+    /// full.
+    fn append(&mut self, bytes: &[u8]) -> bool {
+        let n = bytes.len().min(self.data.capacity() - self.data.len());
+        self.data.extend_from_slice(&bytes[..n]).ok();
+        if n < bytes.len() {
+            self.truncated = true;
+        }
+        !self.truncated
+    }
+}
+
+/// Raw response to a [`DiagnosticQuery`].
+struct RawResponse(Vec<u8, MAX_RESTART_CAPTURE_LEN>);
+
+impl AtatResp for RawResponse {}
+
+/// A diagnostic query, as sent to the module.
+struct DiagnosticQuery(&'static [u8]);
+
+impl AtatCmd for DiagnosticQuery {
+    type Response = RawResponse;
+
+    const MAX_LEN: usize = 16;
+
+    const MAX_TIMEOUT_MS: u32 = DIAGNOSTIC_TIMEOUT_MS;
+
+    fn write(&self, buf: &mut [u8]) -> usize {
+        buf[..self.0.len()].copy_from_slice(self.0);
+        self.0.len()
+    }
+
+    fn parse(
+        &self,
+        resp: Result<&[u8], atat::InternalError>,
+    ) -> core::result::Result<Self::Response, atat::Error> {
+        let resp = resp.map_err(atat::Error::from)?;
+        let n = resp.len().min(MAX_RESTART_CAPTURE_LEN);
+        Ok(RawResponse(Vec::from_slice(&resp[..n]).unwrap_or_default()))
+    }
+}
+
+/// Issue the diagnostic queries, within `budget`, capturing the responses.
+///
+/// Queries are not retried, to keep the capture within budget.
+pub(crate) async fn capture<A: AtatClient>(at_client: &mut A, budget: Duration) -> RestartCapture {
+    let mut capture = RestartCapture::new(Instant::now());
+
+    let queries = async {
+        for query in DIAGNOSTIC_QUERIES {
+            if !capture.append(query) {
+                return;
+            }
+
+            let full = match at_client.send(&DiagnosticQuery(query)).await {
+                Ok(RawResponse(response)) => !capture.append(&response),
+                Err(e) => {
+                    warn!("Diagnostic query failed: {:?}", e);
+                    !capture.append(b"ERROR")
+                }
+            };
+            if full || !capture.append(b"\r\n") {
+                return;
+            }
+        }
+    };
+
+    if with_timeout(budget, queries).await.is_err() {
+        warn!("Restart capture ran out of time");
+        capture.truncated = true;
+    }
+
+    capture
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::MockUbloxModule;
+
+    /// Time does not advance in tests, so only queries left unanswered run
+    /// out of time.
+    const BUDGET: Duration = Duration::from_millis(0);
+
+    fn capture_with(module: &mut MockUbloxModule) -> RestartCapture {
+        embassy_futures::block_on(capture(module, BUDGET))
+    }
+
+    #[test]
+    fn scripted_responses() {
+        let mut module = MockUbloxModule::new();
+        module.respond("AT+UMSTAT=0", "+UMSTAT:0,1");
+        module.fail("AT+UMSTAT=1", atat::Error::Error);
+        module.respond("AT+CGMR", "7.0.0-049");
+        let capture = capture_with(&mut module);
+
+        assert!(!capture.truncated);
+        assert_eq!(
+            capture.data.as_slice(),
+            b"AT+UMSTAT=0\r\n+UMSTAT:0,1\r\nAT+UMSTAT=1\r\nERROR\r\nAT+CGMR\r\n7.0.0-049\r\n"
+        );
+    }
+
+    #[test]
+    fn oversized() {
+        let mut module = MockUbloxModule::new();
+        module.respond("AT+UMSTAT=0", "+UMSTAT:0,1");
+        module.respond("AT+UMSTAT=1", &"x".repeat(MAX_RESTART_CAPTURE_LEN));
+        let capture = capture_with(&mut module);
+
+        assert!(capture.truncated);
+        assert_eq!(capture.data.len(), MAX_RESTART_CAPTURE_LEN);
+        assert!(capture
+            .data
+            .starts_with(b"AT+UMSTAT=0\r\n+UMSTAT:0,1\r\nAT+UMSTAT=1\r\nxxx"));
+    }
+
+    #[test]
+    fn out_of_time() {
+        // The module answers the first query only
+        let mut module = MockUbloxModule::new();
+        module.respond("AT+UMSTAT=0", "+UMSTAT:0,1");
+        module.stall("AT");
+        let capture = capture_with(&mut module);
+
+        assert!(capture.truncated);
+        assert_eq!(
+            capture.data.as_slice(),
+            b"AT+UMSTAT=0\r\n+UMSTAT:0,1\r\nAT+UMSTAT=1\r\n"
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Compact binary snapshots of the driver statistics, for shipping to a
+//! backend.
+//!
+//! A [`MetricsSnapshot`] is encoded using [postcard](https://docs.rs/postcard)
+//! into a caller provided buffer, without allocating.
+//!
+//! # Schema evolution
+//!
+//! Every snapshot starts with a [`SCHEMA_VERSION`] byte. The schema is
+//! append-only: new fields are only ever added to the end of
+//! [`MetricsSnapshot`], and bump the schema version. Existing fields are
+//! never removed, reordered or changed in type, so a decoder for version `n`
+//! can decode the leading fields of any version `>= n`.
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::asynch::control::Control;
+use crate::asynch::{LinkState, LINK_HISTORY_LEN};
+
+/// Version of the snapshot schema.
+pub const SCHEMA_VERSION: u8 = 1;
+
+/// A recorded link state transition, see
+/// [`LinkEvent`](crate::asynch::LinkEvent).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkTransition {
+    /// Time of the transition, in milliseconds since boot.
+    pub timestamp_ms: u64,
+    /// Whether the link went up or down.
+    pub up: bool,
+    /// Disconnect reason reported by the module, as its numeric code.
+    pub reason: Option<u8>,
+}
+
+/// Snapshot of the driver statistics.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    /// Always [`SCHEMA_VERSION`] for snapshots captured by this version of
+    /// the driver.
+    pub version: u8,
+    /// Time of capture, in milliseconds since boot.
+    pub uptime_ms: u64,
+    pub link_up: bool,
+    /// See [`UrcStats::high_water`](crate::asynch::UrcStats::high_water).
+    pub urc_high_water: u32,
+    /// See [`UrcStats::lost`](crate::asynch::UrcStats::lost).
+    pub urc_lost: u64,
+    /// Link state transitions, oldest first.
+    pub link_transitions: Vec<LinkTransition, LINK_HISTORY_LEN>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EncodeError {
+    /// The buffer is too small to hold the encoded snapshot.
+    BufferTooSmall,
+    Serialize,
+}
+
+impl MetricsSnapshot {
+    /// Capture the current statistics of the driver.
+    ///
+    /// If `reset_counters` is set, the counters are cleared after being
+    /// captured, so the next snapshot only counts from this one. The link
+    /// history is kept, as the last transitions are of use in every snapshot,
+    /// see [`Control::link_history`].
+    pub fn capture<const INGRESS_BUF_SIZE: usize, const URC_CAPACITY: usize>(
+        control: &Control<'_, INGRESS_BUF_SIZE, URC_CAPACITY>,
+        reset_counters: bool,
+    ) -> Self {
+        let urc_stats = control.urc_stats();
+
+        let link_transitions = control
+            .link_history()
+            .iter()
+            .map(|event| LinkTransition {
+                timestamp_ms: event.timestamp.as_millis(),
+                up: event.link_state == LinkState::Up,
+                reason: event.reason.map(|r| r as u8),
+            })
+            .collect();
+
+        if reset_counters {
+            control.reset_urc_stats();
+        }
+
+        Self {
+            version: SCHEMA_VERSION,
+            uptime_ms: embassy_time::Instant::now().as_millis(),
+            link_up: control.link_state() == LinkState::Up,
+            urc_high_water: urc_stats.high_water as u32,
+            urc_lost: urc_stats.lost,
+            link_transitions,
+        }
+    }
+
+    /// Encode the snapshot into `buf`, returning the used part of the buffer.
+    pub fn encode<'b>(&self, buf: &'b mut [u8]) -> Result<&'b mut [u8], EncodeError> {
+        postcard::to_slice(self, buf).map_err(|e| match e {
+            postcard::Error::SerializeBufferFull => EncodeError::BufferTooSmall,
+            _ => EncodeError::Serialize,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn snapshot() -> MetricsSnapshot {
+        MetricsSnapshot {
+            version: SCHEMA_VERSION,
+            uptime_ms: 1000,
+            link_up: true,
+            urc_high_water: 3,
+            urc_lost: 2,
+            link_transitions: Vec::from_slice(&[
+                LinkTransition {
+                    timestamp_ms: 500,
+                    up: true,
+                    reason: None,
+                },
+                LinkTransition {
+                    timestamp_ms: 900,
+                    up: false,
+                    reason: Some(3),
+                },
+            ])
+            .unwrap(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buf = [0u8; 64];
+        let encoded = snapshot().encode(&mut buf).unwrap();
+
+        let decoded: MetricsSnapshot = postcard::from_bytes(encoded).unwrap();
+        assert_eq!(decoded, snapshot());
+    }
+
+    #[cfg(not(feature = "edm"))]
+    #[test]
+    fn reset_keeps_link_history() {
+        use crate::asynch::state;
+        use crate::test_util::Harness;
+
+        let harness = Harness::new();
+        let mut state = state::State::new();
+        let ch = state::Runner::new(&mut state);
+        let control = harness.control(&ch);
+        ch.record_urc_lost(2);
+
+        let first = MetricsSnapshot::capture(&control, true);
+        assert_eq!(first.urc_lost, 2);
+        assert_eq!(first.link_transitions.len(), 1);
+
+        let second = MetricsSnapshot::capture(&control, true);
+        assert_eq!(second.urc_lost, 0);
+        assert_eq!(second.link_transitions, first.link_transitions);
+    }
+
+    #[test]
+    fn schema_stability() {
+        let mut buf = [0u8; 64];
+        let encoded = snapshot().encode(&mut buf).unwrap();
+
+        assert_eq!(
+            encoded,
+            &[
+                0x01, // version
+                0xE8, 0x07, // uptime_ms
+                0x01, // link_up
+                0x03, // urc_high_water
+                0x02, // urc_lost
+                0x02, // link_transitions length
+                0xF4, 0x03, 0x01, 0x00, // 500 ms, up
+                0x84, 0x07, 0x00, 0x01, 0x03, // 900 ms, down, reason 3
+            ]
+        );
+    }
+
+    #[test]
+    fn buffer_too_small() {
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            snapshot().encode(&mut buf),
+            Err(EncodeError::BufferTooSmall)
+        );
+    }
+}
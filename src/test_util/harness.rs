@@ -0,0 +1,127 @@
+//! The runner side of the driver, for tests of the clients of the runner
+//! against a [`MockUbloxModule`].
+use core::future::Future;
+
+use atat::{ResponseSlot, UrcChannel};
+use embassy_futures::{
+    block_on,
+    select::{select, Either},
+};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel, mutex::Mutex, pipe::Pipe};
+use heapless::Vec;
+
+use super::MockUbloxModule;
+use crate::asynch::control::{CommandLock, Control, ProxyClient};
+use crate::asynch::runner::{MAX_CMD_LEN, URC_SUBSCRIBERS};
+use crate::asynch::{state, UbloxUrc};
+use crate::command::{wifi::urc::WifiLinkConnected, Urc};
+use crate::connection::WiFiState;
+use crate::network::WifiNetwork;
+use crate::timeouts::Timeouts;
+
+pub(crate) const INGRESS_BUF_SIZE: usize = 256;
+pub(crate) const URC_CAPACITY: usize = 4;
+
+/// Channels between the runner and its clients, with the module end served
+/// by [`Harness::serve`].
+pub(crate) struct Harness {
+    pub(crate) res_slot: ResponseSlot<INGRESS_BUF_SIZE>,
+    pub(crate) urc_channel: UrcChannel<UbloxUrc, URC_CAPACITY, { URC_SUBSCRIBERS }>,
+    pub(crate) requests: Channel<NoopRawMutex, Vec<u8, MAX_CMD_LEN>, 1>,
+    cmd_lock: CommandLock,
+    raw_rx: Pipe<NoopRawMutex, INGRESS_BUF_SIZE>,
+    raw_tx: Pipe<NoopRawMutex, MAX_CMD_LEN>,
+}
+
+impl Harness {
+    pub(crate) fn new() -> Self {
+        Self {
+            res_slot: ResponseSlot::new(),
+            urc_channel: UrcChannel::new(),
+            requests: Channel::new(),
+            cmd_lock: Mutex::new(None),
+            raw_rx: Pipe::new(),
+            raw_tx: Pipe::new(),
+        }
+    }
+
+    /// A client, as given to the network device.
+    pub(crate) fn client(&self) -> ProxyClient<'_, INGRESS_BUF_SIZE> {
+        ProxyClient::new(
+            self.requests.sender(),
+            &self.res_slot,
+            &self.cmd_lock,
+            Timeouts::DEFAULT.command_default,
+        )
+    }
+
+    /// A control on `ch`, with the module marked initialized.
+    pub(crate) fn control<'a>(
+        &'a self,
+        ch: &state::Runner<'a>,
+    ) -> Control<'a, INGRESS_BUF_SIZE, URC_CAPACITY> {
+        ch.mark_initialized();
+        Control::new(
+            ch.clone(),
+            &self.urc_channel,
+            self.requests.sender(),
+            &self.res_slot,
+            &self.cmd_lock,
+            &self.raw_rx,
+            &self.raw_tx,
+        )
+    }
+
+    /// Run `test` to completion, with `module` serving its commands.
+    pub(crate) fn serve<F: Future>(&self, module: &mut MockUbloxModule, test: F) -> F::Output {
+        let mut buf = [0u8; INGRESS_BUF_SIZE];
+
+        #[cfg(feature = "edm")]
+        let digester = crate::command::custom_digest::EdmDigester::new();
+        #[cfg(not(feature = "edm"))]
+        let digester = atat::AtDigester::<UbloxUrc>::new();
+
+        let mut ingress = atat::Ingress::new(digester, &mut buf, &self.res_slot, &self.urc_channel);
+
+        match block_on(select(module.serve(&self.requests, &mut ingress), test)) {
+            Either::First(never) => never,
+            Either::Second(output) => output,
+        }
+    }
+
+    /// Follow the station connection from the link URCs, as the network
+    /// device does, with the network up as soon as the link is.
+    pub(crate) async fn device(&self, ch: &state::Runner<'_>) -> ! {
+        let mut subscription = self.urc_channel.subscribe().unwrap();
+        loop {
+            let event = subscription.next_message_pure().await;
+            #[cfg(feature = "edm")]
+            let Some(event) = event.extract_urc() else {
+                continue;
+            };
+
+            match event {
+                Urc::WifiLinkConnected(WifiLinkConnected { bssid, channel, .. }) => {
+                    ch.update_connection_with(|con| connect(con, bssid, channel))
+                }
+                Urc::WifiLinkDisconnected(_) => {
+                    ch.update_connection_with(|con| con.wifi_state = WiFiState::NotConnected)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Mark the station connected to `bssid`, with the network up.
+pub(crate) fn connect(
+    con: &mut crate::connection::WifiConnection,
+    bssid: crate::command::wifi::types::Bssid,
+    channel: u8,
+) {
+    con.wifi_state = WiFiState::Connected;
+    con.ipv4_up = true;
+    con.ipv6_link_local_up = true;
+    con.network
+        .replace(WifiNetwork::new_station(bssid, channel));
+}
@@ -0,0 +1,888 @@
+//! Simulated module, answering commands with canned responses.
+use std::collections::VecDeque;
+
+use atat::{asynch::AtatClient, AtatCmd, AtatIngress};
+use embassy_sync::{blocking_mutex::raw::NoopRawMutex, channel::Channel};
+use embassy_time::Duration;
+
+use super::ManualClock;
+use crate::asynch::runner::MAX_CMD_LEN;
+
+#[cfg(feature = "internal-network-stack")]
+use crate::command::edm::edm_frame;
+#[cfg(feature = "internal-network-stack")]
+use crate::command::edm::types::{
+    ConnectType, PayloadType, Protocol, AT_COMMAND_POSITION, PAYLOAD_OVERHEAD, STARTBYTE,
+};
+#[cfg(feature = "internal-network-stack")]
+use no_std_net::Ipv4Addr;
+#[cfg(feature = "internal-network-stack")]
+use ublox_sockets::{ChannelId, PeerHandle};
+
+/// Local port of the first peer connected by [`MockUbloxModule`].
+#[cfg(feature = "internal-network-stack")]
+const FIRST_LOCAL_PORT: u16 = 49152;
+
+/// Time passing between two commands served by
+/// [`serve()`](MockUbloxModule::serve), covering the pause the driver makes
+/// between commands.
+const COMMAND_GAP: Duration = Duration::from_millis(20);
+
+/// Reply of the mock to a command.
+#[derive(Clone)]
+enum Reply {
+    /// Response lines, followed by `OK`.
+    Response(String),
+    Error(atat::Error),
+    /// The `>` prompt for the binary data of a credential import.
+    Prompt,
+    /// No reply at all, as a module that stopped responding or lost its
+    /// reply.
+    Stall,
+}
+
+struct Canned {
+    prefix: String,
+    reply: Reply,
+}
+
+/// A credential import waiting for its binary data.
+struct Import {
+    data_type: u8,
+    name: String,
+    size: usize,
+    data: Vec<u8>,
+}
+
+struct Sent {
+    /// Bytes as written by the driver, EDM framed or not.
+    raw: Vec<u8>,
+    /// The AT command line, without any EDM framing.
+    line: Vec<u8>,
+}
+
+#[cfg(feature = "internal-network-stack")]
+struct MockPeer {
+    handle: PeerHandle,
+    channel_id: ChannelId,
+    data: Vec<u8>,
+}
+
+/// Simulated module, for testing code on top of the driver without hardware.
+///
+/// The mock is an [`AtatClient`], for code taking one directly, and can
+/// [`serve()`](MockUbloxModule::serve) the requests of the driver, for code
+/// going through [`Control`](crate::asynch::control::Control).
+///
+/// Commands are answered as an EDM AT confirmation if sent wrapped in an EDM
+/// frame, and as a plain AT response otherwise. Out of the box, the mock
+/// answers:
+///
+/// - `+UWSCAN` with the networks added by
+///   [`add_scan_result()`](MockUbloxModule::add_scan_result).
+/// - `+UWSCA` activating or deactivating a station, queueing the URCs of the
+///   link and the network coming up or going down.
+/// - `+USECMNG=0` importing a credential, with the `>` prompt for its data
+///   and the MD5 set by [`import_md5()`](MockUbloxModule::import_md5) once
+///   all of it is received, see [`imported()`](MockUbloxModule::imported).
+/// - `+UDCP` with a new peer handle. For URLs with an IPv4 address, the EDM
+///   connect event and `+UUDPC` URC of a new channel are queued.
+/// - `+UDCPC` closing a peer, queueing the EDM disconnect event and `+UUDPD`
+///   URC.
+/// - EDM data commands, recording the data sent on the channel, see
+///   [`sent_data()`](MockUbloxModule::sent_data).
+/// - Any other command with `OK`.
+///
+/// Peers and EDM framing require the `internal-network-stack` feature.
+///
+/// Responses of a command can be overridden with
+/// [`respond()`](MockUbloxModule::respond) and
+/// [`fail()`](MockUbloxModule::fail), or for the next matching command only
+/// with [`respond_once()`](MockUbloxModule::respond_once) and
+/// [`fail_once()`](MockUbloxModule::fail_once).
+///
+/// URCs, whether queued by the mock or injected by the test, are held back
+/// until delivered to an [`Ingress`](atat::Ingress), for example that of the
+/// [`Runner`](crate::asynch::Runner), with
+/// [`deliver()`](MockUbloxModule::deliver).
+#[derive(Default)]
+pub struct MockUbloxModule {
+    canned: Vec<Canned>,
+    canned_once: VecDeque<Canned>,
+    scan_results: Vec<String>,
+    sent: Vec<Sent>,
+    pending: VecDeque<Vec<u8>>,
+    /// URCs to queue after replying to a command, by command prefix.
+    triggered_urcs: Vec<(String, String)>,
+    /// Partial command line received by `serve()`.
+    line: Vec<u8>,
+    /// BSSID of the access point the station associates with.
+    bssid: String,
+    import: Option<Import>,
+    import_md5: String,
+    /// Time taken to store the data of an import, before answering it.
+    import_time: Option<Duration>,
+    /// Time taken to send the reply to the bytes last received by `serve()`.
+    reply_delay: Option<Duration>,
+    imported: Vec<(String, Vec<u8>)>,
+    #[cfg(feature = "internal-network-stack")]
+    peers: Vec<MockPeer>,
+    #[cfg(feature = "internal-network-stack")]
+    next_peer_handle: u8,
+    #[cfg(feature = "internal-network-stack")]
+    next_channel_id: u8,
+}
+
+impl MockUbloxModule {
+    pub fn new() -> Self {
+        Self {
+            bssid: "D47B75A1B2C3".into(),
+            import_md5: "00000000000000000000000000000000".into(),
+            #[cfg(feature = "internal-network-stack")]
+            next_peer_handle: 1,
+            #[cfg(feature = "internal-network-stack")]
+            next_channel_id: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Answer commands starting with `prefix`, e.g. `AT+CGMR`, with
+    /// `response`, followed by `OK`.
+    ///
+    /// Overrides the built-in response to the command. The first matching
+    /// prefix wins.
+    pub fn respond(&mut self, prefix: &str, response: &str) {
+        self.canned
+            .push(Canned::new(prefix, Reply::Response(response.into())));
+    }
+
+    /// Fail commands starting with `prefix` with `error`.
+    ///
+    /// Served commands are answered with `ERROR`, or not at all for
+    /// [`atat::Error::Timeout`].
+    pub fn fail(&mut self, prefix: &str, error: atat::Error) {
+        self.canned.push(Canned::new(prefix, Reply::Error(error)));
+    }
+
+    /// Never answer commands starting with `prefix`, leaving the command
+    /// pending until it is given up on.
+    ///
+    /// The command is acted on as usual, as by a module losing its reply,
+    /// e.g. a credential import still takes its data.
+    pub fn stall(&mut self, prefix: &str) {
+        self.canned.push(Canned::new(prefix, Reply::Stall));
+    }
+
+    /// As [`respond()`](Self::respond), for the next matching command only.
+    ///
+    /// Takes precedence over the other responses, in the order added.
+    pub fn respond_once(&mut self, prefix: &str, response: &str) {
+        self.canned_once
+            .push_back(Canned::new(prefix, Reply::Response(response.into())));
+    }
+
+    /// As [`fail()`](Self::fail), for the next matching command only.
+    pub fn fail_once(&mut self, prefix: &str, error: atat::Error) {
+        self.canned_once
+            .push_back(Canned::new(prefix, Reply::Error(error)));
+    }
+
+    /// Queue `urc` after replying to the next command starting with
+    /// `prefix`, e.g. a URC arriving between the prompt and the data of a
+    /// credential import.
+    pub fn inject_urc_after(&mut self, prefix: &str, urc: &str) {
+        self.triggered_urcs.push((prefix.into(), urc.into()));
+    }
+
+    /// Add a network to the scan results, as the `+UWSCAN:` line of the
+    /// module, e.g. `+UWSCAN:D47B75A1B2C3,1,"Blackbird",6,-52,18,8,8`.
+    pub fn add_scan_result(&mut self, line: &str) {
+        self.scan_results.push(line.into());
+    }
+
+    /// BSSID of the access point the station associates with on activation,
+    /// as 12 hexadecimal digits, `D47B75A1B2C3` by default.
+    pub fn station_bssid(&mut self, bssid: &str) {
+        self.bssid = bssid.into();
+    }
+
+    /// MD5 reported by the module for the credentials imported from now on,
+    /// as 32 hexadecimal digits.
+    pub fn import_md5(&mut self, md5: &str) {
+        self.import_md5 = md5.into();
+    }
+
+    /// Time the module takes to store the data of a credential import
+    /// before answering it, none by default.
+    ///
+    /// [`serve()`](MockUbloxModule::serve) advances the clock by it ahead of
+    /// the answer.
+    pub fn import_time(&mut self, duration: Duration) {
+        self.import_time = Some(duration);
+    }
+
+    /// Data of the last complete import of the credential `name`.
+    pub fn imported(&self, name: &str) -> Option<&[u8]> {
+        self.imported
+            .iter()
+            .rev()
+            .find(|(imported, _)| imported == name)
+            .map(|(_, data)| data.as_slice())
+    }
+
+    /// AT command lines sent to the module, oldest first, without any EDM
+    /// framing.
+    pub fn sent_commands(&self) -> impl Iterator<Item = &[u8]> {
+        self.sent.iter().map(|sent| sent.line.as_slice())
+    }
+
+    /// Commands sent to the module, oldest first, as written by the driver.
+    pub fn sent_raw(&self) -> impl Iterator<Item = &[u8]> {
+        self.sent.iter().map(|sent| sent.raw.as_slice())
+    }
+
+    /// Forget the commands sent so far.
+    pub fn clear_sent(&mut self) {
+        self.sent.clear();
+    }
+
+    /// Data sent on the channel of a peer connected by the mock.
+    #[cfg(feature = "internal-network-stack")]
+    pub fn sent_data(&self, channel_id: ChannelId) -> Option<&[u8]> {
+        self.peers
+            .iter()
+            .find(|peer| peer.channel_id == channel_id)
+            .map(|peer| peer.data.as_slice())
+    }
+
+    /// Channel of a peer connected by the mock.
+    #[cfg(feature = "internal-network-stack")]
+    pub fn channel_id(&self, handle: PeerHandle) -> Option<ChannelId> {
+        self.peers
+            .iter()
+            .find(|peer| peer.handle == handle)
+            .map(|peer| peer.channel_id)
+    }
+
+    /// Queue a URC, e.g. `+UUNU:0`.
+    pub fn inject_urc(&mut self, urc: &str) {
+        let urc = format!("\r\n{}\r\n", urc);
+
+        #[cfg(feature = "internal-network-stack")]
+        self.queue(PayloadType::ATEvent, urc.as_bytes());
+        #[cfg(not(feature = "internal-network-stack"))]
+        self.pending.push_back(urc.into_bytes());
+    }
+
+    /// Queue data received from the remote peer on `channel_id`.
+    #[cfg(feature = "internal-network-stack")]
+    pub fn inject_data(&mut self, channel_id: ChannelId, data: &[u8]) {
+        let mut payload = vec![channel_id.0];
+        payload.extend_from_slice(data);
+        self.queue(PayloadType::DataEvent, &payload);
+    }
+
+    /// Queue the EDM connect event of an IPv4 connection on `channel_id`.
+    #[cfg(feature = "internal-network-stack")]
+    pub fn inject_connect_event(
+        &mut self,
+        channel_id: ChannelId,
+        protocol: Protocol,
+        remote: (Ipv4Addr, u16),
+        local_port: u16,
+    ) {
+        let mut payload = vec![channel_id.0, ConnectType::IPv4 as u8, protocol as u8];
+        payload.extend_from_slice(&remote.0.octets());
+        payload.extend_from_slice(&remote.1.to_be_bytes());
+        payload.extend_from_slice(&Ipv4Addr::UNSPECIFIED.octets());
+        payload.extend_from_slice(&local_port.to_be_bytes());
+        self.queue(PayloadType::ConnectEvent, &payload);
+    }
+
+    /// Queue the EDM disconnect event of `channel_id`.
+    #[cfg(feature = "internal-network-stack")]
+    pub fn inject_disconnect_event(&mut self, channel_id: ChannelId) {
+        self.queue(PayloadType::DisconnectEvent, &[channel_id.0]);
+    }
+
+    /// Number of frames waiting to be delivered.
+    pub fn pending_frames(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Take the next frame waiting to be delivered.
+    pub fn next_frame(&mut self) -> Option<Vec<u8>> {
+        self.pending.pop_front()
+    }
+
+    /// Deliver all frames waiting to be delivered to `ingress`, in order.
+    pub async fn deliver(&mut self, ingress: &mut impl AtatIngress) {
+        while let Some(frame) = self.pending.pop_front() {
+            ingress.write(&frame).await;
+        }
+    }
+
+    /// Serve the commands written by the driver to `requests`, the request
+    /// channel of the runner, writing the replies to `ingress`. Never returns.
+    ///
+    /// Commands are taken as written by
+    /// [`Control`](crate::asynch::control::Control) in AT mode, or as EDM
+    /// frames, as written by the network stack. Pending URCs are delivered
+    /// after every reply. The clock of the mock time driver is advanced after
+    /// every command, as the driver waits for a short while between
+    /// commands.
+    pub async fn serve(
+        &mut self,
+        requests: &Channel<NoopRawMutex, heapless::Vec<u8, MAX_CMD_LEN>, 1>,
+        ingress: &mut impl AtatIngress,
+    ) -> ! {
+        loop {
+            self.deliver(ingress).await;
+
+            let request = requests.receive().await;
+            if let Some(reply) = self.receive(&request) {
+                if let Some(delay) = self.reply_delay.take() {
+                    // Let the driver see the time pass before the reply
+                    ManualClock::new().advance_by(delay);
+                    embassy_futures::yield_now().await;
+                }
+                ingress.write(&reply).await;
+            }
+            ManualClock::new().advance_by(COMMAND_GAP);
+        }
+    }
+
+    /// Handle bytes written by the driver, returning the reply once a
+    /// complete command is received.
+    fn receive(&mut self, bytes: &[u8]) -> Option<Vec<u8>> {
+        if let Some(import) = &mut self.import {
+            import.data.extend_from_slice(bytes);
+            if import.data.len() < import.size {
+                return None;
+            }
+            let import = self.import.take().unwrap();
+            self.reply_delay = self.import_time;
+            let reply = format!(
+                "\r\n+USECMNG:0,{},\"{}\",\"{}\"\r\nOK\r\n",
+                import.data_type, import.name, self.import_md5
+            );
+            self.imported.push((import.name, import.data));
+            return Some(reply.into_bytes());
+        }
+
+        #[cfg(feature = "internal-network-stack")]
+        if self.line.is_empty() && bytes.first() == Some(&STARTBYTE) {
+            return self.receive_edm(bytes);
+        }
+
+        self.line.extend_from_slice(bytes);
+        if !self.line.ends_with(b"\r\n") {
+            return None;
+        }
+        let line = core::mem::take(&mut self.line);
+        self.sent.push(Sent {
+            raw: line.clone(),
+            line: line.clone(),
+        });
+
+        let reply = self.respond_to(&line);
+        at_reply(reply)
+    }
+
+    /// Handle an EDM frame written by the driver.
+    #[cfg(feature = "internal-network-stack")]
+    fn receive_edm(&mut self, frame: &[u8]) -> Option<Vec<u8>> {
+        let payload = &frame[AT_COMMAND_POSITION..frame.len() - 1];
+        if frame[4] == PayloadType::DataCommand as u8 {
+            self.record_data(ChannelId(payload[0]), &payload[1..]);
+            return None;
+        }
+        if frame[4] != PayloadType::ATRequest as u8 {
+            return None;
+        }
+
+        self.sent.push(Sent {
+            raw: frame.to_vec(),
+            line: payload.to_vec(),
+        });
+
+        let reply = self.respond_to(payload);
+        at_reply(reply).map(|reply| edm_packet(PayloadType::ATConfirmation, &reply))
+    }
+
+    #[cfg(feature = "internal-network-stack")]
+    fn queue(&mut self, payload_type: PayloadType, payload: &[u8]) {
+        self.pending.push_back(edm_packet(payload_type, payload));
+    }
+
+    fn respond_to(&mut self, line: &[u8]) -> Reply {
+        let triggered = self
+            .triggered_urcs
+            .iter()
+            .position(|(prefix, _)| line.starts_with(prefix.as_bytes()))
+            .map(|i| self.triggered_urcs.remove(i).1);
+
+        let reply = self.reply_to(line);
+        if let Some(urc) = triggered {
+            self.inject_urc(&urc);
+        }
+        reply
+    }
+
+    fn reply_to(&mut self, line: &[u8]) -> Reply {
+        if let Some(i) = self
+            .canned_once
+            .iter()
+            .position(|canned| line.starts_with(canned.prefix.as_bytes()))
+        {
+            return self.canned_once.remove(i).unwrap().reply;
+        }
+        if let Some(canned) = self
+            .canned
+            .iter()
+            .find(|canned| line.starts_with(canned.prefix.as_bytes()))
+        {
+            let reply = canned.reply.clone();
+            if matches!(reply, Reply::Stall) {
+                self.builtin_reply(line);
+            }
+            return reply;
+        }
+
+        self.builtin_reply(line)
+    }
+
+    /// Reply to `line` of a module answering as usual.
+    fn builtin_reply(&mut self, line: &[u8]) -> Reply {
+        let Ok(line) = core::str::from_utf8(line) else {
+            return Reply::Error(atat::Error::Parse);
+        };
+        let line = line.trim_end();
+
+        if line == "AT+UWSCAN" || line.starts_with("AT+UWSCAN=") {
+            return Reply::Response(self.scan_results.join("\r\n"));
+        }
+
+        if let Some(args) = line.strip_prefix("AT+UWSCA=") {
+            match args.split_once(',') {
+                Some((_, "3")) => {
+                    self.inject_urc(&format!("+UUWLE:0,{},6", self.bssid));
+                    self.inject_urc("+UUNU:0");
+                }
+                Some((_, "4")) => {
+                    self.inject_urc("+UUWLD:0,0");
+                    self.inject_urc("+UUND:0");
+                }
+                _ => {}
+            }
+        } else if let Some(args) = line.strip_prefix("AT+USECMNG=0,") {
+            let mut args = args.split(',');
+            let (Some(data_type), Some(name), Some(size)) = (
+                args.next().and_then(|data_type| data_type.parse().ok()),
+                args.next(),
+                args.next().and_then(|size| size.parse().ok()),
+            ) else {
+                return Reply::Error(atat::Error::Error);
+            };
+            self.import = Some(Import {
+                data_type,
+                name: name.trim_matches('"').into(),
+                size,
+                data: Vec::new(),
+            });
+            return Reply::Prompt;
+        }
+
+        #[cfg(feature = "internal-network-stack")]
+        if let Some(url) = line.strip_prefix("AT+UDCP=") {
+            return match self.connect_peer(url.trim_matches('"')) {
+                Ok(handle) => Reply::Response(format!("+UDCP:{}", handle.0)),
+                Err(e) => Reply::Error(e),
+            };
+        } else if let Some(handle) = line.strip_prefix("AT+UDCPC=") {
+            let Ok(handle) = handle.parse() else {
+                return Reply::Error(atat::Error::Parse);
+            };
+            if let Err(e) = self.close_peer(PeerHandle(handle)) {
+                return Reply::Error(e);
+            }
+        }
+
+        Reply::Response(String::new())
+    }
+
+    #[cfg(feature = "internal-network-stack")]
+    fn connect_peer(&mut self, url: &str) -> Result<PeerHandle, atat::Error> {
+        let handle = PeerHandle(self.next_peer_handle);
+        let channel_id = ChannelId(self.next_channel_id);
+        self.peers.push(MockPeer {
+            handle,
+            channel_id,
+            data: Vec::new(),
+        });
+        self.next_peer_handle = self.next_peer_handle.wrapping_add(1);
+        self.next_channel_id = self.next_channel_id.wrapping_add(1);
+
+        if let Some((protocol, remote_ip, remote_port)) = parse_url(url) {
+            let local_port = FIRST_LOCAL_PORT + u16::from(handle.0);
+            let ip_protocol = protocol.clone() as u8;
+            self.inject_connect_event(channel_id, protocol, (remote_ip, remote_port), local_port);
+            self.inject_urc(&format!(
+                "+UUDPC:{},2,{},0.0.0.0,{},{},{}",
+                handle.0, ip_protocol, local_port, remote_ip, remote_port
+            ));
+        }
+
+        Ok(handle)
+    }
+
+    #[cfg(feature = "internal-network-stack")]
+    fn close_peer(&mut self, handle: PeerHandle) -> Result<(), atat::Error> {
+        let Some(i) = self.peers.iter().position(|peer| peer.handle == handle) else {
+            return Err(atat::Error::Error);
+        };
+        let peer = self.peers.swap_remove(i);
+
+        self.inject_disconnect_event(peer.channel_id);
+        self.inject_urc(&format!("+UUDPD:{}", handle.0));
+        Ok(())
+    }
+
+    #[cfg(feature = "internal-network-stack")]
+    fn record_data(&mut self, channel_id: ChannelId, data: &[u8]) {
+        if let Some(peer) = self
+            .peers
+            .iter_mut()
+            .find(|peer| peer.channel_id == channel_id)
+        {
+            peer.data.extend_from_slice(data);
+        }
+    }
+
+    /// Answer an EDM frame sent as a command.
+    #[cfg(feature = "internal-network-stack")]
+    async fn send_edm<Cmd: AtatCmd>(
+        &mut self,
+        cmd: &Cmd,
+        frame: &[u8],
+    ) -> Result<Cmd::Response, atat::Error> {
+        let payload = &frame[AT_COMMAND_POSITION..frame.len() - 1];
+        if frame[4] == PayloadType::DataCommand as u8 {
+            self.record_data(ChannelId(payload[0]), &payload[1..]);
+            return cmd.parse(Ok(&[]));
+        }
+        if frame[4] != PayloadType::ATRequest as u8 {
+            return cmd.parse(Ok(&[]));
+        }
+
+        self.sent.push(Sent {
+            raw: frame.to_vec(),
+            line: payload.to_vec(),
+        });
+
+        let mut response = match self.respond_to(payload) {
+            Reply::Response(response) => response.into_bytes(),
+            Reply::Prompt => b">".to_vec(),
+            Reply::Error(e) => return Err(e),
+            Reply::Stall => core::future::pending().await,
+        };
+        response.extend_from_slice(b"\r\nOK\r\n");
+        cmd.parse(Ok(&edm_packet(PayloadType::ATConfirmation, &response)))
+    }
+}
+
+/// Reply of the module in AT mode, if any.
+fn at_reply(reply: Reply) -> Option<Vec<u8>> {
+    match reply {
+        Reply::Response(response) if response.is_empty() => Some(b"\r\nOK\r\n".to_vec()),
+        Reply::Response(response) => Some(format!("\r\n{}\r\nOK\r\n", response).into_bytes()),
+        Reply::Prompt => Some(b"\r\n>".to_vec()),
+        Reply::Error(atat::Error::Timeout) | Reply::Stall => None,
+        Reply::Error(_) => Some(b"\r\nERROR\r\n".to_vec()),
+    }
+}
+
+impl Canned {
+    fn new(prefix: &str, reply: Reply) -> Self {
+        Self {
+            prefix: prefix.into(),
+            reply,
+        }
+    }
+}
+
+/// EDM frame of `payload`.
+#[cfg(feature = "internal-network-stack")]
+fn edm_packet(payload_type: PayloadType, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; payload.len() + PAYLOAD_OVERHEAD];
+    let len = edm_frame(payload_type, payload, &mut frame);
+    frame.truncate(len);
+    frame
+}
+
+/// Protocol, address and port of a peer URL with an IPv4 address, e.g.
+/// `tcp://192.168.0.1:8080/`.
+#[cfg(feature = "internal-network-stack")]
+fn parse_url(url: &str) -> Option<(Protocol, Ipv4Addr, u16)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let protocol = match scheme {
+        "tcp" => Protocol::TCP,
+        "udp" => Protocol::UDP,
+        _ => return None,
+    };
+    let authority = rest.split(['/', '?']).next()?;
+    let (host, port) = authority.rsplit_once(':')?;
+    Some((protocol, host.parse().ok()?, port.parse().ok()?))
+}
+
+impl AtatClient for MockUbloxModule {
+    async fn send<Cmd: AtatCmd>(&mut self, cmd: &Cmd) -> Result<Cmd::Response, atat::Error> {
+        let mut buf = vec![0; Cmd::MAX_LEN];
+        let len = cmd.write(&mut buf);
+        if len == 0 {
+            return Err(atat::Error::Write);
+        }
+        let buf = &buf[..len];
+
+        #[cfg(feature = "internal-network-stack")]
+        if buf[0] == STARTBYTE {
+            return self.send_edm(cmd, buf).await;
+        }
+
+        self.sent.push(Sent {
+            raw: buf.to_vec(),
+            line: buf.to_vec(),
+        });
+
+        match self.respond_to(buf) {
+            Reply::Response(response) => cmd.parse(Ok(response.as_bytes())),
+            Reply::Prompt => cmd.parse(Ok(b">")),
+            Reply::Error(e) => Err(e),
+            Reply::Stall => core::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::{general::SoftwareVersion, security::SendSecurityDataImport, AT};
+    use embassy_futures::{block_on, select::select};
+
+    #[test]
+    fn canned_responses() {
+        let mut module = MockUbloxModule::new();
+        module.fail("AT+CGMR", atat::Error::Timeout);
+        module.respond_once("AT\r\n", "");
+        module.fail_once("AT\r\n", atat::Error::Error);
+
+        assert!(block_on(module.send(&AT)).is_ok());
+        assert_eq!(
+            block_on(module.send(&AT)).map(|_| ()),
+            Err(atat::Error::Error)
+        );
+        // Built-in response once the one-shot responses are used up
+        assert!(block_on(module.send(&AT)).is_ok());
+        assert_eq!(
+            block_on(module.send(&SoftwareVersion)).map(|_| ()),
+            Err(atat::Error::Timeout)
+        );
+        assert_eq!(module.sent_commands().count(), 4);
+
+        module.clear_sent();
+        assert_eq!(module.sent_commands().count(), 0);
+    }
+
+    #[test]
+    fn long_commands() {
+        let mut module = MockUbloxModule::new();
+        let data = [b'x'; 2048];
+
+        // Longer than the requests of the runner
+        block_on(module.send(&SendSecurityDataImport {
+            data: atat::serde_bytes::Bytes::new(&data),
+        }))
+        .ok();
+        assert_eq!(module.sent_commands().next(), Some(data.as_slice()));
+    }
+
+    #[cfg(not(feature = "internal-network-stack"))]
+    #[test]
+    fn serve_import() {
+        use crate::asynch::runner::URC_SUBSCRIBERS;
+        use crate::command::Urc;
+
+        let res_slot = atat::ResponseSlot::<256>::new();
+        let urc_channel = atat::UrcChannel::<Urc, 2, { URC_SUBSCRIBERS }>::new();
+        let mut buf = [0u8; 256];
+        let mut ingress = atat::Ingress::new(
+            atat::AtDigester::<Urc>::new(),
+            &mut buf,
+            &res_slot,
+            &urc_channel,
+        );
+        let requests = Channel::new();
+        let mut subscription = urc_channel.subscribe().unwrap();
+
+        let mut module = MockUbloxModule::new();
+        module.import_md5("0e4d4b7d7b2ab0b5a3b6c4e1c86f0e73");
+        module.inject_urc_after("AT+USECMNG=0", "+UUNU:0");
+
+        let driver = async {
+            let send = |bytes: &[u8]| requests.send(heapless::Vec::from_slice(bytes).unwrap());
+
+            send(b"AT+USECMNG=0,0,\"app_ca\",4\r\n").await;
+            // The prompt, followed by the URC
+            drop(res_slot.get().await);
+            res_slot.reset();
+            assert!(matches!(
+                subscription.next_message_pure().await,
+                Urc::NetworkUp(_)
+            ));
+
+            send(b"ca").await;
+            send(b"ca").await;
+            drop(res_slot.get().await);
+        };
+        block_on(select(module.serve(&requests, &mut ingress), driver));
+
+        assert_eq!(module.imported("app_ca"), Some(b"caca".as_slice()));
+        assert_eq!(
+            module.sent_commands().collect::<Vec<_>>(),
+            [b"AT+USECMNG=0,0,\"app_ca\",4\r\n".as_slice()]
+        );
+    }
+
+    #[cfg(feature = "internal-network-stack")]
+    mod edm {
+        use super::*;
+        use crate::command::{
+            data_mode::{ClosePeerConnection, ConnectPeer},
+            edm::{urc::EdmEvent, EdmAtCmdWrapper, EdmDataCommand},
+            network::urc::NetworkUp,
+            wifi::WifiScan,
+            Urc,
+        };
+        use atat::AtatUrc;
+
+        fn next_event(module: &mut MockUbloxModule) -> EdmEvent {
+            EdmEvent::parse(&module.next_frame().unwrap()).unwrap()
+        }
+
+        #[test]
+        fn scan() {
+            let mut module = MockUbloxModule::new();
+            module.add_scan_result("+UWSCAN:D47B75A1B2C3,1,\"Blackbird\",6,-52,18,8,8");
+
+            let response =
+                block_on(module.send(&EdmAtCmdWrapper(WifiScan { ssid: None }))).unwrap();
+            assert_eq!(response.network_list.len(), 1);
+            assert_eq!(
+                module.sent_commands().collect::<Vec<_>>(),
+                [b"AT+UWSCAN\r\n".as_slice()]
+            );
+            assert_eq!(module.sent_raw().next().unwrap()[0], STARTBYTE);
+        }
+
+        #[test]
+        fn peer_lifecycle() {
+            let mut module = MockUbloxModule::new();
+
+            let response = block_on(module.send(&EdmAtCmdWrapper(ConnectPeer {
+                url: "tcp://192.168.0.1:8080/",
+            })))
+            .unwrap();
+            let channel_id = module.channel_id(response.peer_handle).unwrap();
+
+            match next_event(&mut module) {
+                EdmEvent::IPv4ConnectEvent(ev) => {
+                    assert_eq!(ev.channel_id, channel_id);
+                    assert_eq!(ev.protocol, Protocol::TCP);
+                    assert_eq!(ev.remote_ip, Ipv4Addr::new(192, 168, 0, 1));
+                    assert_eq!(ev.remote_port, 8080);
+                }
+                ev => panic!("Unexpected event {:?}", ev),
+            }
+            match next_event(&mut module) {
+                EdmEvent::ATEvent(Urc::PeerConnected(urc)) => {
+                    assert_eq!(urc.handle, response.peer_handle);
+                    assert_eq!(urc.remote_port, 8080);
+                }
+                ev => panic!("Unexpected event {:?}", ev),
+            }
+
+            block_on(module.send(&EdmDataCommand {
+                channel: channel_id,
+                data: b"hello",
+            }))
+            .unwrap();
+            assert_eq!(module.sent_data(channel_id), Some(b"hello".as_slice()));
+
+            block_on(module.send(&EdmAtCmdWrapper(ClosePeerConnection {
+                peer_handle: response.peer_handle,
+            })))
+            .unwrap();
+            assert_eq!(
+                next_event(&mut module),
+                EdmEvent::DisconnectEvent(channel_id)
+            );
+            assert!(matches!(
+                next_event(&mut module),
+                EdmEvent::ATEvent(Urc::PeerDisconnected(_))
+            ));
+            assert_eq!(module.pending_frames(), 0);
+
+            // The peer is gone
+            assert!(block_on(module.send(&EdmAtCmdWrapper(ClosePeerConnection {
+                peer_handle: response.peer_handle,
+            })))
+            .is_err());
+        }
+
+        #[test]
+        fn failed_connect() {
+            let mut module = MockUbloxModule::new();
+            module.fail("AT+UDCP=", atat::Error::Timeout);
+
+            assert_eq!(
+                block_on(module.send(&EdmAtCmdWrapper(ConnectPeer {
+                    url: "tcp://192.168.0.1:8080/",
+                })))
+                .map(|_| ()),
+                Err(atat::Error::Timeout)
+            );
+            assert_eq!(module.pending_frames(), 0);
+        }
+
+        #[test]
+        fn deliver_urcs() {
+            let res_slot = atat::ResponseSlot::<256>::new();
+            let urc_channel =
+                atat::UrcChannel::<EdmEvent, 2, { crate::asynch::runner::URC_SUBSCRIBERS }>::new();
+            let mut buf = [0u8; 256];
+            let mut ingress = atat::Ingress::new(
+                crate::command::custom_digest::EdmDigester::new(),
+                &mut buf,
+                &res_slot,
+                &urc_channel,
+            );
+            let mut subscription = urc_channel.subscribe().unwrap();
+
+            let mut module = MockUbloxModule::new();
+            module.inject_urc("+UUNU:0");
+            module.inject_data(ChannelId(1), b"data");
+            block_on(module.deliver(&mut ingress));
+
+            assert_eq!(
+                subscription.try_next_message_pure(),
+                Some(EdmEvent::ATEvent(Urc::NetworkUp(NetworkUp {
+                    interface_id: 0
+                })))
+            );
+            assert!(matches!(
+                subscription.try_next_message_pure(),
+                Some(EdmEvent::DataEvent(ev)) if ev.data == b"data"[..]
+            ));
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Utilities for writing deterministic host-side tests against the driver.
+//!
+//! All timeouts in the driver are driven by `embassy-time`. Enabling the
+//! `test-util` feature selects the `embassy-time` mock driver, allowing tests to
+//! advance time manually instead of depending on a hardware timer.
+//!
+//! A [`MockUbloxModule`] simulates the module, answering commands with canned
+//! responses.
+use embassy_time::{Duration, Instant, MockDriver};
+
+#[cfg(test)]
+pub(crate) mod harness;
+mod mock;
+
+#[cfg(test)]
+pub(crate) use harness::Harness;
+pub use mock::MockUbloxModule;
+
+/// Manually advanced clock, backed by the `embassy-time` mock driver.
+///
+/// Every timer in the driver (command timeouts, link-state waits, socket
+/// timeouts) observes the time of this clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ManualClock;
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Advance the clock by `ms` milliseconds, waking any expired timers.
+    pub fn advance(&self, ms: u64) {
+        MockDriver::get().advance(Duration::from_millis(ms));
+    }
+
+    /// Advance the clock by `duration`.
+    pub fn advance_by(&self, duration: Duration) {
+        MockDriver::get().advance(duration);
+    }
+
+    /// Current time of the clock.
+    pub fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Reset the clock back to zero.
+    pub fn reset(&self) {
+        MockDriver::get().reset();
+    }
+}
@@ -0,0 +1,264 @@
+//! Timeouts of the externally visible waits of the driver.
+//!
+//! The timeouts are set through
+//! [`WifiConfig::TIMEOUTS`](crate::WifiConfig::TIMEOUTS), and default to
+//! [`Timeouts::DEFAULT`]. Each field documents whether it is safe to shorten.
+//!
+//! The time a closing TCP socket is given to hand its queued data to the
+//! module is not among them, as it is set per socket, see
+//! `TcpSocket::set_linger`.
+use embassy_time::Duration;
+
+use crate::options::DEFAULT_JOIN_TIMEOUT;
+
+/// Timeouts of the driver, see the [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timeouts {
+    /// Wait for the startup message of the module after a hard reset. Not
+    /// safe to shorten, the module takes several seconds to boot.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub startup: Duration,
+    /// Wait for the startup message of the module after a soft reboot, e.g.
+    /// to apply a stored configuration. Not safe to shorten.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub reboot: Duration,
+    /// Time allowed for switching the module to EDM, including retries. Safe
+    /// to shorten to a few hundred milliseconds, at the cost of more
+    /// initialization attempts on a busy module.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub edm_entry: Duration,
+    /// Delay after entering EDM before the first EDM packet is sent, as
+    /// required by the module. Not safe to shorten.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub edm_settle: Duration,
+    /// Lower bound of the response timeout of every AT command. Commands
+    /// declaring a longer timeout keep theirs. Also bounds the wait for the
+    /// runner to take each part of a command. Not safe to shorten below the
+    /// default, which most commands declare.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub command_default: Duration,
+    /// Wait for the results of a Wi-Fi scan. A scan across all channels takes
+    /// several seconds, so only shorten it for scans restricted to few
+    /// channels.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub scan: Duration,
+    /// Wait for the link to come up when joining a network, unless
+    /// overridden per connection. Safe to shorten for known networks.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub connect: Duration,
+    /// Wait for the connection to go down when leaving a network. Safe to
+    /// shorten.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub disconnect: Duration,
+    /// Wait for the module to resolve a hostname, with the internal network
+    /// stack. Safe to shorten for responsive DNS servers.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub dns: Duration,
+    /// Wait for the reply to a ping. Safe to shorten.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub ping: Duration,
+    /// Interval at which `Control::wait_for_ip` queries the network status
+    /// of the module. Safe to shorten, at the cost of more commands while
+    /// waiting.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub ip_poll: Duration,
+    /// Wait for the response of an SNTP server, see
+    /// `UbloxStack::ntp_time`. Safe to shorten for servers on the local
+    /// network.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub ntp: Duration,
+    /// Wait for the module to connect a TCP socket, with the internal
+    /// network stack. Safe to shorten for hosts on the local network.
+    #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+    pub socket_connect: Duration,
+}
+
+impl Timeouts {
+    pub const DEFAULT: Self = Self {
+        startup: Duration::from_secs(5),
+        reboot: Duration::from_secs(5),
+        edm_entry: Duration::from_secs(4),
+        edm_settle: Duration::from_millis(50),
+        command_default: Duration::from_millis(1000),
+        scan: Duration::from_secs(10),
+        connect: DEFAULT_JOIN_TIMEOUT,
+        disconnect: Duration::from_secs(10),
+        dns: Duration::from_secs(10),
+        ping: Duration::from_secs(15),
+        ip_poll: Duration::from_millis(250),
+        ntp: Duration::from_secs(5),
+        socket_connect: Duration::from_secs(20),
+    };
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    /// Durations still hardcoded in the driver, as internal pacing, protocol
+    /// limits or defaults of other settings, rather than waits visible to the
+    /// application. Any other duration literal belongs in
+    /// [`Timeouts`](super::Timeouts).
+    const REGISTRY: &[(&str, &str)] = &[
+        // Reset pin pulse
+        ("asynch/network.rs", "Duration::from_millis(100)"),
+        // Pacing of the EDM switch retries
+        ("asynch/network.rs", "Duration::from_millis(10)"),
+        // Minimum gap between commands
+        ("asynch/control.rs", "Timer::after_millis(20)"),
+        // Settling after a baud rate change
+        ("asynch/runner.rs", "Timer::after_millis(40)"),
+        // Draining the UART after switching to PPP
+        ("asynch/runner.rs", "Duration::from_millis(500)"),
+        // Window of the URCs of a single link event
+        ("asynch/state.rs", "Duration::from_secs(2)"),
+        // Time the module keeps pinging a host to resolve it
+        ("asynch/ublox_stack/dns.rs", "Duration::from_secs(10)"),
+        // Idle time after which the peer of a UDP sender is released
+        ("asynch/ublox_stack/mod.rs", "Duration::from_secs(60)"),
+        // Tick of the socket loop, for the timers of the sockets
+        ("asynch/ublox_stack/mod.rs", "Duration::from_millis(100)"),
+        // Default of `ConnectionOptions::join_timeout`
+        ("options.rs", "Duration::from_secs(20)"),
+        // Default sampling interval of `RoamingConfig`
+        ("options.rs", "Duration::from_secs(10)"),
+        // Serial port read timeout of the host tools
+        ("tools.rs", "Duration::from_millis(10)"),
+        // Response to the data of a credential import by the host tools
+        ("tools.rs", "Duration::from_secs(3)"),
+        // Response to a raw AT command of the CLI
+        ("bin/ublox-cli.rs", "Duration::from_secs(10)"),
+        // Gap between the commands served by the mock module
+        ("test_util/mock.rs", "Duration::from_millis(20)"),
+    ];
+
+    /// Waits bounded by a timeout other than one of [`Timeouts`](super::Timeouts),
+    /// as the first argument of `with_timeout`. Timeouts given by the
+    /// application are fine, any other belongs in `Timeouts`.
+    const WAITS: &[(&str, &str)] = &[
+        // Response timeout of a command, at least `command_default`, and the
+        // timeouts of `wait_for_ip` and `import_credentials`
+        ("asynch/control.rs", "timeout"),
+        // `startup`, `reboot` and `edm_entry`
+        ("asynch/network.rs", "timeout"),
+        // Connect timeout of a socket, `socket_connect` unless overridden
+        ("asynch/ublox_stack/tcp.rs", "timeout"),
+        // Linger time of `TcpSocket::close_linger`, set per socket
+        ("asynch/ublox_stack/tcp.rs", "linger"),
+        // Time left of the restart capture
+        ("restart_capture.rs", "budget"),
+        ("tools.rs", "timeout"),
+    ];
+
+    const PATTERNS: &[&str] = &[
+        "Duration::from_secs(",
+        "Duration::from_millis(",
+        "Duration::from_micros(",
+        "Timer::after_secs(",
+        "Timer::after_millis(",
+        "Timer::after_micros(",
+    ];
+
+    /// `source` up to its first test module.
+    fn without_tests(source: &str) -> &str {
+        let mut offset = 0;
+        let mut attribute = None;
+        for line in source.split_inclusive('\n') {
+            if line.starts_with("mod ") {
+                if let Some(start) = attribute {
+                    return &source[..start];
+                }
+            }
+            attribute = (line.starts_with("#[cfg(") && line.contains("test")).then_some(offset);
+            offset += line.len();
+        }
+        source
+    }
+
+    /// The sources of the driver, by path relative to `src`, without their
+    /// tests, as tests are free to use any duration.
+    fn sources() -> std::vec::Vec<(String, String)> {
+        fn visit(dir: &Path, root: &Path, sources: &mut std::vec::Vec<(String, String)>) {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    visit(&path, root, sources);
+                } else if path.extension().is_some_and(|ext| ext == "rs") {
+                    let file = path.strip_prefix(root).unwrap().to_str().unwrap();
+                    let source = std::fs::read_to_string(&path).unwrap();
+                    sources.push((file.replace('\\', "/"), without_tests(&source).into()));
+                }
+            }
+        }
+
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut sources = std::vec::Vec::new();
+        visit(&root, &root, &mut sources);
+        sources
+    }
+
+    #[test]
+    fn no_unregistered_durations() {
+        let sources = sources();
+        assert!(sources.iter().any(|(file, _)| file == "asynch/runner.rs"));
+
+        for (file, source) in &sources {
+            // The defaults of the timeouts themselves
+            if file == "timeouts.rs" {
+                continue;
+            }
+
+            for pattern in PATTERNS {
+                for (start, _) in source.match_indices(pattern) {
+                    let rest = &source[start..];
+                    let literal = &rest[..rest.find(')').unwrap() + 1];
+                    let arg = &literal[pattern.len()..];
+                    if !arg.starts_with(|c: char| c.is_ascii_digit()) {
+                        continue;
+                    }
+
+                    assert!(
+                        REGISTRY.contains(&(file.as_str(), literal)),
+                        "{} in {} should be part of Timeouts",
+                        literal,
+                        file
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn no_unregistered_waits() {
+        for (file, source) in &sources() {
+            for (start, _) in source.match_indices("with_timeout(") {
+                // Not a function merely named alike
+                let before = source[..start].chars().next_back();
+                if before.is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    continue;
+                }
+
+                let rest = &source[start + "with_timeout(".len()..];
+                let arg = rest[..rest.find(',').unwrap()].trim();
+                // Literals are audited by `no_unregistered_durations`
+                if arg.contains("timeouts()") || arg.starts_with("Duration::") {
+                    continue;
+                }
+
+                assert!(
+                    WAITS.contains(&(file.as_str(), arg)),
+                    "with_timeout({}, ..) in {} should be part of Timeouts",
+                    arg,
+                    file
+                );
+            }
+        }
+    }
+}
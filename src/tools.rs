@@ -0,0 +1,490 @@
+//! Building blocks of the `ublox-cli` host tool.
+//!
+//! The tool talks to a module from a host through a serial port, using the
+//! same command types and digester as the driver, for bring-up and field
+//! debugging. The module is used in plain AT command mode, without EDM, and
+//! commands are issued directly by a [`Session`] rather than through the
+//! [`Runner`](crate::asynch::Runner).
+use std::io;
+
+use atat::{AtDigester, AtatCmd, AtatUrc, DigestResult, Digester};
+use embassy_time::{with_timeout, Duration};
+use embedded_io_async::{BufRead, ErrorType, Read, Write};
+use serialport::SerialPort;
+
+use crate::{
+    command::{
+        general::{responses::SoftwareVersionResponse, types::FirmwareVersion, SoftwareVersion},
+        network::{responses::NetworkStatusResponse, GetNetworkStatus},
+        security::{
+            types::SecurityDataType, PrepareSecurityDataImport, SendSecurityDataImport,
+            MAX_SECURITY_DATA_SIZE,
+        },
+        system::{types::EchoOn, SetEcho},
+        wifi::{
+            responses::{WifiScanResponse, WifiStatusResponse, MAX_SCAN_RESULTS},
+            types::{StatusId, WifiStatus, WifiStatusVal},
+            GetWifiStatus, WifiScan,
+        },
+        Urc, AT,
+    },
+    connection::NetworkStatusSummary,
+    error::Error,
+    network::{strongest_networks, WifiNetwork},
+    options::CredentialNamespace,
+    Transport,
+};
+
+/// Serial port transport for running the driver on a host.
+///
+/// Reads poll the port with a short timeout, yielding to the executor in
+/// between, so timers keep running while waiting for the module.
+pub struct SerialTransport {
+    tx: Box<dyn SerialPort>,
+    rx: Box<dyn SerialPort>,
+    buf: [u8; 256],
+    pos: usize,
+    len: usize,
+}
+
+impl SerialTransport {
+    /// Open the serial port at `path`, with the given baud rate.
+    pub fn open(path: &str, baud_rate: u32, flow_control: bool) -> io::Result<Self> {
+        let flow_control = if flow_control {
+            serialport::FlowControl::Hardware
+        } else {
+            serialport::FlowControl::None
+        };
+
+        let tx = serialport::new(path, baud_rate)
+            .flow_control(flow_control)
+            .timeout(core::time::Duration::from_millis(10))
+            .open()?;
+        let rx = tx.try_clone()?;
+
+        Ok(Self {
+            tx,
+            rx,
+            buf: [0; 256],
+            pos: 0,
+            len: 0,
+        })
+    }
+}
+
+/// Write half of a [`SerialTransport`].
+pub struct SerialTx<'a>(&'a mut dyn SerialPort);
+
+/// Read half of a [`SerialTransport`].
+pub struct SerialRx<'a>(&'a mut dyn SerialPort);
+
+async fn read_port(port: &mut dyn SerialPort, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match io::Read::read(port, buf) {
+            Ok(n) if n > 0 => return Ok(n),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+        embassy_futures::yield_now().await;
+    }
+}
+
+fn write_port(port: &mut dyn SerialPort, buf: &[u8]) -> io::Result<usize> {
+    io::Write::write(port, buf)
+}
+
+impl ErrorType for SerialTransport {
+    type Error = io::Error;
+}
+
+impl ErrorType for SerialTx<'_> {
+    type Error = io::Error;
+}
+
+impl ErrorType for SerialRx<'_> {
+    type Error = io::Error;
+}
+
+impl Read for SerialTransport {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.pos < self.len {
+            let n = buf.len().min(self.len - self.pos);
+            buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+            self.pos += n;
+            return Ok(n);
+        }
+        read_port(self.rx.as_mut(), buf).await
+    }
+}
+
+impl BufRead for SerialTransport {
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        if self.pos == self.len {
+            self.len = read_port(self.rx.as_mut(), &mut self.buf).await?;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.len);
+    }
+}
+
+impl Write for SerialTransport {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        write_port(self.tx.as_mut(), buf)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        io::Write::flush(&mut self.tx)
+    }
+}
+
+impl Read for SerialRx<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        read_port(self.0, buf).await
+    }
+}
+
+impl Write for SerialTx<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        write_port(self.0, buf)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        io::Write::flush(self.0)
+    }
+}
+
+impl Transport for SerialTransport {
+    fn set_baudrate(&mut self, baudrate: u32) {
+        if let Err(e) = self.tx.set_baud_rate(baudrate) {
+            warn!("Failed to set baud rate {}: {}", baudrate, e);
+        }
+    }
+
+    fn split_ref(&mut self) -> (impl Write, impl Read) {
+        (SerialTx(self.tx.as_mut()), SerialRx(self.rx.as_mut()))
+    }
+}
+
+enum Digested {
+    Response(Result<Vec<u8>, atat::Error>),
+    Urc(Vec<u8>),
+}
+
+/// AT command session with a module, over any transport.
+pub struct Session<T> {
+    transport: T,
+    digester: AtDigester<Urc>,
+    buf: Vec<u8>,
+}
+
+impl<T: Read + Write> Session<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            digester: AtDigester::new(),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Bring the module into a known state for the session: check that it
+    /// responds, disable echo and read its firmware version.
+    pub async fn init(&mut self) -> Result<FirmwareVersion, Error> {
+        let mut attempts = 3;
+        while let Err(e) = self.send(&AT).await {
+            attempts -= 1;
+            if attempts == 0 {
+                return Err(e);
+            }
+        }
+
+        self.send(&SetEcho { on: EchoOn::Off }).await?;
+
+        let SoftwareVersionResponse { version } = self.send(&SoftwareVersion).await?;
+        Ok(version)
+    }
+
+    /// Send a command and wait for its response.
+    pub async fn send<Cmd: AtatCmd>(&mut self, cmd: &Cmd) -> Result<Cmd::Response, Error> {
+        let mut req = vec![0; Cmd::MAX_LEN];
+        let len = cmd.write(&mut req);
+        self.write_raw(&req[..len]).await?;
+
+        if !Cmd::EXPECTS_RESPONSE_CODE {
+            return Ok(cmd.parse(Ok(&[]))?);
+        }
+
+        let response = self
+            .response(Duration::from_millis(Cmd::MAX_TIMEOUT_MS.into()))
+            .await??;
+        Ok(cmd.parse(Ok(&response))?)
+    }
+
+    /// Scan for networks, strongest first, skipping entries that cannot be
+    /// parsed.
+    pub async fn scan(&mut self) -> Result<Vec<WifiNetwork>, Error> {
+        let WifiScanResponse { network_list } = self.send(&WifiScan { ssid: None }).await?;
+
+        Ok(strongest_networks::<MAX_SCAN_RESULTS>(
+            network_list
+                .into_iter()
+                .filter_map(|network| WifiNetwork::try_from(network).ok()),
+        )
+        .into_iter()
+        .collect())
+    }
+
+    /// Status of the Wi-Fi station.
+    pub async fn wifi_status(&mut self) -> Result<WifiStatusVal, Error> {
+        let WifiStatusResponse { status_id } = self
+            .send(&GetWifiStatus {
+                status_id: StatusId::Status,
+            })
+            .await?;
+
+        match status_id {
+            WifiStatus::Status(s) => Ok(s),
+            _ => Err(Error::AT(atat::Error::InvalidResponse)),
+        }
+    }
+
+    /// IP configuration of the interface `interface_id`.
+    pub async fn network_status(
+        &mut self,
+        interface_id: u8,
+    ) -> Result<NetworkStatusSummary, Error> {
+        let mut summary = NetworkStatusSummary::new();
+        for status in NetworkStatusSummary::QUERIES {
+            let NetworkStatusResponse { status, .. } = self
+                .send(&GetNetworkStatus {
+                    interface_id,
+                    status,
+                })
+                .await?;
+            summary.update(status)?;
+        }
+        Ok(summary)
+    }
+
+    /// Import a certificate or private key under `name`, overwriting any
+    /// existing data of the same name.
+    pub async fn import_credentials(
+        &mut self,
+        data_type: SecurityDataType,
+        name: &str,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        if data.len() > MAX_SECURITY_DATA_SIZE {
+            return Err(Error::BadLength);
+        }
+        CredentialNamespace::default().apply(name)?;
+
+        self.send(&PrepareSecurityDataImport {
+            data_type,
+            data_size: data.len(),
+            internal_name: name,
+            password: None,
+        })
+        .await?;
+
+        self.write_raw(data).await?;
+
+        let response = self.response(Duration::from_secs(3)).await??;
+        SendSecurityDataImport {
+            data: atat::serde_bytes::Bytes::new(data),
+        }
+        .parse(Ok(&response))?;
+
+        Ok(())
+    }
+
+    /// Send a raw command line, such as `AT+UMLA=1`, returning the
+    /// information text of the response.
+    pub async fn raw_at(&mut self, line: &str, timeout: Duration) -> Result<Vec<u8>, Error> {
+        self.write_raw(line.trim_end().as_bytes()).await?;
+        self.write_raw(b"\r\n").await?;
+
+        Ok(self.response(timeout).await??)
+    }
+
+    /// Wait for the next URC from the module, skipping any that cannot be
+    /// parsed.
+    pub async fn next_urc(&mut self) -> Result<Urc, Error> {
+        loop {
+            match self.digest().await? {
+                Digested::Urc(urc) => match Urc::parse(&urc) {
+                    Some(urc) => return Ok(urc),
+                    None => warn!("Unable to parse URC: {:?}", atat::helpers::LossyStr(&urc)),
+                },
+                Digested::Response(_) => debug!("Dropping unsolicited response"),
+            }
+        }
+    }
+
+    async fn write_raw(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.transport
+            .write_all(data)
+            .await
+            .map_err(|_| Error::AT(atat::Error::Write))?;
+        self.transport
+            .flush()
+            .await
+            .map_err(|_| Error::AT(atat::Error::Write))
+    }
+
+    async fn response(&mut self, timeout: Duration) -> Result<Result<Vec<u8>, atat::Error>, Error> {
+        with_timeout(timeout, async {
+            loop {
+                match self.digest().await? {
+                    Digested::Response(response) => return Ok::<_, Error>(response),
+                    Digested::Urc(urc) => {
+                        debug!(
+                            "Dropping URC while waiting for response: {:?}",
+                            atat::helpers::LossyStr(&urc)
+                        )
+                    }
+                }
+            }
+        })
+        .await
+        .map_err(|_| Error::Timeout)?
+    }
+
+    async fn digest(&mut self) -> Result<Digested, Error> {
+        loop {
+            let (result, used) = self.digester.digest(&self.buf);
+            let digested = match result {
+                DigestResult::None => None,
+                // The module is ready for raw data, see `import_credentials`
+                DigestResult::Prompt(_) => Some(Digested::Response(Ok(Vec::new()))),
+                DigestResult::Urc(urc) => Some(Digested::Urc(urc.to_vec())),
+                DigestResult::Response(response) => Some(Digested::Response(
+                    response.map(<[u8]>::to_vec).map_err(atat::Error::from),
+                )),
+            };
+            self.buf.drain(..used);
+
+            if let Some(digested) = digested {
+                return Ok(digested);
+            }
+
+            if used == 0 {
+                let mut chunk = [0; 256];
+                let n = self
+                    .transport
+                    .read(&mut chunk)
+                    .await
+                    .map_err(|_| Error::AT(atat::Error::Read))?;
+                self.buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// Transport answering each command line with a canned response.
+    struct MockTransport {
+        responses: Vec<(&'static str, &'static str)>,
+        line: Vec<u8>,
+        rx: VecDeque<u8>,
+        written: Vec<Vec<u8>>,
+    }
+
+    impl MockTransport {
+        fn new(responses: &[(&'static str, &'static str)]) -> Self {
+            Self {
+                responses: responses.to_vec(),
+                line: Vec::new(),
+                rx: VecDeque::new(),
+                written: Vec::new(),
+            }
+        }
+    }
+
+    impl ErrorType for MockTransport {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for MockTransport {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            assert!(!self.rx.is_empty(), "read without pending response");
+            let n = buf.len().min(self.rx.len());
+            for (b, r) in buf.iter_mut().zip(self.rx.drain(..n)) {
+                *b = r;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for MockTransport {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.line.extend_from_slice(buf);
+            if self.line.ends_with(b"\r\n") {
+                let line = core::mem::take(&mut self.line);
+                let response = self
+                    .responses
+                    .iter()
+                    .find(|(cmd, _)| line.starts_with(cmd.as_bytes()))
+                    .map_or("ERROR\r\n", |(_, response)| *response);
+                self.rx.extend(response.bytes());
+                self.written.push(line);
+            }
+            Ok(buf.len())
+        }
+    }
+
+    const INIT: [(&str, &str); 3] = [
+        ("AT\r\n", "OK\r\n"),
+        ("ATE0\r\n", "OK\r\n"),
+        ("AT+CGMR\r\n", "7.0.0\r\nOK\r\n"),
+    ];
+
+    #[test]
+    fn init() {
+        let mut session = Session::new(MockTransport::new(&INIT));
+
+        let version = embassy_futures::block_on(session.init()).unwrap();
+        assert_eq!(version, FirmwareVersion::new(7, 0, 0));
+        assert_eq!(session.transport.written.len(), 3);
+    }
+
+    #[test]
+    fn scan() {
+        let mut session = Session::new(MockTransport::new(&[(
+            "AT+UWSCAN\r\n",
+            "+UWSCAN:D47B75A1B2C3,1,\"Blackbird\",6,-52,18,8,8\r\nOK\r\n",
+        )]));
+
+        let networks = embassy_futures::block_on(session.scan()).unwrap();
+        assert_eq!(networks.len(), 1);
+        assert_eq!(networks[0].ssid.as_str(), "Blackbird");
+        assert_eq!(networks[0].channel, 6);
+        assert_eq!(networks[0].rssi, -52);
+    }
+
+    #[test]
+    fn raw_at() {
+        let mut session = Session::new(MockTransport::new(&[
+            ("AT+UMLA=1\r\n", "+UMLA:D47B75A1B2C3\r\nOK\r\n"),
+            ("AT+UNKNOWN\r\n", "ERROR\r\n"),
+        ]));
+
+        let response =
+            embassy_futures::block_on(session.raw_at("AT+UMLA=1\n", Duration::from_secs(1)))
+                .unwrap();
+        assert_eq!(response, b"+UMLA:D47B75A1B2C3");
+
+        assert!(matches!(
+            embassy_futures::block_on(session.raw_at("AT+UNKNOWN", Duration::from_secs(1))),
+            Err(Error::AT(atat::Error::Error))
+        ));
+    }
+}
@@ -0,0 +1,13 @@
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Overwrite `buf` with zeroes, in a way that is not optimized away.
+///
+/// Used to wipe buffers that may have held credentials, such as serialized
+/// commands carrying a Wi-Fi passphrase.
+pub(crate) fn zeroize(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        // Safety: `b` is a valid, aligned reference
+        unsafe { core::ptr::write_volatile(b, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}